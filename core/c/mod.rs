@@ -25,6 +25,21 @@ impl Display for Script {
     }
 }
 
+impl Script {
+    /// Name of scalar variable that holds return value of the translated call, if any.
+    pub fn ret_var(&self) -> Option<&str> {
+        if let Some(Stmt::VarDecl(d)) = self.0.last() {
+            if let (Some(Exp::Call(_)), Declarator::Ident(ident)) = (&d.init.init, &d.init.decl) {
+                return match d.ts {
+                    TypeSpecifier::Struct(_) | TypeSpecifier::Union(_) => None,
+                    _ => Some(ident),
+                };
+            }
+        }
+        None
+    }
+}
+
 pub fn to_script(p: &Prog, t: &Target) -> Script {
     let mut s: State = Default::default();
 
@@ -17,7 +17,7 @@ use std::path::PathBuf;
 use ndarray::Axis;
 use rand::distributions::Alphanumeric;
 use rand::prelude::*;
-use rand::{random, thread_rng, Rng};
+use rand::Rng;
 
 use fots::types::{
     Field, Flag, FnInfo, GroupId, NumInfo, NumLimit, PtrDir, StrType, TypeId, TypeInfo,
@@ -25,6 +25,7 @@ use fots::types::{
 
 use crate::analyze::{RTable, Relation};
 use crate::prog::{Arg, ArgIndex, ArgPos, Call, Prog};
+use crate::rng::{random, thread_rng};
 use crate::target::Target;
 use crate::value::{NumValue, Value};
 
@@ -36,8 +37,25 @@ pub struct Config {
     pub str_max_len: usize,
     pub path_max_depth: usize,
     pub sp_delta: f64,
+    /// Generate out-of-spec values far more often, i.e. unknown flags, null pointers and
+    /// boundary numbers, for exercising error paths
+    pub negative: bool,
 }
 
+/// Chance of generating out-of-spec value in negative mode.
+const NEGATIVE_RATE: f64 = 0.1;
+/// Numbers generated in place of valid ones in negative mode.
+const BOUNDARY_VALS: [i64; 8] = [
+    0,
+    -1,
+    i8::MIN as i64,
+    i16::MIN as i64,
+    i32::MIN as i64,
+    i32::MAX as i64,
+    i64::MIN,
+    i64::MAX,
+];
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -47,6 +65,7 @@ impl Default for Config {
             str_max_len: 32,
             path_max_depth: 4,
             sp_delta: 0.4,
+            negative: false,
         }
     }
 }
@@ -59,10 +78,11 @@ pub fn gen<S: std::hash::BuildHasher>(
     assert!(!rs.is_empty());
     assert_eq!(t.groups.len(), rs.len());
 
-    let mut rng = thread_rng();
-    // choose group
-    let gid = rs.keys().choose(&mut rng).unwrap();
-    gen_prog(*gid, &rs[gid], t, conf)
+    // choose group, from sorted ids so that choice only depends on rng.
+    let mut gids = rs.keys().collect::<Vec<_>>();
+    gids.sort();
+    let gid = gids.choose(&mut thread_rng()).unwrap();
+    gen_prog(**gid, &rs[gid], t, conf)
 }
 
 pub fn gen_prog(gid: GroupId, r: &RTable, t: &Target, conf: &Config) -> Prog {
@@ -281,6 +301,9 @@ fn gen_call(t: &Target, f: &FnInfo, s: &mut State) {
 /// generate value for any type
 fn gen_value(tid: TypeId, t: &Target, s: &mut State) -> Value {
     match t.type_of(tid) {
+        TypeInfo::Num(_) if s.conf.negative && random::<f64>() < NEGATIVE_RATE => Value::Num(
+            NumValue::Signed(*BOUNDARY_VALS.choose(&mut thread_rng()).unwrap()),
+        ),
         TypeInfo::Num(num_info) => gen_num(num_info),
         TypeInfo::Ptr { dir, tid, depth } => {
             assert_eq!(*depth, 1, "Multi-level pointer not supported");
@@ -291,7 +314,7 @@ fn gen_value(tid: TypeId, t: &Target, s: &mut State) -> Value {
         TypeInfo::Str { str_type, vals } => gen_str(str_type, vals, s),
         TypeInfo::Struct { fields, .. } => gen_struct(&fields[..], t, s),
         TypeInfo::Union { fields, .. } => gen_union(&fields[..], t, s),
-        TypeInfo::Flag { flags, .. } => gen_flag(&flags[..]),
+        TypeInfo::Flag { flags, .. } => gen_flag(&flags[..], s.conf.negative),
 
         TypeInfo::Alias { tid: under_id, .. } => gen_alias(tid, *under_id, t, s),
         TypeInfo::Res { tid: under_tid } => gen_res(tid, *under_tid, t, s),
@@ -323,19 +346,25 @@ fn gen_ptr(dir: PtrDir, tid: TypeId, t: &Target, s: &mut State) -> Value {
         return Value::default_val(tid, t);
     }
 
-    if thread_rng().gen::<f64>() >= 0.001 {
+    let null_rate = if s.conf.negative {
+        NEGATIVE_RATE
+    } else {
+        0.001
+    };
+    if thread_rng().gen::<f64>() >= null_rate {
         gen_value(tid, t, s)
     } else {
         Value::None
     }
 }
 
-fn gen_flag(flags: &[Flag]) -> Value {
+fn gen_flag(flags: &[Flag], negative: bool) -> Value {
     assert!(!flags.is_empty());
 
     let mut rng = thread_rng();
+    let unknown_rate = if negative { NEGATIVE_RATE } else { 0.005 };
 
-    if rng.gen::<f64>() < 0.005 {
+    if rng.gen::<f64>() < unknown_rate {
         Value::Num(NumValue::Signed(rng.gen::<u8>() as i64))
     } else {
         let flag = flags.iter().choose(&mut rng).unwrap();
@@ -11,5 +11,6 @@ pub mod gen;
 pub mod minimize;
 pub mod mutate;
 pub mod prog;
+pub mod rng;
 pub mod target;
 pub mod value;
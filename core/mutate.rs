@@ -1,29 +1,45 @@
 use crate::analyze::RTable;
 use crate::gen::{gen_seq, Config};
 use crate::prog::Prog;
+use crate::rng::{thread_rng, FixedState};
 use crate::target::Target;
 use fots::types::GroupId;
 use rand::prelude::*;
 use std::collections::{HashMap, HashSet};
 
 #[allow(clippy::type_complexity)]
-const MUTATE_METHOD: [fn(&Prog, &Target, &RTable, &HashSet<Prog>, &Config) -> Prog; 2] =
+const MUTATE_METHOD: [fn(&Prog, &Target, &RTable, &HashSet<Prog, FixedState>, &Config) -> Prog; 2] =
     [seq_reuse, merge_seq /*remove_call*/];
 
 pub fn mutate(
-    corpus: &HashSet<Prog>,
+    corpus: &HashSet<Prog, FixedState>,
     t: &Target,
     rt: &HashMap<GroupId, RTable>,
     conf: &Config,
 ) -> Prog {
-    let mut rng = thread_rng();
-    let p = corpus.iter().choose(&mut rng).unwrap();
-    let rt = &rt[&p.gid];
-    let method = MUTATE_METHOD.choose(&mut rng).unwrap();
+    let p = corpus.iter().choose(&mut thread_rng()).unwrap();
+    mutate_prog(p, corpus, t, &rt[&p.gid], conf)
+}
+
+/// Mutate given prog of corpus, `rt` being relations of its group.
+pub fn mutate_prog(
+    p: &Prog,
+    corpus: &HashSet<Prog, FixedState>,
+    t: &Target,
+    rt: &RTable,
+    conf: &Config,
+) -> Prog {
+    let method = MUTATE_METHOD.choose(&mut thread_rng()).unwrap();
     method(p, t, rt, corpus, conf)
 }
 
-fn seq_reuse(p: &Prog, t: &Target, _rt: &RTable, _corpus: &HashSet<Prog>, conf: &Config) -> Prog {
+fn seq_reuse(
+    p: &Prog,
+    t: &Target,
+    _rt: &RTable,
+    _corpus: &HashSet<Prog, FixedState>,
+    conf: &Config,
+) -> Prog {
     let seq = extract_seq(p, t);
     gen_seq(&seq, p.gid, t, conf)
 }
@@ -37,7 +53,13 @@ fn extract_seq(p: &Prog, t: &Target) -> Vec<usize> {
     seq
 }
 
-fn merge_seq(p0: &Prog, t: &Target, _rt: &RTable, corpus: &HashSet<Prog>, conf: &Config) -> Prog {
+fn merge_seq(
+    p0: &Prog,
+    t: &Target,
+    _rt: &RTable,
+    corpus: &HashSet<Prog, FixedState>,
+    conf: &Config,
+) -> Prog {
     let mut rng = thread_rng();
     let merge_point = rng.gen_range(0, p0.len());
     let mut s0 = extract_seq(p0, t);
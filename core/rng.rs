@@ -0,0 +1,75 @@
+//! Randomness of generation and mutation. Choices come from rng of each thread by default,
+//! or from one rng shared by all threads once `seed` is called, so that runs with same seed
+//! make same choices.
+use rand::distributions::{Distribution, Standard};
+use rand::rngs::StdRng;
+use rand::{Error, Rng, RngCore, SeedableRng};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::BuildHasherDefault;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref SEEDED: Mutex<Option<StdRng>> = Mutex::new(None);
+}
+static IS_SEEDED: AtomicBool = AtomicBool::new(false);
+
+/// Hasher with fixed keys, so that sets and maps that choices are made from are iterated in
+/// same order across runs.
+pub type FixedState = BuildHasherDefault<DefaultHasher>;
+
+/// Make all following choices from rng seeded by `seed`.
+pub fn seed(seed: u64) {
+    *SEEDED.lock().unwrap() = Some(StdRng::seed_from_u64(seed));
+    IS_SEEDED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_seeded() -> bool {
+    IS_SEEDED.load(Ordering::SeqCst)
+}
+
+/// Rng of current run, in place of `rand::thread_rng`.
+pub fn thread_rng() -> RunRng {
+    RunRng
+}
+
+/// Random value from rng of current run, in place of `rand::random`.
+pub fn random<T>() -> T
+where
+    Standard: Distribution<T>,
+{
+    thread_rng().gen()
+}
+
+/// Handle of rng of current run, seeded one if any, rng of current thread otherwise.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RunRng;
+
+impl RunRng {
+    fn with<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        if is_seeded() {
+            let mut seeded = SEEDED.lock().unwrap();
+            f(seeded.as_mut().unwrap())
+        } else {
+            f(&mut rand::thread_rng())
+        }
+    }
+}
+
+impl RngCore for RunRng {
+    fn next_u32(&mut self) -> u32 {
+        Self::with(|r| r.next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        Self::with(|r| r.next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        Self::with(|r| r.fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        Self::with(|r| r.try_fill_bytes(dest))
+    }
+}
@@ -1,10 +1,11 @@
 use rand::prelude::SliceRandom;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 use fots::types::{TypeId, TypeInfo};
 
 use crate::gen::gen_slice_len;
 use crate::prog::ArgIndex;
+use crate::rng::thread_rng;
 use crate::target::Target;
 
 /// Value of type
@@ -0,0 +1,4 @@
+fn main() {
+    // sanitizer callbacks of harness are resolved from executor itself.
+    println!("cargo:rustc-link-arg-bins=-rdynamic");
+}
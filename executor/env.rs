@@ -0,0 +1,194 @@
+//! Environment set up before executing each prog, so that descriptions of fs, net and
+//! devices have something to operate on.
+
+use crate::Sandbox;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(target_os = "linux")]
+use linux::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Feature {
+    /// Run progs in a fresh tmpfs as working directory.
+    Tmpfs,
+    /// Dummy net devices.
+    Netdev,
+    /// Binderfs mounted on /dev/binderfs.
+    Binderfs,
+    /// Loop devices /dev/loop0-7.
+    Loopdev,
+}
+
+impl FromStr for Feature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tmpfs" => Ok(Feature::Tmpfs),
+            "netdev" => Ok(Feature::Netdev),
+            "binderfs" => Ok(Feature::Binderfs),
+            "loopdev" => Ok(Feature::Loopdev),
+            _ => Err(format!(
+                "unknown feature `{}`, expected tmpfs, netdev, binderfs or loopdev",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Feature::Tmpfs => "tmpfs",
+            Feature::Netdev => "netdev",
+            Feature::Binderfs => "binderfs",
+            Feature::Loopdev => "loopdev",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Set up features and enter sandbox in current process.
+/// Features are set up inside namespaces if sandbox creates them, so that they are
+/// private to current prog, otherwise they are set up before privilege is dropped.
+/// Loop devices are always created before, device nodes can not be created inside user
+/// namespace and /dev is shared anyway.
+pub fn prepare(features: &[Feature], sandbox: Sandbox) {
+    if sandbox == Sandbox::Namespace {
+        let (devs, private): (Vec<Feature>, Vec<Feature>) =
+            features.iter().partition(|f| **f == Feature::Loopdev);
+        setup(&devs);
+        sandbox.enter();
+        setup(&private);
+    } else {
+        setup(features);
+        sandbox.enter();
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn setup(features: &[Feature]) {
+    if let Some(f) = features.first() {
+        exits!(
+            exitcode::UNAVAILABLE,
+            "Feature {} is only supported on linux",
+            f
+        )
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn setup(features: &[Feature]) {
+    for f in features {
+        let ret = match f {
+            Feature::Tmpfs => setup_tmpfs(),
+            Feature::Netdev => setup_netdev(),
+            Feature::Binderfs => setup_binderfs(),
+            Feature::Loopdev => setup_loopdev(),
+        };
+        ret.unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to set up {}: {}", f, e))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use nix::mount::{mount, MsFlags};
+    use nix::sched::{unshare, CloneFlags};
+    use nix::sys::stat::{makedev, mknod, Mode, SFlag};
+    use nix::unistd::chdir;
+    use std::fs::create_dir_all;
+    use std::path::Path;
+    use std::process::Command;
+
+    /// Working directory of executed progs, backed by fresh tmpfs.
+    const WORK_DIR: &str = "/tmp/healer-work";
+    const BINDERFS_DIR: &str = "/dev/binderfs";
+    const DUMMY_NETDEVS: [&str; 2] = ["healer_dummy0", "healer_dummy1"];
+    const LOOP_NUM: u64 = 8;
+    const LOOP_MAJOR: u64 = 7;
+
+    pub fn setup_tmpfs() -> Result<(), String> {
+        // private mount namespace, so that tmpfs goes away with current process.
+        unshare(CloneFlags::CLONE_NEWNS).map_err(|e| format!("unshare: {}", e))?;
+        mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+            None::<&str>,
+        )
+        .map_err(|e| format!("remount root private: {}", e))?;
+        create_dir_all(WORK_DIR).map_err(|e| format!("create {}: {}", WORK_DIR, e))?;
+        mount(
+            Some("tmpfs"),
+            WORK_DIR,
+            Some("tmpfs"),
+            MsFlags::empty(),
+            Some("mode=777"),
+        )
+        .map_err(|e| format!("mount tmpfs: {}", e))?;
+        chdir(WORK_DIR).map_err(|e| format!("chdir: {}", e))
+    }
+
+    pub fn setup_netdev() -> Result<(), String> {
+        for dev in DUMMY_NETDEVS.iter() {
+            if Path::new("/sys/class/net").join(dev).exists() {
+                continue;
+            }
+            ip(&["link", "add", dev, "type", "dummy"])?;
+            ip(&["link", "set", dev, "up"])?;
+        }
+        Ok(())
+    }
+
+    fn ip(args: &[&str]) -> Result<(), String> {
+        let output = Command::new("ip")
+            .args(args)
+            .output()
+            .map_err(|e| format!("ip {}: {}", args.join(" "), e))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "ip {}: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr)
+            ))
+        }
+    }
+
+    pub fn setup_binderfs() -> Result<(), String> {
+        if Path::new(BINDERFS_DIR).join("binder-control").exists() {
+            return Ok(());
+        }
+        create_dir_all(BINDERFS_DIR).map_err(|e| format!("create {}: {}", BINDERFS_DIR, e))?;
+        mount(
+            Some("binder"),
+            BINDERFS_DIR,
+            Some("binder"),
+            MsFlags::empty(),
+            None::<&str>,
+        )
+        .map_err(|e| format!("mount binderfs: {}", e))
+    }
+
+    pub fn setup_loopdev() -> Result<(), String> {
+        for i in 0..LOOP_NUM {
+            let dev = format!("/dev/loop{}", i);
+            if Path::new(&dev).exists() {
+                continue;
+            }
+            mknod(
+                dev.as_str(),
+                SFlag::S_IFBLK,
+                Mode::from_bits_truncate(0o660),
+                makedev(LOOP_MAJOR, i),
+            )
+            .map_err(|e| format!("mknod {}: {}", dev, e))?;
+        }
+        Ok(())
+    }
+}
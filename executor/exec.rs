@@ -6,30 +6,43 @@ use core::target::Target;
 use nix::fcntl::{fcntl, FcntlArg};
 use nix::poll::{poll, PollFd, PollFlags};
 use nix::sys::signal::{kill, Signal};
-use nix::sys::wait::{wait, waitpid, WaitPidFlag};
-use nix::unistd::{dup2, fork, ForkResult, Pid};
+use nix::sys::wait::{wait, waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{dup2, fork, lseek, ForkResult, Pid, Whence};
 use os_pipe::PipeWriter;
 use rand::random;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
+use std::convert::TryFrom;
 use std::fmt;
-use std::fs::{read_to_string, write};
+use std::fs::{read_to_string, write, File, OpenOptions};
 use std::io::Read;
 use std::mem;
 use std::ops::Index;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
 use std::process::exit;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-pub fn fork_exec(p: Prog, t: &Target, conf: &Config) -> ExecResult {
-    if conf.concurrency || random::<f64>() < 0.0025 {
-        bg_run(&p, t);
+/// Execute `p` in a forked child, returning its result with bugs of kernel noticed meanwhile.
+/// `progress` is told each call finished, as soon as it is traced.
+pub fn fork_exec(
+    p: Prog,
+    t: &Target,
+    conf: &Config,
+    timeouts: &Timeouts,
+    opt: &ExecOpt,
+    progress: &mut dyn FnMut(&CallInfo),
+) -> (ExecResult, Vec<Finding>) {
+    let call_num = p.len();
+    if opt.threaded || random::<f64>() < 0.0025 {
+        bg_run(&p, t, conf);
     }
     // transfer usefull data
     let (mut rp, mut wp) = os_pipe::pipe()
         .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to create date pipe : {}", e));
+    #[cfg(target_os = "linux")]
     fcntl(wp.as_raw_fd(), FcntlArg::F_SETPIPE_SZ(1024 * 1024)).unwrap_or_else(|e| {
         exits!(
             exitcode::OSERR,
@@ -45,11 +58,22 @@ pub fn fork_exec(p: Prog, t: &Target, conf: &Config) -> ExecResult {
     // sync data transfer
     #[cfg(feature = "kcov")]
     let (notifer, waiter) = crate::utils::event();
+    // only kernel messages printed during this execution are of interest
+    let mut kmsg = Kmsg::open();
+    // prog child reports resources left behind by prog, while it still holds them.
+    let (leak_rp, leak_wp) = if conf.leak_check {
+        let (rp, wp) = os_pipe::pipe()
+            .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to create leak pipe : {}", e));
+        (Some(rp), Some(wp))
+    } else {
+        (None, None)
+    };
 
     match fork() {
         Ok(ForkResult::Child) => {
             drop(rp);
             drop(err_rp);
+            drop(leak_rp);
             #[cfg(feature = "kcov")]
             drop(notifer);
 
@@ -71,31 +95,119 @@ pub fn fork_exec(p: Prog, t: &Target, conf: &Config) -> ExecResult {
             });
             drop(err_wp);
             #[cfg(feature = "kcov")]
-            sync_exec(&p, t, &mut wp, waiter, conf);
+            sync_exec(&p, t, &mut wp, waiter, conf, opt, leak_wp);
             #[cfg(not(feature = "kcov"))]
-            sync_exec(&p, t);
+            sync_exec(&p, t, conf, leak_wp);
             // subprocess exits here
             exit(exitcode::OK)
         }
         Ok(ForkResult::Parent { child }) => {
             drop(wp);
             drop(err_wp);
+            drop(leak_wp);
             #[cfg(feature = "kcov")]
             drop(waiter);
 
             #[cfg(feature = "kcov")]
-            let ret = watch(child, &mut rp, &mut err_rp, notifer, conf);
+            let ret = watch(child, &mut rp, &mut err_rp, notifer, timeouts, progress);
 
             #[cfg(not(feature = "kcov"))]
-            let ret = watch(child, &mut err_rp);
+            let ret = watch(child, &mut err_rp, timeouts);
+
+            let mut findings = Vec::new();
+            #[cfg(feature = "kcov")]
+            {
+                if opt.memleak_check && matches!(ret, ExecResult::Ok(_)) {
+                    if let Some(leak) = check_leak() {
+                        findings.push(Finding {
+                            kind: FindingKind::Memleak,
+                            report: leak,
+                        });
+                    }
+                }
+            }
+            if let Some(complaints) = kmsg.as_mut().and_then(Kmsg::complaints) {
+                findings.push(Finding {
+                    kind: FindingKind::Kmsg,
+                    report: complaints,
+                });
+            }
+            if let Some(leaks) = leak_rp.and_then(read_leaks) {
+                findings.push(Finding {
+                    kind: FindingKind::Leak,
+                    report: leaks,
+                });
+            }
+            let ret = ret.fill_skipped(call_num);
+            if opt.cmps {
+                (ret.into_cmps(), findings)
+            } else {
+                (ret, findings)
+            }
+        }
+        Err(e) => exits!(exitcode::OSERR, "Fail to fork: {}", e),
+    }
+}
+
+/// Message of fork server child to process holding connection.
+#[derive(Serialize, Deserialize)]
+enum ChildMsg {
+    /// Call finished, as `progress` of `fork_exec` is told
+    Call(CallInfo),
+    /// Result of whole prog
+    Done(ExecResult, Vec<Finding>),
+}
+
+/// Fork-server style execution: run `fork_exec` in a fresh child and read back its result,
+/// so that state of executor is isolated between progs and failure of the child does not
+/// kill the process holding connection. `progress` is told each call finished by child.
+pub fn server_exec(
+    p: Prog,
+    t: &Target,
+    conf: &Config,
+    timeouts: &Timeouts,
+    opt: &ExecOpt,
+    progress: &mut dyn FnMut(&CallInfo),
+) -> (ExecResult, Vec<Finding>) {
+    let (mut rp, mut wp) = os_pipe::pipe()
+        .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to create result pipe : {}", e));
 
-            ret
+    match fork() {
+        Ok(ForkResult::Child) => {
+            drop(rp);
+            let mut send = |msg: &ChildMsg| {
+                crate::transfer::send(msg, &mut wp)
+                    .unwrap_or_else(|e| exits!(exitcode::IOERR, "Fail to send result: {}", e))
+            };
+            let (result, findings) = fork_exec(p, t, conf, timeouts, opt, &mut |info| {
+                send(&ChildMsg::Call(info.clone()))
+            });
+            send(&ChildMsg::Done(result, findings));
+            exit(exitcode::OK)
+        }
+        Ok(ForkResult::Parent { child }) => {
+            drop(wp);
+            let result = loop {
+                match crate::transfer::recv::<ChildMsg, _>(&mut rp) {
+                    Ok(ChildMsg::Call(info)) => progress(&info),
+                    Ok(ChildMsg::Done(result, findings)) => break Ok((result, findings)),
+                    Err(e) => break Err(e),
+                }
+            };
+            let status = waitpid(child, None);
+            result.unwrap_or_else(|e| {
+                let reason = Reason(format!(
+                    "Executor child exited abnormally ({:?}): {}",
+                    status, e
+                ));
+                (ExecResult::Failed(reason), Vec::new())
+            })
         }
         Err(e) => exits!(exitcode::OSERR, "Fail to fork: {}", e),
     }
 }
 
-fn bg_run(p: &Prog, t: &Target) {
+fn bg_run(p: &Prog, t: &Target, conf: &Config) {
     match fork() {
         Ok(ForkResult::Child) => match fork() {
             Ok(ForkResult::Child) => {
@@ -108,7 +220,7 @@ fn bg_run(p: &Prog, t: &Target) {
                         Ok(ForkResult::Parent { child }) => {
                             childs.insert(child);
                         }
-                        Ok(ForkResult::Child) => bg_fork_run(p, t),
+                        Ok(ForkResult::Child) => bg_fork_run(p, t, conf),
                         Err(_) => break,
                     }
                 }
@@ -131,7 +243,7 @@ fn bg_run(p: &Prog, t: &Target) {
                 }
 
                 for pid in childs.iter() {
-                    kill_and_wait(*pid)
+                    kill_and_wait(*pid);
                 }
                 exit(0);
             }
@@ -144,7 +256,7 @@ fn bg_run(p: &Prog, t: &Target) {
     }
 }
 
-fn bg_fork_run(p: &Prog, t: &Target) {
+fn bg_fork_run(p: &Prog, t: &Target, conf: &Config) {
     #[cfg(feature = "jit")]
     use jit::bg_exec;
     #[cfg(feature = "syscall")]
@@ -173,6 +285,7 @@ fn bg_fork_run(p: &Prog, t: &Target) {
             exit(0)
         }
         Ok(ForkResult::Child) => {
+            crate::env::prepare(&conf.features, conf.sandbox);
             for _ in 0..4 {
                 bg_exec(p, t);
             }
@@ -183,13 +296,17 @@ fn bg_fork_run(p: &Prog, t: &Target) {
 }
 
 #[cfg(not(feature = "kcov"))]
-fn watch<T: Read + AsRawFd>(child: Pid, err: &mut T) -> ExecResult {
+fn watch<T: Read + AsRawFd>(child: Pid, err: &mut T, timeouts: &Timeouts) -> ExecResult {
     let mut fds = vec![PollFd::new(err.as_raw_fd(), PollFlags::POLLIN)];
 
-    match poll(&mut fds, 5_000) {
+    match poll(&mut fds, timeouts.prog as i32) {
         Ok(0) => {
             kill_and_wait(child);
-            ExecResult::Failed(Reason(String::from("Time out")))
+            // calls are not traced without kcov, so which one hung is unknown.
+            ExecResult::Hanged {
+                call: None,
+                info: Vec::new(),
+            }
         }
         Ok(_) => {
             assert!(fds[0].revents().is_some() && !fds[0].revents().unwrap().is_empty());
@@ -212,60 +329,54 @@ fn watch<T: Read + AsRawFd>(
     data: &mut T,
     err: &mut T,
     notifer: crate::utils::Notifier,
-    conf: &Config,
+    timeouts: &Timeouts,
+    progress: &mut dyn FnMut(&CallInfo),
 ) -> ExecResult {
     let mut fds = vec![
         PollFd::new(data.as_raw_fd(), PollFlags::POLLIN),
         PollFd::new(err.as_raw_fd(), PollFlags::POLLIN),
     ];
-    let mut covs = Vec::new();
-    let wait_timeout = if conf.memleak_check { 3000 } else { 1000 };
-    let mut wait_time = Duration::from_secs(0);
+    let mut info = Vec::new();
+    let prog_timeout = Duration::from_millis(timeouts.prog);
+    let start = Instant::now();
 
     loop {
-        match poll(&mut fds, wait_timeout) {
+        if start.elapsed() > prog_timeout {
+            kill_and_wait(child);
+            info.shrink_to_fit();
+            return ExecResult::Hanged {
+                call: Some(info.len()),
+                info,
+            };
+        }
+
+        match poll(&mut fds, timeouts.call as i32) {
             Ok(0) => {
-                // timeout
+                // current call did not finish in time
                 kill_and_wait(child);
-                return if covs.is_empty() {
-                    ExecResult::Failed(Reason(String::from("Time out")))
-                } else {
-                    covs.shrink_to_fit();
-                    ExecResult::Ok(covs)
+                info.shrink_to_fit();
+                return ExecResult::Hanged {
+                    call: Some(info.len()),
+                    info,
                 };
             }
             Ok(_) => {
-                wait_time += Duration::from_millis(wait_timeout as u64);
-
-                if let Some(revents) = fds[1].revents() {
-                    if !revents.is_empty() {
-                        kill_and_wait(child);
-
-                        let mut err_msg = Vec::new();
-                        err.read_to_end(&mut err_msg).unwrap();
-                        return if covs.is_empty() {
-                            ExecResult::Failed(Reason(String::from_utf8(err_msg).unwrap()))
-                        } else {
-                            covs.shrink_to_fit();
-                            if conf.memleak_check {
-                                if let Some(leak) = check_leak(child.to_string()) {
-                                    return ExecResult::Failed(Reason(format!(
-                                        "CRASH-MEMLEAK:\n{}",
-                                        leak
-                                    )));
-                                }
-                            }
-                            ExecResult::Ok(covs)
-                        };
-                    }
-                }
-
-                // Data pipe is ok
+                // Data pipe is ok, drain it before checking err pipe so that
+                // info of finished calls is not lost if the child exited.
                 if let Some(revents) = fds[0].revents() {
                     if revents.contains(PollFlags::POLLIN) {
                         let len = data.read_u32::<NativeEndian>().unwrap_or_else(|e| {
                             exits!(exitcode::OSERR, "Fail to read length of covs: {}", e)
                         });
+                        let ret = data.read_i64::<NativeEndian>().unwrap_or_else(|e| {
+                            exits!(exitcode::OSERR, "Fail to read return value: {}", e)
+                        });
+                        let errno = data.read_i32::<NativeEndian>().unwrap_or_else(|e| {
+                            exits!(exitcode::OSERR, "Fail to read errno: {}", e)
+                        });
+                        let elapsed = data.read_u64::<NativeEndian>().unwrap_or_else(|e| {
+                            exits!(exitcode::OSERR, "Fail to read elapsed time: {}", e)
+                        });
                         let len = len as usize * mem::size_of::<usize>();
                         let mut buf = bytes::BytesMut::with_capacity(len);
                         unsafe {
@@ -278,17 +389,185 @@ fn watch<T: Read + AsRawFd>(
 
                         let mut new_cov = Vec::from(buf.as_ref().as_slice_of::<usize>().unwrap());
                         new_cov.shrink_to_fit();
-                        covs.push(new_cov);
+                        info.push(CallInfo {
+                            covs: new_cov,
+                            ret,
+                            errno,
+                            elapsed,
+                            ..Default::default()
+                        });
+                        progress(info.last().unwrap());
+                        continue;
+                    }
+                }
+
+                if let Some(revents) = fds[1].revents() {
+                    if !revents.is_empty() {
+                        let status = kill_and_wait(child);
+
+                        // Executor itself was killed by signal during current call.
+                        if let Some(WaitStatus::Signaled(_, sig, _)) = status {
+                            if sig != Signal::SIGKILL {
+                                info.push(CallInfo {
+                                    signal: Some(sig as i32),
+                                    ..Default::default()
+                                });
+                                info.shrink_to_fit();
+                                return ExecResult::Ok(info);
+                            }
+                        }
+
+                        let mut err_msg = Vec::new();
+                        err.read_to_end(&mut err_msg).unwrap();
+                        return if info.is_empty() {
+                            ExecResult::Failed(Reason(String::from_utf8(err_msg).unwrap()))
+                        } else {
+                            info.shrink_to_fit();
+                            ExecResult::Ok(info)
+                        };
                     }
                 }
             }
-            Err(_) => {
-                wait_time += Duration::from_millis(wait_timeout as u64);
-                if wait_time > Duration::from_secs(10) {
-                    return ExecResult::Failed(Reason("Time out".to_string()));
+            // interrupted, check prog timeout and poll again
+            Err(_) => continue,
+        }
+    }
+}
+
+const KMSG: &str = "/dev/kmsg";
+/// Kernel complaints that do not necessarily crash the kernel.
+const KMSG_COMPLAINTS: [&str; 5] = [
+    "WARNING:",
+    "BUG:",
+    "INFO: task",
+    "UBSAN:",
+    "general protection fault",
+];
+
+/// Reader of kernel log, starting from the position where it was opened.
+struct Kmsg {
+    f: File,
+}
+
+impl Kmsg {
+    /// None if kernel log is not available, e.g. kmsg is not readable in guest.
+    fn open() -> Option<Self> {
+        let f = OpenOptions::new()
+            .read(true)
+            .custom_flags(nix::libc::O_NONBLOCK)
+            .open(KMSG)
+            .ok()?;
+        lseek(f.as_raw_fd(), 0, Whence::SeekEnd).ok()?;
+        Some(Self { f })
+    }
+
+    /// New complaint lines since last reading.
+    fn complaints(&mut self) -> Option<String> {
+        let mut complaints = String::new();
+        // each read returns one record, fails with EAGAIN once all records are consumed.
+        let mut buf = vec![0; 8192];
+        loop {
+            match self.f.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let record = String::from_utf8_lossy(&buf[..n]);
+                    // record: "prio,seq,timestamp,flags;message\n"
+                    let msg = record.splitn(2, ';').nth(1).unwrap_or(&record);
+                    let msg = msg.lines().next().unwrap_or("");
+                    if KMSG_COMPLAINTS.iter().any(|c| msg.contains(c)) {
+                        complaints.push_str(msg);
+                        complaints.push('\n');
+                    }
                 }
+                // EPIPE: records were overwritten before read, keep reading from next one.
+                Err(ref e) if e.raw_os_error() == Some(nix::libc::EPIPE) => continue,
+                Err(_) => break,
             }
         }
+        if complaints.is_empty() {
+            None
+        } else {
+            Some(complaints)
+        }
+    }
+}
+
+/// Resources held by prog child, those held once prog returns but not before it are leaked.
+struct Resources {
+    /// Open fds, with what each refers to.
+    fds: BTreeSet<String>,
+    mounts: BTreeSet<String>,
+}
+
+impl Resources {
+    /// Resources of calling process, which is prog child.
+    fn snapshot() -> Self {
+        let fds = std::fs::read_dir("/proc/self/fd")
+            .map(|d| {
+                d.filter_map(|e| e.ok())
+                    .map(|e| {
+                        let target = std::fs::read_link(e.path()).unwrap_or_default();
+                        format!(
+                            "{} -> {}",
+                            e.file_name().to_string_lossy(),
+                            target.display()
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mounts = read_to_string("/proc/self/mounts")
+            .map(|s| s.lines().map(String::from).collect())
+            .unwrap_or_default();
+        Self { fds, mounts }
+    }
+
+    /// Describe resources held by `after` but not by `self`.
+    fn leaks(&self, after: &Resources) -> Option<String> {
+        use std::fmt::Write;
+        let mut leaks = String::new();
+        for fd in after.fds.difference(&self.fds) {
+            writeln!(leaks, "fd: {}", fd).unwrap();
+        }
+        for m in after.mounts.difference(&self.mounts) {
+            writeln!(leaks, "mount: {}", m).unwrap();
+        }
+        if leaks.is_empty() {
+            None
+        } else {
+            Some(leaks)
+        }
+    }
+}
+
+/// Run `execute` in prog child, writing resources it left behind to `leaks` if given.
+fn check_resources<R>(leaks: Option<PipeWriter>, execute: impl FnOnce() -> R) -> R {
+    let before = leaks.as_ref().map(|_| Resources::snapshot());
+    let ret = execute();
+    if let (Some(before), Some(mut out)) = (before, leaks) {
+        if let Some(l) = before.leaks(&Resources::snapshot()) {
+            // report is best effort, prog result is still sent.
+            let _ = std::io::Write::write_all(&mut out, l.as_bytes());
+        }
+    }
+    ret
+}
+
+/// Leaks written by prog child, read without blocking in case a process forked by prog
+/// still holds the pipe.
+fn read_leaks(mut leaks: os_pipe::PipeReader) -> Option<String> {
+    fcntl(
+        leaks.as_raw_fd(),
+        FcntlArg::F_SETFL(nix::fcntl::OFlag::O_NONBLOCK),
+    )
+    .ok()?;
+    let mut buf = Vec::new();
+    // bytes read before the pipe would block are kept in buf.
+    let _ = leaks.read_to_end(&mut buf);
+    if buf.is_empty() {
+        None
+    } else {
+        Some(String::from_utf8_lossy(&buf).into_owned())
     }
 }
 
@@ -298,7 +577,7 @@ fn mem_leak_clear() {
     write(MEM_LEAK, "clear").unwrap();
 }
 
-fn check_leak(_: String) -> Option<String> {
+fn check_leak() -> Option<String> {
     use std::fmt::Write;
     let mut executor_leak = String::new();
     write(MEM_LEAK, "scan").unwrap();
@@ -340,16 +619,239 @@ fn parse_leak(leaks_src: &str) -> Vec<&str> {
 }
 
 // Following result is ignored because we know that we are killing correct sub process.
+// Killing an exited child has no effect, so the returned status tells how it actually exited.
 #[allow(unused_must_use)]
-fn kill_and_wait(child: Pid) {
+fn kill_and_wait(child: Pid) -> Option<WaitStatus> {
     kill(child, Some(Signal::SIGKILL));
-    waitpid(child, None);
+    waitpid(child, None).ok()
+}
+
+/// Request sent to executor for each execution.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecReq {
+    /// Id of request, marked in kernel log before its prog is executed.
+    pub id: u64,
+    pub p: Prog,
+    pub timeouts: Timeouts,
+    pub opt: ExecOpt,
+}
+
+/// Options of one execution, so that different executions can use different settings
+/// without restarting executor.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ExecOpt {
+    /// Collect coverage of each call, only return value and errno are reported if not.
+    pub collect_cover: bool,
+    /// Collect comparison operands (KCOV_TRACE_CMP) instead of pcs.
+    pub cmps: bool,
+    /// Also execute prog concurrently in background processes.
+    pub threaded: bool,
+    /// Scan kmemleak after execution.
+    pub memleak_check: bool,
+    /// Inject fault into first allocation of call with this index.
+    pub fault_call: Option<usize>,
+    /// Times of executing prog, execution stops early once it did not succeed.
+    pub repeat: u32,
+}
+
+impl Default for ExecOpt {
+    fn default() -> Self {
+        Self {
+            collect_cover: true,
+            cmps: false,
+            threaded: false,
+            memleak_check: false,
+            fault_call: None,
+            repeat: 1,
+        }
+    }
+}
+
+/// Message sent from fuzzer to executor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// Execute one prog, send back one result.
+    Exec(ExecReq),
+    /// Execute progs in order, send back one result as soon as each prog finished.
+    Batch(Vec<ExecReq>),
+}
+
+/// Message sent from executor to fuzzer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Response {
+    /// Executor is alive and still executing, with progress of current prog since last
+    /// heartbeat, none if calls are not traced.
+    Heartbeat(Option<Progress>),
+    /// Result of one prog, in order of requests, with bugs of kernel noticed while executing it.
+    Result(ExecResult, Vec<Finding>),
+}
+
+/// Calls of prog being executed that finished since last heartbeat.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Progress {
+    /// Prog is executed again from its first call, calls told before are outdated
+    pub restarted: bool,
+    pub calls: Vec<CallInfo>,
+}
+
+/// Bug of kernel noticed by executor around executing a prog, which does not necessarily
+/// make the prog fail.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Finding {
+    pub kind: FindingKind,
+    /// Report of kernel, e.g. leaked objects or complaint lines of kernel log
+    pub report: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FindingKind {
+    /// Memory leak reported by kmemleak
+    Memleak,
+    /// Warnings, bugs and other complaints in kernel log that did not take kernel down
+    Kmsg,
+    /// Fds and mounts left behind by prog, not a bug of kernel by itself
+    Leak,
+}
+
+/// Timeouts of one execution, in milliseconds.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Timeouts {
+    /// Max time for executing whole prog.
+    pub prog: u64,
+    /// Max time for executing a single call.
+    pub call: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            prog: 10_000,
+            call: 1_000,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ExecResult {
-    Ok(Vec<Vec<usize>>),
+    Ok(Vec<CallInfo>),
     Failed(Reason),
+    /// Comparisons traced during each call, only returned if requested.
+    Cmps(Vec<Vec<Comparison>>),
+    /// Call with index `call` did not return within timeout, `info` contains
+    /// execution info of calls that finished before it. `call` is none if which
+    /// call hung is unknown, e.g. calls are not traced.
+    Hanged {
+        call: Option<usize>,
+        info: Vec<CallInfo>,
+    },
+}
+
+impl ExecResult {
+    /// Pad info with skipped calls, so that there is one entry for each call of prog.
+    fn fill_skipped(mut self, call_num: usize) -> Self {
+        match self {
+            ExecResult::Ok(ref mut info) | ExecResult::Hanged { ref mut info, .. } => {
+                while info.len() < call_num {
+                    info.push(CallInfo::skipped());
+                }
+            }
+            ExecResult::Failed(_) | ExecResult::Cmps(_) => (),
+        }
+        self
+    }
+
+    /// Interpret collected words as comparison records.
+    fn into_cmps(self) -> Self {
+        match self {
+            ExecResult::Ok(info) => {
+                ExecResult::Cmps(info.iter().map(|i| Comparison::parse(&i.covs)).collect())
+            }
+            ExecResult::Hanged { call, mut info } => {
+                for i in info.iter_mut() {
+                    i.covs.clear();
+                }
+                ExecResult::Hanged { call, info }
+            }
+            r => r,
+        }
+    }
+}
+
+/// Comparison record traced by KCOV_TRACE_CMP.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Comparison {
+    pub ty: u64,
+    pub arg1: u64,
+    pub arg2: u64,
+    pub pc: u64,
+}
+
+impl Comparison {
+    const KCOV_CMP_CONST: u64 = 1;
+    /// Number of words of each record in kcov buffer.
+    pub const WORDS: usize = 4;
+
+    /// One of operands is compile-time constant.
+    pub fn is_const(&self) -> bool {
+        self.ty & Self::KCOV_CMP_CONST != 0
+    }
+
+    /// Size of operands, in bytes.
+    pub fn size(&self) -> usize {
+        1 << ((self.ty >> 1) & 3)
+    }
+
+    fn parse(words: &[usize]) -> Vec<Self> {
+        words
+            .chunks_exact(Self::WORDS)
+            .map(|r| Self {
+                ty: r[0] as u64,
+                arg1: r[1] as u64,
+                arg2: r[2] as u64,
+                pc: r[3] as u64,
+            })
+            .collect()
+    }
+}
+
+/// Execution info of a single call.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CallInfo {
+    /// Coverage collected during the call.
+    #[serde(with = "crate::transfer::covs")]
+    pub covs: Vec<usize>,
+    /// Return value, 0 if the call has no return value.
+    pub ret: i64,
+    /// Errno after the call returned.
+    pub errno: i32,
+    /// Wall-clock time of the call, in microseconds.
+    pub elapsed: u64,
+    /// Call was not executed at all.
+    pub skipped: bool,
+    /// Signal that killed executor during the call, e.g. SIGSEGV.
+    pub signal: Option<i32>,
+}
+
+impl CallInfo {
+    pub fn skipped() -> Self {
+        Self {
+            skipped: true,
+            ..Default::default()
+        }
+    }
+
+    /// Call was executed but failed, e.g. resource creation failure.
+    pub fn is_failed(&self) -> bool {
+        !self.skipped && self.errno != 0
+    }
+
+    /// Name of signal that killed executor during the call, if any.
+    pub fn fault(&self) -> Option<String> {
+        self.signal.map(|sig| match Signal::try_from(sig) {
+            Ok(s) => format!("{:?}", s),
+            Err(_) => format!("unknown signal {}", sig),
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -374,8 +876,10 @@ pub fn sync_exec(
     out: &mut PipeWriter,
     waiter: crate::utils::Waiter,
     conf: &Config,
+    opt: &ExecOpt,
+    leaks: Option<PipeWriter>,
 ) {
-    if conf.memleak_check {
+    if opt.memleak_check {
         mem_leak_clear();
     }
 
@@ -383,15 +887,15 @@ pub fn sync_exec(
     use jit::exec;
     #[cfg(feature = "syscall")]
     use syscall::exec;
-    exec(p, t, out, waiter);
+    exec(p, t, out, waiter, conf, opt, leaks);
 }
 
 #[cfg(not(feature = "kcov"))]
-fn sync_exec(p: &Prog, t: &Target) {
+fn sync_exec(p: &Prog, t: &Target, conf: &Config, leaks: Option<PipeWriter>) {
     #[cfg(feature = "jit")]
     use jit::exec;
     #[cfg(feature = "syscall")]
     use syscall::exec;
 
-    exec(p, t);
+    exec(p, t, conf, leaks);
 }
@@ -1,4 +1,6 @@
+use crate::exec::ExecOpt;
 use crate::utils::Waiter;
+use crate::Config;
 use core::c;
 use core::c::cths::CTHS;
 use core::c::iter_trans;
@@ -7,7 +9,7 @@ use core::target::Target;
 use os_pipe::PipeWriter;
 use std::ffi::CString;
 use std::fmt::Write;
-use std::fs::{create_dir_all, write};
+use std::fs::{create_dir_all, write, OpenOptions};
 use std::io::ErrorKind;
 use std::os::raw::c_int;
 use std::os::unix::io::*;
@@ -16,10 +18,31 @@ use std::process::exit;
 use tcc::{Context, Guard};
 
 #[cfg(feature = "kcov")]
-pub fn exec(p: &Prog, t: &Target, out: &mut PipeWriter, waiter: Waiter) {
+pub fn exec(
+    p: &Prog,
+    t: &Target,
+    out: &mut PipeWriter,
+    waiter: Waiter,
+    conf: &Config,
+    opt: &ExecOpt,
+    leaks: Option<PipeWriter>,
+) {
     prepare_env();
+    // kcov and harness are opened before entering sandbox, where they may be not accessible.
+    let cover = match conf.harness.as_ref() {
+        Some(harness) => Cover::Harness(crate::harness::load(harness) as usize),
+        None => Cover::Kcov(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(KCOV_PATH)
+                .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to open {}: {}", KCOV_PATH, e))
+                .into_raw_fd(),
+        ),
+    };
+    crate::env::prepare(&conf.features, conf.sandbox);
     let p = {
-        instrument_prog(p, t, out.as_raw_fd(), waiter.as_raw_fd()).unwrap_or_else(|e| {
+        instrument_prog(p, t, out.as_raw_fd(), waiter.as_raw_fd(), cover, opt).unwrap_or_else(|e| {
             eprintln!("{}", e);
             exit(exitcode::SOFTWARE);
         })
@@ -45,7 +68,8 @@ pub fn exec(p: &Prog, t: &Target, out: &mut PipeWriter, waiter: Waiter) {
         std::mem::transmute(symbol)
     };
 
-    let code = execute();
+    // resources set up above are held before prog runs, so they are not taken as leaked.
+    let code = super::check_resources(leaks, execute);
     if code != 0 {
         exits!(
             exitcode::SOFTWARE,
@@ -56,8 +80,9 @@ pub fn exec(p: &Prog, t: &Target, out: &mut PipeWriter, waiter: Waiter) {
 }
 
 #[cfg(not(feature = "kcov"))]
-pub fn exec(p: &Prog, t: &Target) {
+pub fn exec(p: &Prog, t: &Target, conf: &Config, leaks: Option<PipeWriter>) {
     prepare_env();
+    crate::env::prepare(&conf.features, conf.sandbox);
     let p = c::to_prog(p, t);
     let p = CString::new(p.as_bytes()).unwrap();
     let sym = CString::new("main").unwrap();
@@ -78,7 +103,7 @@ pub fn exec(p: &Prog, t: &Target) {
         let symbol = p.get_symbol(&sym).unwrap();
         std::mem::transmute(symbol)
     };
-    execute();
+    super::check_resources(leaks, execute);
 }
 
 pub fn bg_exec(p: &Prog, t: &Target) {
@@ -100,11 +125,22 @@ pub fn bg_exec(p: &Prog, t: &Target) {
     }
 }
 
+/// Where coverage of each call comes from.
+#[derive(Debug, Clone, Copy)]
+pub enum Cover {
+    /// Kernel coverage, fd of opened kcov.
+    Kcov(RawFd),
+    /// Coverage of in-process harness, address of buffer filled by sanitizer callbacks.
+    Harness(usize),
+}
+
 pub fn instrument_prog(
     p: &Prog,
     t: &Target,
     data_fd: RawFd,
     sync_fd: RawFd,
+    cover: Cover,
+    opt: &ExecOpt,
 ) -> Result<String, String> {
     let mut includes = hashset! {
         "stdio.h".to_string(),
@@ -117,46 +153,54 @@ pub fn instrument_prog(
         "sys/mman.h".to_string(),
         "unistd.h".to_string(),
         "fcntl.h".to_string(),
-        "string.h".to_string()
+        "string.h".to_string(),
+        "errno.h".to_string(),
+        "time.h".to_string()
     };
 
     let macros = r#"
+#ifdef __FreeBSD__
+#include <sys/kcov.h>
+#define KCOV_INIT_TRACE  KIOSETBUFSIZE
+#define KCOV_ENABLE      KIOENABLE
+#define KCOV_DISABLE     KIODISABLE
+#define KCOV_TRACE_PC    KCOV_MODE_TRACE_PC
+#define KCOV_TRACE_CMP   KCOV_MODE_TRACE_CMP
+#else
 #define KCOV_INIT_TRACE  _IOR('c', 1, unsigned long)
 #define KCOV_ENABLE      _IO('c', 100)
 #define KCOV_DISABLE     _IO('c', 101)
-#define COVER_SIZE       1024*1024
 #define KCOV_TRACE_PC    0
+#define KCOV_TRACE_CMP   1
+#endif
+#define COVER_SIZE       1024*1024
     "#;
 
     let sync_send = format!(
         r#"
-int sync_send(unsigned long *cover, uint32_t len){{
-    if (len == 0){{
-        return 0;
-    }}
+int sync_send(unsigned long *cover, uint32_t len, long ret, int err, uint64_t elapsed){{
     char *cover_ = (void*)(cover + 1);
     int l2;
     int event_fd = {}, data_fd = {};
-    char l[4];
+    char header[24];
     char event[8];
 
-    memcpy(l, &len, 4);
-    if (write(data_fd, l, 4) == -1){{
+    memcpy(header, &len, 4);
+    memcpy(header + 4, &ret, 8);
+    memcpy(header + 12, &err, 4);
+    memcpy(header + 16, &elapsed, 8);
+    if (write(data_fd, header, 24) == -1){{
         return -1;
     }}
 
     len = len * sizeof(unsigned long);
-    while(1){{
+    while(len != 0){{
         l2 = write(data_fd, cover_, len);
         if(l2 == -1){{
             return -1;
         }}
         len -= l2;
         cover_ += l2;
-
-        if (len == 0){{
-            break;
-        }}
     }}
     if(read(event_fd, event, 8) == -1){{
         return -1;
@@ -166,15 +210,20 @@ int sync_send(unsigned long *cover, uint32_t len){{
         sync_fd, data_fd
     );
 
-    let kcov_open = format!(
-        r#"
+    let vars = r#"
     int fd;
     unsigned long *cover;
     uint32_t len = 0;
-
-    fd = open("/sys/kernel/debug/kcov", O_RDWR);
-    if (fd == -1)
-            return {};
+    long ret = 0;
+    int err = 0;
+    int fail_fd = -1;
+    struct timespec start, end;
+    uint64_t elapsed = 0;
+    "#;
+    let kcov_open = match cover {
+        Cover::Kcov(kcov_fd) => format!(
+            r#"
+    fd = {};
     if (ioctl(fd, KCOV_INIT_TRACE, COVER_SIZE))
             return {};
     cover = (unsigned long*)mmap(NULL, COVER_SIZE * sizeof(unsigned long),
@@ -182,10 +231,17 @@ int sync_send(unsigned long *cover, uint32_t len){{
     if ((void*)cover == MAP_FAILED)
             return {};
     "#,
-        StatusCode::KcovOpenErr as i32,
-        StatusCode::KcovInitErr as i32,
-        StatusCode::MmapErr as i32
-    );
+            kcov_fd,
+            StatusCode::KcovInitErr as i32,
+            StatusCode::MmapErr as i32
+        ),
+        Cover::Harness(addr) => format!(
+            r#"
+    cover = (unsigned long*){:#x}UL;
+    "#,
+            addr
+        ),
+    };
 
     let mut stmts = Vec::new();
     for (i, s) in iter_trans(p, t).enumerate() {
@@ -207,40 +263,95 @@ int sync_send(unsigned long *cover, uint32_t len){{
         includes.extend(header);
 
         let generated_call = s.to_string();
-        let s = format!(
-            r#"
-    if (ioctl(fd, KCOV_ENABLE, KCOV_TRACE_PC))
+        let (fault_on, fault_off) = if opt.fault_call == Some(i) {
+            (FAULT_ON, FAULT_OFF)
+        } else {
+            ("", "")
+        };
+        let ret = s.ret_var().map(|v| format!("(long){}", v));
+        // each cmp record takes 4 words
+        let (mode, words) = if opt.cmps {
+            ("KCOV_TRACE_CMP", "4")
+        } else {
+            ("KCOV_TRACE_PC", "1")
+        };
+        let words = if opt.collect_cover { words } else { "0" };
+        let s = match cover {
+            Cover::Kcov(_) => format!(
+                r#"{}
+    if (ioctl(fd, KCOV_ENABLE, {}))
             return {};
     cover[0] = 0;
+    errno = 0;
+    clock_gettime(CLOCK_MONOTONIC, &start);
     {}
-    len = cover[0];
+    clock_gettime(CLOCK_MONOTONIC, &end);
+    err = errno;
+    ret = {};
+    elapsed = (end.tv_sec - start.tv_sec) * 1000000 + (end.tv_nsec - start.tv_nsec) / 1000;
+    len = cover[0] * {};
     if (ioctl(fd, KCOV_DISABLE, 0))
-            return {};
-    if (sync_send(cover, len) == -1)
+            return {};{}
+    if (sync_send(cover, len, ret, err, elapsed) == -1)
         return {};"#,
-            StatusCode::KcovEnableErr as i32,
-            generated_call,
-            StatusCode::KcovDisableErr as i32,
-            StatusCode::CovSendErr as i32
-        );
+                fault_on,
+                mode,
+                StatusCode::KcovEnableErr as i32,
+                generated_call,
+                ret.as_deref().unwrap_or("0"),
+                words,
+                StatusCode::KcovDisableErr as i32,
+                fault_off,
+                StatusCode::CovSendErr as i32
+            ),
+            // harness only traces pcs, no comparison is reported.
+            Cover::Harness(_) => format!(
+                r#"{}
+    cover[0] = 0;
+    errno = 0;
+    clock_gettime(CLOCK_MONOTONIC, &start);
+    {}
+    clock_gettime(CLOCK_MONOTONIC, &end);
+    err = errno;
+    ret = {};
+    elapsed = (end.tv_sec - start.tv_sec) * 1000000 + (end.tv_nsec - start.tv_nsec) / 1000;
+    len = {};{}
+    if (sync_send(cover, len, ret, err, elapsed) == -1)
+        return {};"#,
+                fault_on,
+                generated_call,
+                ret.as_deref().unwrap_or("0"),
+                if opt.cmps || !opt.collect_cover {
+                    "0"
+                } else {
+                    "cover[0]"
+                },
+                fault_off,
+                StatusCode::CovSendErr as i32
+            ),
+        };
         stmts.push(s);
     }
 
-    let clean = format!(
-        r#"
+    let clean = match cover {
+        Cover::Kcov(_) => format!(
+            r#"
     if (munmap(cover, COVER_SIZE * sizeof(unsigned long)))
             return {};
     if (close(fd))
             return {};
     return {};
     "#,
-        StatusCode::MmapErr as i32,
-        StatusCode::KcovCloseErr as i32,
-        StatusCode::Ok as i32
-    );
+            StatusCode::MmapErr as i32,
+            StatusCode::KcovCloseErr as i32,
+            StatusCode::Ok as i32
+        ),
+        Cover::Harness(_) => format!("    return {};", StatusCode::Ok as i32),
+    };
 
     let execute = {
         let mut buf = String::new();
+        writeln!(buf, "{}", vars).unwrap();
         writeln!(buf, "{}", kcov_open).unwrap();
         for s in stmts {
             writeln!(buf, "{}", s).unwrap();
@@ -261,6 +372,22 @@ int sync_send(unsigned long *cover, uint32_t len){{
     Ok(buf)
 }
 
+/// Make first allocation of the call fail with linux fault injection, nothing happens if
+/// fault injection is not enabled in kernel.
+const FAULT_ON: &str = r#"
+    fail_fd = open("/proc/thread-self/fail-nth", O_RDWR);
+    if (fail_fd != -1 && write(fail_fd, "1", 1) == -1) {
+        close(fail_fd);
+        fail_fd = -1;
+    }"#;
+/// Stop injecting faults once the call returned.
+const FAULT_OFF: &str = r#"
+    if (fail_fd != -1) {
+        write(fail_fd, "0", 1);
+        close(fail_fd);
+        fail_fd = -1;
+    }"#;
+
 #[derive(Debug)]
 enum StatusCode {
     Ok = 0,
@@ -292,6 +419,10 @@ impl From<i32> for StatusCode {
 }
 
 const TCC_INCLUDE: &str = "/usr/local/include/healer/tcc";
+#[cfg(all(feature = "kcov", target_os = "linux"))]
+const KCOV_PATH: &str = "/sys/kernel/debug/kcov";
+#[cfg(all(feature = "kcov", target_os = "freebsd"))]
+const KCOV_PATH: &str = "/dev/kcov";
 
 fn new_tcc<'a, 'b>(g: &'a mut Guard) -> Context<'a, 'b> {
     let mut cc = tcc::Context::new(g).unwrap();
@@ -1,15 +1,25 @@
+use crate::exec::ExecOpt;
 use crate::utils::Waiter;
+use crate::Config;
 use core::prog::Prog;
 use core::target::Target;
 use os_pipe::PipeWriter;
 
 #[cfg(feature = "kcov")]
-pub fn exec(_p: &Prog, _t: &Target, _out: &mut PipeWriter, _waiter: Waiter) {
+pub fn exec(
+    _p: &Prog,
+    _t: &Target,
+    _out: &mut PipeWriter,
+    _waiter: Waiter,
+    _conf: &Config,
+    _opt: &ExecOpt,
+    _leaks: Option<PipeWriter>,
+) {
     todo!()
 }
 
 #[cfg(not(feature = "kcov"))]
-pub fn exec(_p: &Prog, _t: &Target) {}
+pub fn exec(_p: &Prog, _t: &Target, _conf: &Config, _leaks: Option<PipeWriter>) {}
 
 pub fn bg_exec(_p: &Prog, _t: &Target) {
     todo!()
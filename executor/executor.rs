@@ -1,8 +1,9 @@
 use core::target::Target;
-use executor::{exec_loop, Config};
+use executor::{exec_loop, Config, Feature, Sandbox};
 use fots::types::Items;
-use std::fs::{read, write};
+use std::fs::{read, OpenOptions};
 use std::net::TcpStream;
+use std::path::PathBuf;
 use std::process::exit;
 use std::thread::sleep;
 use std::time::Duration;
@@ -12,17 +13,35 @@ use structopt::StructOpt;
 #[structopt(name = "healer-executor")]
 pub struct Settings {
     /// Address of healer-fuzzer
-    #[structopt(short = "a", long)]
-    addr: String,
+    #[structopt(short = "a", long, required_unless = "serial")]
+    addr: Option<String>,
+    /// Serial port to talk with healer-fuzzer through, instead of network
+    #[structopt(long, conflicts_with = "addr")]
+    serial: Option<PathBuf>,
     /// Path of fots file
     #[structopt(short = "t", long)]
     target: String,
 
-    #[structopt(short = "c", long)]
-    concurrency: bool,
+    /// Execute each prog in a fresh forked child
+    #[structopt(short = "f", long = "fork-server")]
+    fork_server: bool,
 
-    #[structopt(short = "m", long = "memleak-check")]
-    memleak_check: bool,
+    /// Sandbox of executed progs: none, setuid or namespace
+    #[structopt(short = "s", long, default_value = "none")]
+    sandbox: Sandbox,
+
+    /// Environment features set up before executing each prog: tmpfs, netdev, binderfs, loopdev
+    #[structopt(short = "e", long, use_delimiter = true)]
+    features: Vec<Feature>,
+
+    /// Report fds and mounts left behind by each prog
+    #[structopt(short = "l", long = "leak-check")]
+    leak_check: bool,
+
+    /// Shared library built with -fsanitize-coverage=trace-pc-guard, whose functions
+    /// are called by progs in-process
+    #[structopt(long)]
+    harness: Option<PathBuf>,
 }
 
 fn main() {
@@ -38,13 +57,34 @@ fn main() {
     });
     let target = Target::from(items);
 
-    if settings.memleak_check {
-        write("/sys/kernel/debug/kmemleak", "clear").unwrap();
+    let conf = Config {
+        fork_server: settings.fork_server,
+        sandbox: settings.sandbox,
+        features: settings.features,
+        leak_check: settings.leak_check,
+        harness: settings.harness,
+    };
+
+    if let Some(serial) = settings.serial.as_ref() {
+        let conn = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(serial)
+            .unwrap_or_else(|e| {
+                eprintln!("Fail to open serial port {}:{}", serial.display(), e);
+                exit(exitcode::NOHOST);
+            });
+        exec_loop(target, conn, conf)
+    } else {
+        let conn = connect(settings.addr.as_ref().unwrap());
+        exec_loop(target, conn, conf)
     }
+}
 
+fn connect(addr: &str) -> TcpStream {
     let mut retry = 1;
-    let conn = loop {
-        match TcpStream::connect(&settings.addr) {
+    loop {
+        match TcpStream::connect(addr) {
             Ok(c) => break c,
             Err(e) => {
                 if retry == 5 {
@@ -56,12 +96,5 @@ fn main() {
                 continue;
             }
         }
-    };
-
-    let conf = Config {
-        memleak_check: settings.memleak_check,
-        concurrency: settings.concurrency,
-    };
-
-    exec_loop(target, conn, conf)
+    }
 }
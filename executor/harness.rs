@@ -0,0 +1,74 @@
+//! In-process harness of user space library. Harness is a shared library built with
+//! `-fsanitize-coverage=trace-pc-guard`, its functions are called by prog directly and
+//! coverage is collected by sanitizer callbacks defined here, in kcov buffer layout:
+//! first word is number of recorded entries, followed by entries.
+
+use nix::libc;
+use std::ffi::{CStr, CString};
+use std::path::Path;
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+/// Number of words of coverage buffer.
+pub const COVER_SIZE: usize = 1024 * 1024;
+
+static COVER: AtomicPtr<usize> = AtomicPtr::new(std::ptr::null_mut());
+/// Next id assigned to guard, 0 means guard is disabled.
+static NEXT_GUARD: AtomicU32 = AtomicU32::new(1);
+
+/// Load harness into current process, so that its symbols are visible to compiled prog.
+/// Return coverage buffer shared with sanitizer callbacks.
+pub fn load<P: AsRef<Path>>(path: P) -> *mut usize {
+    let cover = cover();
+    let path = path.as_ref();
+    let c_path = CString::new(path.to_str().unwrap()).unwrap();
+    let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_GLOBAL) };
+    if handle.is_null() {
+        let err = unsafe { CStr::from_ptr(libc::dlerror()) };
+        exits!(
+            exitcode::SOFTWARE,
+            "Fail to load harness {}: {}",
+            path.display(),
+            err.to_string_lossy()
+        )
+    }
+    cover
+}
+
+fn cover() -> *mut usize {
+    let mut cover = COVER.load(Ordering::SeqCst);
+    if cover.is_null() {
+        let buf = vec![0usize; COVER_SIZE].into_boxed_slice();
+        cover = Box::leak(buf).as_mut_ptr();
+        COVER.store(cover, Ordering::SeqCst);
+    }
+    cover
+}
+
+/// # Safety
+/// Called by sanitizer instrumented code with guards of a loaded module.
+#[no_mangle]
+pub unsafe extern "C" fn __sanitizer_cov_trace_pc_guard_init(start: *mut u32, stop: *mut u32) {
+    if start == stop || *start != 0 {
+        return;
+    }
+    let mut guard = start;
+    while guard < stop {
+        *guard = NEXT_GUARD.fetch_add(1, Ordering::SeqCst);
+        guard = guard.add(1);
+    }
+}
+
+/// # Safety
+/// Called by sanitizer instrumented code on each edge.
+#[no_mangle]
+pub unsafe extern "C" fn __sanitizer_cov_trace_pc_guard(guard: *mut u32) {
+    let cover = COVER.load(Ordering::Relaxed);
+    if cover.is_null() || *guard == 0 {
+        return;
+    }
+    let n = *cover;
+    if n + 1 < COVER_SIZE {
+        *cover.add(n + 1) = *guard as usize;
+        *cover = n + 1;
+    }
+}
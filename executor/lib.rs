@@ -6,32 +6,185 @@ extern crate nix;
 extern crate maplit;
 
 use core::target::Target;
-use std::io::{Read, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 #[macro_use]
 #[allow(dead_code)]
 mod utils;
 pub mod cover;
+pub mod env;
 #[allow(unused_imports, unused_mut, dead_code)]
 pub mod exec;
+pub mod harness;
+pub mod sandbox;
 pub mod transfer;
 
-pub use exec::{ExecResult, Reason};
+pub use env::Feature;
+pub use exec::{
+    CallInfo, Comparison, ExecOpt, ExecReq, ExecResult, Finding, FindingKind, Progress, Reason,
+    Request, Response, Timeouts,
+};
+pub use sandbox::Sandbox;
 
 pub struct Config {
-    pub memleak_check: bool,
-    pub concurrency: bool,
+    /// Execute each prog in a fresh child of the process holding connection.
+    pub fork_server: bool,
+    /// Sandbox that progs are executed in.
+    pub sandbox: Sandbox,
+    /// Environment features set up before executing each prog.
+    pub features: Vec<Feature>,
+    /// Report fds and mounts left behind by each prog.
+    pub leak_check: bool,
+    /// Shared library whose functions are called by progs in-process, instead of syscalls.
+    pub harness: Option<PathBuf>,
 }
 
-/// Read prog from conn, translate by target, run the translated test program.
-pub fn exec_loop<T: Read + Write>(t: Target, mut conn: T, conf: Config) {
+/// Interval of heartbeats sent while executing progs.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Written to kernel log with id of request before executing its prog, so that fuzzer
+/// can tell which of progs in flight a crash report in console follows.
+pub const PROG_MARK: &str = "healer: executing prog ";
+
+/// Connection to fuzzer, which can be split into reading and writing halves.
+pub trait Conn: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+}
+
+impl Conn for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+}
+
+/// Serial port, e.g. virtio serial port.
+impl Conn for File {
+    fn try_clone(&self) -> io::Result<Self> {
+        File::try_clone(self)
+    }
+}
+
+/// Read exec request from conn, translate prog by target, run the translated test program.
+pub fn exec_loop<T: Conn>(t: Target, mut conn: T, conf: Config) {
+    let agreed = handshake(&mut conn);
+    let out = conn
+        .try_clone()
+        .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to clone connection:{}", e));
+    let out = Arc::new(Mutex::new(out));
+    let busy = Arc::new(AtomicBool::new(false));
+    let progress = Arc::new(Mutex::new(Progress::default()));
+    heartbeat(out.clone(), busy.clone(), progress.clone(), agreed);
+
     loop {
-        let p = transfer::recv_prog(&mut conn)
+        let req: Request = transfer::recv(&mut conn)
             .unwrap_or_else(|e| exits!(exitcode::SOFTWARE, "Fail to recv:{}", e));
 
-        let result = exec::fork_exec(p, &t, &conf);
+        busy.store(true, Ordering::SeqCst);
+        match req {
+            Request::Exec(req) => exec_one(req, &t, &out, &progress, &conf, &agreed),
+            Request::Batch(reqs) => {
+                for req in reqs {
+                    exec_one(req, &t, &out, &progress, &conf, &agreed)
+                }
+            }
+        }
+        busy.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Tell fuzzer that executor is still alive while progs are being executed, so that
+/// a prog that never returns is not confused with a crashed guest. Calls finished since
+/// last heartbeat go with it, so that fuzzer still has them if prog never returns.
+fn heartbeat<T: Conn>(
+    out: Arc<Mutex<T>>,
+    busy: Arc<AtomicBool>,
+    progress: Arc<Mutex<Progress>>,
+    agreed: transfer::Handshake,
+) {
+    thread::spawn(move || loop {
+        thread::sleep(HEARTBEAT_INTERVAL);
+        if busy.load(Ordering::SeqCst) {
+            let mut out = out.lock().unwrap();
+            let progress = if cfg!(feature = "kcov") {
+                Some(mem::take(&mut *progress.lock().unwrap()))
+            } else {
+                None
+            };
+            if let Err(e) = transfer::send_as(&Response::Heartbeat(progress), &mut *out, &agreed) {
+                exits!(exitcode::SOFTWARE, "Fail to send heartbeat:{}", e)
+            }
+        }
+    });
+}
+
+/// Tell fuzzer what we support, then follow what fuzzer decides for rest of connection.
+fn handshake<T: Read + Write>(conn: &mut T) -> transfer::Handshake {
+    let ours = transfer::Handshake {
+        compress: true,
+        format: transfer::Format::Bincode,
+    };
+    transfer::send(&ours, conn)
+        .unwrap_or_else(|e| exits!(exitcode::SOFTWARE, "Fail to send handshake:{}", e));
+    let agreed: transfer::Handshake = transfer::recv(conn)
+        .unwrap_or_else(|e| exits!(exitcode::SOFTWARE, "Fail to recv handshake:{}", e));
+    agreed
+}
 
-        transfer::send(&result, &mut conn)
-            .unwrap_or_else(|e| exits!(exitcode::SOFTWARE, "Fail to Send {:?}:{}", result, e));
+/// Mark start of prog in kernel log, failing to do so only loses attribution of crashes.
+fn mark(id: u64) {
+    if let Ok(mut kmsg) = OpenOptions::new().write(true).open("/dev/kmsg") {
+        // one write is one record of kernel log.
+        let _ = kmsg.write_all(format!("{}{}\n", PROG_MARK, id).as_bytes());
     }
 }
+
+fn exec_one<T: Write>(
+    req: ExecReq,
+    t: &Target,
+    out: &Mutex<T>,
+    progress: &Mutex<Progress>,
+    conf: &Config,
+    agreed: &transfer::Handshake,
+) {
+    mark(req.id);
+    let mut result = ExecResult::Ok(Vec::new());
+    let mut findings = Vec::new();
+    for i in 0..req.opt.repeat.max(1) {
+        if i != 0 {
+            let mut progress = progress.lock().unwrap();
+            progress.restarted = true;
+            progress.calls.clear();
+        }
+        let mut tell = |info: &CallInfo| {
+            let mut info = info.clone();
+            if req.opt.cmps {
+                info.covs.clear();
+            }
+            progress.lock().unwrap().calls.push(info);
+        };
+        let (r, f) = if conf.fork_server {
+            exec::server_exec(req.p.clone(), t, conf, &req.timeouts, &req.opt, &mut tell)
+        } else {
+            exec::fork_exec(req.p.clone(), t, conf, &req.timeouts, &req.opt, &mut tell)
+        };
+        result = r;
+        findings = f;
+        if !findings.is_empty() || !matches!(result, ExecResult::Ok(_) | ExecResult::Cmps(_)) {
+            break;
+        }
+    }
+
+    let resp = Response::Result(result, findings);
+    let mut out = out.lock().unwrap();
+    *progress.lock().unwrap() = Progress::default();
+    transfer::send_as(&resp, &mut *out, agreed)
+        .unwrap_or_else(|e| exits!(exitcode::SOFTWARE, "Fail to Send {:?}:{}", resp, e));
+}
@@ -0,0 +1,106 @@
+//! Sandbox that progs are executed in.
+
+use nix::unistd::{setgid, setgroups, setuid, Gid, Uid};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Uid and gid of user nobody.
+const NOBODY: u32 = 65534;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sandbox {
+    /// Run progs as root, without any isolation.
+    None,
+    /// Drop privilege to user nobody.
+    Setuid,
+    /// Run progs inside fresh user, mount, net, ipc and uts namespaces.
+    Namespace,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Sandbox::None
+    }
+}
+
+impl FromStr for Sandbox {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Sandbox::None),
+            "setuid" => Ok(Sandbox::Setuid),
+            "namespace" => Ok(Sandbox::Namespace),
+            _ => Err(format!(
+                "unknown sandbox `{}`, expected none, setuid or namespace",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Sandbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Sandbox::None => "none",
+            Sandbox::Setuid => "setuid",
+            Sandbox::Namespace => "namespace",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl Sandbox {
+    /// Enter sandbox in current process. Resources that need privilege, e.g. kcov,
+    /// should be opened before calling this.
+    pub fn enter(self) {
+        let ret = match self {
+            Sandbox::None => Ok(()),
+            Sandbox::Setuid => drop_privilege(),
+            Sandbox::Namespace => isolate(),
+        };
+        ret.unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to enter {} sandbox: {}", self, e))
+    }
+}
+
+fn drop_privilege() -> Result<(), String> {
+    setgroups(&[]).map_err(|e| format!("setgroups: {}", e))?;
+    setgid(Gid::from_raw(NOBODY)).map_err(|e| format!("setgid: {}", e))?;
+    setuid(Uid::from_raw(NOBODY)).map_err(|e| format!("setuid: {}", e))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn isolate() -> Result<(), String> {
+    Err("namespaces are only supported on linux".to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn isolate() -> Result<(), String> {
+    use nix::mount::{mount, MsFlags};
+    use nix::sched::{unshare, CloneFlags};
+    use std::fs::write;
+
+    let flags = CloneFlags::CLONE_NEWUSER
+        | CloneFlags::CLONE_NEWNS
+        | CloneFlags::CLONE_NEWNET
+        | CloneFlags::CLONE_NEWIPC
+        | CloneFlags::CLONE_NEWUTS;
+    unshare(flags).map_err(|e| format!("unshare: {}", e))?;
+
+    // keep being root inside new user namespace
+    write("/proc/self/setgroups", "deny").map_err(|e| format!("setgroups deny: {}", e))?;
+    write("/proc/self/uid_map", "0 0 1").map_err(|e| format!("uid_map: {}", e))?;
+    write("/proc/self/gid_map", "0 0 1").map_err(|e| format!("gid_map: {}", e))?;
+
+    // mounts made by progs should not propagate to guest
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )
+    .map_err(|e| format!("remount root private: {}", e))
+}
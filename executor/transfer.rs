@@ -1,16 +1,117 @@
 //! A implementation of very sample object transfer protocal.
+//!
+//! Every message is framed as `magic | len | crc32 | flags | body`. Receiver scans for the magic
+//! bytes before reading a header, so a torn or corrupted stream is resynchronized at the
+//! next frame instead of being deserialized into bogus values. Header is always four
+//! little-endian u32s, whatever codec the body is encoded with.
+//!
+//! Body is encoded by a `Codec`, bincode by default, or postcard whose wire format is stable
+//! and simple enough to be implemented by executors not written in Rust. Body can also be
+//! compressed with lz4 and coverage inside can be varint-delta encoded. Flags tell how body
+//! was encoded, so receiver never depends on settings of its side. Settings are negotiated
+//! by `Handshake` right after connection established and kept by each side per connection,
+//! so that connections of one process can be encoded differently.
 
-use crate::ExecResult;
-use bytes::BytesMut;
+use crate::Response;
 use core::prog::Prog;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::cell::Cell;
 use std::io;
 use std::io::{Read, Write};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+/// Magic bytes at the beginning of every frame, "HEAL" on the wire as header is little-endian.
+pub const MAGIC: u32 = u32::from_le_bytes(*b"HEAL");
+/// Max length of body of a frame, frames longer than this are treated as corrupted.
+pub const MAX_BODY_LEN: u32 = 64 * 1024 * 1024;
+/// Body of frame is compressed with lz4.
+const FLAG_LZ4: u32 = 1;
+/// Body of frame is encoded with postcard instead of bincode.
+const FLAG_POSTCARD: u32 = 1 << 1;
+
+thread_local! {
+    /// Whether coverage inside body being encoded or decoded is varint-delta encoded, set
+    /// from flags of its frame only while body is encoded or decoded.
+    static DELTA: Cell<bool> = Cell::new(false);
+}
+
+fn with_delta<R>(on: bool, f: impl FnOnce() -> R) -> R {
+    let prev = DELTA.with(|d| d.replace(on));
+    let ret = f();
+    DELTA.with(|d| d.set(prev));
+    ret
+}
+
+/// Exchanged once after connection established, executor sends its capability and
+/// preference first, fuzzer replies with the agreed setting, which both sides then send
+/// frames of the connection with. Default is what is used before agreeing on anything.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default)]
+pub struct Handshake {
+    pub compress: bool,
+    pub format: Format,
+}
+
+/// Wire format of body of frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Fast, but both sides must be built from exactly matching Rust types.
+    Bincode,
+    /// Stable and documented wire format.
+    Postcard,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::Bincode
+    }
+}
+
+/// Serialization of body of frames.
+pub trait Codec {
+    /// Flag set in header of frames encoded by this codec.
+    const FLAG: u32;
+
+    fn encode<T: Serialize>(v: &T) -> Result<Vec<u8>, Error>;
+
+    fn decode<T: DeserializeOwned>(buf: &[u8]) -> Result<T, Error>;
+}
+
+pub struct Bincode;
+
+impl Codec for Bincode {
+    const FLAG: u32 = 0;
+
+    fn encode<T: Serialize>(v: &T) -> Result<Vec<u8>, Error> {
+        bincode::serialize(v).map_err(|e| e.into())
+    }
+
+    fn decode<T: DeserializeOwned>(buf: &[u8]) -> Result<T, Error> {
+        bincode::deserialize(buf).map_err(|e| e.into())
+    }
+}
+
+pub struct Postcard;
+
+impl Codec for Postcard {
+    const FLAG: u32 = FLAG_POSTCARD;
+
+    fn encode<T: Serialize>(v: &T) -> Result<Vec<u8>, Error> {
+        postcard::to_stdvec(v).map_err(|e| e.into())
+    }
+
+    fn decode<T: DeserializeOwned>(buf: &[u8]) -> Result<T, Error> {
+        postcard::from_bytes(buf).map_err(|e| e.into())
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct Header {
+    pub magic: u32,
     pub len: u32,
+    pub checksum: u32,
+    pub flags: u32,
 }
 
 #[derive(Debug, Error)]
@@ -19,36 +120,135 @@ pub enum Error {
     Io(#[from] io::Error),
     #[error("Serialize: {0}")]
     Serialize(#[from] bincode::Error),
+    #[error("Postcard: {0}")]
+    Postcard(#[from] postcard::Error),
+    #[error("Frame too long: {0} bytes")]
+    TooLong(u32),
+    #[error("Checksum mismatch: expected {expected:#x}, got {actual:#x}")]
+    Checksum { expected: u32, actual: u32 },
+    #[error("Decompress: {0}")]
+    Decompress(String),
 }
 
-pub fn recv_prog<S: Read>(src: &mut S) -> Result<Prog, Error> {
-    let header = Header::default();
-    let headler_len = bincode::serialized_size(&header)? as usize;
+impl Header {
+    fn new(body: &[u8], flags: u32) -> Self {
+        Self {
+            magic: MAGIC,
+            len: body.len() as u32,
+            checksum: crc32fast::hash(body),
+            flags,
+        }
+    }
+
+    fn len() -> usize {
+        bincode::serialized_size(&Header::default()).unwrap() as usize
+    }
+
+    fn check(&self, body: &[u8]) -> Result<(), Error> {
+        let actual = crc32fast::hash(body);
+        if actual != self.checksum {
+            Err(Error::Checksum {
+                expected: self.checksum,
+                actual,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn frame<T: Serialize>(v: &T, agreed: &Handshake) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let (mut body, mut flags) = with_delta(agreed.compress, || match agreed.format {
+        Format::Bincode => Bincode::encode(v).map(|b| (b, Bincode::FLAG)),
+        Format::Postcard => Postcard::encode(v).map(|b| (b, Postcard::FLAG)),
+    })?;
+    if agreed.compress {
+        body = lz4_flex::compress_prepend_size(&body);
+        flags |= FLAG_LZ4;
+    }
+    if body.len() > MAX_BODY_LEN as usize {
+        return Err(Error::TooLong(body.len() as u32));
+    }
+    let header = bincode::serialize(&Header::new(&body, flags))?;
+    Ok((header, body))
+}
+
+fn unframe<T: DeserializeOwned>(header: &Header, body: Vec<u8>) -> Result<T, Error> {
+    header.check(&body)?;
+    let body = if header.flags & FLAG_LZ4 != 0 {
+        lz4_flex::decompress_size_prepended(&body)
+            .map_err(|e| Error::Decompress(format!("{:?}", e)))?
+    } else {
+        body
+    };
+    with_delta(header.flags & FLAG_LZ4 != 0, || {
+        if header.flags & FLAG_POSTCARD != 0 {
+            Postcard::decode(&body)
+        } else {
+            Bincode::decode(&body)
+        }
+    })
+}
 
-    let mut header_buf = BytesMut::with_capacity(headler_len);
-    assert!(header_buf.capacity() >= headler_len);
-    unsafe {
-        header_buf.set_len(headler_len);
+/// Parse header from buf, whose first 4 bytes must be magic.
+fn parse_header(buf: &[u8]) -> Result<Header, Error> {
+    let header: Header = bincode::deserialize(buf)?;
+    debug_assert_eq!(header.magic, MAGIC);
+    if header.len > MAX_BODY_LEN {
+        Err(Error::TooLong(header.len))
+    } else {
+        Ok(header)
     }
-    src.read_exact(&mut header_buf)?;
-    let header: Header = bincode::deserialize(&header_buf)?;
+}
+
+fn is_magic(buf: &[u8]) -> bool {
+    buf[..4] == MAGIC.to_le_bytes()
+}
 
-    let body_len = header.len as usize;
-    let mut body_buf = BytesMut::with_capacity(body_len);
-    unsafe {
-        body_buf.set_len(body_len);
+fn sync_magic<S: Read>(src: &mut S, buf: &mut [u8]) -> Result<(), Error> {
+    src.read_exact(&mut buf[..4])?;
+    while !is_magic(buf) {
+        buf.copy_within(1..4, 0);
+        src.read_exact(&mut buf[3..4])?;
     }
+    Ok(())
+}
+
+async fn async_sync_magic<S: AsyncRead + Unpin>(src: &mut S, buf: &mut [u8]) -> Result<(), Error> {
+    src.read_exact(&mut buf[..4]).await?;
+    while !is_magic(buf) {
+        buf.copy_within(1..4, 0);
+        src.read_exact(&mut buf[3..4]).await?;
+    }
+    Ok(())
+}
+
+pub fn recv<T: DeserializeOwned, S: Read>(src: &mut S) -> Result<T, Error> {
+    let mut header_buf = vec![0; Header::len()];
+    sync_magic(src, &mut header_buf)?;
+    src.read_exact(&mut header_buf[4..])?;
+    let header = parse_header(&header_buf)?;
+
+    let mut body_buf = vec![0; header.len as usize];
     src.read_exact(&mut body_buf)?;
+    unframe(&header, body_buf)
+}
 
-    bincode::deserialize(&body_buf).map_err(|e| e.into())
+pub fn recv_prog<S: Read>(src: &mut S) -> Result<Prog, Error> {
+    recv(src)
 }
 
 pub fn send<T: Serialize, S: Write>(v: &T, out: &mut S) -> Result<(), Error> {
-    let len = bincode::serialized_size(v)? as u32;
-    let header = Header { len };
+    send_as(v, out, &Handshake::default())
+}
 
-    let header = bincode::serialize(&header)?;
-    let body = bincode::serialize(v)?;
+/// Send `v` encoded as `agreed` for connection `out`.
+pub fn send_as<T: Serialize, S: Write>(
+    v: &T,
+    out: &mut S,
+    agreed: &Handshake,
+) -> Result<(), Error> {
+    let (header, body) = frame(v, agreed)?;
 
     out.write_all(&header)?;
     out.write_all(&body)?;
@@ -60,33 +260,265 @@ pub async fn async_send<T: Serialize, S: AsyncWrite + Unpin>(
     p: &T,
     out: &mut S,
 ) -> Result<(), Error> {
-    let len = bincode::serialized_size(p)? as u32;
-    let header = Header { len };
-    let header = bincode::serialize(&header)?;
-    let body = bincode::serialize(p)?;
+    async_send_as(p, out, &Handshake::default()).await
+}
+
+/// Send `p` encoded as `agreed` for connection `out`.
+pub async fn async_send_as<T: Serialize, S: AsyncWrite + Unpin>(
+    p: &T,
+    out: &mut S,
+    agreed: &Handshake,
+) -> Result<(), Error> {
+    let (header, body) = frame(p, agreed)?;
 
     out.write_all(&header).await?;
     out.write_all(&body).await?;
     Ok(())
 }
 
-pub async fn async_recv_result<T: AsyncRead + Unpin>(src: &mut T) -> Result<ExecResult, Error> {
-    let header = Header::default();
-    let headler_len = bincode::serialized_size(&header)? as usize;
-    let mut header_buf = BytesMut::with_capacity(headler_len);
-    assert!(header_buf.capacity() >= headler_len);
-    unsafe {
-        header_buf.set_len(headler_len);
+pub async fn async_recv<T: DeserializeOwned, S: AsyncRead + Unpin>(
+    src: &mut S,
+) -> Result<T, Error> {
+    let mut header_buf = vec![0; Header::len()];
+    async_sync_magic(src, &mut header_buf).await?;
+    src.read_exact(&mut header_buf[4..]).await?;
+    let header = parse_header(&header_buf)?;
+
+    let mut body_buf = vec![0; header.len as usize];
+    src.read_exact(&mut body_buf).await?;
+    unframe(&header, body_buf)
+}
+
+pub async fn async_recv_response<T: AsyncRead + Unpin>(src: &mut T) -> Result<Response, Error> {
+    async_recv(src).await
+}
+
+/// Serde helper for coverage, encoded as zigzag varint of deltas between adjacent pcs inside
+/// compressed frames, as plain pcs otherwise, e.g. outside frames.
+pub mod covs {
+    use super::DELTA;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(covs: &[usize], s: S) -> Result<S::Ok, S::Error> {
+        if DELTA.with(|d| d.get()) {
+            encode(covs).serialize(s)
+        } else {
+            covs.serialize(s)
+        }
     }
-    src.read_exact(&mut header_buf).await?;
-    let header: Header = bincode::deserialize(&header_buf)?;
 
-    let body_len = header.len as usize;
-    let mut body_buf = BytesMut::with_capacity(body_len);
-    unsafe {
-        body_buf.set_len(body_len);
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<usize>, D::Error> {
+        if DELTA.with(|d| d.get()) {
+            decode(&Vec::<u8>::deserialize(d)?).ok_or_else(|| {
+                <D::Error as serde::de::Error>::custom("truncated varint-delta coverage")
+            })
+        } else {
+            Vec::deserialize(d)
+        }
     }
-    src.read_exact(&mut body_buf).await?;
 
-    bincode::deserialize(&body_buf).map_err(|e| e.into())
+    pub fn encode(covs: &[usize]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(covs.len() * 2);
+        let mut prev = 0i64;
+        for &pc in covs {
+            let delta = (pc as i64).wrapping_sub(prev);
+            prev = pc as i64;
+            let mut zz = ((delta << 1) ^ (delta >> 63)) as u64;
+            while zz >= 0x80 {
+                buf.push((zz as u8) | 0x80);
+                zz >>= 7;
+            }
+            buf.push(zz as u8);
+        }
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Vec<usize>> {
+        let mut covs = Vec::new();
+        let mut prev = 0i64;
+        let mut zz = 0u64;
+        let mut shift = 0;
+        for &b in buf {
+            if shift >= 64 {
+                return None;
+            }
+            zz |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                let delta = ((zz >> 1) as i64) ^ -((zz & 1) as i64);
+                prev = prev.wrapping_add(delta);
+                covs.push(prev as usize);
+                zz = 0;
+                shift = 0;
+            } else {
+                shift += 7;
+            }
+        }
+        if shift == 0 {
+            Some(covs)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framed<T: Serialize>(v: &T) -> Vec<u8> {
+        let mut buf = Vec::new();
+        send(v, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let buf = framed(&(String::from("healer"), 42u32));
+        assert_eq!(&buf[..4], b"HEAL");
+        let v: (String, u32) = recv(&mut &buf[..]).unwrap();
+        assert_eq!(v, (String::from("healer"), 42));
+    }
+
+    #[test]
+    fn corrupt_body_is_rejected_and_next_frame_read() {
+        let mut buf = framed(&String::from("corrupted"));
+        let last = buf.len() - 1;
+        buf[last] ^= 0xff;
+        buf.extend(framed(&String::from("intact")));
+        let mut src = &buf[..];
+        assert!(matches!(
+            recv::<String, _>(&mut src),
+            Err(Error::Checksum { .. })
+        ));
+        assert_eq!(recv::<String, _>(&mut src).unwrap(), "intact");
+    }
+
+    #[test]
+    fn torn_stream_resyncs_at_next_frame() {
+        // tail of a frame whose header was lost, then noise looking like start of magic.
+        let torn = framed(&String::from("torn frame"));
+        let mut buf = torn[Header::len() + 1..].to_vec();
+        buf.extend(b"HE\0HEA");
+        buf.extend(framed(&String::from("next")));
+        assert_eq!(recv::<String, _>(&mut &buf[..]).unwrap(), "next");
+    }
+
+    #[test]
+    fn overlong_frame_is_rejected() {
+        let header = Header {
+            magic: MAGIC,
+            len: MAX_BODY_LEN + 1,
+            ..Default::default()
+        };
+        let buf = bincode::serialize(&header).unwrap();
+        assert!(matches!(
+            recv::<String, _>(&mut &buf[..]),
+            Err(Error::TooLong(_))
+        ));
+    }
+
+    /// Frame of `v` encoded as `flags` tell, whatever local settings are.
+    fn framed_as<T: Serialize>(v: &T, flags: u32) -> Vec<u8> {
+        let mut body = if flags & FLAG_POSTCARD != 0 {
+            Postcard::encode(v).unwrap()
+        } else {
+            Bincode::encode(v).unwrap()
+        };
+        if flags & FLAG_LZ4 != 0 {
+            body = lz4_flex::compress_prepend_size(&body);
+        }
+        let mut buf = bincode::serialize(&Header::new(&body, flags)).unwrap();
+        buf.extend(body);
+        buf
+    }
+
+    #[test]
+    fn body_is_decoded_as_flags_tell() {
+        let v = (String::from("healer"), vec![1u64, u64::MAX], Some(-3i32));
+        for flags in vec![0, FLAG_LZ4, FLAG_POSTCARD, FLAG_POSTCARD | FLAG_LZ4] {
+            let buf = framed_as(&v, flags);
+            let decoded: (String, Vec<u64>, Option<i32>) = recv(&mut &buf[..]).unwrap();
+            assert_eq!(decoded, v, "flags {:#x}", flags);
+        }
+    }
+
+    #[test]
+    fn body_is_not_decoded_with_wrong_codec() {
+        let v = (String::from("healer"), vec![1u64, u64::MAX]);
+        let postcard = Postcard::encode(&v).unwrap();
+        let mut buf = bincode::serialize(&Header::new(&postcard, 0)).unwrap();
+        buf.extend(postcard);
+        assert!(matches!(
+            recv::<(String, Vec<u64>), _>(&mut &buf[..]),
+            Err(Error::Serialize(_))
+        ));
+    }
+
+    #[test]
+    fn frames_are_encoded_as_agreed_for_their_connection() {
+        let postcard = Handshake {
+            compress: false,
+            format: Format::Postcard,
+        };
+        let v = (String::from("healer"), 42u32);
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        send_as(&v, &mut a, &postcard).unwrap();
+        send(&v, &mut b).unwrap();
+        assert_eq!(parse_header(&a).unwrap().flags, FLAG_POSTCARD);
+        assert_eq!(parse_header(&b).unwrap().flags, 0);
+        assert_eq!(recv::<(String, u32), _>(&mut &a[..]).unwrap(), v);
+        assert_eq!(recv::<(String, u32), _>(&mut &b[..]).unwrap(), v);
+    }
+
+    #[test]
+    fn covs_round_trip() {
+        let cases = vec![
+            vec![],
+            vec![
+                0xffff_ffff_8100_0010,
+                0xffff_ffff_8100_0000,
+                0xffff_ffff_80ff_fff0,
+                5,
+            ],
+            vec![usize::MAX, 0, usize::MAX, 1, usize::MAX],
+        ];
+        for covs in cases {
+            assert_eq!(covs::decode(&covs::encode(&covs)), Some(covs));
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Covered {
+        #[serde(with = "covs")]
+        covs: Vec<usize>,
+    }
+
+    #[test]
+    fn covs_are_encoded_as_their_frame_tells() {
+        let v = Covered {
+            covs: vec![0xffff_ffff_8100_0010, 0xffff_ffff_8100_0000, 5],
+        };
+        for compress in vec![false, true] {
+            for format in vec![Format::Bincode, Format::Postcard] {
+                let agreed = Handshake { compress, format };
+                let mut buf = Vec::new();
+                send_as(&v, &mut buf, &agreed).unwrap();
+                // whatever is being encoded on this thread meanwhile.
+                let decoded = with_delta(!compress, || recv::<Covered, _>(&mut &buf[..]));
+                assert_eq!(decoded.unwrap(), v, "{:?}", agreed);
+            }
+        }
+        // plain pcs outside frames.
+        let plain: Vec<usize> = bincode::deserialize(&bincode::serialize(&v).unwrap()).unwrap();
+        assert_eq!(plain, v.covs);
+    }
+
+    #[test]
+    fn covs_reject_truncated_varint() {
+        let buf = covs::encode(&[0x7fff_ffff_ffff]);
+        assert!(buf.len() > 1);
+        assert_eq!(covs::decode(&buf[..buf.len() - 1]), None);
+        assert_eq!(covs::decode(&[0xff; 11]), None);
+    }
 }
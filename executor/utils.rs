@@ -91,3 +91,57 @@ pub mod evt {
         }
     }
 }
+
+#[cfg(not(target_os = "linux"))]
+pub use pipe_evt::*;
+
+/// Event implemented with pipe, for systems without eventfd.
+#[cfg(not(target_os = "linux"))]
+pub mod pipe_evt {
+    use os_pipe::{pipe, PipeReader, PipeWriter};
+    use std::io::{Read, Write};
+    use std::os::unix::io::{AsRawFd, RawFd};
+
+    pub fn event() -> (Notifier, Waiter) {
+        let (rp, wp) =
+            pipe().unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to create event pipe: {}", e));
+        (Notifier { wp }, Waiter { rp })
+    }
+
+    pub struct Notifier {
+        wp: PipeWriter,
+    }
+
+    impl Notifier {
+        pub fn notify(&self) {
+            (&self.wp)
+                .write_all(&1u64.to_ne_bytes())
+                .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to write event pipe: {}", e));
+        }
+    }
+
+    impl AsRawFd for Notifier {
+        fn as_raw_fd(&self) -> RawFd {
+            self.wp.as_raw_fd()
+        }
+    }
+
+    pub struct Waiter {
+        rp: PipeReader,
+    }
+
+    impl Waiter {
+        pub fn wait(&self) {
+            let mut buf = [0; 8];
+            (&self.rp)
+                .read_exact(&mut buf)
+                .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to read event pipe: {}", e));
+        }
+    }
+
+    impl AsRawFd for Waiter {
+        fn as_raw_fd(&self) -> RawFd {
+            self.rp.as_raw_fd()
+        }
+    }
+}
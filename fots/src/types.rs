@@ -71,6 +71,41 @@ impl Items {
     pub fn load(b: &[u8]) -> bincode::Result<Self> {
         bincode::deserialize(b)
     }
+
+    /// Merge items of another description bundle, shifting its ids past ids of self, so that
+    /// ids of self stay unchanged.
+    pub fn merge(&mut self, mut other: Items) {
+        let tid_base = self.types.iter().map(|t| t.tid + 1).max().unwrap_or(0);
+        let gid_base = self.groups.iter().map(|g| g.id + 1).max().unwrap_or(0);
+        let fid_base = self
+            .groups
+            .iter()
+            .flat_map(|g| g.fns.iter())
+            .map(|f| f.id + 1)
+            .max()
+            .unwrap_or(0);
+
+        for t in other.types.iter_mut() {
+            t.tid += tid_base;
+            t.info.shift_tid(tid_base);
+        }
+        for g in other.groups.iter_mut() {
+            g.id += gid_base;
+            for f in g.fns.iter_mut() {
+                f.id += fid_base;
+                f.gid += gid_base;
+                if let Some(r_tid) = f.r_tid.as_mut() {
+                    *r_tid += tid_base;
+                }
+                for p in f.params.iter_mut().flatten() {
+                    p.tid += tid_base;
+                }
+            }
+        }
+        self.types.append(&mut other.types);
+        self.groups.append(&mut other.groups);
+        self.rules.append(&mut other.rules);
+    }
 }
 
 /// Not sure if rule def is useful for program generation, so it's
@@ -261,6 +296,23 @@ impl TypeInfo {
         ]
     }
 
+    /// Shift ids of types this type refers to.
+    fn shift_tid(&mut self, by: TypeId) {
+        match self {
+            TypeInfo::Ptr { tid, .. }
+            | TypeInfo::Slice { tid, .. }
+            | TypeInfo::Alias { tid, .. }
+            | TypeInfo::Res { tid }
+            | TypeInfo::Len { tid, .. } => *tid += by,
+            TypeInfo::Struct { fields, .. } | TypeInfo::Union { fields, .. } => {
+                for f in fields.iter_mut() {
+                    f.tid += by;
+                }
+            }
+            TypeInfo::Num(_) | TypeInfo::Str { .. } | TypeInfo::Flag { .. } => (),
+        }
+    }
+
     pub fn default_ptr(tid: TypeId) -> TypeInfo {
         TypeInfo::Ptr {
             dir: PtrDir::In,
@@ -0,0 +1,171 @@
+//! Circuit breaker, pausing fuzzing or stopping fuzzer once target crashes too often or
+//! guests keep failing to boot, e.g. kernel build is broken, instead of rebooting forever.
+
+use crate::error::{Error, Result};
+use log::Level;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{delay_until, Duration, Instant};
+
+/// Minutes of pausing once tripped, if not given.
+const DEFAULT_PAUSE: u64 = 10;
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BreakerConf {
+    /// Trip once more crashes than this are found in last hour
+    pub max_crashes_per_hour: Option<usize>,
+    /// Trip once guests fail to boot this many times in a row, vm whose guest fails to boot
+    /// is retried instead of failing fuzzer
+    pub max_boot_failures: Option<usize>,
+    /// Minutes of pausing fuzzing once tripped, 10 by default
+    pub pause: Option<u64>,
+    /// Stop fuzzer once tripped, instead of pausing
+    #[serde(default)]
+    pub exit: bool,
+}
+
+impl BreakerConf {
+    pub fn check(&self) -> Result<()> {
+        if self.max_crashes_per_hour.is_none() && self.max_boot_failures.is_none() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: breaker needs max_crashes_per_hour or max_boot_failures"
+            )
+        }
+        if self.max_crashes_per_hour == Some(0)
+            || self.max_boot_failures == Some(0)
+            || self.pause == Some(0)
+        {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: max_crashes_per_hour, max_boot_failures and pause of breaker should be positive"
+            )
+        }
+        Ok(())
+    }
+
+    fn pause(&self) -> Duration {
+        Duration::from_secs(self.pause.unwrap_or(DEFAULT_PAUSE) * 60)
+    }
+}
+
+pub struct Breaker {
+    conf: BreakerConf,
+    /// Time of crashes found in last hour
+    crashes: Mutex<VecDeque<Instant>>,
+    /// Boot failures in a row, of any vm
+    boot_failures: AtomicUsize,
+    paused_until: Mutex<Option<Instant>>,
+    /// Reason of each trip, so that fuzzer alerts and stops if needed
+    tripped: mpsc::UnboundedSender<String>,
+}
+
+impl Breaker {
+    pub fn new(conf: BreakerConf, tripped: mpsc::UnboundedSender<String>) -> Self {
+        Self {
+            conf,
+            crashes: Mutex::new(VecDeque::new()),
+            boot_failures: AtomicUsize::new(0),
+            paused_until: Mutex::new(None),
+            tripped,
+        }
+    }
+
+    /// Whether fuzzer stops once tripped.
+    pub fn exits(&self) -> bool {
+        self.conf.exit
+    }
+
+    /// Whether vm whose guest fails to boot should be retried.
+    pub fn retries_boot(&self) -> bool {
+        self.conf.max_boot_failures.is_some()
+    }
+
+    /// Count crash found now, tripping if there are too many in last hour.
+    pub async fn crashed(&self) {
+        let max = match self.conf.max_crashes_per_hour {
+            Some(max) => max,
+            None => return,
+        };
+        let now = Instant::now();
+        let tripped = {
+            let mut crashes = self.crashes.lock().await;
+            crashes.push_back(now);
+            while crashes.front().map_or(false, |t| now - *t > HOUR) {
+                crashes.pop_front();
+            }
+            // counting starts over after trip.
+            let tripped = crashes.len() > max;
+            if tripped {
+                crashes.clear();
+            }
+            tripped
+        };
+        if tripped {
+            self.trip(format!("more than {} crashes in last hour", max))
+                .await;
+        }
+    }
+
+    /// Count failure of executor or of booting its guest, tripping if they failed too many
+    /// times in a row.
+    pub async fn boot_failed(&self, e: &Error) {
+        warn!("Executor failed, restarting: {}", e);
+        let max = match self.conf.max_boot_failures {
+            Some(max) => max,
+            None => return,
+        };
+        let failures = self.boot_failures.fetch_add(1, Ordering::SeqCst) + 1;
+        if failures >= max {
+            self.boot_failures.store(0, Ordering::SeqCst);
+            self.trip(format!("guests failed to boot {} times in a row", failures))
+                .await;
+        }
+    }
+
+    pub fn booted(&self) {
+        self.boot_failures.store(0, Ordering::SeqCst);
+    }
+
+    /// Whether fuzzing is paused by tripped breaker now.
+    pub async fn paused(&self) -> bool {
+        self.paused_until
+            .lock()
+            .await
+            .map_or(false, |until| until > Instant::now())
+    }
+
+    /// Wait until pause of tripped breaker is over, returns at once if not tripped.
+    pub async fn wait(&self) {
+        let until = *self.paused_until.lock().await;
+        if let Some(until) = until {
+            delay_until(until).await;
+        }
+    }
+
+    async fn trip(&self, reason: String) {
+        if self.conf.exit {
+            event!(
+                Level::Error,
+                "breaker_tripped",
+                { "reason": reason, "exit": true },
+                "Circuit breaker tripped: {}, stopping",
+                reason
+            );
+        } else {
+            let pause = self.conf.pause();
+            *self.paused_until.lock().await = Some(Instant::now() + pause);
+            event!(
+                Level::Error,
+                "breaker_tripped",
+                { "reason": reason, "exit": false },
+                "Circuit breaker tripped: {}, pausing fuzzing for {} minutes",
+                reason,
+                pause.as_secs() / 60
+            );
+        }
+        self.tripped.send(reason).ok();
+    }
+}
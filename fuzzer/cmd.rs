@@ -0,0 +1,746 @@
+//! Subcommands of fuzzer besides fuzzing, working on crashes and corpus it saved.
+
+use crate::corpus::{Corpus, ExportFormat, Merged, Meta};
+use crate::error::Result;
+use crate::exec::Executor;
+use crate::feedback::Cover;
+use crate::fuzzer::REPRO_REPEAT;
+use crate::guest::Crash;
+use crate::hook::Hooks;
+use crate::report::CrashedCase;
+use crate::syz::{self, Imported};
+use crate::{
+    check_corpus, check_shards, filter_items, load_corpus, load_dump, load_items, load_target,
+    retain_known, seed_rng, Config,
+};
+use chrono::{Local, TimeZone};
+use core::analyze::static_analyze;
+use core::c::{to_prog, to_script};
+use core::gen::gen as gen_prog;
+use core::prog::Prog;
+use core::target::Target;
+use executor::{Comparison, ExecOpt, ExecResult};
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::fs::{read, read_dir, write};
+
+/// Crash reproduced by triage and saved crashes reproducing it.
+pub struct Triaged {
+    pub crash: Crash,
+    pub files: Vec<PathBuf>,
+}
+
+/// Re-execute prog of crash saved at `path` on a fresh guest, returning the crash it
+/// caused, if reproduced.
+pub async fn repro(cfg: &Config, path: &Path) -> Result<Option<Crash>> {
+    let case = load_crash(path).await?;
+    let p = prog_of(&case, path)?;
+    let target = load_target(cfg).await?;
+    let mut executor = boot(cfg).await?;
+    repro_prog(&mut executor, &target, p).await
+}
+
+/// Re-execute every crash saved in `dir`, grouping reproduced ones by their report.
+pub async fn triage(cfg: &Config, dir: &Path) -> Result<Vec<Triaged>> {
+    let paths = crash_files(dir).await?;
+    let target = load_target(cfg).await?;
+    let mut executor = boot(cfg).await?;
+    let mut triaged: Vec<Triaged> = Vec::new();
+    let mut seen: HashMap<md5::Digest, usize> = HashMap::new();
+    for path in paths {
+        let case = match load_crash(&path).await {
+            Ok(case) => case,
+            Err(e) => {
+                warn!("Skip {}", e);
+                continue;
+            }
+        };
+        let p = match prog_of(&case, &path) {
+            Ok(p) => p,
+            Err(e) => {
+                warn!("Skip {}", e);
+                continue;
+            }
+        };
+        match repro_prog(&mut executor, &target, p).await? {
+            Some(crash) => {
+                info!("{}: reproduced", path.display());
+                executor.start().await?;
+                let digest = md5::compute(&crash.inner);
+                match seen.get(&digest) {
+                    Some(&i) => triaged[i].files.push(path),
+                    None => {
+                        seen.insert(digest, triaged.len());
+                        triaged.push(Triaged {
+                            crash,
+                            files: vec![path],
+                        });
+                    }
+                }
+            }
+            None => info!("{}: not reproduced", path.display()),
+        }
+    }
+    Ok(triaged)
+}
+
+/// Outcome of replaying one prog.
+pub struct Replayed {
+    /// Crash file of prog, or its index in corpus dump.
+    pub name: String,
+    /// Prog was saved as crash, so it is expected to crash again.
+    pub saved_crash: bool,
+    /// Crash caused by prog in any of its executions.
+    pub crash: Option<Crash>,
+}
+
+/// Outcome of replaying corpus dump or crashes.
+#[derive(Default)]
+pub struct Replay {
+    pub replayed: Vec<Replayed>,
+    pub exec: usize,
+    pub failed: usize,
+    pub blocks: usize,
+    pub branches: usize,
+}
+
+/// Execute each prog of corpus dump, or of crashes if `path` is a directory of them,
+/// `repeat` times without generating new progs, collecting coverage and crashes.
+pub async fn replay(cfg: &Config, path: &Path, repeat: usize) -> Result<Replay> {
+    if repeat == 0 {
+        bail!(exitcode::USAGE, "Repeat of replay should be positive")
+    }
+    let target = load_target(cfg).await?;
+    let mut progs = Vec::new();
+    let saved_crash = path.is_dir();
+    if saved_crash {
+        for path in crash_files(path).await? {
+            let case = load_crash(&path).await;
+            match case.and_then(|case| prog_of(&case, &path).map(Prog::clone)) {
+                Ok(p) => progs.push((path.display().to_string(), p)),
+                Err(e) => warn!("Skip {}", e),
+            }
+        }
+    } else {
+        let corpus = load_corpus(&Some(path.to_path_buf())).await?;
+        check_corpus(&target, &corpus)?;
+        progs.extend(
+            corpus
+                .into_iter()
+                .enumerate()
+                .map(|(i, p)| (format!("#{}", i), p)),
+        );
+    }
+
+    let mut executor = boot(cfg).await?;
+    let mut cover = Cover::default();
+    let mut replay = Replay::default();
+    for (name, p) in progs {
+        let mut crash = None;
+        for _ in 0..repeat {
+            replay.exec += 1;
+            let info = match executor.exec(&p, &target).await? {
+                Ok(ExecResult::Ok(info)) | Ok(ExecResult::Hanged { info, .. }) => info,
+                Ok(_) => {
+                    replay.failed += 1;
+                    continue;
+                }
+                Err(c) => {
+                    crash = Some(c.unwrap_or_default());
+                    executor.start().await?;
+                    break;
+                }
+            };
+            for call in info.iter() {
+                cover.extend_raw(&call.covs);
+            }
+        }
+        info!(
+            "{}: {}",
+            name,
+            if crash.is_some() { "crashed" } else { "done" }
+        );
+        replay.replayed.push(Replayed {
+            name,
+            saved_crash,
+            crash,
+        });
+    }
+    replay.blocks = cover.blocks.len();
+    replay.branches = cover.branches.len();
+    Ok(replay)
+}
+
+/// Execute each prog of corpus dump at `path` once with comparisons traced, returning
+/// distinct comparisons of each call in order of progs, none for progs that failed or crashed.
+pub async fn cmps(cfg: &Config, path: &Path) -> Result<Vec<Option<Vec<Vec<Comparison>>>>> {
+    let target = load_target(cfg).await?;
+    let progs = load_corpus(&Some(path.to_path_buf())).await?;
+    check_corpus(&target, &progs)?;
+
+    let mut executor = boot(cfg).await?;
+    let mut traced = Vec::with_capacity(progs.len());
+    for p in progs.iter() {
+        match executor.exec_cmps(p, &target).await? {
+            Ok(ExecResult::Cmps(cmps)) => traced.push(Some(
+                cmps.into_iter()
+                    .map(|c| c.into_iter().unique().collect())
+                    .collect(),
+            )),
+            Ok(_) => traced.push(None),
+            Err(_) => {
+                traced.push(None);
+                executor.start().await?;
+            }
+        }
+    }
+    Ok(traced)
+}
+
+/// Outcome of distilling corpus dump.
+#[derive(Default)]
+pub struct Distill {
+    pub progs: usize,
+    pub kept: usize,
+    pub failed: usize,
+    pub crashed: usize,
+    pub blocks: usize,
+    pub branches: usize,
+}
+
+/// Execute each prog of corpus dump at `path` once, and write the minimal subset of them
+/// covering the same blocks and branches to `out`. Progs that fail or crash are dropped.
+pub async fn distill(cfg: &Config, path: &Path, out: &Path) -> Result<Distill> {
+    let target = load_target(cfg).await?;
+    let progs = load_corpus(&Some(path.to_path_buf())).await?;
+    check_corpus(&target, &progs)?;
+
+    let mut executor = boot(cfg).await?;
+    let mut distill = Distill {
+        progs: progs.len(),
+        ..Default::default()
+    };
+    let feedback = exec_cover(
+        &mut executor,
+        &target,
+        progs,
+        &mut distill.failed,
+        &mut distill.crashed,
+    )
+    .await?;
+    let corpus = Corpus::default();
+    for p in feedback.keys() {
+        corpus.insert(p.clone()).await;
+    }
+
+    let kept = corpus.distill(&feedback).await?;
+    let mut cover = Cover::default();
+    for p in kept.iter() {
+        cover.merge(&feedback[p]);
+    }
+    distill.kept = kept.len();
+    distill.blocks = cover.blocks.len();
+    distill.branches = cover.branches.len();
+    dump(&kept, out).await?;
+    Ok(distill)
+}
+
+/// Coverage of each of `progs` executed once, counting progs that fail or crash, which are
+/// left out.
+async fn exec_cover(
+    executor: &mut Executor,
+    target: &Target,
+    progs: Vec<Prog>,
+    failed: &mut usize,
+    crashed: &mut usize,
+) -> Result<HashMap<Prog, Cover>> {
+    let mut feedback = HashMap::new();
+    for p in progs {
+        let info = match executor.exec(&p, target).await? {
+            Ok(ExecResult::Ok(info)) | Ok(ExecResult::Hanged { info, .. }) => info,
+            Ok(_) => {
+                *failed += 1;
+                continue;
+            }
+            Err(_) => {
+                *crashed += 1;
+                executor.start().await?;
+                continue;
+            }
+        };
+        let mut cover = Cover::default();
+        for call in info.iter() {
+            cover.extend_raw(&call.covs);
+        }
+        feedback.insert(p, cover);
+    }
+    Ok(feedback)
+}
+
+/// Outcome of merging each corpus dump.
+pub struct MergedDump {
+    pub path: PathBuf,
+    pub progs: usize,
+    pub merged: Merged,
+}
+
+/// Outcome of merging corpus dumps.
+#[derive(Default)]
+pub struct Merge {
+    pub dumps: Vec<MergedDump>,
+    pub failed: usize,
+    pub crashed: usize,
+    /// Progs and blocks and branches of merged corpus
+    pub progs: usize,
+    pub blocks: usize,
+    pub branches: usize,
+}
+
+/// Execute each distinct prog of corpus `dumps` once, e.g. of runs on different machines,
+/// and merge dumps in order into `out`, keeping only progs that add coverage to progs merged
+/// before them. Progs that fail or crash are dropped.
+pub async fn merge(cfg: &Config, dumps: &[PathBuf], out: &Path) -> Result<Merge> {
+    let target = load_target(cfg).await?;
+    let mut corpora = Vec::new();
+    let mut progs = HashSet::new();
+    for path in dumps {
+        let entries = load_dump(path).await?;
+        let corpus = Corpus::default();
+        for (p, meta) in entries {
+            progs.insert(p.clone());
+            corpus.insert_with(p, meta).await;
+        }
+        check_corpus(&target, &corpus.progs().await?)?;
+        corpora.push(corpus);
+    }
+
+    let mut executor = boot(cfg).await?;
+    let mut merge = Merge::default();
+    let feedback = exec_cover(
+        &mut executor,
+        &target,
+        progs.into_iter().collect(),
+        &mut merge.failed,
+        &mut merge.crashed,
+    )
+    .await?;
+    let merged = Corpus::default();
+    for (path, corpus) in dumps.iter().zip(corpora.iter()) {
+        let m = merged.merge(corpus, &feedback).await?;
+        merge.blocks = m.blocks;
+        merge.branches = m.branches;
+        merge.dumps.push(MergedDump {
+            path: path.clone(),
+            progs: corpus.len().await,
+            merged: m,
+        });
+    }
+    merge.progs = merged.len().await;
+    merged.save(out).await?;
+    Ok(merge)
+}
+
+/// Calls and types exercised by progs of dry run.
+#[derive(Default)]
+pub struct GenStats {
+    pub progs: usize,
+    pub calls: usize,
+    /// Times each call is generated, most frequent first
+    pub call_freq: Vec<(String, usize)>,
+    /// Progs generated of each group, most frequent first
+    pub group_freq: Vec<(String, usize)>,
+    /// Calls of target never generated
+    pub missed_calls: Vec<String>,
+    /// Number of types of params and return values generated, and of all of them
+    pub types: (usize, usize),
+}
+
+/// Generate `n` progs with gen conf of `cfg` without guest, printing their scripts, and
+/// return statistics of calls and types they exercise, for debugging descriptions.
+pub async fn dry_run(cfg: &Config, n: usize) -> Result<GenStats> {
+    let target = load_target(cfg).await?;
+    let rt = static_analyze(&target);
+    let conf = cfg.gen.clone().unwrap_or_default().to_conf();
+    seed_rng(cfg);
+
+    let mut calls = HashMap::new();
+    let mut groups = HashMap::new();
+    let mut types = HashSet::new();
+    let mut stats = GenStats::default();
+    for i in 0..n {
+        let p = gen_prog(&target, &rt, &conf);
+        println!("========== Prog {}:\n{}", i, to_script(&p, &target));
+        *groups.entry(p.gid).or_insert(0) += 1;
+        for c in p.calls.iter() {
+            *calls.entry(c.fid).or_insert(0) += 1;
+            types.extend(c.args.iter().chain(c.ret.iter()).map(|a| a.tid));
+        }
+        stats.calls += p.calls.len();
+    }
+    stats.progs = n;
+
+    let all_types = target
+        .fns
+        .keys()
+        .map(|fid| target.fn_of(*fid))
+        .flat_map(|f| {
+            f.params
+                .iter()
+                .flatten()
+                .map(|p| p.tid)
+                .chain(f.r_tid.iter().copied())
+        })
+        .collect::<HashSet<_>>();
+    stats.types = (types.intersection(&all_types).count(), all_types.len());
+    stats.call_freq = calls
+        .iter()
+        .map(|(fid, n)| (target.fn_of(*fid).dec_name.clone(), *n))
+        .sorted_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+        .collect();
+    stats.group_freq = groups
+        .iter()
+        .map(|(gid, n)| (target.group_name_of(*gid).to_string(), *n))
+        .sorted_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+        .collect();
+    stats.missed_calls = target
+        .fns
+        .keys()
+        .filter(|fid| !calls.contains_key(fid))
+        .map(|fid| target.fn_of(*fid).dec_name.clone())
+        .sorted()
+        .collect();
+    Ok(stats)
+}
+
+/// Target and corpus loaded by config check.
+pub struct ConfigCheck {
+    pub groups: usize,
+    pub calls: usize,
+    pub corpus: usize,
+    /// Progs of corpus dropped since their calls are filtered
+    pub dropped: usize,
+}
+
+/// Run all checks of `cfg` and load its target and corpus as fuzzing does, without booting
+/// any guest or writing to work dir.
+pub async fn check_config(cfg: &Config) -> Result<ConfigCheck> {
+    cfg.check()?;
+    let (mut items, _) = load_items(cfg).await?;
+    let filtered = filter_items(&mut items, cfg)?;
+    let target = Target::from(items);
+    let mut corpus = load_corpus(&cfg.curpus).await?;
+    let dropped = if filtered {
+        retain_known(&target, &mut corpus)
+    } else {
+        0
+    };
+    check_corpus(&target, &corpus)?;
+    check_shards(&target, cfg)?;
+    Ok(ConfigCheck {
+        groups: target.groups.len(),
+        calls: target.fns.len(),
+        corpus: corpus.len(),
+        dropped,
+    })
+}
+
+/// Generate `n` progs without guest, writing them as corpus dump to `out`, or printing
+/// them otherwise.
+pub async fn gen(cfg: &Config, n: usize, out: Option<&Path>) -> Result<()> {
+    let target = load_target(cfg).await?;
+    let rt = static_analyze(&target);
+    seed_rng(cfg);
+    let progs = (0..n)
+        .map(|_| gen_prog(&target, &rt, &Default::default()))
+        .collect::<Vec<_>>();
+    match out {
+        Some(out) => dump(&progs, out).await,
+        None => {
+            for p in progs.iter() {
+                println!("{}", to_prog(p, &target));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Convert programs of syzkaller `corpus.db` at `db` into corpus dump `out`, mapping their
+/// calls onto target, with values generated by gen conf of `cfg`.
+pub async fn import(cfg: &Config, db: &Path, out: &Path) -> Result<Imported> {
+    let target = load_target(cfg).await?;
+    let conf = cfg.gen.clone().unwrap_or_default().to_conf();
+    seed_rng(cfg);
+    let mut imported = syz::import(&target, db, &conf)?;
+    if cfg.dedup.unwrap_or(true) {
+        // values are generated rather than carried over, so near duplicates are common.
+        let mut corpus = Corpus::default();
+        corpus.set_dedup(Arc::new(target)).await?;
+        for p in std::mem::take(&mut imported.progs) {
+            if corpus.insert_with(p.clone(), Meta::default()).await {
+                imported.progs.push(p);
+            }
+        }
+        imported.near_duplicates = corpus.near_duplicates.load(Ordering::SeqCst);
+    }
+    dump(&imported.progs, out).await?;
+    Ok(imported)
+}
+
+/// Export progs of corpus dump at `path` into `dir` as C programs or text, with a manifest.
+pub async fn export(cfg: &Config, path: &Path, dir: &Path, format: ExportFormat) -> Result<usize> {
+    let target = load_target(cfg).await?;
+    let progs = load_corpus(&Some(path.to_path_buf())).await?;
+    check_corpus(&target, &progs)?;
+    let corpus = Corpus::default();
+    for p in progs {
+        corpus.insert(p).await;
+    }
+    corpus.export(&target, dir, format).await
+}
+
+/// Show size of corpus dumps, printing their progs if `verbose`, and merge them into `out`
+/// without duplicated progs.
+pub async fn corpus(
+    cfg: &Config,
+    dumps: &[PathBuf],
+    out: Option<&Path>,
+    verbose: bool,
+) -> Result<()> {
+    let target = load_target(cfg).await?;
+    let merged = Corpus::default();
+    for path in dumps {
+        let entries = load_dump(path).await?;
+        let progs = entries.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>();
+        check_corpus(&target, &progs)?;
+        println!("{}: {} progs", path.display(), entries.len());
+        for (p, meta) in entries {
+            if verbose {
+                println!("{}\n{}", describe(&meta), to_prog(&p, &target));
+            }
+            merged.insert_with(p, meta).await;
+        }
+    }
+    println!("Total: {} unique progs", merged.len().await);
+    match out {
+        Some(out) => merged.save(out).await,
+        None => Ok(()),
+    }
+}
+
+/// Progs selected by names of their calls or group, any prog if none is given.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Declared or called names of calls, prog calling any of them matches
+    pub calls: Vec<String>,
+    /// Identifiers of groups, prog of any of them matches
+    pub groups: Vec<String>,
+}
+
+impl Filter {
+    /// Check that each name of filter is found in `target`.
+    fn check(&self, target: &Target) -> Result<()> {
+        for call in self.calls.iter() {
+            let found = target
+                .iter_group()
+                .flat_map(|g| g.fns.iter())
+                .any(|f| &f.dec_name == call || &f.call_name == call);
+            if !found {
+                bail!(exitcode::USAGE, "Call {} is not found in target", call)
+            }
+        }
+        for group in self.groups.iter() {
+            if target.iter_group().all(|g| &g.ident != group) {
+                bail!(exitcode::USAGE, "Group {} is not found in target", group)
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(&self, p: &Prog, target: &Target) -> bool {
+        let in_groups = self.groups.is_empty()
+            || self
+                .groups
+                .iter()
+                .any(|g| g == &target.groups[&p.gid].ident);
+        let calls = self.calls.is_empty()
+            || p.calls.iter().any(|c| {
+                let f = target.fn_of(c.fid);
+                self.calls
+                    .iter()
+                    .any(|name| name == &f.dec_name || name == &f.call_name)
+            });
+        in_groups && calls
+    }
+}
+
+/// Progs of corpus dump at `path` matching `filter` with their meta, numbered by their order
+/// in dump, which is how `show` picks them.
+async fn select(target: &Target, path: &Path, filter: &Filter) -> Result<Vec<(usize, Prog, Meta)>> {
+    filter.check(target)?;
+    let entries = load_dump(path).await?;
+    let progs = entries.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>();
+    check_corpus(target, &progs)?;
+    Ok(entries
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (p, _))| filter.matches(p, target))
+        .map(|(id, (p, meta))| (id, p, meta))
+        .collect())
+}
+
+/// Print id, group, calls and coverage brought by each prog of corpus dump at `path` that
+/// matches `filter`.
+pub async fn list(cfg: &Config, path: &Path, filter: &Filter) -> Result<()> {
+    let target = load_target(cfg).await?;
+    let selected = select(&target, path, filter).await?;
+    for (id, p, meta) in selected.iter() {
+        let calls = p
+            .calls
+            .iter()
+            .map(|c| target.fn_of(c.fid).dec_name.as_str())
+            .join(", ");
+        println!(
+            "{:>5}  {:<16}  calls: {:<3}  new blocks: {:<5}  new branches: {:<5}  {}",
+            id,
+            target.groups[&p.gid].ident,
+            p.calls.len(),
+            meta.new_blocks,
+            meta.new_branches,
+            calls
+        );
+    }
+    println!("{} progs listed", selected.len());
+    Ok(())
+}
+
+/// Print progs of corpus dump at `path` of `ids`, as listed by `list`, in `format` with their
+/// meta.
+pub async fn show(cfg: &Config, path: &Path, ids: &[usize], format: ExportFormat) -> Result<()> {
+    let target = load_target(cfg).await?;
+    let entries = select(&target, path, &Filter::default()).await?;
+    for id in ids {
+        let (_, p, meta) = entries.get(*id).ok_or_else(|| {
+            err!(
+                exitcode::USAGE,
+                "No prog {} in {}, it has {} progs",
+                id,
+                path.display(),
+                entries.len()
+            )
+        })?;
+        let text = match format {
+            ExportFormat::C => to_prog(p, &target),
+            ExportFormat::Text => to_script(p, &target).to_string(),
+        };
+        println!(
+            "========== {} ({})\n{}\n{}",
+            id,
+            target.groups[&p.gid].ident,
+            describe(meta),
+            text
+        );
+    }
+    Ok(())
+}
+
+/// Write progs of corpus dump at `path` that match `filter` to `out` with their meta,
+/// returning number of progs written.
+pub async fn filter(cfg: &Config, path: &Path, filter: &Filter, out: &Path) -> Result<usize> {
+    let target = load_target(cfg).await?;
+    let selected = select(&target, path, filter).await?;
+    let corpus = Corpus::default();
+    for (_, p, meta) in selected {
+        corpus.insert_with(p, meta).await;
+    }
+    corpus.save(out).await?;
+    Ok(corpus.len().await)
+}
+
+/// Meta of prog as a comment line.
+fn describe(meta: &Meta) -> String {
+    let found = Local
+        .timestamp_opt(meta.found as i64, 0)
+        .single()
+        .filter(|_| meta.found != 0)
+        .map_or_else(
+            || "unknown".to_string(),
+            |t| t.format("%Y-%m-%d %H:%M:%S").to_string(),
+        );
+    format!(
+        "// found: {}  new blocks: {}  new branches: {}  exec: {}us  selected: {}  productive: {}",
+        found, meta.new_blocks, meta.new_branches, meta.exec_us, meta.selected, meta.productive
+    )
+}
+
+/// Files in directory of saved crashes, sorted.
+async fn crash_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let mut entries = read_dir(dir)
+        .await
+        .map_err(|e| err!(exitcode::NOINPUT, "Fail to read {}: {}", dir.display(), e))?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| err!(exitcode::IOERR, "Fail to read {}: {}", dir.display(), e))?
+    {
+        if entry.path().is_file() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+async fn load_crash(path: &Path) -> Result<CrashedCase> {
+    let data = read(path)
+        .await
+        .map_err(|e| err!(exitcode::NOINPUT, "Fail to read {}: {}", path.display(), e))?;
+    serde_json::from_slice(&data)
+        .map_err(|e| err!(exitcode::DATAERR, "Fail to parse {}: {}", path.display(), e))
+}
+
+fn prog_of<'a>(case: &'a CrashedCase, path: &Path) -> Result<&'a Prog> {
+    case.prog.as_ref().ok_or_else(|| {
+        err!(
+            exitcode::DATAERR,
+            "{} carries no prog, it was saved by older fuzzer",
+            path.display()
+        )
+    })
+}
+
+async fn boot(cfg: &Config) -> Result<Executor> {
+    let mut executor = Executor::new(
+        cfg,
+        0,
+        Arc::new(AtomicUsize::new(0)),
+        None,
+        Arc::new(Hooks::default()),
+    )?;
+    executor.start().await?;
+    Ok(executor)
+}
+
+async fn repro_prog(executor: &mut Executor, target: &Target, p: &Prog) -> Result<Option<Crash>> {
+    // coverage is useless for reproducing
+    let opt = ExecOpt {
+        collect_cover: false,
+        repeat: REPRO_REPEAT,
+        ..executor.opt()
+    };
+    match executor.exec_with(p, target, opt).await? {
+        Ok(_) => Ok(None),
+        Err(crash) => Ok(Some(crash.unwrap_or_default())),
+    }
+}
+
+async fn dump(progs: &[Prog], out: &Path) -> Result<()> {
+    let data = bincode::serialize(progs)
+        .map_err(|e| err!(exitcode::SOFTWARE, "Fail to dump progs: {}", e))?;
+    write(out, data)
+        .await
+        .map_err(|e| err!(exitcode::IOERR, "Fail to write {}: {}", out.display(), e))
+}
@@ -1,48 +1,1175 @@
+use crate::error::Result;
+use crate::feedback::Cover;
+use crate::store::{Store, StoreConf};
+use crate::utils::workdir;
+use core::c::{to_prog, to_script};
 use core::prog::Prog;
-use std::collections::HashSet;
+use core::rng::FixedState;
+use core::target::Target;
+use core::value::{NumValue, Value};
+use fots::types::{GroupId, StrType, TypeId, TypeInfo};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::iter::FromIterator;
-use tokio::sync::Mutex;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::{interval_at, Duration, Instant};
+
+/// Magic that dumps with metadata start with, dumps without it are bare progs of older fuzzer.
+const DUMP_MAGIC: &[u8; 8] = b"HLCRPS02";
+/// Magic of dumps whose meta has no count of productive mutations.
+const DUMP_MAGIC_V1: &[u8; 8] = b"HLCRPS01";
+/// Manifest of exported corpus, in export directory.
+pub const EXPORT_INDEX: &str = "index.json";
+/// Interval after which scores of progs are computed again, even if corpus did not change,
+/// since recency decays.
+const RESCORE_INTERVAL: Duration = Duration::from_secs(60);
+/// Hours for recency of prog to halve, if not configured.
+const DEFAULT_HALF_LIFE: u64 = 24;
+/// Times prog is chosen without productive mutation before it is evicted, if not configured.
+const DEFAULT_MIN_SELECTED: u64 = 100;
+/// Minutes between auto-saves of corpus, if not configured.
+const DEFAULT_AUTOSAVE_INTERVAL: u64 = 5;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AutosaveConf {
+    /// Minutes between saves of corpus, 5 by default
+    pub interval: Option<u64>,
+    /// Start from corpus saved by latest run in work dir if no corpus is given, true by default
+    pub load: Option<bool>,
+}
+
+impl AutosaveConf {
+    pub fn check(&self) -> Result<()> {
+        if self.interval == Some(0) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: interval of autosave should be positive"
+            )
+        }
+        Ok(())
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval.unwrap_or(DEFAULT_AUTOSAVE_INTERVAL) * 60)
+    }
+
+    pub fn load(&self) -> bool {
+        self.load.unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SelectionConf {
+    /// Weight of favoring progs of few calls, 1 by default
+    pub size: Option<f64>,
+    /// Weight of favoring progs whose calls finish fast, 1 by default
+    pub speed: Option<f64>,
+    /// Weight of favoring progs that brought coverage recently, 1 by default
+    pub recency: Option<f64>,
+    /// Hours for recency of prog to halve, 24 by default
+    pub half_life: Option<u64>,
+}
+
+impl SelectionConf {
+    pub fn check(&self) -> Result<()> {
+        for (name, w) in [
+            ("size", self.size),
+            ("speed", self.speed),
+            ("recency", self.recency),
+        ]
+        .iter()
+        {
+            if w.map_or(false, |w| !w.is_finite() || w < 0.0) {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: {} of selection should not be negative",
+                    name
+                )
+            }
+        }
+        if self.half_life == Some(0) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: half_life of selection should be positive"
+            )
+        }
+        Ok(())
+    }
+
+    /// Score of prog of `calls` calls with `meta`, at `now` in seconds since unix epoch. Each
+    /// term is in [0, 1] and scaled by its weight, on top of a base of 1, so that no prog is
+    /// starved.
+    fn score(&self, calls: usize, meta: Option<&Meta>, now: u64) -> f64 {
+        let mut score = 1.0 + self.size.unwrap_or(1.0) / calls.max(1) as f64;
+        if let Some(meta) = meta {
+            if meta.exec_us != 0 {
+                let ms = meta.exec_us as f64 / 1000.0;
+                score += self.speed.unwrap_or(1.0) / (1.0 + ms);
+            }
+            if meta.found != 0 {
+                let hours = now.saturating_sub(meta.found) as f64 / 3600.0;
+                let half_life = self.half_life.unwrap_or(DEFAULT_HALF_LIFE) as f64;
+                score += self.recency.unwrap_or(1.0) * 0.5f64.powf(hours / half_life);
+            }
+        }
+        score
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EvictionConf {
+    /// Max number of progs in corpus
+    pub max: usize,
+    /// Times prog is chosen for mutation or replay without bringing new coverage before it
+    /// counts as unproductive, 100 by default
+    pub min_selected: Option<u64>,
+}
+
+impl EvictionConf {
+    pub fn check(&self) -> Result<()> {
+        if self.max < 16 {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: max of eviction should be at least 16 progs"
+            )
+        }
+        if self.min_selected == Some(0) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: min_selected of eviction should be positive"
+            )
+        }
+        Ok(())
+    }
+
+    pub fn min_selected(&self) -> u64 {
+        self.min_selected.unwrap_or(DEFAULT_MIN_SELECTED)
+    }
+}
+
+/// Scores of progs, in order of iteration of corpus, or of store if corpus is stored on disk.
+#[derive(Debug)]
+struct Scores {
+    /// Insertions into corpus when scores were computed
+    version: usize,
+    computed: Instant,
+    gids: Vec<GroupId>,
+    scores: Vec<f64>,
+}
+
+/// What is known about a prog of corpus, saved with it in dump.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Meta {
+    /// Blocks and branches prog brought into coverage when it entered corpus
+    pub new_blocks: usize,
+    pub new_branches: usize,
+    /// When prog entered corpus, in seconds since unix epoch, 0 if unknown
+    pub found: u64,
+    /// Time all calls of prog took when it entered corpus, in microseconds
+    pub exec_us: u64,
+    /// Times prog was chosen for mutation or replay
+    pub selected: u64,
+    /// Times prog mutated from it brought new coverage
+    pub productive: u64,
+}
+
+/// Meta of dumps of `DUMP_MAGIC_V1`.
+#[derive(Deserialize)]
+struct MetaV1 {
+    new_blocks: usize,
+    new_branches: usize,
+    found: u64,
+    exec_us: u64,
+    selected: u64,
+}
+
+impl From<MetaV1> for Meta {
+    fn from(m: MetaV1) -> Self {
+        Self {
+            new_blocks: m.new_blocks,
+            new_branches: m.new_branches,
+            found: m.found,
+            exec_us: m.exec_us,
+            selected: m.selected,
+            productive: 0,
+        }
+    }
+}
+
+impl Meta {
+    /// Meta of prog entering corpus now, whose coverage and execution time are unknown.
+    pub fn now() -> Self {
+        Self {
+            found: chrono::Utc::now().timestamp() as u64,
+            ..Default::default()
+        }
+    }
+}
+
+/// Progs of corpus dump and their meta, which is default for dumps of older fuzzer.
+pub fn parse_dump(data: &[u8]) -> bincode::Result<Vec<(Prog, Meta)>> {
+    if data.starts_with(DUMP_MAGIC) {
+        bincode::deserialize(&data[DUMP_MAGIC.len()..])
+    } else if data.starts_with(DUMP_MAGIC_V1) {
+        let entries: Vec<(Prog, MetaV1)> = bincode::deserialize(&data[DUMP_MAGIC_V1.len()..])?;
+        Ok(entries.into_iter().map(|(p, m)| (p, m.into())).collect())
+    } else {
+        let progs: Vec<Prog> = bincode::deserialize(data)?;
+        Ok(progs.into_iter().map(|p| (p, Meta::default())).collect())
+    }
+}
+
+/// Hash of prog, same across runs.
+pub fn prog_hash(p: &Prog) -> u64 {
+    let mut hasher = FixedState::default().build_hasher();
+    p.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hash of prog that ignores what rarely changes what kernel does: ids of resources, values
+/// of length fields, magnitude of plain numbers beyond their sign and number of bits, and
+/// content of strings that are not file names or chosen from a list. Calls, flags, choices of
+/// unions, null pointers, lengths of arrays and which call each resource comes from are kept.
+pub fn semantic_hash(p: &Prog, target: &Target) -> u64 {
+    let mut hasher = FixedState::default().build_hasher();
+    p.gid.hash(&mut hasher);
+    for c in p.calls.iter() {
+        c.fid.hash(&mut hasher);
+        for arg in c.args.iter() {
+            hash_value(arg.tid, &arg.val, target, &mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+fn hash_value<H: Hasher>(tid: TypeId, val: &Value, t: &Target, h: &mut H) {
+    // which call a resource comes from matters, not its index or value.
+    if let Value::Ref((cid, _)) = val {
+        cid.hash(h);
+        return;
+    }
+    match (t.type_of(tid), val) {
+        (TypeInfo::Res { .. }, _) | (TypeInfo::Len { .. }, _) => (),
+        (TypeInfo::Alias { tid, .. }, _) => hash_value(*tid, val, t, h),
+        (TypeInfo::Num(_), Value::Num(n)) => {
+            let (negative, bits) = match n {
+                NumValue::Signed(v) => (*v < 0, 64 - (v.wrapping_abs() as u64).leading_zeros()),
+                NumValue::Unsigned(v) => (false, 64 - v.leading_zeros()),
+            };
+            (negative, bits).hash(h)
+        }
+        (TypeInfo::Flag { .. }, Value::Num(n)) => n.hash(h),
+        (TypeInfo::Str { str_type, vals }, Value::Str(s)) => {
+            if *str_type == StrType::FileName || vals.is_some() {
+                s.hash(h)
+            } else {
+                s.len().hash(h)
+            }
+        }
+        (TypeInfo::Ptr { tid, .. }, _) => {
+            let null = *val == Value::None;
+            null.hash(h);
+            if !null {
+                hash_value(*tid, val, t, h)
+            }
+        }
+        (TypeInfo::Slice { tid, .. }, Value::Group(vals)) => {
+            vals.len().hash(h);
+            for v in vals.iter() {
+                hash_value(*tid, v, t, h)
+            }
+        }
+        (TypeInfo::Struct { fields, .. }, Value::Group(vals)) => {
+            for (f, v) in fields.iter().zip(vals.iter()) {
+                hash_value(f.tid, v, t, h)
+            }
+        }
+        (TypeInfo::Union { fields, .. }, Value::Opt { choice, val }) => {
+            choice.hash(h);
+            if let Some(f) = fields.get(*choice) {
+                hash_value(f.tid, val, t, h)
+            }
+        }
+        // value not matching its type, taken as it is.
+        _ => val.hash(h),
+    }
+}
+
+/// Target that progs are normalized against to find near duplicates.
+struct Dedup(Arc<Target>);
+
+impl fmt::Debug for Dedup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Dedup")
+    }
+}
+
+/// Outcome of merging corpus into another.
+#[derive(Debug, Default)]
+pub struct Merged {
+    /// Progs adding coverage, merged
+    pub added: usize,
+    /// Progs in corpus already
+    pub duplicates: usize,
+    /// Progs covering nothing new
+    pub redundant: usize,
+    /// Progs without coverage, e.g. failed ones
+    pub uncovered: usize,
+    /// Blocks and branches covered by merged corpus
+    pub blocks: usize,
+    pub branches: usize,
+}
+
+/// Form of progs exported from corpus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// C program of prog, compilable and runnable on its own
+    C,
+    /// Statements prog is translated into, as in saved test cases
+    Text,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::C => "c",
+            ExportFormat::Text => "txt",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "c" => Ok(ExportFormat::C),
+            "text" => Ok(ExportFormat::Text),
+            _ => Err(format!("unknown export format {}, c or text expected", s)),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ExportFormat::C => "c",
+            ExportFormat::Text => "text",
+        };
+        write!(f, "{}", name)
+    }
+}
 
 #[derive(Debug, Default)]
 pub struct Corpus {
-    pub inner: Mutex<HashSet<Prog>>,
+    /// Progs, iterated in same order across runs, since mutation chooses from them. Only a
+    /// bounded cache of them if corpus is stored on disk.
+    pub inner: Mutex<HashSet<Prog, FixedState>>,
+    /// Disk storage of all progs, if configured
+    store: Option<Mutex<Store>>,
+    /// Meta of each prog, by its hash
+    meta: std::sync::Mutex<HashMap<u64, Meta>>,
+    /// Weights of choosing progs, chosen uniformly if none
+    selection: Option<SelectionConf>,
+    scores: std::sync::Mutex<Option<Scores>>,
+    /// Target of semantic dedup, only exact duplicates are rejected if none
+    dedup: Option<Dedup>,
+    /// Hash of each prog by its semantic hash
+    semantic: std::sync::Mutex<HashMap<u64, u64>>,
+    /// Progs rejected as near duplicates of progs in corpus
+    pub near_duplicates: AtomicUsize,
+    /// Cap on progs, with coverage of each prog seen by this run, if configured
+    eviction: Option<EvictionConf>,
+    covers: std::sync::Mutex<HashMap<u64, Cover>>,
+    /// Progs inserted when eviction was last tried
+    evicted_at: AtomicUsize,
+    /// Progs inserted so far, and number of them when corpus was last saved
+    inserted: AtomicUsize,
+    saved: AtomicUsize,
 }
 
 impl Corpus {
+    /// Corpus stored on disk at `path`, starting with progs stored there by previous run.
+    pub fn open(conf: &StoreConf, path: &Path) -> Result<Self> {
+        let (store, cached) = Store::open(path, conf.cache())?;
+        Ok(Self {
+            inner: Mutex::new(HashSet::from_iter(cached)),
+            store: Some(Mutex::new(store)),
+            ..Default::default()
+        })
+    }
+
+    /// Choose progs by scores of `conf` from now on, uniformly if none.
+    pub fn set_selection(&mut self, conf: Option<SelectionConf>) {
+        self.selection = conf;
+    }
+
+    /// Reject progs whose semantic hash of `target` equals that of a prog in corpus from now
+    /// on, including progs in corpus already.
+    pub async fn set_dedup(&mut self, target: Arc<Target>) -> Result<()> {
+        let progs = self.progs().await?;
+        {
+            let mut semantic = self.semantic.lock().unwrap();
+            for p in progs.iter() {
+                semantic
+                    .entry(semantic_hash(p, &target))
+                    .or_insert_with(|| prog_hash(p));
+            }
+        }
+        self.dedup = Some(Dedup(target));
+        Ok(())
+    }
+
+    /// Evict progs once corpus grows beyond max of `conf` from now on, none if not given.
+    pub fn set_eviction(&mut self, conf: Option<EvictionConf>) {
+        self.eviction = conf;
+    }
+
     pub async fn insert(&self, p: Prog) -> bool {
-        let mut inner = self.inner.lock().await;
-        inner.insert(p)
+        self.insert_with(p, Meta::now()).await
+    }
+
+    /// Insert `p` that brought no new coverage to this run, e.g. synced from other workers.
+    /// False if it is in corpus already, or a near duplicate of a prog in corpus with semantic
+    /// dedup.
+    pub async fn insert_with(&self, p: Prog, meta: Meta) -> bool {
+        let hash = prog_hash(&p);
+        if let Some(d) = self.dedup.as_ref() {
+            match self.semantic.lock().unwrap().get(&semantic_hash(&p, &d.0)) {
+                Some(h) if *h == hash => return false,
+                Some(_) => {
+                    self.near_duplicates.fetch_add(1, Ordering::SeqCst);
+                    return false;
+                }
+                None => (),
+            }
+        }
+        self.insert_covered(p, meta, None).await
+    }
+
+    /// Insert `p` that brought new coverage, with its `meta` and `cover`, if known, which
+    /// eviction judges it by. Never rejected as a near duplicate, false only if it is in
+    /// corpus already.
+    pub async fn insert_covered(&self, p: Prog, meta: Meta, cover: Option<Cover>) -> bool {
+        let hash = prog_hash(&p);
+        let semantic = self.dedup.as_ref().map(|d| semantic_hash(&p, &d.0));
+        let inserted = match self.store.as_ref() {
+            Some(store) => {
+                let mut store = store.lock().await;
+                match store.append(&p) {
+                    Ok(false) => return false,
+                    Ok(true) => (),
+                    // kept in memory at least.
+                    Err(e) => warn!("{}", e),
+                }
+                let mut inner = self.inner.lock().await;
+                if inner.len() >= store.cache {
+                    // first prog in iteration order, so that eviction does not draw from rng.
+                    let evicted = inner.iter().next().cloned().unwrap();
+                    inner.remove(&evicted);
+                }
+                inner.insert(p);
+                true
+            }
+            None => self.inner.lock().await.insert(p),
+        };
+        if inserted {
+            self.inserted.fetch_add(1, Ordering::SeqCst);
+            self.meta.lock().unwrap().insert(hash, meta);
+            if let Some(semantic) = semantic {
+                self.semantic
+                    .lock()
+                    .unwrap()
+                    .entry(semantic)
+                    .or_insert(hash);
+            }
+            if let (Some(conf), Some(cover)) = (self.eviction.as_ref(), cover) {
+                self.covers.lock().unwrap().insert(hash, cover);
+                self.evict(conf).await;
+            }
+        }
+        inserted
+    }
+
+    /// Once corpus grows beyond max, evict progs down to 90% of max: first progs whose
+    /// coverage is all covered by other progs, then progs chosen `min_selected` times whose
+    /// mutants never brought new coverage, least productive and most chosen first. Coverage is
+    /// only known for progs found by this run, so progs loaded from dumps or disk are only
+    /// evicted once unproductive. Corpus stays beyond max if no prog can be evicted, and
+    /// eviction is tried again after as many insertions as it would evict.
+    async fn evict(&self, conf: &EvictionConf) {
+        let slack = conf.max / 10;
+        let inserted = self.inserted.load(Ordering::SeqCst);
+        let hashes = match self.store.as_ref() {
+            Some(store) => store
+                .lock()
+                .await
+                .entries
+                .iter()
+                .map(|e| e.hash)
+                .collect::<Vec<_>>(),
+            None => self.inner.lock().await.iter().map(prog_hash).collect(),
+        };
+        if hashes.len() <= conf.max || inserted < self.evicted_at.load(Ordering::SeqCst) + slack {
+            return;
+        }
+        self.evicted_at.store(inserted, Ordering::SeqCst);
+        let mut n = hashes.len() - (conf.max - slack);
+        let (victims, subsumed) = {
+            let meta = self.meta.lock().unwrap();
+            let covers = self.covers.lock().unwrap();
+            let mut order = hashes
+                .iter()
+                .map(|h| (*h, meta.get(h).cloned().unwrap_or_default()))
+                .collect::<Vec<_>>();
+            order.sort_by_key(|(_, m)| (m.productive, Reverse(m.selected)));
+
+            let mut blocks = HashMap::new();
+            let mut branches = HashMap::new();
+            for h in hashes.iter() {
+                if let Some(c) = covers.get(h) {
+                    for b in c.blocks.iter() {
+                        *blocks.entry(b).or_insert(0) += 1;
+                    }
+                    for b in c.branches.iter() {
+                        *branches.entry(b).or_insert(0) += 1;
+                    }
+                }
+            }
+            let mut victims = HashSet::new();
+            for (h, _) in order.iter() {
+                if n == 0 {
+                    break;
+                }
+                let c = match covers.get(h) {
+                    Some(c) => c,
+                    None => continue,
+                };
+                if c.blocks.iter().all(|b| blocks[b] > 1)
+                    && c.branches.iter().all(|b| branches[b] > 1)
+                {
+                    for b in c.blocks.iter() {
+                        *blocks.get_mut(b).unwrap() -= 1;
+                    }
+                    for b in c.branches.iter() {
+                        *branches.get_mut(b).unwrap() -= 1;
+                    }
+                    victims.insert(*h);
+                    n -= 1;
+                }
+            }
+            let subsumed = victims.len();
+            for (h, m) in order.iter() {
+                if n == 0 {
+                    break;
+                }
+                if m.productive == 0 && m.selected >= conf.min_selected() && victims.insert(*h) {
+                    n -= 1;
+                }
+            }
+            (victims, subsumed)
+        };
+        if victims.is_empty() {
+            return;
+        }
+        if let Some(store) = self.store.as_ref() {
+            if let Err(e) = store.lock().await.remove(&victims) {
+                warn!("{}", e);
+                return;
+            }
+        }
+        self.inner
+            .lock()
+            .await
+            .retain(|p| !victims.contains(&prog_hash(p)));
+        self.meta
+            .lock()
+            .unwrap()
+            .retain(|h, _| !victims.contains(h));
+        self.covers
+            .lock()
+            .unwrap()
+            .retain(|h, _| !victims.contains(h));
+        self.semantic
+            .lock()
+            .unwrap()
+            .retain(|_, h| !victims.contains(h));
+        // indices of scores no longer match progs.
+        self.scores.lock().unwrap().take();
+        info!(
+            "Corpus beyond {} progs, evicted {} covered by others and {} unproductive",
+            conf.max,
+            subsumed,
+            victims.len() - subsumed
+        );
+    }
+
+    /// Count choice of `p` for mutation or replay.
+    pub fn selected(&self, p: &Prog) {
+        if let Some(meta) = self.meta.lock().unwrap().get_mut(&prog_hash(p)) {
+            meta.selected += 1;
+        }
+    }
+
+    /// Count prog mutated from prog of `hash` bringing new coverage.
+    pub fn productive(&self, hash: u64) {
+        if let Some(meta) = self.meta.lock().unwrap().get_mut(&hash) {
+            meta.productive += 1;
+        }
+    }
+
+    pub fn meta(&self, p: &Prog) -> Option<Meta> {
+        self.meta.lock().unwrap().get(&prog_hash(p)).cloned()
     }
 
     pub async fn len(&self) -> usize {
+        if let Some(store) = self.store.as_ref() {
+            return store.lock().await.entries.len();
+        }
         let inner = self.inner.lock().await;
         inner.len()
     }
 
     pub async fn is_empty(&self) -> bool {
+        if let Some(store) = self.store.as_ref() {
+            return store.lock().await.entries.is_empty();
+        }
         let inner = self.inner.lock().await;
         inner.is_empty()
     }
 
-    pub async fn dump(&self) -> bincode::Result<Vec<u8>> {
+    /// All progs, read from disk if corpus is stored there.
+    pub async fn progs(&self) -> Result<Vec<Prog>> {
+        match self.store.as_ref() {
+            Some(store) => store.lock().await.read_all(),
+            None => Ok(self.inner.lock().await.iter().cloned().collect()),
+        }
+    }
+
+    /// Progs with their meta, prefixed by magic of dump format.
+    pub async fn dump(&self) -> Result<Vec<u8>> {
+        let progs = self.progs().await?;
+        let entries = {
+            let meta = self.meta.lock().unwrap();
+            progs
+                .into_iter()
+                .map(|mut p| {
+                    let m = meta.get(&prog_hash(&p)).cloned().unwrap_or_default();
+                    p.shrink();
+                    (p, m)
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut data = DUMP_MAGIC.to_vec();
+        bincode::serialize_into(&mut data, &entries)
+            .map_err(|e| err!(exitcode::DATAERR, "Fail to dump corpus: {}", e))?;
+        Ok(data)
+    }
+
+    pub fn load(c: &[u8]) -> bincode::Result<Self> {
+        let entries = parse_dump(c)?;
+        let meta = entries
+            .iter()
+            .map(|(p, m)| (prog_hash(p), m.clone()))
+            .collect();
+        Ok(Self {
+            inner: Mutex::new(HashSet::from_iter(entries.into_iter().map(|(p, _)| p))),
+            meta: std::sync::Mutex::new(meta),
+            ..Default::default()
+        })
+    }
+
+    /// Write dump of corpus to `path` through a temporary file renamed over it, so that a
+    /// crash of fuzzer never leaves it half written.
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let inserted = self.inserted.load(Ordering::SeqCst);
+        let corpus = self.dump().await?;
+        workdir::write_atomic(path, corpus).await.map_err(|e| {
+            err!(
+                exitcode::IOERR,
+                "Fail to persist corpus to {} : {}",
+                path.display(),
+                e
+            )
+        })?;
+        self.saved.store(inserted, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Prog of groups `gids`(all groups if none) for mutation or replay, chosen by scores of
+    /// selection conf, uniformly without it. Progs stored on disk are chosen from all of them
+    /// and read back, so that progs evicted from memory still get chosen. None if there is no
+    /// such prog.
+    pub async fn select_weighted<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        gids: Option<&[GroupId]>,
+    ) -> Option<Prog> {
+        let in_groups = |gid: &GroupId| gids.map_or(true, |gids| gids.binary_search(gid).is_ok());
+        if let Some(store) = self.store.as_ref() {
+            let store = store.lock().await;
+            let entry = match self.selection.as_ref() {
+                Some(conf) => {
+                    let i = self.sample(conf, rng, &in_groups, || {
+                        store.entries.iter().map(|e| (e.gid, e.calls, e.hash))
+                    })?;
+                    &store.entries[i]
+                }
+                None => store
+                    .entries
+                    .iter()
+                    .filter(|e| in_groups(&e.gid))
+                    .choose(rng)?,
+            };
+            return match store.read(entry) {
+                Ok(p) => Some(p),
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+            };
+        }
         let inner = self.inner.lock().await;
-        let mut progs = inner
+        match self.selection.as_ref() {
+            Some(conf) => {
+                let i = self.sample(conf, rng, &in_groups, || {
+                    inner.iter().map(|p| (p.gid, p.calls.len(), prog_hash(p)))
+                })?;
+                inner.iter().nth(i).cloned()
+            }
+            None if gids.is_none() => inner.iter().choose(rng).cloned(),
+            None => inner
+                .iter()
+                .filter(|p| in_groups(&p.gid))
+                .choose(rng)
+                .cloned(),
+        }
+    }
+
+    /// Index of prog chosen by scores, which are computed again from group, number of calls
+    /// and hash of each prog given by `progs` once corpus changed or scores got old.
+    fn sample<R, F, I>(
+        &self,
+        conf: &SelectionConf,
+        rng: &mut R,
+        in_groups: &dyn Fn(&GroupId) -> bool,
+        progs: F,
+    ) -> Option<usize>
+    where
+        R: Rng + ?Sized,
+        F: FnOnce() -> I,
+        I: Iterator<Item = (GroupId, usize, u64)>,
+    {
+        let version = self.inserted.load(Ordering::SeqCst);
+        let mut scores = self.scores.lock().unwrap();
+        let stale = scores.as_ref().map_or(true, |s| {
+            s.version != version || s.computed.elapsed() >= RESCORE_INTERVAL
+        });
+        if stale {
+            let now = chrono::Utc::now().timestamp() as u64;
+            let meta = self.meta.lock().unwrap();
+            let (gids, values) = progs()
+                .map(|(gid, calls, hash)| (gid, conf.score(calls, meta.get(&hash), now)))
+                .unzip();
+            *scores = Some(Scores {
+                version,
+                computed: Instant::now(),
+                gids,
+                scores: values,
+            });
+        }
+        let scores = scores.as_ref().unwrap();
+        let weights = scores
+            .gids
             .iter()
-            .map(|p| {
-                let mut p = p.clone();
-                p.shrink();
-                p
-            })
+            .zip(scores.scores.iter())
+            .map(|(gid, s)| if in_groups(gid) { *s } else { 0.0 });
+        // fails if no prog is in groups.
+        let index = WeightedIndex::new(weights).ok()?;
+        Some(index.sample(rng))
+    }
+
+    /// Minimal subset of corpus covering the same blocks and branches, `feedback` being
+    /// coverage of each prog; progs without coverage are left out. Prog bringing most coverage
+    /// not covered yet is taken first, prog of fewer calls wins a tie.
+    pub async fn distill(&self, feedback: &HashMap<Prog, Cover>) -> Result<Vec<Prog>> {
+        let progs = self
+            .progs()
+            .await?
+            .into_iter()
+            .filter(|p| feedback.contains_key(p))
             .collect::<Vec<_>>();
-        progs.shrink_to_fit();
-        bincode::serialize(&progs)
+        // gain of each prog only shrinks as more is covered, so gains in heap are upper
+        // bounds, and a prog whose fresh gain still tops the heap is the best one.
+        let mut heap = progs
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (feedback[p].len(), Reverse(p.calls.len()), Reverse(i)))
+            .collect::<BinaryHeap<_>>();
+        let mut covered = Cover::default();
+        let mut kept = Vec::new();
+        while let Some((_, calls, i)) = heap.pop() {
+            let cover = &feedback[&progs[i.0]];
+            let gain = cover.diff_len(&covered);
+            if gain == 0 {
+                continue;
+            }
+            if heap.peek().map_or(true, |top| (gain, calls, i) >= *top) {
+                covered.merge(cover);
+                kept.push(progs[i.0].clone());
+            } else {
+                heap.push((gain, calls, i));
+            }
+        }
+        Ok(kept)
     }
 
-    pub fn load(c: &[u8]) -> bincode::Result<Self> {
-        let mut progs: Vec<Prog> = bincode::deserialize(c)?;
-        progs.shrink_to_fit();
-        Ok(Self {
-            inner: Mutex::new(HashSet::from_iter(progs)),
+    /// Merge progs of `other` into corpus, keeping only those adding coverage to what progs of
+    /// corpus cover, `feedback` being coverage of each prog. Progs of `other` are judged in
+    /// order, each against coverage merged before it, so a prog kept may be covered by ones
+    /// merged after it; distill the result for the smallest corpus.
+    pub async fn merge(&self, other: &Corpus, feedback: &HashMap<Prog, Cover>) -> Result<Merged> {
+        let mut cover = Cover::default();
+        let mut known = HashSet::new();
+        for p in self.progs().await? {
+            if let Some(c) = feedback.get(&p) {
+                cover.merge(c);
+            }
+            known.insert(prog_hash(&p));
+        }
+        let mut merged = Merged::default();
+        for p in other.progs().await? {
+            let hash = prog_hash(&p);
+            if known.contains(&hash) {
+                merged.duplicates += 1;
+                continue;
+            }
+            let c = match feedback.get(&p) {
+                Some(c) => c,
+                None => {
+                    merged.uncovered += 1;
+                    continue;
+                }
+            };
+            if c.diff_len(&cover) == 0 {
+                merged.redundant += 1;
+                continue;
+            }
+            let meta = other.meta(&p).unwrap_or_default();
+            self.insert_covered(p, meta, Some(c.clone())).await;
+            cover.merge(c);
+            known.insert(hash);
+            merged.added += 1;
+        }
+        merged.blocks = cover.blocks.len();
+        merged.branches = cover.branches.len();
+        Ok(merged)
+    }
+
+    /// Write each prog to a file of its own under `dir` in `format`, with a manifest listing
+    /// file, group and calls of each prog in `index.json`. Returns number of progs written.
+    pub async fn export(&self, target: &Target, dir: &Path, format: ExportFormat) -> Result<usize> {
+        tokio::fs::create_dir_all(dir).await.map_err(|e| {
+            err!(
+                exitcode::CANTCREAT,
+                "Fail to create {}: {}",
+                dir.display(),
+                e
+            )
+        })?;
+        let progs = self.progs().await?;
+        let mut index = Vec::new();
+        for (id, p) in progs.iter().enumerate() {
+            let file = format!("prog-{:05}.{}", id, format.extension());
+            let content = match format {
+                ExportFormat::C => to_prog(p, target),
+                ExportFormat::Text => to_script(p, target).to_string(),
+            };
+            let path = dir.join(&file);
+            tokio::fs::write(&path, content)
+                .await
+                .map_err(|e| err!(exitcode::IOERR, "Fail to write {}: {}", path.display(), e))?;
+            let calls = p
+                .calls
+                .iter()
+                .map(|c| target.fn_of(c.fid).dec_name.clone())
+                .collect::<Vec<_>>();
+            index.push(serde_json::json!({
+                "id": id,
+                "file": file,
+                "group": target.group_name_of(p.gid),
+                "calls": calls,
+            }));
+        }
+        let manifest = serde_json::json!({
+            "format": format.to_string(),
+            "progs": index,
+        });
+        let path = dir.join(EXPORT_INDEX);
+        workdir::write_atomic(&path, serde_json::to_string_pretty(&manifest).unwrap())
+            .await
+            .map_err(|e| err!(exitcode::IOERR, "Fail to write {}: {}", path.display(), e))?;
+        Ok(progs.len())
+    }
+
+    /// Save corpus to `path` every `interval` until shutdown, skipping saves while no prog is
+    /// inserted since the last one.
+    pub async fn autosave(
+        &self,
+        path: &Path,
+        interval: Duration,
+        mut shutdown: broadcast::Receiver<()>,
+    ) {
+        let mut tick = interval_at(Instant::now() + interval, interval);
+        loop {
+            tokio::select! {
+                _ = tick.tick() => (),
+                _ = shutdown.recv() => break,
+            }
+            if self.inserted.load(Ordering::SeqCst) == self.saved.load(Ordering::SeqCst) {
+                continue;
+            }
+            match self.save(path).await {
+                Ok(()) => debug!("Corpus saved to {}", path.display()),
+                Err(e) => warn!("Fail to auto-save corpus: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::prog::{Arg, ArgPos, Call};
+
+    const DESCRIPTION: &str = "
+        type fd = res<i32>
+        flag open_flags{O_RDONLY=0, O_WRONLY=1}
+        union addr{v4 i32, v6 i64}
+        group G {
+            fn open(flags open_flags) fd
+            fn write(f fd, buf *[i8], count len<i32, buf>)
+            fn bind(f fd, a *addr)
+        }
+    ";
+
+    fn target() -> Target {
+        Target::from(fots::parse_items(DESCRIPTION).unwrap())
+    }
+
+    fn call(t: &Target, name: &str, vals: Vec<Value>) -> Call {
+        let f = t
+            .iter_group()
+            .flat_map(|g| g.fns.iter())
+            .find(|f| f.dec_name == name)
+            .unwrap();
+        let mut c = Call::new(f.id);
+        c.args = f
+            .params
+            .iter()
+            .flatten()
+            .zip(vals)
+            .map(|(p, val)| Arg { tid: p.tid, val })
+            .collect();
+        c
+    }
+
+    fn prog(t: &Target, calls: Vec<Call>) -> Prog {
+        let gid = t.iter_group().next().unwrap().id;
+        let mut p = Prog::new(gid);
+        p.calls = calls;
+        p
+    }
+
+    fn num(n: u64) -> Value {
+        Value::Num(NumValue::Unsigned(n))
+    }
+
+    fn write(t: &Target, fd: Value, buf: Vec<Value>, count: u64) -> Prog {
+        let open = call(t, "open", vec![num(0)]);
+        let write = call(t, "write", vec![fd, Value::Group(buf), num(count)]);
+        prog(t, vec![open, write])
+    }
+
+    fn bind(t: &Target, choice: usize) -> Prog {
+        let addr = Value::Opt {
+            choice,
+            val: Box::new(num(1)),
+        };
+        prog(t, vec![call(t, "bind", vec![num(3), addr])])
+    }
+
+    fn open(t: &Target, flags: u64) -> Prog {
+        prog(t, vec![call(t, "open", vec![num(flags)])])
+    }
+
+    fn cover(blocks: &[usize]) -> Cover {
+        let mut c = Cover::default();
+        for b in blocks {
+            c.extend_raw(&[*b]);
+        }
+        c
+    }
+
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        tokio::runtime::Runtime::new().unwrap().block_on(f)
+    }
+
+    #[test]
+    fn semantic_hash_ignores_resource_ids() {
+        let t = target();
+        let buf = vec![num(1)];
+        let a = write(&t, num(3), buf.clone(), 1);
+        let b = write(&t, num(42), buf.clone(), 1);
+        assert_ne!(prog_hash(&a), prog_hash(&b));
+        assert_eq!(semantic_hash(&a, &t), semantic_hash(&b, &t));
+
+        let a = write(&t, Value::Ref((0, ArgPos::Ret)), buf.clone(), 1);
+        assert_ne!(semantic_hash(&a, &t), semantic_hash(&b, &t));
+        let b = write(&t, Value::Ref((0, ArgPos::Arg(0))), buf, 1);
+        assert_eq!(semantic_hash(&a, &t), semantic_hash(&b, &t));
+    }
+
+    #[test]
+    fn semantic_hash_ignores_len_values() {
+        let t = target();
+        let a = write(&t, num(3), vec![num(1), num(2)], 2);
+        let b = write(&t, num(3), vec![num(1), num(2)], 4096);
+        assert_ne!(prog_hash(&a), prog_hash(&b));
+        assert_eq!(semantic_hash(&a, &t), semantic_hash(&b, &t));
+
+        // length of array itself still matters.
+        let c = write(&t, num(3), vec![num(1)], 2);
+        assert_ne!(semantic_hash(&a, &t), semantic_hash(&c, &t));
+    }
+
+    #[test]
+    fn semantic_hash_keeps_flags() {
+        let t = target();
+        let a = prog(&t, vec![call(&t, "open", vec![num(0)])]);
+        let b = prog(&t, vec![call(&t, "open", vec![num(1)])]);
+        assert_ne!(semantic_hash(&a, &t), semantic_hash(&b, &t));
+    }
+
+    #[test]
+    fn semantic_hash_keeps_union_choices() {
+        let t = target();
+        assert_ne!(
+            semantic_hash(&bind(&t, 0), &t),
+            semantic_hash(&bind(&t, 1), &t)
+        );
+    }
+
+    #[test]
+    fn eviction_prefers_covered_then_unproductive() {
+        block_on(async {
+            let t = target();
+            let mut corpus = Corpus::default();
+            corpus.set_eviction(Some(EvictionConf {
+                max: 16,
+                min_selected: Some(3),
+            }));
+            for i in 0..14 {
+                let p = open(&t, i);
+                assert!(
+                    corpus
+                        .insert_covered(p, Meta::default(), Some(cover(&[i as usize])))
+                        .await
+                );
+            }
+            // same coverage, the most chosen one of them goes first.
+            let chosen = open(&t, 100);
+            let meta = Meta {
+                selected: 5,
+                ..Default::default()
+            };
+            corpus
+                .insert_covered(chosen.clone(), meta, Some(cover(&[100])))
+                .await;
+            let kept = open(&t, 101);
+            corpus
+                .insert_covered(kept.clone(), Meta::default(), Some(cover(&[100])))
+                .await;
+            assert_eq!(corpus.len().await, 16);
+
+            let unproductive = open(&t, 102);
+            let meta = Meta {
+                selected: 3,
+                ..Default::default()
+            };
+            corpus
+                .insert_covered(unproductive.clone(), meta, Some(cover(&[102])))
+                .await;
+            assert_eq!(corpus.len().await, 15);
+            assert!(corpus.meta(&chosen).is_none());
+            assert!(corpus.meta(&unproductive).is_none());
+            assert!(corpus.meta(&kept).is_some());
+        })
+    }
+
+    #[test]
+    fn eviction_keeps_unique_productive_progs() {
+        block_on(async {
+            let t = target();
+            let mut corpus = Corpus::default();
+            corpus.set_eviction(Some(EvictionConf {
+                max: 16,
+                min_selected: None,
+            }));
+            for i in 0..20 {
+                let p = open(&t, i);
+                corpus
+                    .insert_covered(p, Meta::default(), Some(cover(&[i as usize])))
+                    .await;
+            }
+            assert_eq!(corpus.len().await, 20);
+        })
+    }
+
+    #[test]
+    fn merge_keeps_progs_adding_coverage() {
+        block_on(async {
+            let t = target();
+            let corpus = Corpus::default();
+            let other = Corpus::default();
+            let mut feedback = HashMap::new();
+            let known = open(&t, 0);
+            corpus.insert(known.clone()).await;
+            feedback.insert(known.clone(), cover(&[1]));
+
+            other.insert(known).await;
+            let redundant = open(&t, 1);
+            feedback.insert(redundant.clone(), cover(&[1]));
+            other.insert(redundant.clone()).await;
+            let uncovered = open(&t, 2);
+            other.insert(uncovered.clone()).await;
+            // same coverage, only the one judged first is merged.
+            let a = open(&t, 3);
+            feedback.insert(a.clone(), cover(&[2]));
+            other.insert(a.clone()).await;
+            let b = open(&t, 4);
+            feedback.insert(b.clone(), cover(&[2]));
+            other.insert(b.clone()).await;
+
+            let merged = corpus.merge(&other, &feedback).await.unwrap();
+            assert_eq!(merged.duplicates, 1);
+            assert_eq!(merged.uncovered, 1);
+            assert_eq!(merged.added, 1);
+            assert_eq!(merged.redundant, 2);
+            assert_eq!(merged.blocks, 2);
+            assert_eq!(corpus.len().await, 2);
+            assert!(corpus.meta(&a).is_some() != corpus.meta(&b).is_some());
+            assert!(corpus.meta(&redundant).is_none());
+            assert!(corpus.meta(&uncovered).is_none());
         })
     }
 }
@@ -0,0 +1,523 @@
+//! Distributed fuzzing. Manager owns corpus, feedback and crash dedup of the campaign,
+//! workers fuzz on their own guests, possibly on other hosts, and sync with manager
+//! periodically over tcp, with frames of `executor::transfer`.
+
+use crate::corpus::Corpus;
+use crate::error::Result;
+use crate::feedback::{Block, Branch, FeedBack};
+use crate::fuzzer::Fuzzer;
+use crate::report::CrashedCase;
+use crate::utils::workdir;
+use crate::{load_corpus, wait_exit_signal, Config};
+use core::prog::Prog;
+use executor::transfer::{async_recv, async_send};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::{delay_for, interval_at, timeout, Duration, Instant};
+
+/// Max time waiting for reply of manager.
+const RPC_TIMEOUT: Duration = Duration::from_secs(60);
+/// Interval of logging stats of campaign by manager.
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+/// Progs, and blocks and branches, in one part of findings sent to newly connected worker,
+/// keeping each reply far below max body of a message however long campaign runs.
+const CHUNK_PROGS: usize = 1024;
+const CHUNK_COVER: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManagerConf {
+    /// Address manager listens on
+    pub addr: SocketAddr,
+    /// Name of worker in logs and crashes of manager, `worker-<pid>` if not given
+    pub name: Option<String>,
+    /// Interval of syncing with manager, in seconds
+    pub sync_interval: Option<u64>,
+}
+
+impl ManagerConf {
+    pub fn check(&self) -> Result<()> {
+        if self.sync_interval == Some(0) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: sync_interval of manager should be positive"
+            )
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("worker-{}", process::id()))
+    }
+
+    fn sync_interval(&self) -> Duration {
+        Duration::from_secs(self.sync_interval.unwrap_or(30))
+    }
+}
+
+/// Progs that brought new coverage and the coverage itself.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Findings {
+    pub progs: Vec<Prog>,
+    pub blocks: Vec<Block>,
+    pub branches: Vec<Branch>,
+}
+
+impl Findings {
+    fn append(&mut self, other: Findings) {
+        self.progs.extend(other.progs);
+        self.blocks.extend(other.blocks);
+        self.branches.extend(other.branches);
+    }
+
+    /// Split into parts of at most `CHUNK_PROGS` progs and `CHUNK_COVER` blocks and branches,
+    /// in order, with at least one part.
+    fn chunks(self) -> Vec<Findings> {
+        let mut progs = self.progs.into_iter();
+        let mut blocks = self.blocks.into_iter();
+        let mut branches = self.branches.into_iter();
+        let mut chunks = Vec::new();
+        loop {
+            let chunk = Findings {
+                progs: progs.by_ref().take(CHUNK_PROGS).collect(),
+                blocks: blocks.by_ref().take(CHUNK_COVER).collect(),
+                branches: branches.by_ref().take(CHUNK_COVER).collect(),
+            };
+            let last = chunk.progs.len() < CHUNK_PROGS
+                && chunk.blocks.len() < CHUNK_COVER
+                && chunk.branches.len() < CHUNK_COVER;
+            chunks.push(chunk);
+            if last {
+                return chunks;
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Request {
+    /// First request of worker.
+    Connect { name: String },
+    /// Findings of worker since last sync.
+    Sync {
+        exec: usize,
+        findings: Findings,
+        crashes: Vec<CrashedCase>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Reply {
+    /// Last part of everything campaign found so far, for newly connected worker, whose
+    /// other parts are sent before it as `Synced`.
+    Connected(Findings),
+    /// Findings of other workers since last sync.
+    Synced(Findings),
+}
+
+/// Findings of worker waiting for next sync.
+#[derive(Default)]
+pub struct Outbox {
+    findings: Mutex<Findings>,
+}
+
+impl Outbox {
+    pub async fn push(&self, p: &Prog, blocks: &HashSet<Block>, branches: &HashSet<Branch>) {
+        let mut findings = self.findings.lock().await;
+        findings.progs.push(p.clone());
+        findings.blocks.extend(blocks.iter().cloned());
+        findings.branches.extend(branches.iter().cloned());
+    }
+
+    async fn take(&self) -> Findings {
+        std::mem::take(&mut *self.findings.lock().await)
+    }
+
+    async fn put_back(&self, findings: Findings) {
+        self.findings.lock().await.append(findings);
+    }
+}
+
+/// Connection of worker to manager.
+pub struct Worker {
+    conf: ManagerConf,
+    name: String,
+    conn: Option<TcpStream>,
+    /// Executions and crashes already reported
+    exec: usize,
+    crashed: usize,
+}
+
+impl Worker {
+    /// Connect to manager, taking corpus and coverage of campaign into fuzzer.
+    pub async fn connect(conf: &ManagerConf, fuzzer: &Fuzzer) -> Result<Self> {
+        let mut worker = Worker {
+            conf: conf.clone(),
+            name: conf.name(),
+            conn: None,
+            exec: fuzzer.exec_cnt.load(Ordering::SeqCst),
+            crashed: fuzzer.record.len().await.2,
+        };
+        worker.reconnect(fuzzer).await?;
+        info!("Connected to manager {} as {}", conf.addr, worker.name);
+        Ok(worker)
+    }
+
+    /// Sync with manager periodically, and once more when fuzzer shuts down.
+    pub async fn work(mut self, fuzzer: Fuzzer, mut shutdown: broadcast::Receiver<()>) {
+        let interval = self.conf.sync_interval();
+        let mut sync = interval_at(Instant::now() + interval, interval);
+        loop {
+            tokio::select! {
+                _ = sync.tick() => {
+                    if let Err(e) = self.sync(&fuzzer).await {
+                        warn!("Fail to sync with manager {}: {}", self.conf.addr, e);
+                    }
+                }
+                _ = shutdown.recv() => {
+                    if let Err(e) = self.sync(&fuzzer).await {
+                        warn!("Fail to sync with manager {}: {}", self.conf.addr, e);
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn reconnect(&mut self, fuzzer: &Fuzzer) -> Result<()> {
+        let mut conn = TcpStream::connect(self.conf.addr).await.map_err(|e| {
+            err!(
+                exitcode::UNAVAILABLE,
+                "Fail to connect to manager {}: {}",
+                self.conf.addr,
+                e
+            )
+        })?;
+        let req = Request::Connect {
+            name: self.name.clone(),
+        };
+        let mut reply = call(&mut conn, &req).await?;
+        loop {
+            match reply {
+                Reply::Synced(findings) => merge(fuzzer, findings).await,
+                Reply::Connected(findings) => {
+                    merge(fuzzer, findings).await;
+                    break;
+                }
+            }
+            reply = recv_reply(&mut conn).await?;
+        }
+        self.conn = Some(conn);
+        Ok(())
+    }
+
+    async fn sync(&mut self, fuzzer: &Fuzzer) -> Result<()> {
+        if self.conn.is_none() {
+            self.reconnect(fuzzer).await?;
+        }
+        let outbox = fuzzer.outbox.as_ref().unwrap();
+        let exec = fuzzer.exec_cnt.load(Ordering::SeqCst);
+        let (crashes, crashed) = fuzzer.record.crashes_since(self.crashed).await;
+        let req = Request::Sync {
+            exec: exec - self.exec,
+            findings: outbox.take().await,
+            crashes,
+        };
+        let conn = self.conn.as_mut().unwrap();
+        match call(conn, &req).await {
+            Ok(Reply::Synced(findings)) => {
+                merge(fuzzer, findings).await;
+                self.exec = exec;
+                self.crashed = crashed;
+                Ok(())
+            }
+            ret => {
+                // reconnect and resend on next sync.
+                self.conn = None;
+                if let Request::Sync { findings, .. } = req {
+                    outbox.put_back(findings).await;
+                }
+                match ret {
+                    Ok(_) => Err(err!(exitcode::PROTOCOL, "Unexpected reply of manager")),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+}
+
+async fn call(conn: &mut TcpStream, req: &Request) -> Result<Reply> {
+    async_send(req, conn)
+        .await
+        .map_err(|e| err!(exitcode::IOERR, "Fail to send request: {}", e))?;
+    recv_reply(conn).await
+}
+
+async fn recv_reply(conn: &mut TcpStream) -> Result<Reply> {
+    match timeout(RPC_TIMEOUT, async_recv(conn)).await {
+        Ok(reply) => reply.map_err(|e| err!(exitcode::IOERR, "Fail to recv reply: {}", e)),
+        Err(_) => bail!(exitcode::UNAVAILABLE, "Manager did not reply in time"),
+    }
+}
+
+/// Take findings of other workers, which need no analyzing again, like resumed corpus.
+async fn merge(fuzzer: &Fuzzer, findings: Findings) {
+    for p in findings.progs {
+        fuzzer.corpus.insert(p).await;
+    }
+    fuzzer
+        .feedback
+        .merge(
+            findings.blocks.into_iter().collect(),
+            findings.branches.into_iter().collect(),
+        )
+        .await;
+}
+
+/// Position of worker in findings log of manager.
+#[derive(Default)]
+struct Cursor {
+    progs: usize,
+    blocks: usize,
+    branches: usize,
+}
+
+impl Cursor {
+    /// Findings logged after cursor.
+    fn read(&self, log: &Findings) -> Findings {
+        Findings {
+            progs: log.progs[self.progs..].to_vec(),
+            blocks: log.blocks[self.blocks..].to_vec(),
+            branches: log.branches[self.branches..].to_vec(),
+        }
+    }
+
+    fn seek_end(&mut self, log: &Findings) {
+        self.progs = log.progs.len();
+        self.blocks = log.blocks.len();
+        self.branches = log.branches.len();
+    }
+}
+
+struct Manager {
+    corpus: Corpus,
+    feedback: FeedBack,
+    /// Accepted findings in order, each worker syncs from its own cursor
+    log: Mutex<Findings>,
+    crash_digests: Mutex<HashSet<md5::Digest>>,
+    exec: AtomicUsize,
+    crashes: AtomicUsize,
+    workers: AtomicUsize,
+}
+
+/// Serve workers on `addr` until SIGINT or SIGTERM is received, corpus of config is the
+/// initial corpus of campaign. Corpus is persisted to work dir on exit and every
+/// checkpoint interval.
+pub async fn manage(cfg: &Config, addr: SocketAddr) -> Result<()> {
+    let manager = Arc::new(Manager {
+        corpus: Corpus::default(),
+        feedback: FeedBack::default(),
+        log: Mutex::new(Findings::default()),
+        crash_digests: Mutex::new(HashSet::new()),
+        exec: AtomicUsize::new(0),
+        crashes: AtomicUsize::new(0),
+        workers: AtomicUsize::new(0),
+    });
+    for p in load_corpus(&cfg.curpus).await? {
+        if manager.corpus.insert(p.clone()).await {
+            manager.log.lock().await.progs.push(p);
+        }
+    }
+    let mut listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| err!(exitcode::UNAVAILABLE, "Fail to listen on {}: {}", addr, e))?;
+    info!(
+        "Manager listening on {}, corpus: {}",
+        addr,
+        manager.corpus.len().await
+    );
+
+    let reporter = manager.clone();
+    tokio::spawn(async move {
+        loop {
+            delay_for(REPORT_INTERVAL).await;
+            reporter.report().await;
+        }
+    });
+    let mut checkpoint = cfg
+        .checkpoint_interval()
+        .map(|i| interval_at(Instant::now() + i, i));
+    let exit = wait_exit_signal();
+    tokio::pin!(exit);
+    loop {
+        tokio::select! {
+            _ = &mut exit => break,
+            _ = crate::next_tick(&mut checkpoint) => {
+                if let Err(e) = manager.persist().await {
+                    warn!("Fail to save checkpoint: {}", e);
+                }
+            }
+            conn = listener.accept() => match conn {
+                Ok((conn, peer)) => {
+                    let manager = manager.clone();
+                    tokio::spawn(async move { manager.serve(conn, peer).await });
+                }
+                Err(e) => warn!("Fail to accept worker: {}", e),
+            },
+        }
+    }
+    manager.report().await;
+    manager.persist().await
+}
+
+impl Manager {
+    async fn serve(&self, mut conn: TcpStream, peer: SocketAddr) {
+        let name = match async_recv(&mut conn).await {
+            Ok(Request::Connect { name }) => name,
+            Ok(_) => {
+                warn!("{}: sync before connect, dropped", peer);
+                return;
+            }
+            Err(e) => {
+                warn!("{}: fail to recv request: {}", peer, e);
+                return;
+            }
+        };
+        let mut cursor = Cursor::default();
+        let backlog = {
+            let log = self.log.lock().await;
+            cursor.seek_end(&log);
+            Cursor::default().read(&log)
+        };
+        let mut chunks = backlog.chunks();
+        let last = Reply::Connected(chunks.pop().unwrap());
+        let replies = chunks.into_iter().map(Reply::Synced).chain(Some(last));
+        for reply in replies {
+            if let Err(e) = async_send(&reply, &mut conn).await {
+                warn!("{}({}): fail to send reply: {}", name, peer, e);
+                return;
+            }
+        }
+        self.workers.fetch_add(1, Ordering::SeqCst);
+        info!("Worker {}({}) connected", name, peer);
+
+        loop {
+            let (exec, findings, crashes) = match async_recv(&mut conn).await {
+                Ok(Request::Sync {
+                    exec,
+                    findings,
+                    crashes,
+                }) => (exec, findings, crashes),
+                Ok(Request::Connect { .. }) => {
+                    warn!("{}({}): connect twice", name, peer);
+                    break;
+                }
+                Err(e) => {
+                    info!("Worker {}({}) disconnected: {}", name, peer, e);
+                    break;
+                }
+            };
+            self.exec.fetch_add(exec, Ordering::SeqCst);
+            for case in crashes {
+                self.crash(&name, case).await;
+            }
+            let reply = Reply::Synced(self.sync(&mut cursor, findings).await);
+            if let Err(e) = async_send(&reply, &mut conn).await {
+                warn!("{}({}): fail to send reply: {}", name, peer, e);
+                break;
+            }
+        }
+        self.workers.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Accept findings of worker, returning findings of others since its last sync.
+    async fn sync(&self, cursor: &mut Cursor, findings: Findings) -> Findings {
+        // log is locked throughout, so that findings of worker are skipped by its cursor.
+        let mut log = self.log.lock().await;
+        let reply = cursor.read(&log);
+        for p in findings.progs {
+            if self.corpus.insert(p.clone()).await {
+                log.progs.push(p);
+            }
+        }
+        let blocks = self.feedback.diff_block(&findings.blocks).await;
+        let branches = self.feedback.diff_branch(&findings.branches).await;
+        log.blocks.extend(blocks.iter().cloned());
+        log.branches.extend(branches.iter().cloned());
+        self.feedback.merge(blocks, branches).await;
+        cursor.seek_end(&log);
+        reply
+    }
+
+    /// Save crash unless same crash was reported by any worker.
+    async fn crash(&self, worker: &str, case: CrashedCase) {
+        let digest = md5::compute(&case.crash.inner);
+        if !self.crash_digests.lock().await.insert(digest) {
+            return;
+        }
+        self.crashes.fetch_add(1, Ordering::SeqCst);
+        warn!("Worker {} crashed: {}", worker, case.meta.title);
+        let path = workdir::path("crashes").join(format!("{}-{}", worker, case.meta.title));
+        let crash = serde_json::to_string_pretty(&case).unwrap();
+        if let Err(e) = workdir::write_atomic(&path, crash).await {
+            warn!(
+                "Fail to persist crashed test case to {} : {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    async fn report(&self) {
+        let (blocks, branches) = self.feedback.len().await;
+        info!(
+            "Workers: {} exec: {} corpus: {} blocks: {} branches: {} crashes: {}",
+            self.workers.load(Ordering::SeqCst),
+            self.exec.load(Ordering::SeqCst),
+            self.corpus.len().await,
+            blocks,
+            branches,
+            self.crashes.load(Ordering::SeqCst)
+        );
+    }
+
+    async fn persist(&self) -> Result<()> {
+        self.corpus.save(&workdir::path("corpus")).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_keep_order_and_bound_size() {
+        let findings = Findings {
+            progs: vec![Prog::new(0); CHUNK_PROGS * 2 + 1],
+            blocks: (0..CHUNK_COVER + 1).map(Block::from).collect(),
+            branches: Vec::new(),
+        };
+        let chunks = findings.chunks();
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|c| c.progs.len() <= CHUNK_PROGS));
+        assert_eq!(chunks[2].progs.len(), 1);
+        let blocks = chunks
+            .into_iter()
+            .flat_map(|c| c.blocks)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            blocks,
+            (0..CHUNK_COVER + 1).map(Block::from).collect::<Vec<_>>()
+        );
+
+        let chunks = Findings::default().chunks();
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].progs.is_empty());
+    }
+}
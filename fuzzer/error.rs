@@ -0,0 +1,41 @@
+//! Error of fuzzer, propagated up to caller of `fuzz`, which decides what to do with it,
+//! e.g. exiting with its code.
+
+use std::fmt;
+
+/// Error carrying one of `exitcode`, telling what went wrong.
+#[derive(Debug)]
+pub struct Error {
+    pub code: exitcode::ExitCode,
+    pub msg: String,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn new(code: exitcode::ExitCode, msg: String) -> Self {
+        Self { code, msg }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Build error of code from format arguments.
+macro_rules! err {
+    ( $code:expr, $fmt:expr $( , $arg:expr )* $(,)? ) => {
+        $crate::error::Error::new($code, format!($fmt $( , $arg )*))
+    };
+}
+
+/// Return error of code from current function.
+macro_rules! bail {
+    ( $code:expr, $fmt:expr $( , $arg:expr )* $(,)? ) => {
+        return Err(err!($code, $fmt $( , $arg )*))
+    };
+}
@@ -1,60 +1,239 @@
+use crate::error::Result;
 use crate::guest;
-use crate::guest::{Crash, Guest};
+use crate::guest::{Crash, CrashSource, Guest, GuestPool, HealthProbe, KernelInfo};
+use crate::hook::Hooks;
 use crate::utils::cli::{App, Arg, OptVal};
-use crate::utils::free_ipv4_port;
+use crate::utils::registry::{reserve_ports, Ports};
+use crate::utils::{vm_log, workdir};
 use crate::Config;
+use chrono::{DateTime, Local};
 use core::c::to_prog;
 use core::prog::Prog;
 use core::target::Target;
-use executor::transfer::{async_recv_result, async_send};
-use executor::{ExecResult, Reason};
+use executor::transfer::{async_recv, async_recv_response, async_send_as, Format, Handshake};
+use executor::{
+    CallInfo, ExecOpt, ExecReq, ExecResult, Feature, FindingKind, Reason, Request, Response,
+    Sandbox, Timeouts, PROG_MARK,
+};
+use std::collections::VecDeque;
 use std::env::temp_dir;
 use std::path::PathBuf;
-use std::process::exit;
-use tokio::fs::write;
-use tokio::io::AsyncReadExt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::fs::{read, write, File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::process::Child;
-use tokio::sync::oneshot;
-use tokio::time::{delay_for, timeout, Duration};
+use tokio::sync::Mutex;
+use tokio::time::{delay_for, timeout, Duration, Instant};
 
 // config for executor
 #[derive(Debug, Clone, Deserialize)]
 pub struct ExecutorConf {
     pub path: PathBuf,
     pub host_ip: Option<String>,
+    /// Also execute progs concurrently in background, default of each execution
     pub concurrency: bool,
+    /// Scan kmemleak after each execution, default of each execution
     pub memleak_check: bool,
     pub script_mode: bool,
+    /// Timeout of executing whole prog, in milliseconds
+    pub prog_timeout: Option<u64>,
+    /// Timeout of executing a single call, in milliseconds
+    pub call_timeout: Option<u64>,
+    /// Fork a fresh executor child for each prog, surviving its failures without reconnecting
+    #[serde(default)]
+    pub fork_server: bool,
+    /// Restore guest from snapshot taken after boot every n progs
+    pub snapshot_interval: Option<usize>,
+    /// Restore guest from snapshot instead of rebooting it after crash, same as "snapshot"
+    /// recovery
+    #[serde(default)]
+    pub fast_reboot: bool,
+    /// How guest is recovered after crash: "reboot", "snapshot" or "overlay"
+    pub recovery: Option<Recovery>,
+    /// Compress results sent back by executor, if executor supports it
+    #[serde(default)]
+    pub compress: bool,
+    /// Wire format of messages exchanged with executor: "bincode" or "postcard",
+    /// the one preferred by executor is used by default
+    pub format: Option<Format>,
+    /// Sandbox of executed progs: "none", "setuid" or "namespace"
+    #[serde(default)]
+    pub sandbox: Sandbox,
+    /// Environment features set up before executing each prog, e.g. ["tmpfs", "netdev"]
+    #[serde(default)]
+    pub features: Vec<Feature>,
+    /// Report fds and mounts left behind by each prog
+    #[serde(default)]
+    pub leak_check: bool,
+    /// Shared library built with -fsanitize-coverage=trace-pc-guard, fuzzed in-process
+    /// instead of kernel
+    pub harness: Option<PathBuf>,
+    /// Time waiting for executor to connect, in seconds
+    pub connect_timeout: Option<u64>,
+    /// Times of relaunching executor after failing to connect
+    pub connect_retry: Option<u32>,
+    /// Action taken once all retries failed: "restart" guest or "abort" fuzzer
+    #[serde(default)]
+    pub connect_give_up: GiveUp,
+    /// Interval of probing guest in background, in seconds, 0 disables probing
+    pub health_check_interval: Option<u64>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GiveUp {
+    /// Reboot guest, then try again.
+    Restart,
+    /// Exit whole fuzzer.
+    Abort,
+}
+
+impl Default for GiveUp {
+    fn default() -> Self {
+        GiveUp::Restart
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Recovery {
+    /// Reboot guest, keeping its disk.
+    Reboot,
+    /// Restore snapshot taken after boot, rebooting only if that failed.
+    Snapshot,
+    /// Reboot qemu guest from a freshly created overlay, dropping changes to disk.
+    Overlay,
+}
+
+const DEFAULT_CONNECT_TIMEOUT: u64 = 32;
+const DEFAULT_CONNECT_RETRY: u32 = 3;
+/// Delay before first relaunching executor, doubled each time up to 64 times.
+const CONNECT_BACKOFF: Duration = Duration::from_millis(500);
+const DEFAULT_HEALTH_CHECK_INTERVAL: u64 = 30;
+/// Guest is considered unhealthy after failing this many probes in a row.
+const HEALTH_CHECK_FAILURES: usize = 3;
+
 impl ExecutorConf {
-    pub fn check(&self) {
+    pub fn check(&self) -> Result<()> {
         if !self.path.is_file() {
-            eprintln!(
+            bail!(
+                exitcode::CONFIG,
                 "Config Error: executor executable file {} is invalid",
                 self.path.display()
-            );
-            exit(exitcode::CONFIG)
+            )
         }
 
         if let Some(ip) = &self.host_ip {
             use std::net::ToSocketAddrs;
             let addr = format!("{}:8080", ip);
             if let Err(e) = addr.to_socket_addrs() {
-                eprintln!(
+                bail!(
+                    exitcode::CONFIG,
                     "Config Error: invalid host ip `{}`: {}",
                     self.host_ip.as_ref().unwrap(),
                     e
-                );
-                exit(exitcode::CONFIG)
+                )
             }
         }
+
+        if let Some(harness) = self.harness.as_ref() {
+            if !harness.is_file() {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: harness {} is invalid",
+                    harness.display()
+                )
+            }
+        }
+
+        if self.connect_timeout == Some(0) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: connect timeout should be positive"
+            )
+        }
+
+        if self.snapshot_interval == Some(0) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: snapshot interval should be positive"
+            )
+        }
+
+        if self.fast_reboot && self.recovery.map_or(false, |r| r != Recovery::Snapshot) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: fast_reboot conflicts with recovery other than snapshot"
+            )
+        }
+
+        let timeouts = self.timeouts();
+        if timeouts.prog == 0 || timeouts.call == 0 || timeouts.call > timeouts.prog {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: invalid timeouts: prog timeout {}ms, call timeout {}ms, \
+                 both should be positive and call timeout should not be longger than prog timeout",
+                timeouts.prog,
+                timeouts.call
+            )
+        }
+        Ok(())
+    }
+
+    /// Features of executor that rely on linux are not available on other os.
+    pub fn check_portable(&self, os: &str) -> Result<()> {
+        if self.memleak_check
+            || self.leak_check
+            || self.sandbox == Sandbox::Namespace
+            || !self.features.is_empty()
+        {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: memleak_check, leak_check, namespace sandbox and features \
+                 are only supported on linux, not {}",
+                os
+            )
+        }
+        Ok(())
+    }
+
+    /// Options of executions that do not ask for specific ones.
+    pub fn exec_opt(&self) -> ExecOpt {
+        ExecOpt {
+            threaded: self.concurrency,
+            memleak_check: self.memleak_check,
+            ..Default::default()
+        }
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        let default = Timeouts::default();
+        let default_call = if self.memleak_check {
+            3 * default.call
+        } else {
+            default.call
+        };
+        Timeouts {
+            prog: self.prog_timeout.unwrap_or(default.prog),
+            call: self.call_timeout.unwrap_or(default_call),
+        }
     }
 }
 
+/// Extra time waiting for executor to send back result, beyond prog timeout.
+const TRANSFER_SLACK: Duration = Duration::from_secs(5);
+/// Executor is considered dead if nothing, even heartbeat, arrives within this.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(8);
+/// Time waiting rest of crash report to reach console once its beginning is seen.
+const CONSOLE_SETTLE: Duration = Duration::from_secs(1);
+
 pub struct Executor {
+    id: usize,
     inner: ExecutorImpl,
+    /// Bumped each time executor completes an execution or starts, see `progress`
+    progress: Arc<AtomicUsize>,
 }
 
 enum ExecutorImpl {
@@ -63,74 +242,236 @@ enum ExecutorImpl {
 }
 
 impl Executor {
-    pub fn new(cfg: &Config) -> Self {
+    /// Create executor for the `id`th vm, restarts of unhealthy guest are counted
+    /// into `health_restarts`, guest that goes down is replaced by one from `pool` if any,
+    /// restarts of guest are reported to `hooks`.
+    pub fn new(
+        cfg: &Config,
+        id: usize,
+        health_restarts: Arc<AtomicUsize>,
+        pool: Option<GuestPool>,
+        hooks: Arc<Hooks>,
+    ) -> Result<Self> {
         let inner = if cfg.executor.script_mode {
-            ExecutorImpl::Scripy(ScriptExecutor::new(cfg))
+            ExecutorImpl::Scripy(ScriptExecutor::new(cfg, id)?)
         } else {
-            ExecutorImpl::Linux(LinuxExecutor::new(cfg))
+            ExecutorImpl::Linux(LinuxExecutor::new(cfg, id, health_restarts, pool, hooks)?)
+        };
+        Ok(Self {
+            id,
+            inner,
+            progress: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Id of vm executor runs on.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Counter of executions completed and starts of executor, which stays unchanged while
+    /// executor is stuck, e.g. its guest is wedged.
+    pub fn progress(&self) -> Arc<AtomicUsize> {
+        self.progress.clone()
+    }
+
+    fn made_progress(&self, n: usize) {
+        self.progress.fetch_add(n, Ordering::SeqCst);
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        match self.inner {
+            ExecutorImpl::Linux(ref mut e) => e.start().await?,
+            ExecutorImpl::Scripy(ref mut e) => e.start().await?,
+        }
+        self.made_progress(1);
+        Ok(())
+    }
+
+    /// State of executor and its guest, logged when executor is found stuck. Console output
+    /// of guest read meanwhile is saved to its console log.
+    pub async fn diagnose(&mut self) -> String {
+        match self.inner {
+            ExecutorImpl::Linux(ref mut e) => e.diagnose().await,
+            ExecutorImpl::Scripy(ref e) => format!("guest alive: {}", e.guest.is_alive().await),
+        }
+    }
+
+    /// Name of kernel guest of executor runs, if several kernels are configured.
+    pub fn kernel(&self) -> Option<&str> {
+        match self.inner {
+            ExecutorImpl::Linux(ref e) => e.guest.kernel(),
+            ExecutorImpl::Scripy(ref e) => e.guest.kernel(),
+        }
+    }
+
+    /// Kernel running in guest of executor.
+    pub async fn kernel_info(&self) -> Option<KernelInfo> {
+        match self.inner {
+            ExecutorImpl::Linux(ref e) => e.guest.kernel_info().await,
+            ExecutorImpl::Scripy(ref e) => e.guest.kernel_info().await,
+        }
+    }
+
+    pub async fn exec(
+        &mut self,
+        p: &Prog,
+        t: &Target,
+    ) -> Result<Result<ExecResult, Option<Crash>>> {
+        let ret = match self.inner {
+            ExecutorImpl::Linux(ref mut e) => e.exec(p).await?,
+            ExecutorImpl::Scripy(ref mut e) => e.exec(p, t).await?,
         };
-        Self { inner }
+        self.made_progress(1);
+        Ok(ret)
     }
 
-    pub async fn start(&mut self) {
+    /// Execute prog with given options instead of configured ones.
+    /// Script executor does not support options and returns normal result.
+    pub async fn exec_with(
+        &mut self,
+        p: &Prog,
+        t: &Target,
+        opt: ExecOpt,
+    ) -> Result<Result<ExecResult, Option<Crash>>> {
+        let ret = match self.inner {
+            ExecutorImpl::Linux(ref mut e) => e.exec_with(p, opt).await?,
+            ExecutorImpl::Scripy(ref mut e) => e.exec(p, t).await?,
+        };
+        self.made_progress(1);
+        Ok(ret)
+    }
+
+    /// Execute prog, collecting comparison operands instead of coverage.
+    pub async fn exec_cmps(
+        &mut self,
+        p: &Prog,
+        t: &Target,
+    ) -> Result<Result<ExecResult, Option<Crash>>> {
+        let opt = ExecOpt {
+            cmps: true,
+            ..self.opt()
+        };
+        self.exec_with(p, t, opt).await
+    }
+
+    /// Options used by executions that do not specify them.
+    pub fn opt(&self) -> ExecOpt {
         match self.inner {
-            ExecutorImpl::Linux(ref mut e) => e.start().await,
-            ExecutorImpl::Scripy(ref mut e) => e.start().await,
+            ExecutorImpl::Linux(ref e) => e.opt,
+            ExecutorImpl::Scripy(_) => ExecOpt::default(),
         }
     }
 
-    pub async fn exec(&mut self, p: &Prog, t: &Target) -> Result<ExecResult, Option<Crash>> {
+    /// Submit prog for executing without waiting for its result, so that next prog can be
+    /// in flight while result of previous one is being analyzed.
+    pub async fn submit(&mut self, p: &Prog, t: &Target) -> Result<()> {
         match self.inner {
-            ExecutorImpl::Linux(ref mut e) => e.exec(p).await,
-            ExecutorImpl::Scripy(ref mut e) => e.exec(p, t).await,
+            ExecutorImpl::Linux(ref mut e) => e.submit(p).await,
+            ExecutorImpl::Scripy(ref mut e) => {
+                let ret = e.exec(p, t).await?;
+                e.ready.push_back(ret);
+                Ok(())
+            }
+        }
+    }
+
+    /// Wait result of the earliest submitted prog. Return none if nothing was submitted or
+    /// the prog was dropped because executor was restarted before executing it.
+    pub async fn next_result(&mut self) -> Result<Option<Result<ExecResult, Option<Crash>>>> {
+        let ret = match self.inner {
+            ExecutorImpl::Linux(ref mut e) => e.next_result().await?,
+            ExecutorImpl::Scripy(ref mut e) => e.ready.pop_front(),
+        };
+        if ret.is_some() {
+            self.made_progress(1);
         }
+        Ok(ret)
+    }
+
+    /// Execute progs in one round-trip, return results in order.
+    /// Results may be fewer than progs, progs after a crash or connection loss are not executed.
+    pub async fn exec_batch(
+        &mut self,
+        ps: &[Prog],
+        t: &Target,
+    ) -> Result<Vec<Result<ExecResult, Option<Crash>>>> {
+        let rets = match self.inner {
+            ExecutorImpl::Linux(ref mut e) => e.exec_batch(ps).await?,
+            ExecutorImpl::Scripy(ref mut e) => {
+                let mut rets = Vec::with_capacity(ps.len());
+                for p in ps {
+                    let ret = e.exec(p, t).await?;
+                    let crashed = ret.is_err();
+                    rets.push(ret);
+                    if crashed {
+                        break;
+                    }
+                }
+                rets
+            }
+        };
+        self.made_progress(rets.len());
+        Ok(rets)
     }
 }
 
 struct ScriptExecutor {
     path_on_host: PathBuf,
     guest: Guest,
+    timeouts: Timeouts,
+    ready: VecDeque<Result<ExecResult, Option<Crash>>>,
 }
 
 impl ScriptExecutor {
-    pub fn new(cfg: &Config) -> Self {
-        let guest = Guest::new(cfg);
+    pub fn new(cfg: &Config, id: usize) -> Result<Self> {
+        let guest = Guest::new(cfg, id)?;
 
-        Self {
+        Ok(Self {
             path_on_host: cfg.executor.path.clone(),
             guest,
-        }
+            timeouts: cfg.executor.timeouts(),
+            ready: VecDeque::new(),
+        })
     }
 
-    pub async fn start(&mut self) {
-        self.guest.boot().await;
+    pub async fn start(&mut self) -> Result<()> {
+        self.guest.boot().await
     }
 
-    pub async fn exec(&mut self, p: &Prog, t: &Target) -> Result<ExecResult, Option<Crash>> {
+    pub async fn exec(
+        &mut self,
+        p: &Prog,
+        t: &Target,
+    ) -> Result<Result<ExecResult, Option<Crash>>> {
         let p_text = to_prog(p, t);
         let tmp = temp_dir().join("HEALER_test_case_v1-1-1.c");
         if let Err(e) = write(&tmp, &p_text).await {
-            eprintln!(
+            bail!(
+                exitcode::IOERR,
                 "Failed to write test case to tmp dir \"{}\": {}",
                 tmp.display(),
                 e
-            );
-            exit(1);
+            )
         }
 
-        let guest_case_file = self.guest.copy(&tmp).await;
+        let guest_case_file = self.guest.copy(&tmp).await?;
         let mut executor = App::new(self.path_on_host.to_str().unwrap());
         executor.arg(Arg::new_flag(guest_case_file.to_str().unwrap()));
 
-        let mut exec_handle = self.guest.run_cmd(&executor).await;
+        let mut exec_handle = self.guest.run_cmd(&executor).await?;
 
-        match timeout(Duration::new(15, 0), &mut exec_handle).await {
-            Err(_) => Ok(ExecResult::Failed(Reason("Time out".to_string()))),
+        let wait = Duration::from_millis(self.timeouts.prog) + TRANSFER_SLACK;
+        match timeout(wait, &mut exec_handle).await {
+            Err(_) => Ok(Ok(ExecResult::Hanged {
+                call: None,
+                info: Vec::new(),
+            })),
             Ok(_) => {
                 let mut stdout = exec_handle.stdout.take().unwrap();
                 let mut output = String::new();
                 stdout.read_to_string(&mut output).await.unwrap();
-                self.parse_exec_result(output).await
+                Ok(self.parse_exec_result(output).await)
             }
         }
     }
@@ -151,12 +492,12 @@ impl ScriptExecutor {
             } else if result_line.contains("failed") {
                 return Ok(ExecResult::Failed(Reason(out)));
             } else if result_line.contains("crashed") {
-                return Err(Some(Crash { inner: out }));
+                return Err(Some(Crash::new(out, CrashSource::Executor)));
             }
         }
 
         if !self.guest.is_alive().await {
-            Err(Some(Crash { inner: out }))
+            Err(Some(Crash::new(out, CrashSource::Down)))
         } else {
             Ok(ExecResult::Ok(Default::default()))
         }
@@ -164,198 +505,1020 @@ impl ScriptExecutor {
 }
 
 struct LinuxExecutor {
+    id: usize,
     guest: Guest,
-    port: u16,
+    /// Standby guests that replace current one when it goes down
+    pool: Option<GuestPool>,
+    /// Port that fuzzer listens on for executor
+    port: Ports,
     exec_handle: Option<Child>,
     conn: Option<TcpStream>,
-    concurrency: bool,
-    memleak_check: bool,
+    /// Setting agreed with executor on current connection
+    agreed: Handshake,
+    opt: ExecOpt,
+    fork_server: bool,
+    compress: bool,
+    format: Option<Format>,
+    sandbox: Sandbox,
+    features: Vec<Feature>,
+    leak_check: bool,
+    harness: Option<PathBuf>,
     executor_bin_path: PathBuf,
     target_path: PathBuf,
     host_ip: String,
+    timeouts: Timeouts,
+    /// Hash and guest path of executor binary copied into current guest
+    deployed: Option<(md5::Digest, PathBuf)>,
+    /// Restore guest from snapshot every n progs
+    snapshot_interval: Option<usize>,
+    /// Restore guest from snapshot instead of rebooting it
+    recovery: Recovery,
+    /// Snapshot of current boot has been saved, with executor binary deployed in it
+    snapshot_saved: bool,
+    snapshot_deployed: Option<(md5::Digest, PathBuf)>,
+    /// Number of progs sent since last restoring
+    exec_since_restore: usize,
+    /// Reboot guest after running this long or executing this many progs
+    reboot_duration: Option<Duration>,
+    reboot_exec: Option<usize>,
+    /// Time and number of progs since guest was booted or restored
+    booted_at: Instant,
+    exec_since_boot: usize,
+    /// Host time at which guest booted, mapping kernel timestamps of guest to host time
+    guest_boot: Option<DateTime<Local>>,
+
+    /// Host file that output of executor in guest is streamed to.
+    log_path: PathBuf,
+    /// Host file that console output of guest is saved to.
+    console_path: PathBuf,
+    os: String,
+    connect_timeout: Duration,
+    connect_retry: u32,
+    connect_give_up: GiveUp,
+    health_check_interval: Option<Duration>,
+    /// Monitor watching current boot of guest
+    health: Option<HealthMonitor>,
+    health_restarts: Arc<AtomicUsize>,
+    /// Hooks told of restarts, guest has been started once if `started`
+    hooks: Arc<Hooks>,
+    started: bool,
+
+    /// Number of submitted progs whose result is not received yet.
+    in_flight: usize,
+    /// Received results of submitted progs, none for progs dropped by restarting.
+    ready: VecDeque<Option<Result<ExecResult, Option<Crash>>>>,
+    /// Id of next request, and ids of sent requests whose result is not received yet
+    next_id: u64,
+    sent: VecDeque<u64>,
+    /// Id of prog last marked in console
+    mark: Option<u64>,
+    /// Crash in console that follows mark of a prog still in flight, with id of the prog
+    pending: Option<(u64, Crash)>,
 }
 
 impl LinuxExecutor {
-    pub fn new(cfg: &Config) -> Self {
-        let guest = Guest::new(cfg);
-        let port = free_ipv4_port()
-            .unwrap_or_else(|| exits!(exitcode::TEMPFAIL, "No Free port for executor driver"));
+    pub fn new(
+        cfg: &Config,
+        id: usize,
+        health_restarts: Arc<AtomicUsize>,
+        pool: Option<GuestPool>,
+        hooks: Arc<Hooks>,
+    ) -> Result<Self> {
+        let guest = Guest::new(cfg, id)?;
+        let port = reserve_ports(1)
+            .ok_or_else(|| err!(exitcode::TEMPFAIL, "No Free port for executor driver"))?;
         let host_ip = cfg
             .executor
             .host_ip
             .as_ref()
             .map(String::from)
-            .unwrap_or_else(|| String::from(guest::LINUX_QEMU_HOST_IP_ADDR));
+            .unwrap_or_else(|| String::from(guest.listen_addr()));
 
-        Self {
+        Ok(Self {
+            id,
             guest,
+            pool,
             port,
             exec_handle: None,
             conn: None,
+            agreed: Handshake::default(),
 
-            concurrency: cfg.executor.concurrency,
-            memleak_check: cfg.executor.memleak_check,
+            opt: cfg.executor.exec_opt(),
+            fork_server: cfg.executor.fork_server,
+            compress: cfg.executor.compress,
+            format: cfg.executor.format,
+            sandbox: cfg.executor.sandbox,
+            features: cfg.executor.features.clone(),
+            leak_check: cfg.executor.leak_check,
+            harness: cfg.executor.harness.clone(),
             executor_bin_path: cfg.executor.path.clone(),
-            target_path: PathBuf::from(&cfg.fots_bin),
+            target_path: cfg.target_path(),
             host_ip,
+            timeouts: cfg.executor.timeouts(),
+            deployed: None,
+            snapshot_interval: cfg.executor.snapshot_interval,
+            recovery: cfg.recovery(),
+            snapshot_saved: false,
+            snapshot_deployed: None,
+            exec_since_restore: 0,
+            reboot_duration: cfg
+                .auto_reboot_duration
+                .map(|m| Duration::from_secs(m * 60)),
+            reboot_exec: cfg.auto_reboot_exec,
+            booted_at: Instant::now(),
+            exec_since_boot: 0,
+            guest_boot: None,
+            log_path: workdir::path(format!("log/executor-{}.log", id)),
+            console_path: workdir::path(format!("log/console-{}.log", id)),
+            os: cfg.guest.os.clone(),
+            connect_timeout: Duration::from_secs(
+                cfg.executor
+                    .connect_timeout
+                    .unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            ),
+            connect_retry: cfg.executor.connect_retry.unwrap_or(DEFAULT_CONNECT_RETRY),
+            connect_give_up: cfg.executor.connect_give_up,
+            health_check_interval: match cfg
+                .executor
+                .health_check_interval
+                .unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL)
+            {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            health: None,
+            health_restarts,
+            hooks,
+            started: false,
+            in_flight: 0,
+            ready: VecDeque::new(),
+            next_id: 0,
+            sent: VecDeque::new(),
+            mark: None,
+            pending: None,
+        })
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        self.drop_in_flight();
+        self.restart().await
+    }
+
+    async fn restart(&mut self) -> Result<()> {
+        if self.started {
+            self.hooks.vm_restart(self.id);
+        }
+        self.started = true;
+        if self.recovery == Recovery::Snapshot && self.snapshot_saved {
+            self.exec_handle = None;
+            self.conn = None;
+            self.health = None;
+            if self.guest.restore_snapshot().await? {
+                self.restored();
+                self.sync_clock().await;
+                return self.start_executer().await;
+            }
+            warn!("Fail to restore snapshot, rebooting ...");
         }
+        self.reboot().await?;
+        self.start_executer().await
     }
 
-    pub async fn start(&mut self) {
+    async fn reboot(&mut self) -> Result<()> {
         // handle should be set to kill on drop
         self.exec_handle = None;
-        self.guest.boot().await;
+        self.conn = None;
+        self.health = None;
+        let standby = match self.pool.as_ref() {
+            Some(pool) => pool.take(self.guest.kernel()).await,
+            None => None,
+        };
+        match standby {
+            Some(guest) => {
+                info!("Switching to standby guest, rebooting old one in background ...");
+                let old = std::mem::replace(&mut self.guest, guest);
+                self.pool.as_ref().unwrap().recycle(old);
+                // drop console output standby guest left while waiting.
+                self.guest.clear().await;
+            }
+            None => self.guest.boot().await?,
+        }
+        // guest runs in snapshot mode, files copied before are gone
+        self.deployed = None;
+        self.snapshot_saved = false;
+        self.booted_at = Instant::now();
+        self.exec_since_boot = 0;
+        self.sync_clock().await;
+        Ok(())
+    }
 
-        self.start_executer().await
+    /// Save snapshot of freshly booted guest with executor deployed, so that restoring it
+    /// skips both booting and copying.
+    async fn save_snapshot(&mut self) {
+        if self.snapshot_saved
+            || (self.snapshot_interval.is_none() && self.recovery != Recovery::Snapshot)
+        {
+            return;
+        }
+        if self.guest.save_snapshot().await {
+            self.snapshot_saved = true;
+            self.snapshot_deployed = self.deployed.clone();
+        } else {
+            warn!("Fail to save snapshot, guest will not be restored");
+            self.snapshot_interval = None;
+            self.recovery = Recovery::Reboot;
+        }
+        self.exec_since_restore = 0;
     }
 
-    pub async fn start_executer(&mut self) {
-        use tokio::io::ErrorKind::*;
+    fn restored(&mut self) {
+        // files copied after snapshot are gone
+        self.deployed = self.snapshot_deployed.clone();
+        self.exec_since_restore = 0;
+        self.booted_at = Instant::now();
+        self.exec_since_boot = 0;
+    }
 
+    /// Restore guest from snapshot if enough progs have been executed since last restoring.
+    async fn maybe_restore(&mut self, n: usize) -> Result<()> {
+        if let Some(interval) = self.snapshot_interval {
+            if self.exec_since_restore >= interval && self.snapshot_saved {
+                self.drain().await?;
+                self.exec_handle = None;
+                self.conn = None;
+                self.health = None;
+                if self.guest.restore_snapshot().await? {
+                    self.restored();
+                    self.sync_clock().await;
+                    self.start_executer().await?;
+                } else {
+                    warn!("Fail to restore snapshot, rebooting ...");
+                    self.start().await?;
+                }
+            }
+        }
+        self.exec_since_restore += n;
+        Ok(())
+    }
+
+    /// Watch guest in background, replacing monitor of its previous boot.
+    fn watch_health(&mut self) {
+        self.health = match (self.health_check_interval, self.guest.health_probe()) {
+            (Some(interval), Some(probe)) => Some(HealthMonitor::spawn(probe, interval)),
+            _ => None,
+        };
+    }
+
+    /// Restart guest flagged by health monitor, instead of sending progs to it and
+    /// waiting them to time out.
+    async fn check_health(&mut self) -> Result<()> {
+        if !self.unhealthy() {
+            return Ok(());
+        }
+        // results of in flight progs may carry the crash that took guest down.
+        self.drain().await?;
+        if !self.unhealthy() || self.ready.iter().any(|r| matches!(r, Some(Err(_)))) {
+            return Ok(());
+        }
+        warn!("Guest stops responding, restarting ...");
+        let output = self.guest.read_console().await;
+        self.save_console(&output).await;
+        self.health_restarts.fetch_add(1, Ordering::SeqCst);
+        self.start().await
+    }
+
+    async fn diagnose(&mut self) -> String {
+        let output = self.guest.read_console().await;
+        self.save_console(&output).await;
+        format!(
+            "guest alive: {}, executor running: {}, progs in flight: {}, up {}s, {} progs since boot\nTail of console:\n{}",
+            self.guest.is_alive().await,
+            self.exec_handle.is_some(),
+            self.in_flight,
+            self.booted_at.elapsed().as_secs(),
+            self.exec_since_boot,
+            guest::console_tail(&String::from_utf8_lossy(&output))
+        )
+    }
+
+    /// Reboot guest that has run long enough, shedding state accumulated in its kernel.
+    /// Guest is restored from snapshot instead if fast reboot is enabled.
+    async fn maybe_recycle(&mut self, n: usize) -> Result<()> {
+        let expired = self
+            .reboot_duration
+            .map_or(false, |d| self.booted_at.elapsed() >= d)
+            || self
+                .reboot_exec
+                .map_or(false, |e| self.exec_since_boot >= e);
+        if expired {
+            self.drain().await?;
+            // guest with crash pending is restarted by fuzzer anyway.
+            if !self.ready.iter().any(|r| matches!(r, Some(Err(_)))) {
+                info!(
+                    "Recycling guest after {}s and {} progs ...",
+                    self.booted_at.elapsed().as_secs(),
+                    self.exec_since_boot
+                );
+                self.start().await?;
+            }
+        }
+        self.exec_since_boot += n;
+        Ok(())
+    }
+
+    fn unhealthy(&self) -> bool {
+        self.health.as_ref().map_or(false, |h| h.unhealthy())
+    }
+
+    /// Copy executor binary to guest if it is not copied yet or has been rebuilt on host,
+    /// return its path in guest.
+    async fn deploy_executor(&mut self) -> Result<PathBuf> {
+        let bin = read(&self.executor_bin_path).await.map_err(|e| {
+            err!(
+                exitcode::IOERR,
+                "Fail to read executor {}: {}",
+                self.executor_bin_path.display(),
+                e
+            )
+        })?;
+        let digest = md5::compute(&bin);
+
+        match self.deployed {
+            Some((deployed, ref path)) if deployed == digest => return Ok(path.clone()),
+            Some(_) => info!("Executor binary changed, redeploying ..."),
+            None => (),
+        }
+        let path = self.guest.copy(&self.executor_bin_path).await?;
+        self.deployed = Some((digest, path.clone()));
+        Ok(path)
+    }
+
+    pub async fn start_executer(&mut self) -> Result<()> {
+        let mut retry = 0;
+        while !self.launch_executer().await? {
+            if retry < self.connect_retry {
+                let backoff = CONNECT_BACKOFF * 2u32.pow(retry.min(6));
+                retry += 1;
+                warn!(
+                    "Fail to connect executor, relaunching in {}ms ({}/{}) ...",
+                    backoff.as_millis(),
+                    retry,
+                    self.connect_retry
+                );
+                delay_for(backoff).await;
+                continue;
+            }
+
+            match self.connect_give_up {
+                GiveUp::Abort => bail!(
+                    exitcode::UNAVAILABLE,
+                    "Fail to connect executor after {} retries",
+                    retry
+                ),
+                GiveUp::Restart => {
+                    warn!("Fail to connect executor, restarting guest ...");
+                    self.reboot().await?;
+                    retry = 0;
+                }
+            }
+        }
+        self.handshake().await?;
+        self.watch_health();
+        Ok(())
+    }
+
+    /// Run executor in guest and wait for its connection, return false if it did not
+    /// connect in time.
+    async fn launch_executer(&mut self) -> Result<bool> {
         self.exec_handle = None;
-        let target = self.guest.copy(&self.target_path).await;
+        self.conn = None;
+        let target = self.guest.copy(&self.target_path).await?;
+        let executor_bin = self.deploy_executor().await?;
+        self.save_snapshot().await;
+        // executor talks through serial port if guest provides one, so that it does not
+        // rely on network of guest.
+        let serial = self.guest.serial_addr();
+        let listener = if serial.is_none() {
+            let listener = self.listen().await?;
+            self.guest.forward(self.port.get(0)).await?;
+            Some(listener)
+        } else {
+            None
+        };
+
+        let mut executor = App::new(executor_bin.to_str().unwrap());
+        executor.arg(Arg::new_opt("-t", OptVal::normal(target.to_str().unwrap())));
+        if serial.is_some() {
+            executor.arg(Arg::new_opt(
+                "--serial",
+                OptVal::normal(guest::VIRTIO_SERIAL_PATH),
+            ));
+        } else {
+            executor.arg(Arg::new_opt(
+                "-a",
+                OptVal::normal(&format!("{}:{}", self.guest.host_addr(), self.port.get(0))),
+            ));
+        }
+        if self.fork_server {
+            executor.arg(Arg::new_flag("-f"));
+        }
+        if self.leak_check {
+            executor.arg(Arg::new_flag("-l"));
+        }
+        if let Some(harness) = self.harness.as_ref() {
+            let harness = self.guest.copy(harness).await?;
+            executor.arg(Arg::new_opt(
+                "--harness",
+                OptVal::normal(harness.to_str().unwrap()),
+            ));
+        }
+        executor.arg(Arg::new_opt(
+            "-s",
+            OptVal::normal(&self.sandbox.to_string()),
+        ));
+        if !self.features.is_empty() {
+            let features = self
+                .features
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            executor.arg(Arg::new_opt("-e", OptVal::normal(&features)));
+        }
+
+        let mut handle = self.guest.run_guest_cmd(&executor).await?;
+        self.stream_output(&mut handle).await?;
+        self.exec_handle = Some(handle);
+        let connected = match serial {
+            Some(addr) => self.connect_serial(&addr).await,
+            None => self.accept(listener.unwrap()).await,
+        };
+        if !connected {
+            self.exec_handle = None;
+        }
+        Ok(connected)
+    }
+
+    async fn listen(&mut self) -> Result<TcpListener> {
+        use tokio::io::ErrorKind::*;
 
-        let (tx, rx) = oneshot::channel();
         let mut retry = 0;
-        let mut listener;
         loop {
-            let host_addr = format!("{}:{}", self.host_ip, self.port);
-            listener = match TcpListener::bind(&host_addr).await {
-                Ok(l) => l,
+            let host_addr = format!("{}:{}", self.host_ip, self.port.get(0));
+            match TcpListener::bind(&host_addr).await {
+                Ok(l) => return Ok(l),
                 Err(e) => {
                     if e.kind() == AddrInUse && retry != 5 {
-                        self.port = free_ipv4_port().unwrap();
+                        self.port = reserve_ports(1).unwrap();
                         retry += 1;
-                        continue;
                     } else {
-                        eprintln!("Fail to listen on {}: {}", host_addr, e);
-                        exit(1);
+                        bail!(
+                            exitcode::UNAVAILABLE,
+                            "Fail to listen on {}: {}",
+                            host_addr,
+                            e
+                        )
                     }
                 }
-            };
-            break;
+            }
         }
+    }
+
+    async fn accept(&mut self, mut listener: TcpListener) -> bool {
         let host_addr = listener.local_addr().unwrap();
+        // connection is queued by listener even if it comes before accepting.
+        match timeout(self.connect_timeout, listener.accept()).await {
+            Ok(Ok((conn, _addr))) => {
+                self.conn = Some(conn);
+                true
+            }
+            Ok(Err(e)) => {
+                warn!("Executor driver: fail to get client: {}", e);
+                false
+            }
+            Err(_) => {
+                warn!("Time out: wait executor connection {}", host_addr);
+                false
+            }
+        }
+    }
 
-        tokio::spawn(async move {
-            match listener.accept().await {
-                Ok((conn, _addr)) => {
-                    tx.send(conn).unwrap();
-                }
-                Err(e) => {
-                    eprintln!("Executor driver: fail to get client: {}", e);
-                    exit(exitcode::OSERR);
-                }
+    /// Connect to socket backing serial port of guest, executor is considered connected
+    /// once its first message arrives.
+    async fn connect_serial(&mut self, addr: &str) -> bool {
+        let mut conn = match TcpStream::connect(addr).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("Executor driver: fail to connect serial {}: {}", addr, e);
+                return false;
             }
-        });
+        };
+        let mut buf = [0; 1];
+        match timeout(self.connect_timeout, conn.peek(&mut buf)).await {
+            Ok(Ok(n)) if n != 0 => {
+                self.conn = Some(conn);
+                true
+            }
+            Ok(Ok(_)) => {
+                warn!("Executor driver: serial {} closed", addr);
+                false
+            }
+            Ok(Err(e)) => {
+                warn!("Executor driver: fail to read serial {}: {}", addr, e);
+                false
+            }
+            Err(_) => {
+                warn!("Time out: wait executor on serial {}", addr);
+                false
+            }
+        }
+    }
 
-        let mut executor = App::new(self.executor_bin_path.to_str().unwrap());
-        executor
-            .arg(Arg::new_opt("-t", OptVal::normal(target.to_str().unwrap())))
-            .arg(Arg::new_opt(
-                "-a",
-                OptVal::normal(&format!(
-                    "{}:{}",
-                    guest::LINUX_QEMU_USER_NET_HOST_IP_ADDR,
-                    self.port
-                )),
-            ));
-        if self.memleak_check {
-            executor.arg(Arg::new_flag("-m"));
+    /// Save console output of guest and look for crash reports in it, since kernel may
+    /// report a bug without killing guest or executor. Crash comes with id of prog
+    /// that was executing when report began, if its mark is known.
+    async fn scan_console(&mut self) -> Option<(Crash, Option<u64>)> {
+        let mut output = self.guest.read_console().await;
+        if output.is_empty() {
+            return None;
         }
-        if self.concurrency {
-            executor.arg(Arg::new_flag("-c"));
+        self.save_console(&output).await;
+        let text = String::from_utf8_lossy(&output).into_owned();
+        if guest::find_crash(&text, &self.os).is_none() {
+            self.mark = last_mark(&text, self.mark);
+            return None;
         }
 
-        self.exec_handle = Some(self.guest.run_cmd(&executor).await);
-        self.conn = match timeout(Duration::new(32, 0), rx).await {
-            Err(_) => {
-                self.exec_handle = None;
-                eprintln!("Time out: wait executor connection {}", host_addr);
-                exit(1)
-            }
-            Ok(conn) => Some(conn.unwrap()),
+        delay_for(CONSOLE_SETTLE).await;
+        let rest = self.guest.read_console().await;
+        self.save_console(&rest).await;
+        output.extend(rest);
+        let text = String::from_utf8_lossy(&output);
+        let crash = guest::find_crash(&text, &self.os)?;
+        let at = guest::report_offset(&text, &self.os).unwrap_or(0);
+        let culprit = last_mark(&text[..at], self.mark);
+        self.mark = last_mark(&text, self.mark);
+        Some((crash, culprit))
+    }
+
+    /// Sync guest clock with host after boot or restore, remembering when guest booted.
+    async fn sync_clock(&mut self) {
+        self.guest_boot = self.guest.sync_clock().await;
+        if self.guest_boot.is_none() {
+            debug!("Fail to read uptime of guest, timestamps are left as is");
+        }
+    }
+
+    /// Rewrite kernel timestamps in console output and kernel log of crash to host time.
+    fn normalize(&self, mut crash: Crash) -> Crash {
+        if let Some(boot) = self.guest_boot {
+            crash.console = guest::normalize_timestamps(&crash.console, boot);
+            crash.dmesg = crash
+                .dmesg
+                .map(|log| guest::normalize_timestamps(&log, boot));
+        }
+        crash
+    }
+
+    /// Guest survived crash, keep its kernel log with report, both in host time.
+    async fn with_dmesg(&self, mut crash: Crash) -> Crash {
+        crash.dmesg = self.guest.dmesg().await;
+        self.normalize(crash)
+    }
+
+    async fn save_console(&self, output: &[u8]) {
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.console_path)
+            .await;
+        let ret = match log {
+            Ok(mut log) => log.write_all(output).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = ret {
+            warn!(
+                "Fail to save console output to {}: {}",
+                self.console_path.display(),
+                e
+            );
+        }
+    }
+
+    /// Copy output of executor into log file continuously, until executor exits.
+    async fn stream_output(&self, handle: &mut Child) -> Result<()> {
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_path)
+            .await
+            .map_err(|e| {
+                err!(
+                    exitcode::IOERR,
+                    "Fail to open executor log {}: {}",
+                    self.log_path.display(),
+                    e
+                )
+            })?;
+        let log = Arc::new(Mutex::new(log));
+        let stdout = handle.stdout.take().unwrap();
+        let stderr = handle.stderr.take().unwrap();
+        vm_log::spawn(stream_lines(stdout, "stdout", log.clone()));
+        vm_log::spawn(stream_lines(stderr, "stderr", log));
+        Ok(())
+    }
+
+    /// Agree on compression and wire format with executor, which sends its capability first.
+    /// Wire format preferred by executor is used if it is not configured.
+    async fn handshake(&mut self) -> Result<()> {
+        let conn = self.conn.as_mut().unwrap();
+        let agreed = match timeout(Duration::new(8, 0), async_recv::<Handshake, _>(conn)).await {
+            Ok(Ok(peer)) => Handshake {
+                compress: self.compress && peer.compress,
+                format: self.format.unwrap_or(peer.format),
+            },
+            Ok(Err(e)) => bail!(exitcode::PROTOCOL, "Fail to recv handshake: {}", e),
+            Err(_) => bail!(exitcode::PROTOCOL, "Time out: wait executor handshake"),
         };
+        // reply is decoded by executor according to its flags, so it can be sent in agreed format.
+        if let Err(e) = async_send_as(&agreed, conn, &agreed).await {
+            bail!(exitcode::PROTOCOL, "Fail to send handshake: {}", e)
+        }
+        self.agreed = agreed;
+        // requests sent over previous connection are never answered.
+        self.sent.clear();
+        self.pending = None;
+        Ok(())
+    }
+
+    pub async fn exec(&mut self, p: &Prog) -> Result<Result<ExecResult, Option<Crash>>> {
+        self.exec_with(p, self.opt).await
+    }
+
+    pub async fn exec_with(
+        &mut self,
+        p: &Prog,
+        opt: ExecOpt,
+    ) -> Result<Result<ExecResult, Option<Crash>>> {
+        self.check_health().await?;
+        self.maybe_recycle(1).await?;
+        self.maybe_restore(1).await?;
+        self.drain().await?;
+        let req = Request::Exec(self.req_of(p, opt));
+        if let Some(ret) = self.send_req(&req).await? {
+            return Ok(ret);
+        }
+        let (ret, _) = self.recv_result().await?;
+        Ok(ret)
     }
 
-    pub async fn exec(&mut self, p: &Prog) -> Result<ExecResult, Option<Crash>> {
+    pub async fn exec_batch(
+        &mut self,
+        ps: &[Prog],
+    ) -> Result<Vec<Result<ExecResult, Option<Crash>>>> {
+        self.check_health().await?;
+        self.maybe_recycle(ps.len()).await?;
+        self.maybe_restore(ps.len()).await?;
+        self.drain().await?;
+        let opt = self.opt;
+        let req = Request::Batch(ps.iter().map(|p| self.req_of(p, opt)).collect());
+        if let Some(ret) = self.send_req(&req).await? {
+            return Ok(vec![ret]);
+        }
+
+        let mut rets = Vec::with_capacity(ps.len());
+        for _ in ps {
+            let (ret, reset) = self.recv_result().await?;
+            let stop = reset || ret.is_err();
+            rets.push(ret);
+            if stop {
+                break;
+            }
+        }
+        Ok(rets)
+    }
+
+    pub async fn submit(&mut self, p: &Prog) -> Result<()> {
+        self.check_health().await?;
+        self.maybe_recycle(1).await?;
+        self.maybe_restore(1).await?;
+        let req = Request::Exec(self.req_of(p, self.opt));
+        if let Some(ret) = self.send_req(&req).await? {
+            self.ready.push_back(Some(ret));
+        } else {
+            self.in_flight += 1;
+        }
+        Ok(())
+    }
+
+    pub async fn next_result(&mut self) -> Result<Option<Result<ExecResult, Option<Crash>>>> {
+        if self.ready.is_empty() && self.in_flight != 0 {
+            self.recv_in_flight().await?;
+        }
+        Ok(self.ready.pop_front().unwrap_or(None))
+    }
+
+    /// Receive results of all in flight progs, so that next result belongs to next request.
+    async fn drain(&mut self) -> Result<()> {
+        while self.in_flight != 0 {
+            self.recv_in_flight().await?;
+        }
+        Ok(())
+    }
+
+    async fn recv_in_flight(&mut self) -> Result<()> {
+        loop {
+            let (ret, reset) = self.recv_result().await?;
+            self.in_flight -= 1;
+            self.ready.push_back(Some(ret));
+            if reset {
+                self.drop_in_flight();
+            }
+            // crash of a later prog is received before anything else is sent to dead kernel.
+            if self.pending.is_none() || self.in_flight == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// In flight progs are lost once executor is restarted.
+    fn drop_in_flight(&mut self) {
+        for _ in 0..self.in_flight {
+            self.ready.push_back(None);
+        }
+        self.in_flight = 0;
+        self.sent.clear();
+        self.pending = None;
+    }
+
+    fn req_of(&mut self, p: &Prog, opt: ExecOpt) -> ExecReq {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sent.push_back(id);
+        ExecReq {
+            id,
+            p: p.clone(),
+            timeouts: self.timeouts,
+            opt,
+        }
+    }
+
+    /// Send request to executor, return result of the request if sending failed.
+    async fn send_req(
+        &mut self,
+        req: &Request,
+    ) -> Result<Option<Result<ExecResult, Option<Crash>>>> {
         // send must be success
         assert!(self.conn.is_some());
         if let Err(e) = timeout(
             Duration::new(15, 0),
-            async_send(p, self.conn.as_mut().unwrap()),
+            async_send_as(req, self.conn.as_mut().unwrap(), &self.agreed),
         )
         .await
         {
             info!("Prog send blocked: {}, restarting...", e);
-            self.start().await;
-            return Ok(ExecResult::Failed(Reason("Prog send blocked".into())));
+            self.start().await?;
+            return Ok(Some(Ok(ExecResult::Failed(Reason(
+                "Prog send blocked".into(),
+            )))));
         }
-        // async_send(p, self.conn.as_mut().unwrap()).await.unwrap();
-        let ret = {
+        Ok(None)
+    }
+
+    /// Receive result of one prog, the flag tells whether connection was reset meanwhile.
+    /// Executor keeps sending heartbeats while executing, so a prog that never returns
+    /// while heartbeats keep coming is a hang of prog, not a crash of guest.
+    async fn recv_result(&mut self) -> Result<(Result<ExecResult, Option<Crash>>, bool)> {
+        let id = self.sent.pop_front();
+        if let Some((culprit, crash)) = self.pending.take() {
+            if Some(culprit) == id {
+                // kernel is already broken, result of prog is of no use.
+                return Ok((Err(Some(crash)), true));
+            }
+            self.pending = Some((culprit, crash));
+        }
+        let deadline = Instant::now() + Duration::from_millis(self.timeouts.prog) + TRANSFER_SLACK;
+        // calls finished so far, none if executor does not trace calls.
+        let mut partial: Option<Vec<CallInfo>> = None;
+        let ret = loop {
             match timeout(
-                Duration::new(15, 0),
-                async_recv_result(self.conn.as_mut().unwrap()),
+                HEARTBEAT_TIMEOUT,
+                async_recv_response(self.conn.as_mut().unwrap()),
             )
             .await
             {
                 Err(e) => {
+                    // executor stopped answering, most likely kernel is stuck or down.
+                    if let Some((crash, _)) = self.scan_console().await {
+                        return Ok((Err(Some(self.normalize(crash))), true));
+                    }
+                    if let Some(ret) = self.check_down().await? {
+                        return Ok(ret);
+                    }
                     info!("Prog recv blocked: {}, restarting...", e);
-                    self.start().await;
-                    return Ok(ExecResult::Failed(Reason("Prog send blocked".into())));
+                    self.restart().await?;
+                    return Ok((
+                        Ok(ExecResult::Failed(Reason("Prog recv blocked".into()))),
+                        true,
+                    ));
                 }
-                Ok(ret) => ret,
+                Ok(Ok(Response::Heartbeat(progress))) => {
+                    if let Some(progress) = progress {
+                        let calls = partial.get_or_insert_with(Vec::new);
+                        if progress.restarted {
+                            calls.clear();
+                        }
+                        calls.extend(progress.calls);
+                    }
+                    if Instant::now() >= deadline {
+                        info!("Prog hanged while executor is alive, restarting executor...");
+                        self.start_executer().await?;
+                        return Ok((
+                            Ok(ExecResult::Hanged {
+                                call: partial.as_ref().map(Vec::len),
+                                info: partial.unwrap_or_default(),
+                            }),
+                            true,
+                        ));
+                    }
+                }
+                Ok(Ok(Response::Result(result, findings))) => break Ok((result, findings)),
+                Ok(Err(e)) => break Err(e),
             }
         };
         match ret {
-            Ok(result) => {
-                self.guest.clear().await;
-                if let ExecResult::Failed(ref reason) = result {
-                    let rea = reason.to_string();
-                    if rea.contains("CRASH-MEMLEAK") {
-                        return Err(Some(Crash { inner: rea }));
+            Ok((result, findings)) => {
+                if let Some((crash, culprit)) = self.scan_console().await {
+                    let crash = self.with_dmesg(crash).await;
+                    match culprit {
+                        // executor got to later prog before crash, this one finished fine.
+                        Some(culprit) if self.sent.contains(&culprit) => {
+                            self.pending = Some((culprit, crash))
+                        }
+                        _ => return Ok((Err(Some(crash)), false)),
                     }
                 }
-                return Ok(result);
+                for f in findings.iter().filter(|f| f.kind == FindingKind::Leak) {
+                    warn!("Prog left resources behind:\n{}", f.report);
+                }
+                if findings.iter().all(|f| f.kind == FindingKind::Leak) {
+                    return Ok((Ok(result), false));
+                }
+                // kernel is still up, fuzzer decides whether findings are a crash at all.
+                let crash = Crash::found(&findings, result).unwrap();
+                return Ok((Err(Some(self.with_dmesg(crash).await)), false));
             }
             Err(_) => {
-                let mut crashed: bool;
-                let mut retry: u8 = 0;
-                loop {
-                    crashed = !self.guest.is_alive().await;
-                    if crashed || retry == 10 {
-                        break;
-                    } else {
-                        retry += 1;
-                        delay_for(Duration::from_millis(500)).await;
-                    }
-                }
-
-                if crashed {
-                    return Err(self.guest.try_collect_crash().await);
+                if let Some(ret) = self.check_down().await? {
+                    return Ok(ret);
                 } else {
-                    let mut handle = self.exec_handle.take().unwrap();
-                    let mut stdout = handle.stdout.take().unwrap();
-                    let mut stderr = handle.stderr.take().unwrap();
-                    handle.await.unwrap_or_else(|e| {
-                        exits!(exitcode::OSERR, "Fail to wait executor handle:{}", e)
-                    });
-
-                    let mut err = Vec::new();
-                    stderr.read_to_end(&mut err).await.unwrap();
-                    let mut out = Vec::new();
-                    stdout.read_to_end(&mut out).await.unwrap();
+                    let handle = self.exec_handle.take().unwrap();
+                    let status = handle
+                        .await
+                        .map_err(|e| err!(exitcode::OSERR, "Fail to wait executor handle:{}", e))?;
 
                     warn!(
-                        "Executor: Connection lost. STDOUT:{}. STDERR: {}",
-                        String::from_utf8(out).unwrap(),
-                        String::from_utf8(err).unwrap()
+                        "Executor: Connection lost, exited with {}, see {} for its output",
+                        status,
+                        self.log_path.display()
                     );
-                    self.start_executer().await;
+                    self.start_executer().await?;
                 }
             }
         }
         // Caused by internal err
-        Ok(ExecResult::Ok(Vec::new()))
+        Ok((Ok(ExecResult::Ok(Vec::new())), true))
+    }
+
+    /// Check whether guest went down, returning what became of prog if so.
+    async fn check_down(&mut self) -> Result<Option<(Result<ExecResult, Option<Crash>>, bool)>> {
+        let mut crashed: bool;
+        let mut retry: u8 = 0;
+        loop {
+            crashed = !self.guest.is_alive().await;
+            if crashed || retry == 10 {
+                break;
+            } else {
+                retry += 1;
+                delay_for(Duration::from_millis(500)).await;
+            }
+        }
+        if !crashed {
+            return Ok(None);
+        }
+
+        if let Some(why) = self.guest.host_failure() {
+            warn!("Guest went down on host side, {}, restarting...", why);
+            self.restart().await?;
+            return Ok(Some((Ok(ExecResult::Failed(Reason(why))), true)));
+        }
+        let crash = self.guest.try_collect_crash().await;
+        Ok(Some((Err(crash.map(|c| self.normalize(c))), true)))
+    }
+}
+
+/// Id of prog last marked in console output, `before` if no mark in it.
+fn last_mark(console: &str, before: Option<u64>) -> Option<u64> {
+    console
+        .rmatch_indices(PROG_MARK)
+        .find_map(|(i, _)| {
+            let id = &console[i + PROG_MARK.len()..];
+            let end = id.find(|c: char| !c.is_ascii_digit()).unwrap_or(id.len());
+            id[..end].parse().ok()
+        })
+        .or(before)
+}
+
+/// Task probing guest in background, it stops once monitor is dropped.
+struct HealthMonitor {
+    unhealthy: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+}
+
+impl HealthMonitor {
+    fn spawn(probe: HealthProbe, interval: Duration) -> Self {
+        let unhealthy = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (flag, stopped) = (unhealthy.clone(), stop.clone());
+        vm_log::spawn(async move {
+            let mut failures = 0;
+            loop {
+                delay_for(interval).await;
+                if stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+                if probe.check().await {
+                    failures = 0;
+                    continue;
+                }
+                failures += 1;
+                if failures == HEALTH_CHECK_FAILURES {
+                    flag.store(true, Ordering::SeqCst);
+                    return;
+                }
+            }
+        });
+        Self { unhealthy, stop }
+    }
+
+    fn unhealthy(&self) -> bool {
+        self.unhealthy.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for HealthMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+async fn stream_lines<R: AsyncRead + Unpin>(out: R, name: &str, log: Arc<Mutex<File>>) {
+    let mut lines = BufReader::new(out).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = format!(
+            "{} {}: {}\n",
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            name,
+            line
+        );
+        let mut log = log.lock().await;
+        if let Err(e) = log.write_all(line.as_bytes()).await {
+            warn!("Fail to write executor log: {}", e);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONSOLE: &str = "[   10.100000] healer: executing prog 7
+[   10.200000] healer: executing prog 8
+[   10.300000] BUG: KASAN: use-after-free in foo+0x10/0x20
+[   10.300001] Read of size 8 at addr ffff888000000000
+[   10.400000] healer: executing prog 9
+";
+
+    #[test]
+    fn crash_follows_last_mark_before_report() {
+        let at = guest::report_offset(CONSOLE, "linux").unwrap();
+        assert_eq!(last_mark(&CONSOLE[..at], None), Some(8));
+        assert_eq!(last_mark(CONSOLE, None), Some(9));
+    }
+
+    #[test]
+    fn mark_of_earlier_output_is_kept() {
+        let console = "[   11.000000] BUG: unable to handle page fault\n";
+        let at = guest::report_offset(console, "linux").unwrap();
+        assert_eq!(at, 0);
+        assert_eq!(last_mark(&console[..at], Some(9)), Some(9));
+        assert_eq!(last_mark(console, None), None);
+    }
+
+    #[test]
+    fn broken_mark_is_skipped() {
+        let console = "healer: executing prog 3\nhealer: executing prog \n";
+        assert_eq!(last_mark(console, Some(1)), Some(3));
     }
 }
@@ -1,17 +1,25 @@
+use itertools::Itertools;
 use std::collections::HashSet;
 use std::iter::Extend;
 use tokio::sync::Mutex;
 
-#[derive(Clone, Debug, Default, Hash, PartialOrd, PartialEq, Ord, Eq)]
+#[derive(Clone, Debug, Default, Hash, PartialOrd, PartialEq, Ord, Eq, Serialize, Deserialize)]
 pub struct Block(usize);
 
+impl Block {
+    /// Address of the block.
+    pub fn addr(&self) -> usize {
+        self.0
+    }
+}
+
 impl From<usize> for Block {
     fn from(raw: usize) -> Self {
         Self(raw)
     }
 }
 
-#[derive(Clone, Debug, Default, Hash, PartialOrd, PartialEq, Ord, Eq)]
+#[derive(Clone, Debug, Default, Hash, PartialOrd, PartialEq, Ord, Eq, Serialize, Deserialize)]
 pub struct Branch(usize);
 
 impl From<(Block, Block)> for Branch {
@@ -28,6 +36,47 @@ impl From<(Block, Block)> for Branch {
     }
 }
 
+/// Blocks and branches covered by one prog.
+#[derive(Debug, Clone, Default)]
+pub struct Cover {
+    pub blocks: HashSet<Block>,
+    pub branches: HashSet<Branch>,
+}
+
+impl Cover {
+    /// Add blocks of kcov trace of one call and branches between them.
+    pub fn extend_raw(&mut self, raw: &[usize]) {
+        let blocks = raw.iter().map(|b| Block::from(*b)).collect::<Vec<_>>();
+        self.branches.extend(
+            blocks
+                .iter()
+                .cloned()
+                .tuple_windows()
+                .map(|(b1, b2)| Branch::from((b1, b2))),
+        );
+        self.blocks.extend(blocks);
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len() + self.branches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty() && self.branches.is_empty()
+    }
+
+    /// Number of blocks and branches of self not in `other`.
+    pub fn diff_len(&self, other: &Cover) -> usize {
+        self.blocks.difference(&other.blocks).count()
+            + self.branches.difference(&other.branches).count()
+    }
+
+    pub fn merge(&mut self, other: &Cover) {
+        self.blocks.extend(other.blocks.iter().cloned());
+        self.branches.extend(other.branches.iter().cloned());
+    }
+}
+
 #[derive(Default)]
 pub struct FeedBack {
     branches: Mutex<HashSet<Branch>>,
@@ -86,6 +135,13 @@ impl FeedBack {
         block_empty || branch_empty
     }
 
+    /// Blocks and branches covered so far.
+    pub async fn dump(&self) -> (Vec<Block>, Vec<Branch>) {
+        let blocks = self.blocks.lock().await.iter().cloned().collect();
+        let branches = self.branches.lock().await.iter().cloned().collect();
+        (blocks, branches)
+    }
+
     pub async fn len(&self) -> (usize, usize) {
         tokio::join!(
             async {
@@ -1,60 +1,221 @@
-use crate::corpus::Corpus;
+use crate::breaker::Breaker;
+use crate::corpus::{parse_dump, prog_hash, Corpus, Meta};
+use crate::dist::Outbox;
+use crate::error::Result;
 use crate::exec::Executor;
-use crate::feedback::{Block, Branch, FeedBack};
-use crate::guest::Crash;
-use crate::report::TestCaseRecord;
-use crate::stats::StatSource;
-use crate::utils::queue::CQueue;
-use crate::Config;
+use crate::feedback::{Block, Branch, Cover, FeedBack};
+use crate::guest::{Crash, CrashKind};
+use crate::hook::Hooks;
+use crate::plateau::{Plateau, Strategy, MUTATE_BOOST};
+use crate::report::{RecordState, TestCaseRecord};
+use crate::schedule::{Kind, Schedule};
+use crate::shard::{Shard, Shards};
+use crate::stats::{CallTimes, StatSource};
+use crate::throughput::Throughput;
+use crate::triage::Triage;
+use crate::utils::{event, workdir};
+use crate::{is_known, Bundle, Config};
 use core::analyze::prog_analyze;
 use core::analyze::static_analyze;
-use core::analyze::RTable;
+use core::analyze::Relation;
 use core::c::to_prog;
-use core::gen::gen;
+use core::gen::gen_prog;
 use core::minimize::remove;
-use core::mutate::mutate;
+use core::mutate::mutate_prog;
 use core::prog::Prog;
+use core::rng::{random, thread_rng};
 use core::target::Target;
-use executor::{ExecResult, Reason};
-use fots::types::GroupId;
+use executor::{CallInfo, ExecOpt, ExecResult, Reason};
+use fots::types::{FnId, GroupId};
 use itertools::Itertools;
+use log::Level;
 use regex::Regex;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashSet, VecDeque};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::fs::write;
+use tokio::fs::read;
 use tokio::sync::broadcast;
 use tokio::sync::Mutex;
+use tokio::time::{delay_for, Duration, Instant};
+
+/// Max number of candidates executed in one round-trip.
+const CANDIDATE_BATCH: usize = 16;
+/// Number of progs submitted to executor before result of the first one is analyzed.
+const PIPELINE_DEPTH: usize = 2;
+/// Chance of dropping generated prog that contains slow calls.
+const SLOW_PROG_DROP: f64 = 0.75;
+/// Times of executing crashed prog when reproducing it, some crashes are flaky.
+pub(crate) const REPRO_REPEAT: u32 = 3;
+/// Max tries of generating prog without disabled calls.
+const GEN_RETRY: usize = 1024;
+/// How often watchdog checks progress of each vm.
+const STALL_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Interval between samples of throughput of each vm.
+const THROUGHPUT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Knobs of prog generation, unset ones keep their defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GenConf {
+    pub prog_max_len: Option<usize>,
+    pub prog_min_len: Option<usize>,
+    pub str_min_len: Option<usize>,
+    pub str_max_len: Option<usize>,
+    pub path_max_depth: Option<usize>,
+    /// Chance of generating special values
+    pub sp_delta: Option<f64>,
+    /// Generate out-of-spec values far more often, for exercising error paths
+    pub negative: Option<bool>,
+}
+
+impl GenConf {
+    pub fn check(&self) -> Result<()> {
+        let conf = self.to_conf();
+        if conf.prog_min_len == 0 || conf.prog_min_len > conf.prog_max_len {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: invalid gen conf: prog_min_len should be positive and not bigger than prog_max_len"
+            )
+        }
+        if conf.str_min_len > conf.str_max_len {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: invalid gen conf: str_min_len is bigger than str_max_len"
+            )
+        }
+        if !(0.0..=1.0).contains(&conf.sp_delta) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: invalid gen conf: sp_delta should be in [0, 1]"
+            )
+        }
+        Ok(())
+    }
+
+    pub fn to_conf(&self) -> core::gen::Config {
+        let default = core::gen::Config::default();
+        core::gen::Config {
+            prog_max_len: self.prog_max_len.unwrap_or(default.prog_max_len),
+            prog_min_len: self.prog_min_len.unwrap_or(default.prog_min_len),
+            str_min_len: self.str_min_len.unwrap_or(default.str_min_len),
+            str_max_len: self.str_max_len.unwrap_or(default.str_max_len),
+            path_max_depth: self.path_max_depth.unwrap_or(default.path_max_depth),
+            sp_delta: self.sp_delta.unwrap_or(default.sp_delta),
+            negative: self.negative.unwrap_or(default.negative),
+        }
+    }
+}
+
+/// State of fuzzer besides corpus, persisted so that campaign can be resumed.
+#[derive(Serialize, Deserialize, Default)]
+pub struct State {
+    pub blocks: Vec<Block>,
+    pub branches: Vec<Branch>,
+    /// Relations learned from executed progs, as ids of related calls in each group, so that
+    /// they survive calls filtered differently by resumed run
+    pub relations: Vec<(GroupId, Vec<(FnId, FnId)>)>,
+    pub crash_digests: Vec<[u8; 16]>,
+    pub exec: usize,
+    pub health_restarts: usize,
+    pub stalls: usize,
+    pub slow_recycles: usize,
+    pub near_duplicates: usize,
+    pub record: RecordState,
+}
+
+/// Why fuzzing with executor is interrupted to restart it.
+enum Interrupted {
+    /// No progress in time of watchdog
+    Stalled,
+    /// Execs per second far below median of vms, for period of throughput
+    Slow { rate: f64, median: f64 },
+}
 
 #[derive(Clone)]
 pub struct Fuzzer {
     pub target: Arc<Target>,
-    pub rt: Arc<Mutex<HashMap<GroupId, RTable>>>,
-    pub conf: core::gen::Config,
+    /// Groups split across vms, with relations and weights of each part
+    pub shards: Arc<Shards>,
+    /// Generation knobs, replaced when config is reloaded
+    pub conf: Arc<Mutex<core::gen::Config>>,
+    /// Calls not to be generated, replaced when config is reloaded
+    pub disabled_calls: Arc<Mutex<HashSet<FnId>>>,
     pub corpus: Arc<Corpus>,
     pub feedback: Arc<FeedBack>,
-    pub candidates: Arc<CQueue<Prog>>,
+    /// Seed corpus triaged by vms booted at start before generation begins
+    pub triage: Arc<Triage>,
     pub record: Arc<TestCaseRecord>,
     pub exec_cnt: Arc<AtomicUsize>,
+    pub call_times: Arc<CallTimes>,
+    /// Restarts of guests found unhealthy by health monitor
+    pub health_restarts: Arc<AtomicUsize>,
+    /// Vms restarted by watchdog because they stopped making progress
+    pub stalls: Arc<AtomicUsize>,
+    /// Time without progress before watchdog restarts vm, if enabled
+    pub watchdog: Option<Duration>,
+    /// Vms recycled because their throughput fell far below median
+    pub slow_recycles: Arc<AtomicUsize>,
+    /// Throughput of each vm, for recycling slow ones, if configured
+    pub throughput: Option<Arc<Throughput>>,
     pub crash_digests: Arc<Mutex<HashSet<md5::Digest>>>,
+    /// Findings to be synced with manager, if this process works for one
+    pub outbox: Option<Arc<Outbox>>,
+    /// Number of generated progs, shared by vms taking turns in deterministic run with
+    /// seed, so that they run same sequence of progs as one vm
+    pub serial: Option<Arc<Mutex<usize>>>,
+    /// Circuit breaker counting crashes and boot failures, if configured
+    pub breaker: Option<Arc<Breaker>>,
+    /// Schedule between generation, mutation and replay of corpus, if configured
+    pub schedule: Option<Arc<Schedule>>,
+    /// Detection of coverage plateau switching strategies, if configured
+    pub plateau: Option<Arc<Plateau>>,
+    /// Hooks of campaign events registered by embedder
+    pub hooks: Arc<Hooks>,
 
     pub suppressions: Vec<Regex>,
     pub ignores: Vec<Regex>,
 }
 
 impl Fuzzer {
-    pub fn new(target: Target, candidates: Vec<Prog>, cfg: &Config) -> Self {
+    pub fn new(target: Target, bundles: Vec<Bundle>, candidates: Vec<Prog>, cfg: &Config) -> Self {
         let target = Arc::new(target);
-        let record = Arc::new(TestCaseRecord::new(target.clone()));
+        let record = Arc::new(TestCaseRecord::new(target.clone(), bundles));
         let rt = static_analyze(&target);
+        let disabled_calls = disabled_calls(&target, cfg);
+        let shards = Shards::new(&target, rt, cfg.shards.unwrap_or(1), cfg);
         Self {
             target,
             record,
             crash_digests: Arc::new(Mutex::new(HashSet::new())),
+            outbox: None,
+            breaker: None,
+            hooks: Arc::new(Hooks::default()),
+            schedule: cfg
+                .schedule
+                .clone()
+                .map(|conf| Arc::new(Schedule::new(conf))),
+            plateau: cfg
+                .plateau
+                .as_ref()
+                .map(|conf| Arc::new(Plateau::new(conf))),
+            serial: cfg.seed.map(|_| Arc::new(Mutex::new(0))),
             exec_cnt: Arc::new(AtomicUsize::new(0)),
-            rt: Arc::new(Mutex::new(rt)),
-            conf: Default::default(),
-            candidates: Arc::new(CQueue::from(candidates)),
+            call_times: Arc::new(CallTimes::default()),
+            health_restarts: Arc::new(AtomicUsize::new(0)),
+            stalls: Arc::new(AtomicUsize::new(0)),
+            watchdog: cfg.watchdog(),
+            slow_recycles: Arc::new(AtomicUsize::new(0)),
+            throughput: cfg
+                .throughput
+                .as_ref()
+                .map(|conf| Arc::new(Throughput::new(conf))),
+            shards: Arc::new(shards),
+            conf: Arc::new(Mutex::new(cfg.gen.clone().unwrap_or_default().to_conf())),
+            disabled_calls: Arc::new(Mutex::new(disabled_calls)),
+            // deterministic run replays seeds in one fixed order.
+            triage: Arc::new(Triage::new(
+                candidates,
+                if cfg.seed.is_some() { 1 } else { cfg.vm_num },
+            )),
             corpus: Arc::new(Corpus::default()),
             feedback: Arc::new(FeedBack::default()),
 
@@ -75,99 +236,633 @@ impl Fuzzer {
         }
     }
 
+    /// Apply generation knobs, disabled calls and group weights of reloaded config.
+    pub async fn reload(&self, cfg: &Config) {
+        *self.conf.lock().await = cfg.gen.clone().unwrap_or_default().to_conf();
+        *self.disabled_calls.lock().await = disabled_calls(&self.target, cfg);
+        self.shards.reload(&self.target, cfg).await;
+    }
+
     pub fn stats(&self) -> StatSource {
         StatSource {
             exec: self.exec_cnt.clone(),
             corpus: self.corpus.clone(),
             feedback: self.feedback.clone(),
-            candidates: self.candidates.clone(),
+            candidates: self.triage.clone(),
             record: self.record.clone(),
+            call_times: self.call_times.clone(),
+            health_restarts: self.health_restarts.clone(),
+            stalls: self.stalls.clone(),
+            slow_recycles: self.slow_recycles.clone(),
         }
     }
-    pub async fn fuzz(self, executor: Executor, mut shutdown: broadcast::Receiver<()>) {
+    /// Fuzz with executor until shutdown, or until executor fails.
+    pub async fn fuzz(
+        self,
+        executor: Executor,
+        mut shutdown: broadcast::Receiver<()>,
+    ) -> Result<()> {
         tokio::select! {
-            _ = shutdown.recv() => (),
-            _ = self.do_fuzz(executor) => ()
+            _ = shutdown.recv() => Ok(()),
+            ret = self.fuzz_retrying(executor) => ret
         }
     }
 
-    async fn do_fuzz(&self, mut executor: Executor) {
-        let mut gen_cnt = 0;
+    /// Fuzz with executor, restarting it once it fails if breaker counts boot failures, once
+    /// it stops making progress if watchdog is enabled, or once it stays far slower than
+    /// other vms if throughput is configured.
+    async fn fuzz_retrying(&self, mut executor: Executor) -> Result<()> {
+        let breaker = self.breaker.as_ref().filter(|b| b.retries_boot());
         loop {
-            let p = self.get_prog(&mut gen_cnt).await;
-            match executor.exec(&p, &self.target).await {
-                Ok(exec_result) => match exec_result {
-                    ExecResult::Ok(raw_branches) => {
-                        self.feedback_analyze(p, raw_branches, &mut executor).await
+            let id = executor.id();
+            let progress = executor.progress();
+            let ret = tokio::select! {
+                ret = self.do_fuzz(&mut executor) => Ok(ret),
+                _ = self.stall(id, progress.clone()) => Err(Interrupted::Stalled),
+                (rate, median) = self.slow(id, progress) => Err(Interrupted::Slow { rate, median }),
+            };
+            let e = match ret {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(e)) => e,
+                Err(interrupted) => {
+                    match self.restart_interrupted(&mut executor, interrupted).await {
+                        Ok(()) => continue,
+                        Err(e) => e,
                     }
-                    ExecResult::Failed(reason) => self.failed_analyze(p, reason).await,
-                },
-                Err(crash) => {
-                    self.crash_analyze(p, crash.unwrap_or_default(), &mut executor)
-                        .await
                 }
             };
+            let breaker = match breaker {
+                Some(breaker) => breaker,
+                None => return Err(e),
+            };
+            breaker.boot_failed(&e).await;
+            loop {
+                breaker.wait().await;
+                match executor.start().await {
+                    Ok(()) => {
+                        breaker.booted();
+                        break;
+                    }
+                    Err(e) => breaker.boot_failed(&e).await,
+                }
+            }
+        }
+    }
+
+    /// Whether vm `id` is idle on purpose, i.e. fuzzing is paused by breaker or vm waits
+    /// others to finish triage.
+    async fn paused(&self, id: usize) -> bool {
+        let paused = match self.breaker.as_ref() {
+            Some(breaker) => breaker.paused().await,
+            None => false,
+        };
+        paused || self.triage.is_waiting(id)
+    }
+
+    /// Wait until `progress` of executor `id` stays unchanged for time of watchdog, time that
+    /// vm is paused is not counted. Never returns if watchdog is disabled.
+    async fn stall(&self, id: usize, progress: Arc<AtomicUsize>) {
+        let limit = match self.watchdog {
+            Some(limit) => limit,
+            None => return std::future::pending().await,
+        };
+        let mut last = progress.load(Ordering::SeqCst);
+        let mut since = Instant::now();
+        loop {
+            delay_for(STALL_CHECK_INTERVAL).await;
+            let now = progress.load(Ordering::SeqCst);
+            if now != last || self.paused(id).await {
+                last = now;
+                since = Instant::now();
+            } else if since.elapsed() >= limit {
+                return;
+            }
+        }
+    }
+
+    /// Wait until execs per second of executor `id` stays below ratio of median of all vms
+    /// for period of throughput, returning its rate and the median. Vm is left out of median
+    /// while it is paused. Never returns if throughput is not configured.
+    async fn slow(&self, id: usize, progress: Arc<AtomicUsize>) -> (f64, f64) {
+        let throughput = match self.throughput.as_ref() {
+            Some(throughput) => throughput,
+            None => return std::future::pending().await,
+        };
+        let tracked = throughput.track(id);
+        let mut last = progress.load(Ordering::SeqCst);
+        let mut since = None;
+        loop {
+            delay_for(THROUGHPUT_INTERVAL).await;
+            let now = progress.load(Ordering::SeqCst);
+            let rate = now.saturating_sub(last) as f64 / THROUGHPUT_INTERVAL.as_secs_f64();
+            last = now;
+            if self.paused(id).await {
+                tracked.pause();
+                since = None;
+                continue;
+            }
+            match tracked.report(rate) {
+                Some(median) if rate < median * throughput.ratio => {
+                    if since.get_or_insert_with(Instant::now).elapsed() >= throughput.period {
+                        return (rate, median);
+                    }
+                }
+                _ => since = None,
+            }
+        }
+    }
+
+    /// Log why executor is interrupted and restart it, progs in flight are dropped.
+    async fn restart_interrupted(
+        &self,
+        executor: &mut Executor,
+        interrupted: Interrupted,
+    ) -> Result<()> {
+        match interrupted {
+            Interrupted::Stalled => {
+                self.stalls.fetch_add(1, Ordering::SeqCst);
+                let minutes = self.watchdog.unwrap_or_default().as_secs() / 60;
+                let diagnosis = executor.diagnose().await;
+                event!(
+                    Level::Error,
+                    "vm_stall",
+                    { "vm": executor.id(), "minutes": minutes },
+                    "Vm {} made no progress in {} minutes, restarting ...\n{}",
+                    executor.id(),
+                    minutes,
+                    diagnosis
+                );
+            }
+            Interrupted::Slow { rate, median } => {
+                self.slow_recycles.fetch_add(1, Ordering::SeqCst);
+                let throughput = self.throughput.as_ref().unwrap();
+                let minutes = throughput.period.as_secs() / 60;
+                let rates = throughput
+                    .rates()
+                    .iter()
+                    .map(|(id, rate)| format!("{}: {:.1}", id, rate))
+                    .join(", ");
+                event!(
+                    Level::Warn,
+                    "vm_slow",
+                    { "vm": executor.id(), "rate": rate, "median": median, "minutes": minutes },
+                    "Vm {} ran {:.1} execs/s in last {} minutes, far below median {:.1} of vms({}), recycling ...",
+                    executor.id(),
+                    rate,
+                    minutes,
+                    median,
+                    rates
+                );
+            }
+        }
+        executor.start().await
+    }
+
+    async fn do_fuzz(&self, executor: &mut Executor) -> Result<()> {
+        self.triage(executor).await?;
+
+        let mut own_cnt = 0;
+        // each round of deterministic run is done by one vm, without pipelining.
+        let depth = if self.serial.is_some() {
+            1
+        } else {
+            PIPELINE_DEPTH
+        };
+        let mut in_flight = VecDeque::with_capacity(depth);
+        // progs dropped by restart of executor before they were executed
+        let mut dropped = VecDeque::new();
+        loop {
+            if let Some(breaker) = self.breaker.as_ref() {
+                breaker.wait().await;
+            }
+            let mut serial = match self.serial.as_ref() {
+                Some(serial) => Some(serial.lock().await),
+                None => None,
+            };
+            let gen_cnt = serial.as_deref_mut().unwrap_or(&mut own_cnt);
+            let shard = self.shards.of_vm(executor.id());
+
+            // keep next prog in flight while analyzing result of current one
+            while in_flight.len() < depth {
+                let (p, kind, seed) = match dropped.pop_front() {
+                    Some(dropped) => dropped,
+                    None => self.get_prog(gen_cnt, shard).await,
+                };
+                executor.submit(&p, &self.target).await?;
+                in_flight.push_back((p, kind, seed));
+            }
+
+            let (p, kind, seed) = in_flight.pop_front().unwrap();
+            match executor.next_result().await? {
+                Some(ret) => {
+                    let new_cover = self.analyze(p, ret, executor).await?;
+                    self.exec_cnt.fetch_add(1, Ordering::SeqCst);
+                    if let (true, Some(seed)) = (new_cover, seed) {
+                        self.corpus.productive(seed);
+                    }
+                    if let (Some(schedule), Some(kind)) = (self.schedule.as_ref(), kind) {
+                        schedule.record(kind, new_cover).await;
+                    }
+                }
+                // submitted again once executor is back, instead of being lost.
+                None => dropped.push_back((p, kind, seed)),
+            }
+            if let Some(plateau) = self.plateau.as_ref().filter(|p| p.due()) {
+                self.check_plateau(plateau).await;
+            }
+        }
+    }
+
+    /// Sample coverage, switching to next strategy if it plateaus.
+    async fn check_plateau(&self, plateau: &Plateau) {
+        let (_, branches) = self.feedback.len().await;
+        let strategy = match plateau.sample(branches) {
+            Some(strategy) => strategy,
+            None => return,
+        };
+        let minutes = plateau.window().as_secs() / 60;
+        event!(
+            Level::Warn,
+            "plateau",
+            { "strategy": strategy.to_string(), "minutes": minutes, "branches": branches },
+            "Coverage grew less than {}% in {} minutes, switching to strategy {}",
+            plateau.min_growth(),
+            minutes,
+            strategy
+        );
+        match strategy {
+            Strategy::Mutate => {
+                if let Some(schedule) = self.schedule.as_ref() {
+                    schedule.boost_mutate();
+                }
+            }
+            // applied to generation knobs of each prog, surviving config reload.
+            Strategy::Negative => (),
+            Strategy::Reshuffle => self.shards.reshuffle().await,
+        }
+    }
+
+    /// Execute seeds of triage with executor until none is left, then wait other vms to
+    /// finish theirs, so that generation starts from coverage of whole seed corpus.
+    async fn triage(&self, executor: &mut Executor) -> Result<()> {
+        loop {
+            if let Some(breaker) = self.breaker.as_ref() {
+                breaker.wait().await;
+            }
+            let _serial = match self.serial.as_ref() {
+                Some(serial) => Some(serial.lock().await),
+                None => None,
+            };
+            let (seeds, _taken) = self.triage.take(executor.id(), CANDIDATE_BATCH).await;
+            if seeds.is_empty() {
+                break;
+            }
+            self.replay(seeds, executor).await?;
+        }
+        if let Some((seeds, duplicates, cost)) = self.triage.wait(executor.id()).await {
+            if seeds + duplicates != 0 {
+                info!(
+                    "Triage finished: {} seeds ({} duplicates dropped), {} kept in corpus, cost {}s",
+                    seeds,
+                    duplicates,
+                    self.corpus.len().await,
+                    cost.as_secs()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute seeds in batch, seeds not executed are given back to triage.
+    async fn replay(&self, seeds: Vec<Prog>, executor: &mut Executor) -> Result<()> {
+        let rets = executor.exec_batch(&seeds, &self.target).await?;
+        let mut seeds = seeds.into_iter();
+        for (ret, p) in rets.into_iter().zip(&mut seeds) {
+            self.analyze(p, ret, executor).await?;
             self.exec_cnt.fetch_add(1, Ordering::SeqCst);
         }
+        self.triage.put_back(executor.id(), seeds.collect()).await;
+        Ok(())
     }
 
-    pub async fn persist(self) {
-        let corpus_path = "./corpus";
-        let corpus = self
-            .corpus
-            .dump()
-            .await
-            .unwrap_or_else(|e| exits!(exitcode::DATAERR, "Fail to dump corpus: {}", e));
-        write(&corpus_path, corpus).await.unwrap_or_else(|e| {
-            exits!(
-                exitcode::IOERR,
-                "Fail to persist corpus to {} : {}",
-                corpus_path,
+    async fn analyze(
+        &self,
+        p: Prog,
+        ret: Result<ExecResult, Option<Crash>>,
+        executor: &mut Executor,
+    ) -> Result<bool> {
+        let mut crash = match ret {
+            Ok(exec_result) => return self.result_analyze(p, exec_result, executor).await,
+            Err(crash) => crash.unwrap_or_default(),
+        };
+        match crash.result.take() {
+            // kernel stayed up, so coverage and calls of prog are as good as ever, and
+            // complaints only make a crash unless ignored or suppressed.
+            Some(exec_result) => {
+                let new = self
+                    .result_analyze(p.clone(), exec_result, executor)
+                    .await?;
+                if !self.should_ignore(&crash.inner)
+                    && !self.suppressions.iter().any(|s| s.is_match(&crash.inner))
+                {
+                    self.crash_analyze(p, crash, executor).await?;
+                }
+                Ok(new)
+            }
+            None => {
+                self.crash_analyze(p, crash, executor).await?;
+                Ok(false)
+            }
+        }
+    }
+
+    async fn result_analyze(
+        &self,
+        p: Prog,
+        exec_result: ExecResult,
+        executor: &mut Executor,
+    ) -> Result<bool> {
+        match exec_result {
+            ExecResult::Ok(info) => {
+                self.call_times.record(&p, &info).await;
+                self.fault_analyze(&p, &info).await;
+                self.feedback_analyze(p, info, executor).await
+            }
+            ExecResult::Failed(reason) => {
+                self.failed_analyze(p, reason).await;
+                Ok(false)
+            }
+            ExecResult::Hanged { call, info } => {
+                self.call_times.record(&p, &info).await;
+                self.hang_analyze(p, call, info, executor).await
+            }
+            // never requested in fuzz loop
+            ExecResult::Cmps(_) => Ok(false),
+        }
+    }
+
+    /// Continue campaign from corpus and state persisted in work dir.
+    pub async fn restore(&self) -> Result<()> {
+        let corpus_path = workdir::path("corpus");
+        let corpus = read(&corpus_path).await.map_err(|e| {
+            err!(
+                exitcode::NOINPUT,
+                "Fail to read corpus {}: {}",
+                corpus_path.display(),
+                e
+            )
+        })?;
+        let mut corpus = parse_dump(&corpus)
+            .map_err(|e| err!(exitcode::DATAERR, "Fail to load corpus: {}", e))?;
+        let len = corpus.len();
+        corpus.retain(|(p, _)| is_known(&self.target, p));
+        let dropped = len - corpus.len();
+        if dropped != 0 {
+            info!(
+                "{} progs of resumed corpus dropped, their calls are filtered",
+                dropped
+            );
+        }
+        let state_path = workdir::path("state");
+        let state = read(&state_path).await.map_err(|e| {
+            err!(
+                exitcode::NOINPUT,
+                "Fail to read state {}: {}",
+                state_path.display(),
                 e
             )
-        });
-        self.record.psersist().await;
+        })?;
+        let state: State = bincode::deserialize(&state)
+            .map_err(|e| err!(exitcode::DATAERR, "Fail to load state: {}", e))?;
+
+        // progs of corpus were analyzed already, re-executing them would find nothing new.
+        for (p, meta) in corpus {
+            self.corpus.insert_covered(p, meta, None).await;
+        }
+        self.feedback
+            .merge(
+                state.blocks.into_iter().collect(),
+                state.branches.into_iter().collect(),
+            )
+            .await;
+        for (gid, relations) in state.relations {
+            let shard = match self.shards.of_group(gid) {
+                Some(shard) => shard,
+                None => continue,
+            };
+            let mut rt = shard.rt.lock().await;
+            if let (Some(r), Some(g)) = (rt.get_mut(&gid), self.target.groups.get(&gid)) {
+                for (f1, f2) in relations {
+                    if let (Some(i), Some(j)) = (g.index_by_id(f1), g.index_by_id(f2)) {
+                        r[(i, j)] = Relation::Some;
+                    }
+                }
+            }
+        }
+        self.crash_digests
+            .lock()
+            .await
+            .extend(state.crash_digests.into_iter().map(md5::Digest));
+        self.exec_cnt.store(state.exec, Ordering::SeqCst);
+        self.health_restarts
+            .store(state.health_restarts, Ordering::SeqCst);
+        self.stalls.store(state.stalls, Ordering::SeqCst);
+        self.slow_recycles
+            .store(state.slow_recycles, Ordering::SeqCst);
+        self.corpus
+            .near_duplicates
+            .store(state.near_duplicates, Ordering::SeqCst);
+        self.record.restore(&state.record).await;
+        Ok(())
+    }
+
+    async fn state(&self) -> State {
+        let (blocks, branches) = self.feedback.dump().await;
+        let mut relations = Vec::new();
+        for shard in self.shards.iter() {
+            let rt = shard.rt.lock().await;
+            relations.extend(rt.iter().map(|(gid, r)| {
+                let fns = &self.target.groups[gid].fns;
+                let related = r
+                    .indexed_iter()
+                    .filter(|(_, relation)| **relation == Relation::Some)
+                    .map(|((i, j), _)| (fns[i].id, fns[j].id))
+                    .collect();
+                (*gid, related)
+            }));
+        }
+        let crash_digests = self
+            .crash_digests
+            .lock()
+            .await
+            .iter()
+            .map(|d| d.0)
+            .collect();
+        State {
+            blocks,
+            branches,
+            relations,
+            crash_digests,
+            exec: self.exec_cnt.load(Ordering::SeqCst),
+            health_restarts: self.health_restarts.load(Ordering::SeqCst),
+            stalls: self.stalls.load(Ordering::SeqCst),
+            slow_recycles: self.slow_recycles.load(Ordering::SeqCst),
+            near_duplicates: self.corpus.near_duplicates.load(Ordering::SeqCst),
+            record: self.record.state().await,
+        }
+    }
+
+    pub async fn persist(self) -> Result<()> {
+        self.checkpoint().await?;
+        self.record.psersist().await
+    }
+
+    /// Save corpus and state to work dir, so that campaign can be resumed from here.
+    pub async fn checkpoint(&self) -> Result<()> {
+        let state_path = workdir::path("state");
+        let state = bincode::serialize(&self.state().await)
+            .map_err(|e| err!(exitcode::DATAERR, "Fail to dump state: {}", e))?;
+        workdir::write_atomic(&state_path, state)
+            .await
+            .map_err(|e| {
+                err!(
+                    exitcode::IOERR,
+                    "Fail to persist state to {} : {}",
+                    state_path.display(),
+                    e
+                )
+            })?;
+
+        self.corpus.save(&workdir::path("corpus")).await?;
+
+        if !self.hooks.is_empty() {
+            self.hooks.checkpoint(&self.stats().snapshot().await);
+        }
+        Ok(())
     }
 
     async fn failed_analyze(&self, p: Prog, reason: Reason) {
         self.record.insert_failed(p, reason).await
     }
 
-    async fn crash_analyze(&self, p: Prog, crash: Crash, executor: &mut Executor) {
+    /// Record prog whose execution killed executor itself, which is a bug of executor
+    /// or description rather than a kernel crash.
+    async fn fault_analyze(&self, p: &Prog, info: &[CallInfo]) {
+        if let Some((call, fault)) = info
+            .iter()
+            .enumerate()
+            .find_map(|(i, info)| info.fault().map(|f| (i, f)))
+        {
+            let reason = Reason(format!("Call {} faulted: {}", call, fault));
+            self.failed_analyze(p.clone(), reason).await
+        }
+    }
+
+    async fn hang_analyze(
+        &self,
+        p: Prog,
+        call: Option<usize>,
+        info: Vec<CallInfo>,
+        executor: &mut Executor,
+    ) -> Result<bool> {
+        if info.iter().all(|i| i.skipped) {
+            let reason = match call {
+                Some(call) => format!("Call {} hung", call),
+                None => "Prog hung".to_string(),
+            };
+            self.failed_analyze(p, Reason(reason)).await;
+            Ok(false)
+        } else {
+            // calls before the hanged one finished normally.
+            self.feedback_analyze(p, info, executor).await
+        }
+    }
+
+    async fn crash_analyze(&self, p: Prog, crash: Crash, executor: &mut Executor) -> Result<()> {
+        let title = crash.inner.lines().next().unwrap_or_default().to_string();
+        if let Some(breaker) = self.breaker.as_ref() {
+            breaker.crashed().await;
+        }
         if self.should_ignore(&crash.inner) {
-            warn!("Crashed, match ignores, restarting ...");
-            executor.start().await;
-            return;
+            event!(
+                Level::Warn,
+                "crash_ignored",
+                { "vm": executor.id(), "title": title },
+                "Crashed, match ignores, restarting ..."
+            );
+            return executor.start().await;
         }
 
-        if self.should_suppress(&crash.inner).await {
-            self.record.insert_crash(p, crash, false).await;
-            warn!("Crashed, match suppressions, restarting ...");
-            executor.start().await;
-            return;
+        if self.should_suppress(&crash).await {
+            self.record
+                .insert_crash(executor.kernel(), p, crash, false)
+                .await?;
+            event!(
+                Level::Warn,
+                "crash_suppressed",
+                { "vm": executor.id(), "title": title },
+                "Crashed, match suppressions, restarting ..."
+            );
+            return executor.start().await;
         }
 
-        warn!("========== Crashed ========= \n{}", crash);
+        event!(
+            Level::Warn,
+            "crash",
+            { "vm": executor.id(), "title": title },
+            "========== Crashed ========= \n{}",
+            crash
+        );
         let p_str = to_prog(&p, &self.target);
         warn!("Caused by:\n{}", p_str);
         warn!("Restarting to repro ...");
-        executor.start().await;
+        executor.start().await?;
 
         self.exec_cnt.fetch_add(1, Ordering::SeqCst);
-        match executor.exec(&p, &self.target).await {
+        // coverage is useless for reproducing
+        let opt = ExecOpt {
+            collect_cover: false,
+            repeat: REPRO_REPEAT,
+            ..executor.opt()
+        };
+        match executor.exec_with(&p, &self.target, opt).await? {
             Ok(exec_result) => {
-                match exec_result {
-                    ExecResult::Ok(_) => warn!("Repo failed, executed successfully"),
-                    ExecResult::Failed(reason) => warn!("Repo failed, executed failed: {}", reason),
+                let reason = match exec_result {
+                    ExecResult::Ok(_) => "executed successfully".to_string(),
+                    ExecResult::Failed(reason) => format!("executed failed: {}", reason),
+                    ExecResult::Hanged {
+                        call: Some(call), ..
+                    } => format!("call {} hung", call),
+                    ExecResult::Hanged { call: None, .. } => "hung".to_string(),
+                    ExecResult::Cmps(_) => unreachable!(),
                 };
-                self.record.insert_crash(p, crash, false).await
+                event!(
+                    Level::Warn,
+                    "repro",
+                    { "vm": executor.id(), "title": title, "repro": false },
+                    "Repo failed, {}",
+                    reason
+                );
+                self.hooks
+                    .crash(executor.id(), &title, &crash.inner, &p, false);
+                self.record
+                    .insert_crash(executor.kernel(), p, crash, false)
+                    .await
             }
             Err(repo_crash) => {
+                let crash = repo_crash.unwrap_or(crash);
+                self.hooks
+                    .crash(executor.id(), &title, &crash.inner, &p, true);
                 self.record
-                    .insert_crash(p, repo_crash.unwrap_or(crash), true)
-                    .await;
-                warn!("Repo successfully, restarting guest ...");
-                executor.start().await;
+                    .insert_crash(executor.kernel(), p, crash, true)
+                    .await?;
+                event!(
+                    Level::Warn,
+                    "repro",
+                    { "vm": executor.id(), "title": title, "repro": true },
+                    "Repo successfully, restarting guest ..."
+                );
+                executor.start().await
             }
         }
     }
@@ -182,10 +877,11 @@ impl Fuzzer {
         }
     }
 
-    async fn should_suppress(&self, reason: &str) -> bool {
-        if reason.contains("CRASH-MEMLEAK") {
+    async fn should_suppress(&self, crash: &Crash) -> bool {
+        if crash.kind == CrashKind::Memleak {
             return true;
         }
+        let reason = &crash.inner;
 
         if self.suppressions.iter().any(|s| s.is_match(reason)) {
             return true;
@@ -199,20 +895,24 @@ impl Fuzzer {
     async fn feedback_analyze(
         &self,
         p: Prog,
-        raw_blocks: Vec<Vec<usize>>,
+        info: Vec<CallInfo>,
         executor: &mut Executor,
-    ) {
-        for (call_index, raw_blocks) in raw_blocks.iter().enumerate() {
-            let (new_blocks_1, new_branches_1) = self.check_new_feedback(raw_blocks).await;
+    ) -> Result<bool> {
+        let mut new_cover = false;
+        for (call_index, call_info) in info.iter().enumerate() {
+            if call_info.skipped {
+                break;
+            }
+            let (new_blocks_1, new_branches_1) = self.check_new_feedback(&call_info.covs).await;
 
             if !new_blocks_1.is_empty() || !new_branches_1.is_empty() {
                 let p = p.sub_prog(call_index);
-                let exec_result = self.exec_no_crash(executor, &p).await;
+                let exec_result = self.exec_no_crash(executor, &p).await?;
 
-                if let ExecResult::Ok(raw_blocks) = exec_result {
-                    if raw_blocks.len() == call_index + 1 {
+                if let ExecResult::Ok(info) = exec_result {
+                    if info.len() == call_index + 1 && !info[call_index].skipped {
                         let (new_block_2, new_branches_2) =
-                            self.check_new_feedback(&raw_blocks[call_index]).await;
+                            self.check_new_feedback(&info[call_index].covs).await;
 
                         let new_block: HashSet<_> =
                             new_blocks_1.intersection(&new_block_2).cloned().collect();
@@ -222,18 +922,19 @@ impl Fuzzer {
                             .collect();
 
                         if !new_block.is_empty() || !new_branches.is_empty() {
-                            let minimized_p = self.minimize(&p, &new_block, executor).await;
-                            let raw_branches = self.exec_no_fail(executor, &minimized_p).await;
+                            let minimized_p = self.minimize(&p, &new_block, executor).await?;
+                            let info = self.exec_no_fail(executor, &minimized_p).await?;
                             {
                                 let g = &self.target.groups[&p.gid];
-                                let mut r = self.rt.lock().await;
+                                let shard = self.shards.of_group(p.gid).unwrap();
+                                let mut r = shard.rt.lock().await;
                                 prog_analyze(g, r.get_mut(&p.gid).unwrap(), &p);
                             }
 
                             let mut blocks = Vec::new();
                             let mut branches = Vec::new();
-                            for raw_branches in raw_branches.iter() {
-                                let (block, branch) = self.cook_raw_block(raw_branches);
+                            for call_info in info.iter() {
+                                let (block, branch) = self.cook_raw_block(&call_info.covs);
                                 blocks.push(block);
                                 branches.push(branch);
                             }
@@ -241,55 +942,140 @@ impl Fuzzer {
                             blocks.shrink_to_fit();
                             branches.shrink_to_fit();
 
-                            self.record
+                            let id = self
+                                .record
                                 .insert_executed(
+                                    executor.kernel(),
                                     &minimized_p,
+                                    &info[..],
                                     &blocks[..],
                                     &branches[..],
                                     &new_block,
                                     &new_branches,
                                 )
                                 .await;
-                            self.corpus.insert(minimized_p).await;
+                            // too frequent for text logs, but kept in json logs.
+                            let level = if event::json() {
+                                Level::Info
+                            } else {
+                                Level::Debug
+                            };
+                            event!(
+                                level,
+                                "new_cover",
+                                {
+                                    "vm": executor.id(),
+                                    "prog": id,
+                                    "blocks": new_block.len(),
+                                    "branches": new_branches.len(),
+                                },
+                                "New coverage: {} blocks, {} branches by:\n{}",
+                                new_block.len(),
+                                new_branches.len(),
+                                to_prog(&minimized_p, &self.target)
+                            );
+                            self.hooks.new_coverage(
+                                executor.id(),
+                                id,
+                                &minimized_p,
+                                new_block.len(),
+                                new_branches.len(),
+                            );
+                            if let Some(outbox) = self.outbox.as_ref() {
+                                outbox.push(&minimized_p, &new_block, &new_branches).await;
+                            }
+                            let meta = Meta {
+                                new_blocks: new_block.len(),
+                                new_branches: new_branches.len(),
+                                exec_us: info.iter().map(|i| i.elapsed).sum(),
+                                ..Meta::now()
+                            };
+                            let cover = Cover {
+                                blocks: blocks.iter().flatten().cloned().collect(),
+                                branches: branches.iter().flatten().cloned().collect(),
+                            };
+                            self.corpus
+                                .insert_covered(minimized_p, meta, Some(cover))
+                                .await;
                             self.feedback.merge(new_block, new_branches).await;
+                            new_cover = true;
                         }
                     }
                 }
             }
         }
+        Ok(new_cover)
     }
 
+    /// Remove calls of `p` that are not needed for covering `new_block`. Progs removing each
+    /// call from current prog are executed in batches, and the first one still covering
+    /// `new_block` is taken, same as trying removals one by one would take.
     async fn minimize(
         &self,
         p: &Prog,
         new_block: &HashSet<Block>,
         executor: &mut Executor,
-    ) -> Prog {
+    ) -> Result<Prog> {
         assert!(!p.calls.is_empty());
 
         let mut p = p.clone();
-        if p.len() == 1 {
-            return p;
-        }
-
-        let mut p_orig;
         let mut i = 0;
-        while i != p.len() - 1 {
-            p_orig = p.clone();
-            if !remove(&mut p, i) {
-                i += 1;
-            } else if let ExecResult::Ok(cover) = self.exec_no_crash(executor, &p).await {
-                let (new_blocks_1, _) = self.check_new_feedback(cover.last().unwrap()).await;
-                if new_blocks_1.is_empty() || new_blocks_1.intersection(new_block).count() == 0 {
-                    i += 1;
-                    p = p_orig;
+        while i + 1 < p.len() {
+            let mut candidates = Vec::new();
+            let mut next = i;
+            while next + 1 < p.len() && candidates.len() < CANDIDATE_BATCH {
+                let mut candidate = p.clone();
+                if remove(&mut candidate, next) {
+                    candidates.push((next, candidate));
                 }
-            } else {
-                p = p_orig;
-                return p;
+                next += 1;
+            }
+            if candidates.is_empty() {
+                i = next;
+                continue;
+            }
+
+            let progs = candidates
+                .iter()
+                .map(|(_, c)| c.clone())
+                .collect::<Vec<_>>();
+            let rets = executor.exec_batch(&progs, &self.target).await?;
+            let executed = rets.len();
+            let mut taken = None;
+            for ((at, candidate), ret) in candidates.into_iter().zip(rets) {
+                self.exec_cnt.fetch_add(1, Ordering::SeqCst);
+                match ret {
+                    Ok(ExecResult::Ok(info)) => {
+                        if let Some(last) = info.last() {
+                            let (new_blocks_1, _) = self.check_new_feedback(&last.covs).await;
+                            if new_blocks_1.intersection(new_block).next().is_some() {
+                                taken = Some((at, candidate));
+                                break;
+                            }
+                        } else {
+                            // nothing came back, e.g. connection lost, same as a failed run.
+                            return Ok(p);
+                        }
+                    }
+                    Ok(_) => return Ok(p),
+                    Err(crash) => {
+                        self.crash_analyze(candidate, crash.unwrap_or_default(), executor)
+                            .await?;
+                        return Ok(p);
+                    }
+                }
+            }
+            match taken {
+                Some((at, candidate)) => {
+                    p = candidate;
+                    i = at;
+                }
+                // rest of batch was not executed, as executor was lost.
+                None if executed < progs.len() => return Ok(p),
+                None => i = next,
             }
         }
-        p
+        Ok(p)
     }
 
     async fn check_new_feedback(&self, raw_blocks: &[usize]) -> (HashSet<Block>, HashSet<Branch>) {
@@ -318,47 +1104,169 @@ impl Fuzzer {
         (blocks, branches)
     }
 
-    async fn exec_no_crash(&self, executor: &mut Executor, p: &Prog) -> ExecResult {
+    async fn exec_no_crash(&self, executor: &mut Executor, p: &Prog) -> Result<ExecResult> {
         self.exec_cnt.fetch_add(1, Ordering::SeqCst);
-        match executor.exec(p, &self.target).await {
-            Ok(exec_result) => exec_result,
+        match executor.exec(p, &self.target).await? {
+            Ok(exec_result) => Ok(exec_result),
             Err(crash) => {
                 self.crash_analyze(p.clone(), crash.unwrap_or_default(), executor)
-                    .await;
-                ExecResult::Failed(Reason(String::from("Crashed")))
+                    .await?;
+                Ok(ExecResult::Failed(Reason(String::from("Crashed"))))
             }
         }
     }
 
-    async fn exec_no_fail(&self, executor: &mut Executor, p: &Prog) -> Vec<Vec<usize>> {
+    async fn exec_no_fail(&self, executor: &mut Executor, p: &Prog) -> Result<Vec<CallInfo>> {
         self.exec_cnt.fetch_add(1, Ordering::SeqCst);
-        match executor.exec(p, &self.target).await {
+        match executor.exec(p, &self.target).await? {
             Ok(exec_result) => match exec_result {
-                ExecResult::Ok(raw_branches) => raw_branches,
-                ExecResult::Failed(_) => Default::default(),
+                ExecResult::Ok(info) => Ok(info),
+                ExecResult::Hanged { info, .. } => Ok(info),
+                ExecResult::Failed(_) | ExecResult::Cmps(_) => Ok(Default::default()),
             },
             Err(crash) => {
                 self.crash_analyze(p.clone(), crash.unwrap_or_default(), executor)
-                    .await;
-                Default::default()
+                    .await?;
+                Ok(Default::default())
             }
         }
     }
 
-    async fn get_prog(&self, gen_cnt: &mut usize) -> Prog {
-        if let Some(p) = self.candidates.pop().await {
-            p
-        } else if self.corpus.is_empty().await || *gen_cnt % 100 != 0 {
-            *gen_cnt += 1;
-            let rt = self.rt.lock().await;
-            gen(&self.target, &rt, &self.conf)
-        } else {
-            let rt = {
-                let rt = self.rt.lock().await;
-                rt.clone()
-            };
+    async fn has_slow_call(&self, p: &Prog) -> bool {
+        for call in p.calls.iter() {
+            if self.call_times.is_slow(call.fid).await {
+                return true;
+            }
+        }
+        false
+    }
+
+    async fn has_disabled_call(&self, p: &Prog) -> bool {
+        let disabled_calls = self.disabled_calls.lock().await;
+        p.calls.iter().any(|c| disabled_calls.contains(&c.fid))
+    }
+
+    /// Next prog of groups of `shard` to execute, how it is got if schedule is configured, and
+    /// hash of prog of corpus it is mutated from, if any.
+    async fn get_prog(
+        &self,
+        gen_cnt: &mut usize,
+        shard: &Shard,
+    ) -> (Prog, Option<Kind>, Option<u64>) {
+        let mut conf = self.conf.lock().await.clone();
+        let boosted = |strategy| self.plateau.as_ref().map_or(false, |p| p.reached(strategy));
+        conf.negative |= boosted(Strategy::Negative);
+        let schedule = match self.schedule.as_ref() {
+            Some(schedule) => schedule,
+            None => {
+                let period = if boosted(Strategy::Mutate) {
+                    100 / MUTATE_BOOST
+                } else {
+                    100
+                };
+                if !self.corpus.is_empty().await && *gen_cnt % period == 0 {
+                    if let Some((p, seed)) = self.mutate(shard, &conf).await {
+                        return (p, None, Some(seed));
+                    }
+                }
+                *gen_cnt += 1;
+                return (self.generate(shard, &conf).await, None, None);
+            }
+        };
+        let kind = schedule.choose(self.corpus.is_empty().await).await;
+        let p = match kind {
+            Kind::Generate => None,
+            Kind::Mutate => self
+                .mutate(shard, &conf)
+                .await
+                .map(|(p, seed)| (p, Some(seed))),
+            Kind::Replay => self.replay_seed().await.map(|p| (p, None)),
+        };
+        match p {
+            Some((p, seed)) => (p, Some(kind), seed),
+            None => (
+                self.generate(shard, &conf).await,
+                Some(Kind::Generate),
+                None,
+            ),
+        }
+    }
+
+    /// Mutate prog of corpus in groups of `shard`, returning mutated prog and hash of prog it
+    /// is mutated from, none if there is no such prog or mutated one has disabled calls.
+    async fn mutate(&self, shard: &Shard, conf: &core::gen::Config) -> Option<(Prog, u64)> {
+        let all = shard.gids.len() == self.target.groups.len();
+        // chosen from disk if corpus is stored there, only spliced with progs in memory.
+        let gids = if all { None } else { Some(&shard.gids[..]) };
+        let seed = self.corpus.select_weighted(&mut thread_rng(), gids).await?;
+        self.corpus.selected(&seed);
+        let p = {
             let corpus = self.corpus.inner.lock().await;
-            mutate(&corpus, &self.target, &rt, &self.conf)
+            let rt = shard.rt.lock().await[&seed.gid].clone();
+            mutate_prog(&seed, &corpus, &self.target, &rt, conf)
+        };
+        if self.has_disabled_call(&p).await {
+            None
+        } else {
+            Some((p, prog_hash(&seed)))
+        }
+    }
+
+    /// Prog of corpus to re-execute as it is, none if it has disabled calls.
+    async fn replay_seed(&self) -> Option<Prog> {
+        let p = self.corpus.select_weighted(&mut thread_rng(), None).await?;
+        self.corpus.selected(&p);
+        if self.has_disabled_call(&p).await {
+            None
+        } else {
+            Some(p)
+        }
+    }
+
+    /// Generate prog without disabled calls, de-prioritizing slow ones.
+    async fn generate(&self, shard: &Shard, conf: &core::gen::Config) -> Prog {
+        for _ in 0..GEN_RETRY {
+            let p = self.gen(shard, conf).await;
+            if self.has_disabled_call(&p).await {
+                continue;
+            }
+            // de-prioritize pathologically slow calls, they waste most of exec time, slowness
+            // depends on timing, so deterministic run keeps them.
+            if self.serial.is_some()
+                || random::<f64>() >= SLOW_PROG_DROP
+                || !self.has_slow_call(&p).await
+            {
+                return p;
+            }
+        }
+        warn!("Fail to generate prog without disabled calls, using one with them");
+        self.gen(shard, conf).await
+    }
+
+    /// Generate prog of group of `shard` chosen by weights.
+    async fn gen(&self, shard: &Shard, conf: &core::gen::Config) -> Prog {
+        let gid = shard.choose_group().await;
+        let rt = shard.rt.lock().await;
+        gen_prog(gid, &rt[&gid], &self.target, conf)
+    }
+}
+
+/// Ids of calls disabled in config, matched against declared or called name.
+fn disabled_calls(target: &Target, cfg: &Config) -> HashSet<FnId> {
+    let mut ids = HashSet::new();
+    for name in cfg.disabled_calls.iter().flatten() {
+        let matched = target
+            .fns
+            .keys()
+            .filter(|&&fid| {
+                let f = target.fn_of(fid);
+                &f.dec_name == name || &f.call_name == name
+            })
+            .collect::<Vec<_>>();
+        if matched.is_empty() {
+            warn!("Disabled call {} is not found in target", name);
         }
+        ids.extend(matched);
     }
+    ids
 }
@@ -1,18 +1,59 @@
+use crate::error::Result;
+use crate::exec::Recovery;
 /// Driver for kernel to be tested
+use crate::utils::cgroup::{Cgroup, CgroupConf};
 use crate::utils::cli::{App, Arg, OptVal};
-use crate::utils::free_ipv4_port;
+use crate::utils::registry::{reserve_dir, reserve_port_from, reserve_ports, Ports, WorkDir};
+use crate::utils::vm_log;
 use crate::Config;
+use chrono::{DateTime, Local};
+use executor::{ExecResult, Finding, FindingKind};
 use nix::fcntl::{fcntl, FcntlArg, OFlag};
 use os_pipe::{pipe, PipeReader, PipeWriter};
-use std::collections::HashMap;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::io::{ErrorKind, Read};
+use std::net::Ipv4Addr;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
-use std::process::exit;
-use tokio::process::Child;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::process::{Child, Command};
+use tokio::sync::{oneshot, watch};
 use tokio::time::{delay_for, timeout, Duration};
 
+mod adb;
+mod bare;
+mod board;
+mod cloud;
+mod container;
+mod gvisor;
+mod initramfs;
+mod libvirt;
+mod local;
+mod pool;
+use adb::Adb;
+pub use adb::AdbConf;
+use bare::Bare;
+pub use bare::BareConf;
+use board::Board;
+pub use board::BoardConf;
+use cloud::Cloud;
+pub use cloud::CloudConf;
+use container::Container;
+pub use container::ContainerConf;
+pub use gvisor::GvisorConf;
+use gvisor::{Gvisor, GVISOR_HOST_IP_ADDR};
+use libvirt::Libvirt;
+pub use libvirt::LibvirtConf;
+use local::Local as LocalGuest;
+pub use local::LocalConf;
+pub use pool::GuestPool;
+
 lazy_static! {
     static ref QEMUS: HashMap<String, App> = {
         let mut qemus = HashMap::new();
@@ -92,7 +133,19 @@ lazy_static! {
                 OptVal::multiple(vec!["root=/dev/vda", "console=ttyAMA0"], Some(' ')),
             ));
 
+        // kernel of freebsd is loaded from image by its own loader
+        let mut freebsd_amd64 = App::new("qemu-system-x86_64");
+        freebsd_amd64
+            .arg(Arg::new_flag("-enable-kvm"))
+            .args(arg_common.iter())
+            .arg(Arg::new_opt("-cpu", OptVal::normal("host")))
+            .arg(Arg::new_opt(
+                "-net",
+                OptVal::multiple(vec!["nic", "model=e1000"], Some(',')),
+            ));
+
         qemus.insert("linux/amd64".to_string(), linux_amd64);
+        qemus.insert("freebsd/amd64".to_string(), freebsd_amd64);
         qemus.insert("linux/arm".to_string(), linux_arm);
         qemus.insert("linux/arm64".to_string(), linux_arm64);
         qemus
@@ -109,8 +162,7 @@ lazy_static! {
             .arg(Arg::new_opt(
                 "-o",
                 OptVal::normal("StrictHostKeyChecking=no"),
-            ))
-            .arg(Arg::new_opt("-o", OptVal::normal("ConnectTimeout=10s")));
+            ));
         ssh
     };
     pub static ref SCP: App = {
@@ -138,24 +190,50 @@ pub struct GuestConf {
     pub arch: String,
     /// Platform to run kernel, qemu or real env
     pub platform: String,
+    /// Time waiting for guest to boot, in seconds, used by platforms whose own wait is
+    /// not configured
+    pub boot_timeout: Option<u64>,
 }
 
-pub const PLATFORM: [&str; 1] = ["qemu"];
+pub const PLATFORM: [&str; 9] = [
+    "qemu",
+    "gvisor",
+    "bare",
+    "libvirt",
+    "board",
+    "adb",
+    "container",
+    "cloud",
+    "local",
+];
 pub const ARCH: [&str; 1] = ["amd64"];
-pub const OS: [&str; 1] = ["linux"];
+pub const OS: [&str; 2] = ["linux", "freebsd"];
 
 impl GuestConf {
-    pub fn check(&self) {
+    pub fn check(&self) -> Result<()> {
         if !PLATFORM.contains(&self.platform.as_str())
             || !ARCH.contains(&self.arch.as_str())
             || !OS.contains(&self.os.as_str())
         {
-            eprintln!(
+            bail!(
+                exitcode::CONFIG,
                 "Config Error: unsupported guest: {:?}",
                 (&self.platform, &self.arch, &self.os)
-            );
-            exit(exitcode::CONFIG)
+            )
+        }
+        if self.os == "freebsd" && self.platform != "qemu" {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: freebsd is only supported on qemu"
+            )
+        }
+        if self.boot_timeout == Some(0) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: boot timeout should be positive"
+            )
         }
+        Ok(())
     }
 }
 
@@ -163,75 +241,671 @@ impl GuestConf {
 pub struct QemuConf {
     pub cpu_num: u32,
     pub mem_size: u32,
+    /// Disk image, not needed if guest runs entirely from initrd
+    #[serde(default)]
     pub image: String,
+    /// Kernel image, not needed if guest boots kernel from image itself, e.g. freebsd
+    #[serde(default)]
     pub kernel: String,
+    /// Initramfs loaded with kernel
+    pub initrd: Option<String>,
     pub wait_boot_time: Option<u8>,
+    /// Transfer executor protocol over virtio-serial instead of guest network
+    #[serde(default)]
+    pub virtio_serial: bool,
+    /// Machine type passed to -machine, replacing default one of target
+    pub machine: Option<String>,
+    /// Kernel command line appended to default one of target
+    pub cmdline: Option<String>,
+    /// Devices, each passed to qemu as -device
+    #[serde(default)]
+    pub devices: Vec<String>,
+    /// Extra arguments passed to qemu as is
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+    /// Boot each vm from its own qcow2 overlay backed by image, instead of -snapshot
+    #[serde(default)]
+    pub overlay: bool,
+    /// Share a host directory with guest over virtio-9p, files are copied through it
+    /// instead of scp
+    #[serde(default)]
+    pub virtfs: bool,
+    /// Directory where memory of crashed vm is dumped as vmcore with dump-guest-memory
+    pub vmcore_dir: Option<PathBuf>,
+    /// Accelerator of qemu, kvm or tcg, kvm if /dev/kvm is usable by default
+    pub accel: Option<String>,
+    /// Append executor and an init script to initrd at boot, so that a stock initrd can
+    /// be used without preparing executor in it
+    #[serde(default)]
+    pub inject: bool,
+    /// Shell script run by injected init before init of initrd, e.g. setting up network
+    pub init_script: Option<PathBuf>,
+    /// Expose qmp socket of each vm in its work dir, for developers to attach
+    #[serde(default)]
+    pub qmp: bool,
+    /// Expose display of each vm over vnc on localhost
+    #[serde(default)]
+    pub vnc: bool,
+    /// Network of vms, user network forwarding ssh by default
+    #[serde(default)]
+    pub net: NetConf,
+    /// Kernels fuzzed side by side, replacing kernel, image and initrd above
+    #[serde(default)]
+    pub kernels: Vec<KernelConf>,
+    /// Cgroup v2 limits on cpu and memory of each qemu process, none by default
+    pub cgroup: Option<CgroupConf>,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct KernelConf {
+    /// Name tagging coverage and crashes found on this kernel
+    pub name: String,
+    /// Kernel image booted by vms
+    pub kernel: String,
+    /// Disk image, image of qemu by default
+    pub image: Option<String>,
+    /// Initramfs, initrd of qemu by default
+    pub initrd: Option<String>,
+    /// Vms pinned to this kernel, numbered from 0
+    #[serde(default)]
+    pub vms: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetMode {
+    /// User network of qemu, guest reaches host at `LINUX_QEMU_USER_NET_HOST_IP_ADDR`.
+    User,
+    /// Tap interface created beforehand.
+    Tap,
+    /// Tap interface attached to bridge by qemu-bridge-helper.
+    Bridge,
+}
+
+impl Default for NetMode {
+    fn default() -> Self {
+        NetMode::User
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NetConf {
+    /// "user", "tap" or "bridge"
+    #[serde(default)]
+    pub mode: NetMode,
+    /// Extra forward rules of user network, in form of hostfwd of qemu, e.g. "tcp::8080-:80"
+    #[serde(default)]
+    pub forwards: Vec<String>,
+    /// Tap interfaces for tap mode, one taken by each vm
+    #[serde(default)]
+    pub taps: Vec<String>,
+    /// Bridge for bridge mode
+    pub bridge: Option<String>,
+    /// Address of host with prefix length on tap or bridge network, e.g. "192.168.100.1/24",
+    /// vms take following addresses
+    pub addr: Option<String>,
+    /// Model of nic, e.g. "virtio-net-pci", e1000 by default
+    pub model: Option<String>,
+}
+
+impl NetConf {
+    pub fn check(&self, os: &str) -> Result<()> {
+        if let Some(f) = self
+            .forwards
+            .iter()
+            .find(|f| !(f.starts_with("tcp:") || f.starts_with("udp:")) || !f.contains('-'))
+        {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: invalid forward rule `{}`, e.g. tcp::8080-:80 expected",
+                f
+            )
+        }
+        if self.mode == NetMode::User {
+            return Ok(());
+        }
+        if !self.forwards.is_empty() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: forwards are only supported by user network"
+            )
+        }
+        if os != "linux" {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: tap and bridge network are only supported on linux guest"
+            )
+        }
+        if self.mode == NetMode::Tap && self.taps.is_empty() {
+            bail!(exitcode::CONFIG, "Config Error: tap network requires taps")
+        }
+        if self.mode == NetMode::Bridge && self.bridge.is_none() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: bridge network requires bridge"
+            )
+        }
+        if self.host_net().is_none() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: invalid net addr {:?}, e.g. 192.168.100.1/24 expected",
+                self.addr
+            )
+        }
+        Ok(())
+    }
+
+    /// Check that network has room for `vms` vms.
+    pub fn check_vms(&self, vms: usize) -> Result<()> {
+        if self.mode == NetMode::Tap && self.taps.len() < vms {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: {} taps for {} vms, each vm needs its own tap",
+                self.taps.len(),
+                vms
+            )
+        }
+        if self.mode != NetMode::User && self.guest_addr(vms - 1).is_none() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: net {} has no room for {} vms",
+                self.addr.as_ref().unwrap(),
+                vms
+            )
+        }
+        Ok(())
+    }
+
+    /// Address of host and prefix length on tap or bridge network.
+    fn host_net(&self) -> Option<(Ipv4Addr, u32)> {
+        let mut parts = self.addr.as_ref()?.splitn(2, '/');
+        let host = parts.next()?.parse::<Ipv4Addr>().ok()?;
+        let prefix = parts.next()?.parse::<u32>().ok()?;
+        if prefix == 0 || prefix > 30 {
+            return None;
+        }
+        Some((host, prefix))
+    }
+
+    fn netmask(&self) -> Option<Ipv4Addr> {
+        let (_, prefix) = self.host_net()?;
+        Some(Ipv4Addr::from(!0u32 << (32 - prefix)))
+    }
+
+    /// Address of `id`th vm, following that of host, none if out of network.
+    fn guest_addr(&self, id: usize) -> Option<Ipv4Addr> {
+        let (host, _) = self.host_net()?;
+        let mask = u32::from(self.netmask()?);
+        let addr = u32::from(host).checked_add(1 + id as u32)?;
+        let broadcast = (u32::from(host) & mask) | !mask;
+        if addr & mask == u32::from(host) & mask && addr < broadcast {
+            Some(Ipv4Addr::from(addr))
+        } else {
+            None
+        }
+    }
+}
+
+/// Options of qemu that are controlled by healer or by dedicated fields of `QemuConf`.
+const RESERVED_QEMU_ARGS: [&str; 15] = [
+    "-m",
+    "-smp",
+    "-hda",
+    "-kernel",
+    "-initrd",
+    "-append",
+    "-machine",
+    "-M",
+    "-serial",
+    "-monitor",
+    "-accel",
+    "-enable-kvm",
+    "-no-kvm",
+    "-qmp",
+    "-vnc",
+];
+
+pub const ACCELS: [&str; 2] = ["kvm", "tcg"];
+/// Boot under tcg is emulated, wait this many times longer than under kvm.
+const TCG_SLOWDOWN: u64 = 5;
+
+lazy_static! {
+    /// /dev/kvm exists and can be opened by fuzzer, e.g. not so in most ci containers.
+    static ref KVM_AVAILABLE: bool = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/kvm")
+        .is_ok();
+}
+
+static TCG_WARNING: Once = Once::new();
+
 impl QemuConf {
-    pub fn check(&self) {
+    pub fn check(&self, os: &str) -> Result<()> {
         let cpu_num = num_cpus::get() as u32;
         if self.cpu_num > cpu_num * 8 || self.cpu_num == 0 {
-            eprintln!(
+            bail!(
+                exitcode::CONFIG,
                 "Config Error: invalid cpu num {}, cpu num must between (0, {}] on your system",
                 self.cpu_num,
                 cpu_num * 8
-            );
-            exit(exitcode::CONFIG)
+            )
         }
 
         if self.mem_size < 512 {
-            eprintln!(
+            bail!(
+                exitcode::CONFIG,
                 "Config Error: invalid mem size {}, mem size must bigger than 512 bytes",
                 self.mem_size
-            );
-            exit(exitcode::CONFIG)
+            )
         }
 
+        if self.kernels.is_empty() {
+            self.check_boot_files(os)?;
+        } else {
+            if os != "linux" {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: kernels are only supported on linux guest"
+                )
+            }
+            for k in self.kernels.iter() {
+                self.with_kernel(k).check_boot_files(os)?;
+            }
+        }
+        if self.virtio_serial && os != "linux" {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: virtio_serial is only supported on linux guest"
+            )
+        }
+        if let Some(script) = self.init_script.as_ref() {
+            if !self.inject || !script.is_file() {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: init_script {} is invalid, it is only run with inject",
+                    script.display()
+                )
+            }
+        }
+        self.net.check(os)?;
+        if let Some(cgroup) = self.cgroup.as_ref() {
+            cgroup.check(self.mem_size)?;
+        }
+        if let Some(accel) = self.accel.as_ref() {
+            if !ACCELS.contains(&accel.as_str()) {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: unsupported accel {}, kvm or tcg expected",
+                    accel
+                )
+            }
+            if accel == "kvm" && !*KVM_AVAILABLE {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: accel kvm requires readable and writable /dev/kvm"
+                )
+            }
+        }
+        if let Some(dir) = self.vmcore_dir.as_ref() {
+            if !dir.is_dir() {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: vmcore_dir {} is invalid",
+                    dir.display()
+                )
+            }
+        }
+        if self.virtfs && os != "linux" {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: virtfs is only supported on linux guest"
+            )
+        }
+        if let Some(machine) = self.machine.as_ref() {
+            if machine.trim().is_empty() || machine.contains(char::is_whitespace) {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: invalid machine type `{}`",
+                    machine
+                )
+            }
+        }
+        if self.cmdline.is_some() && os != "linux" {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: cmdline is only supported on linux guest"
+            )
+        }
+        if let Some(d) = self.devices.iter().find(|d| d.trim().is_empty()) {
+            bail!(exitcode::CONFIG, "Config Error: invalid device `{}`", d)
+        }
+        if let Some(arg) = self
+            .extra_args
+            .iter()
+            .find(|a| RESERVED_QEMU_ARGS.contains(&a.as_str()))
+        {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: {} can not be passed by extra_args, use dedicated option of qemu",
+                arg
+            )
+        }
+        Ok(())
+    }
+
+    fn check_boot_files(&self, os: &str) -> Result<()> {
         let image = Path::new(&self.image);
         let kernel = Path::new(&self.kernel);
-        if !image.is_file() {
-            eprintln!("Config Error: image {} is invalid", self.image);
-            exit(exitcode::CONFIG)
+        if !image.is_file() && (self.initrd.is_none() || !self.image.is_empty()) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: image {} is invalid",
+                self.image
+            )
+        }
+        if os == "linux" && !kernel.is_file() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: kernel {} is invalid",
+                self.kernel
+            )
+        }
+        if let Some(initrd) = self.initrd.as_ref() {
+            if os != "linux" || !Path::new(initrd).is_file() {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: initrd {} is invalid, only linux kernel can be booted with initrd",
+                    initrd
+                )
+            }
+        }
+        if self.overlay && self.image.is_empty() {
+            bail!(exitcode::CONFIG, "Config Error: overlay requires image")
+        }
+        if self.inject && self.initrd.is_none() {
+            bail!(exitcode::CONFIG, "Config Error: inject requires initrd")
+        }
+        Ok(())
+    }
+
+    /// Check names of kernels and vms pinned to them, `vms` is number of working vms.
+    pub fn check_kernels(&self, vms: usize) -> Result<()> {
+        let mut names = HashSet::new();
+        let mut pinned = HashSet::new();
+        for k in self.kernels.iter() {
+            if k.name.is_empty()
+                || k.name
+                    .contains(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_')
+            {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: invalid kernel name `{}`",
+                    k.name
+                )
+            }
+            if !names.insert(&k.name) {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: kernel {} is configured twice",
+                    k.name
+                )
+            }
+            for vm in k.vms.iter() {
+                if *vm >= vms || !pinned.insert(*vm) {
+                    bail!(
+                        exitcode::CONFIG,
+                        "Config Error: vm {} pinned to kernel {} does not exist or is pinned twice",
+                        vm,
+                        k.name
+                    )
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Kernel run by `vm`, the one it is pinned to, otherwise vms rotate over kernels not
+    /// pinned by any vm, or over all kernels if every kernel is pinned. Standby vms are
+    /// numbered after working ones.
+    pub fn kernel_of(&self, vm: usize) -> Option<&KernelConf> {
+        if let Some(k) = self.kernels.iter().find(|k| k.vms.contains(&vm)) {
+            return Some(k);
+        }
+        let free = self
+            .kernels
+            .iter()
+            .filter(|k| k.vms.is_empty())
+            .collect::<Vec<_>>();
+        if free.is_empty() {
+            self.kernels.get(vm.checked_rem(self.kernels.len())?)
+        } else {
+            Some(free[vm % free.len()])
+        }
+    }
+
+    /// Conf with kernel, image and initrd of `k`.
+    fn with_kernel(&self, k: &KernelConf) -> QemuConf {
+        let mut conf = self.clone();
+        conf.kernel = k.kernel.clone();
+        if let Some(image) = k.image.as_ref() {
+            conf.image = image.clone();
         }
-        if !kernel.is_file() {
-            eprintln!("Config Error: kernel {} is invalid", self.kernel);
-            exit(exitcode::CONFIG)
+        if k.initrd.is_some() {
+            conf.initrd = k.initrd.clone();
+        }
+        conf
+    }
+
+    /// Accelerator given by config, otherwise kvm if target supports it and /dev/kvm is
+    /// usable, falling back to tcg.
+    pub fn accel(&self, g: &GuestConf) -> &str {
+        if let Some(accel) = self.accel.as_ref() {
+            return accel;
+        }
+        let target = format!("{}/{}", g.os, g.arch);
+        let kvm_target = QEMUS
+            .get(&target)
+            .map(|qemu| qemu.args.iter().any(is_enable_kvm))
+            .unwrap_or(false);
+        if kvm_target && *KVM_AVAILABLE {
+            "kvm"
+        } else {
+            "tcg"
         }
     }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SSHConf {
+    /// Private key used to login guest
     pub key_path: String,
+    /// User to login as, root by default, user given by fragment of platform takes precedence
+    pub user: Option<String>,
+    /// Port of sshd in guest, 22 by default, qemu guest is reached via forwarded port instead
+    pub port: Option<u16>,
+    /// Timeout of connecting to guest, in seconds
+    pub connect_timeout: Option<u64>,
+    /// Extra options passed to both ssh and scp, e.g. ["Ciphers=aes128-ctr"]
+    #[serde(default)]
+    pub options: Vec<String>,
 }
 
+const DEFAULT_SSH_CONNECT_TIMEOUT: u64 = 10;
+
 impl SSHConf {
-    pub fn check(&self) {
+    pub fn check(&self) -> Result<()> {
         let key = Path::new(&self.key_path);
         if !key.is_file() {
-            eprintln!("Config Error: ssh key file {} is invalid", self.key_path);
-            exit(exitcode::CONFIG)
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: ssh key file {} is invalid",
+                self.key_path
+            )
+        }
+        if self.port == Some(0) || self.connect_timeout == Some(0) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: ssh port and connect timeout should be positive"
+            )
+        }
+        for opt in self.options.iter() {
+            if opt.starts_with('-') || !opt.contains('=') {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: invalid ssh option `{}`, KEY=VALUE expected",
+                    opt
+                )
+            }
+        }
+        Ok(())
+    }
+
+    pub fn user(&self) -> String {
+        self.user
+            .clone()
+            .unwrap_or_else(|| LINUX_QEMU_HOST_USER.to_string())
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(22)
+    }
+
+    fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout.unwrap_or(DEFAULT_SSH_CONNECT_TIMEOUT))
+    }
+
+    /// Identity and options shared by ssh and scp.
+    fn apply(&self, app: &mut App) {
+        app.arg(Arg::new_opt("-i", OptVal::normal(&self.key_path)))
+            .arg(Arg::new_opt(
+                "-o",
+                OptVal::Normal(format!(
+                    "ConnectTimeout={}s",
+                    self.connect_timeout().as_secs()
+                )),
+            ));
+        for opt in self.options.iter() {
+            app.arg(Arg::new_opt("-o", OptVal::normal(opt)));
         }
     }
 }
 
 pub enum Guest {
     LinuxQemu(LinuxQemu),
+    Gvisor(Gvisor),
+    Bare(Bare),
+    Libvirt(Libvirt),
+    Board(Board),
+    Adb(Adb),
+    Container(Container),
+    Cloud(Cloud),
+    Local(LocalGuest),
 }
 
 impl Guest {
-    pub fn new(cfg: &Config) -> Self {
-        // only support linux/amd64 on qemu now.
-        Guest::LinuxQemu(LinuxQemu::new(cfg))
+    /// Guest of `vm`th vm, which picks kernel it runs if several are configured.
+    pub fn new(cfg: &Config, vm: usize) -> Result<Self> {
+        // only support linux/amd64 now.
+        let guest = match cfg.guest.platform.as_str() {
+            "gvisor" => Guest::Gvisor(Gvisor::new(cfg)),
+            "bare" => Guest::Bare(Bare::new(cfg)),
+            "libvirt" => Guest::Libvirt(Libvirt::new(cfg)),
+            "cloud" => Guest::Cloud(Cloud::new(cfg)),
+            "container" => Guest::Container(Container::new(cfg)),
+            "local" => Guest::Local(LocalGuest::new(cfg)),
+            "adb" => Guest::Adb(Adb::new(cfg)),
+            "board" => Guest::Board(Board::new(cfg)),
+            _ => Guest::LinuxQemu(LinuxQemu::new(cfg, vm)?),
+        };
+        Ok(guest)
+    }
+
+    /// Name of kernel guest runs, if several kernels are configured.
+    pub fn kernel(&self) -> Option<&str> {
+        match self {
+            Guest::LinuxQemu(ref guest) => guest.kernel.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Address of host seen from guest
+    pub fn host_addr(&self) -> &str {
+        match self {
+            Guest::LinuxQemu(ref guest) => &guest.host_addr,
+            Guest::Gvisor(_) => GVISOR_HOST_IP_ADDR,
+            Guest::Bare(ref guest) => guest.host_addr(),
+            Guest::Libvirt(ref guest) => guest.host_addr(),
+            Guest::Cloud(ref guest) => guest.host_addr(),
+            Guest::Container(ref guest) => guest.host_addr(),
+            Guest::Local(ref guest) => guest.host_addr(),
+            Guest::Adb(ref guest) => guest.host_addr(),
+            Guest::Board(ref guest) => guest.host_addr(),
+        }
+    }
+
+    /// Address fuzzer listens on for executor by default, host address of network if guest
+    /// reaches host over tap or bridge.
+    pub fn listen_addr(&self) -> &str {
+        match self {
+            Guest::LinuxQemu(ref guest) if guest.qemu.net.mode != NetMode::User => &guest.host_addr,
+            _ => LINUX_QEMU_HOST_IP_ADDR,
+        }
+    }
+
+    /// Make port of host reachable from guest at `host_addr()`, guests on network need nothing.
+    pub async fn forward(&mut self, port: u16) -> Result<()> {
+        if let Guest::Adb(ref mut guest) = self {
+            guest.forward(port).await?;
+        }
+        Ok(())
+    }
+
+    /// Host address of serial port that executor in guest should talk through,
+    /// none if executor should connect to host via network.
+    pub fn serial_addr(&self) -> Option<String> {
+        match self {
+            Guest::LinuxQemu(ref guest) => guest
+                .serial_port
+                .map(|port| format!("{}:{}", LINUX_QEMU_HOST_IP_ADDR, port)),
+            Guest::Gvisor(_)
+            | Guest::Bare(_)
+            | Guest::Libvirt(_)
+            | Guest::Board(_)
+            | Guest::Adb(_)
+            | Guest::Container(_)
+            | Guest::Cloud(_)
+            | Guest::Local(_) => None,
+        }
+    }
+
+    /// Probe that checks guest from background, none if guest can not be checked without
+    /// being borrowed, such guests are only checked after failures.
+    pub fn health_probe(&self) -> Option<HealthProbe> {
+        match self {
+            Guest::LinuxQemu(ref guest) => Some(guest.health_probe()),
+            Guest::Bare(ref guest) => Some(guest.health_probe()),
+            Guest::Libvirt(ref guest) => Some(guest.health_probe()),
+            Guest::Cloud(ref guest) => Some(guest.health_probe()),
+            Guest::Board(ref guest) => Some(guest.health_probe()),
+            Guest::Gvisor(_) | Guest::Adb(_) | Guest::Container(_) | Guest::Local(_) => None,
+        }
     }
 }
 
 impl Guest {
-    /// Boot guest or panic
-    pub async fn boot(&mut self) {
+    /// Boot guest, fail if guest did not come up
+    pub async fn boot(&mut self) -> Result<()> {
         match self {
             Guest::LinuxQemu(ref mut guest) => guest.boot().await,
+            Guest::Gvisor(ref mut guest) => guest.boot().await,
+            Guest::Bare(ref mut guest) => guest.boot().await,
+            Guest::Libvirt(ref mut guest) => guest.boot().await,
+            Guest::Cloud(ref mut guest) => guest.boot().await,
+            Guest::Container(ref mut guest) => guest.boot().await,
+            Guest::Local(ref mut guest) => guest.boot().await,
+            Guest::Adb(ref mut guest) => guest.boot().await,
+            Guest::Board(ref mut guest) => guest.boot().await,
         }
     }
 
@@ -239,13 +913,44 @@ impl Guest {
     pub async fn is_alive(&self) -> bool {
         match self {
             Guest::LinuxQemu(ref guest) => guest.is_alive().await,
+            Guest::Gvisor(ref guest) => guest.is_alive().await,
+            Guest::Bare(ref guest) => guest.is_alive().await,
+            Guest::Libvirt(ref guest) => guest.is_alive().await,
+            Guest::Cloud(ref guest) => guest.is_alive().await,
+            Guest::Container(ref guest) => guest.is_alive().await,
+            Guest::Local(ref guest) => guest.is_alive().await,
+            Guest::Adb(ref guest) => guest.is_alive().await,
+            Guest::Board(ref guest) => guest.is_alive().await,
         }
     }
 
-    /// Run command on guest,return handle or crash
-    pub async fn run_cmd(&self, app: &App) -> Child {
+    /// Run command on guest, return its handle
+    pub async fn run_cmd(&self, app: &App) -> Result<Child> {
         match self {
             Guest::LinuxQemu(ref guest) => guest.run_cmd(app).await,
+            Guest::Gvisor(ref guest) => guest.run_cmd(app).await,
+            Guest::Bare(ref guest) => guest.run_cmd(app).await,
+            Guest::Libvirt(ref guest) => guest.run_cmd(app).await,
+            Guest::Cloud(ref guest) => guest.run_cmd(app).await,
+            Guest::Container(ref guest) => guest.run_cmd(app).await,
+            Guest::Local(ref guest) => guest.run_cmd(app).await,
+            Guest::Adb(ref guest) => guest.run_cmd(app).await,
+            Guest::Board(ref guest) => guest.run_cmd(app).await,
+        }
+    }
+
+    /// Run command whose binary is already in guest
+    pub async fn run_guest_cmd(&self, app: &App) -> Result<Child> {
+        match self {
+            Guest::LinuxQemu(ref guest) => guest.run_guest_cmd(app).await,
+            Guest::Gvisor(ref guest) => guest.run_guest_cmd(app).await,
+            Guest::Bare(ref guest) => guest.run_guest_cmd(app).await,
+            Guest::Libvirt(ref guest) => guest.run_guest_cmd(app).await,
+            Guest::Cloud(ref guest) => guest.run_guest_cmd(app).await,
+            Guest::Container(ref guest) => guest.run_guest_cmd(app).await,
+            Guest::Local(ref guest) => guest.run_guest_cmd(app).await,
+            Guest::Adb(ref guest) => guest.run_guest_cmd(app).await,
+            Guest::Board(ref guest) => guest.run_guest_cmd(app).await,
         }
     }
 
@@ -253,36 +958,433 @@ impl Guest {
     pub async fn try_collect_crash(&mut self) -> Option<Crash> {
         match self {
             Guest::LinuxQemu(ref mut guest) => guest.try_collect_crash().await,
+            Guest::Gvisor(ref mut guest) => guest.try_collect_crash().await,
+            Guest::Bare(ref mut guest) => guest.try_collect_crash().await,
+            Guest::Libvirt(ref mut guest) => guest.try_collect_crash().await,
+            Guest::Cloud(ref mut guest) => guest.try_collect_crash().await,
+            Guest::Container(ref mut guest) => guest.try_collect_crash().await,
+            Guest::Local(ref mut guest) => guest.try_collect_crash().await,
+            Guest::Adb(ref mut guest) => guest.try_collect_crash().await,
+            Guest::Board(ref mut guest) => guest.try_collect_crash().await,
+        }
+    }
+
+    /// Console output of guest since last read. Guests whose console is not captured or is
+    /// watched by their own crash detection just drop output and return nothing.
+    pub async fn read_console(&mut self) -> Vec<u8> {
+        match self {
+            Guest::LinuxQemu(ref mut guest) => guest.read_console(),
+            _ => {
+                self.clear().await;
+                Vec::new()
+            }
         }
     }
 
     pub async fn clear(&mut self) {
         match self {
             Guest::LinuxQemu(ref mut guest) => guest.clear().await,
+            Guest::Gvisor(ref mut guest) => guest.clear().await,
+            Guest::Bare(ref mut guest) => guest.clear().await,
+            Guest::Libvirt(ref mut guest) => guest.clear().await,
+            Guest::Cloud(ref mut guest) => guest.clear().await,
+            Guest::Container(ref mut guest) => guest.clear().await,
+            Guest::Local(ref mut guest) => guest.clear().await,
+            Guest::Adb(ref mut guest) => guest.clear().await,
+            Guest::Board(ref mut guest) => guest.clear().await,
+        }
+    }
+
+    /// Save current state of guest as snapshot
+    pub async fn save_snapshot(&mut self) -> bool {
+        match self {
+            Guest::LinuxQemu(ref mut guest) => guest.save_snapshot().await,
+            Guest::Libvirt(ref mut guest) => guest.save_snapshot().await,
+            // only qemu and libvirt support snapshot
+            Guest::Gvisor(_)
+            | Guest::Bare(_)
+            | Guest::Board(_)
+            | Guest::Adb(_)
+            | Guest::Container(_)
+            | Guest::Cloud(_)
+            | Guest::Local(_) => false,
+        }
+    }
+
+    /// Restore guest from snapshot saved before, return false if failed
+    pub async fn restore_snapshot(&mut self) -> Result<bool> {
+        match self {
+            Guest::LinuxQemu(ref mut guest) => guest.restore_snapshot().await,
+            Guest::Libvirt(ref mut guest) => Ok(guest.restore_snapshot().await),
+            Guest::Gvisor(_)
+            | Guest::Bare(_)
+            | Guest::Board(_)
+            | Guest::Adb(_)
+            | Guest::Container(_)
+            | Guest::Cloud(_)
+            | Guest::Local(_) => Ok(false),
+        }
+    }
+
+    /// Issue qmp command with arguments to vm, e.g. "stop" pausing it or "dump-guest-memory",
+    /// return its result, none if qmp is not exposed or command failed.
+    pub async fn qmp(
+        &self,
+        cmd: &str,
+        args: Option<serde_json::Value>,
+    ) -> Option<serde_json::Value> {
+        match self {
+            Guest::LinuxQemu(ref guest) => guest.qmp(cmd, args).await,
+            Guest::Gvisor(_)
+            | Guest::Bare(_)
+            | Guest::Libvirt(_)
+            | Guest::Board(_)
+            | Guest::Adb(_)
+            | Guest::Container(_)
+            | Guest::Cloud(_)
+            | Guest::Local(_) => None,
         }
     }
 
-    /// Copy file from host to guest, return path in guest or crash
-    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> PathBuf {
+    /// Kernel log read from guest over a fresh session, none if guest is down or does not
+    /// run its own kernel.
+    pub async fn dmesg(&self) -> Option<String> {
+        match self {
+            Guest::LinuxQemu(ref guest) => guest.dmesg().await,
+            Guest::Bare(ref guest) => guest.dmesg().await,
+            Guest::Libvirt(ref guest) => guest.dmesg().await,
+            Guest::Cloud(ref guest) => guest.dmesg().await,
+            Guest::Board(ref guest) => guest.dmesg().await,
+            Guest::Gvisor(_) | Guest::Adb(_) | Guest::Container(_) | Guest::Local(_) => None,
+        }
+    }
+
+    /// Version, config and sanitizers of kernel running in guest, none if guest can not
+    /// even run `uname`.
+    pub async fn kernel_info(&self) -> Option<KernelInfo> {
+        let uname = self
+            .guest_output(App::new("uname").arg(Arg::new_flag("-a")))
+            .await?;
+        let config = self
+            .guest_output(App::new("zcat").arg(Arg::new_flag("/proc/config.gz")))
+            .await
+            .filter(|c| c.contains("CONFIG_"));
+        let kasan = match config.as_ref() {
+            Some(config) => Some(config.contains("CONFIG_KASAN=y")),
+            // kasan announces itself early in kernel log.
+            None => self
+                .dmesg()
+                .await
+                .filter(|log| log.contains("KernelAddressSanitizer initialized"))
+                .map(|_| true),
+        };
+        let kcov = self
+            .guest_output(App::new("ls").arg(Arg::new_flag("/sys/kernel/debug/kcov")))
+            .await
+            .is_some()
+            || config
+                .as_ref()
+                .map_or(false, |c| c.contains("CONFIG_KCOV=y"));
+        let kmemleak = self
+            .guest_output(App::new("ls").arg(Arg::new_flag("/sys/kernel/debug/kmemleak")))
+            .await
+            .is_some()
+            || config
+                .as_ref()
+                .map_or(false, |c| c.contains("CONFIG_DEBUG_KMEMLEAK=y"));
+        Some(KernelInfo {
+            name: self.kernel().map(String::from),
+            uname: uname.trim().to_string(),
+            config_md5: config.as_ref().map(|c| format!("{:x}", md5::compute(c))),
+            kasan,
+            kcov,
+            kmemleak,
+            config,
+        })
+    }
+
+    /// Set clock of guest to host time, then return host time at which guest booted, which
+    /// maps kernel timestamps of guest to host time. Guest clock stops while snapshot is
+    /// not running, so this should be redone after each boot or restore.
+    pub async fn sync_clock(&self) -> Option<DateTime<Local>> {
+        let now = format!("@{}", Local::now().timestamp());
+        let mut set = App::new("date");
+        set.arg(Arg::new_flag("-s")).arg(Arg::new_flag(&now));
+        if self.guest_output(&set).await.is_none() {
+            debug!("Fail to set clock of guest");
+        }
+        let uptime = self
+            .guest_output(App::new("cat").arg(Arg::new_flag("/proc/uptime")))
+            .await?;
+        let uptime: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+        Some(Local::now() - chrono::Duration::microseconds((uptime * 1e6) as i64))
+    }
+
+    /// Stdout of command already in guest, none if it failed or hung.
+    async fn guest_output(&self, app: &App) -> Option<String> {
+        let child = self.run_guest_cmd(app).await.ok()?;
+        match timeout(Duration::from_secs(30), child.wait_with_output()).await {
+            Ok(Ok(out)) if out.status.success() => {
+                Some(String::from_utf8_lossy(&out.stdout).into_owned())
+            }
+            _ => None,
+        }
+    }
+
+    /// Why vm of guest went down on host side rather than crashed, e.g. its qemu process was
+    /// killed by oom killer, none if it did not or this is unknown on platform.
+    pub fn host_failure(&mut self) -> Option<String> {
+        match self {
+            Guest::LinuxQemu(ref mut guest) => guest.host_failure(),
+            _ => None,
+        }
+    }
+
+    /// Copy file from host to guest, return path in guest
+    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf> {
         match self {
             Guest::LinuxQemu(ref guest) => guest.copy(path).await,
+            Guest::Gvisor(ref guest) => guest.copy(path).await,
+            Guest::Bare(ref guest) => guest.copy(path).await,
+            Guest::Libvirt(ref guest) => guest.copy(path).await,
+            Guest::Cloud(ref guest) => guest.copy(path).await,
+            Guest::Container(ref guest) => guest.copy(path).await,
+            Guest::Local(ref guest) => guest.copy(path).await,
+            Guest::Adb(ref guest) => guest.copy(path).await,
+            Guest::Board(ref guest) => guest.copy(path).await,
+        }
+    }
+}
+
+/// Checks whether guest still responds, owned by task watching guest in background.
+#[derive(Debug, Clone)]
+pub struct HealthProbe {
+    /// Ssh config, user, address and port to login guest
+    ssh: (SSHConf, String, String, u16),
+    /// Unix socket of qemu monitor, vm is asked whether it is still running
+    monitor: Option<PathBuf>,
+}
+
+impl HealthProbe {
+    fn ssh(conf: &SSHConf, user: &str, addr: &str, port: u16) -> Self {
+        Self {
+            ssh: (conf.clone(), user.to_string(), addr.to_string(), port),
+            monitor: None,
         }
     }
+
+    /// Return false if vm is stopped or paused, or guest can not be logged in.
+    pub async fn check(&self) -> bool {
+        if let Some(monitor) = self.monitor.as_ref() {
+            match monitor_cmd(monitor, "info status").await {
+                Some(out) if out.contains("VM status: running") => (),
+                _ => return false,
+            }
+        }
+        let (ref conf, ref user, ref addr, port) = self.ssh;
+        ssh_alive(conf, user, addr, port).await
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Crash {
+    /// Report of crash, extracted from raw log
     pub inner: String,
+    #[serde(default)]
+    pub kind: CrashKind,
+    #[serde(default)]
+    pub source: CrashSource,
+    /// Tail of console output when crash was found
+    #[serde(default)]
+    pub console: String,
+    /// Kernel log read from guest after crash, if guest could still be logged in
+    #[serde(default)]
+    pub dmesg: Option<String>,
+    /// Host path of memory of crashed guest, dumped as elf vmcore
+    #[serde(default)]
+    pub vmcore: Option<PathBuf>,
+    /// Result of prog if kernel stayed up, e.g. for bugs found by executor, so that the prog
+    /// is still analyzed
+    #[serde(skip)]
+    pub result: Option<ExecResult>,
 }
 
 impl Default for Crash {
     fn default() -> Self {
         Crash {
             inner: String::new(),
+            kind: CrashKind::default(),
+            source: CrashSource::default(),
+            console: String::new(),
+            dmesg: None,
+            vmcore: None,
+            result: None,
+        }
+    }
+}
+
+impl Crash {
+    /// Crash of `report` found by `source`, kind is told from report.
+    pub fn new(report: String, source: CrashSource) -> Self {
+        Crash {
+            kind: CrashKind::of(&report),
+            inner: report,
+            source,
+            ..Default::default()
+        }
+    }
+
+    /// Crash of bugs of kernel found by executor after executing a prog with `result`, of the
+    /// kind of the first one, none if none was found. Resources leaked by prog are no crash.
+    pub fn found(findings: &[Finding], result: ExecResult) -> Option<Self> {
+        let bugs = findings
+            .iter()
+            .filter(|f| f.kind != FindingKind::Leak)
+            .collect::<Vec<_>>();
+        let kind = match bugs.first()?.kind {
+            FindingKind::Memleak => CrashKind::Memleak,
+            _ => CrashKind::of(&bugs[0].report),
+        };
+        let report = bugs
+            .iter()
+            .map(|f| match f.kind {
+                FindingKind::Memleak => format!("memory leak\n{}", f.report),
+                _ => f.report.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some(Crash {
+            inner: report,
+            kind,
+            source: CrashSource::Executor,
+            result: Some(result),
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CrashKind {
+    /// Kernel panic, oops, bug or sanitizer report
+    Panic,
+    /// Kernel warning
+    Warning,
+    /// Memory leak found by kmemleak
+    Memleak,
+    /// Hung task, lockup or rcu stall
+    Hang,
+}
+
+impl Default for CrashKind {
+    fn default() -> Self {
+        CrashKind::Panic
+    }
+}
+
+/// Report lines telling a hang of kernel.
+const HANG_PATTERNS: [&str; 5] = [
+    "blocked for more than",
+    "soft lockup",
+    "hard LOCKUP",
+    "detected stall",
+    "detected stalls",
+];
+
+impl CrashKind {
+    /// Kind of crash `report` describes, panic unless report tells otherwise.
+    pub fn of(report: &str) -> Self {
+        if report.contains("memory leak") {
+            CrashKind::Memleak
+        } else if HANG_PATTERNS.iter().any(|p| report.contains(p)) {
+            CrashKind::Hang
+        } else if report.contains("WARNING:") && !report.contains("BUG:") {
+            CrashKind::Warning
+        } else {
+            CrashKind::Panic
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CrashSource {
+    /// Report appeared in console or kernel log while guest was still alive
+    Console,
+    /// Reported by executor after executing prog, e.g. memory leak
+    Executor,
+    /// Guest went down, report was recovered afterwards
+    Down,
+}
+
+impl Default for CrashSource {
+    fn default() -> Self {
+        CrashSource::Down
+    }
+}
+
+/// Kernel running in guest, kept with crashes so that they are attributable to a build.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct KernelInfo {
+    /// Name of kernel in config, if several kernels are fuzzed
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Output of `uname -a`
+    pub uname: String,
+    /// Md5 of kernel config, none if guest has no /proc/config.gz
+    pub config_md5: Option<String>,
+    /// Whether kernel is built with kasan, none if unknown
+    pub kasan: Option<bool>,
+    /// Whether kcov is available in guest
+    pub kcov: bool,
+    /// Whether kmemleak is available in guest
+    #[serde(default)]
+    pub kmemleak: bool,
+    /// Kernel config, saved aside instead of with every crash
+    #[serde(skip)]
+    pub config: Option<String>,
+}
+
+/// Bytes of console output kept with crash.
+const CONSOLE_TAIL: usize = 64 << 10;
+
+/// Last `CONSOLE_TAIL` bytes of console output, cut at line boundary.
+pub fn console_tail(console: &str) -> String {
+    if console.len() <= CONSOLE_TAIL {
+        return console.to_string();
+    }
+    let mut start = console.len() - CONSOLE_TAIL;
+    while !console.is_char_boundary(start) {
+        start += 1;
+    }
+    match console[start..].find('\n') {
+        Some(n) => console[start + n + 1..].to_string(),
+        None => console[start..].to_string(),
+    }
+}
+
+lazy_static! {
+    /// Timestamp of kernel log line, seconds since boot, optionally after log level.
+    static ref KERNEL_TIMESTAMP: Regex = Regex::new(r"(?m)^(<\d+>)?\[\s*(\d+)\.(\d+)\]").unwrap();
+}
+
+/// Rewrite kernel timestamps in `log` to host time, given host time at which guest booted.
+pub fn normalize_timestamps(log: &str, boot: DateTime<Local>) -> String {
+    KERNEL_TIMESTAMP
+        .replace_all(log, |caps: &regex::Captures| {
+            let secs: i64 = caps[2].parse().unwrap_or(0);
+            let frac = format!("{:0<6}", &caps[3][..caps[3].len().min(6)]);
+            let micros: i64 = frac.parse().unwrap_or(0);
+            let t = boot + chrono::Duration::seconds(secs) + chrono::Duration::microseconds(micros);
+            format!(
+                "{}[{}]",
+                caps.get(1).map_or("", |l| l.as_str()),
+                t.format("%Y-%m-%d %H:%M:%S%.6f")
+            )
+        })
+        .into_owned()
+}
+
 impl fmt::Display for Crash {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.inner)
@@ -292,60 +1394,322 @@ impl fmt::Display for Crash {
 pub const LINUX_QEMU_HOST_IP_ADDR: &str = "localhost";
 pub const LINUX_QEMU_USER_NET_HOST_IP_ADDR: &str = "10.0.2.10";
 pub const LINUX_QEMU_HOST_USER: &str = "root";
+/// Name of snapshot saved by healer
+const SNAPSHOT_TAG: &str = "healer";
+/// Name of virtio serial port for executor protocol.
+const VIRTIO_SERIAL_NAME: &str = "healer";
+/// Path of virtio serial port in guest, created by udev from name of the port.
+pub const VIRTIO_SERIAL_PATH: &str = "/dev/virtio-ports/healer";
+/// Default of `wait_boot_time` of qemu, in seconds.
+const DEFAULT_WAIT_BOOT_TIME: u64 = 15;
+/// Id of netdev of tap or bridge network.
+const NETDEV_ID: &str = "healer-net";
+/// Vnc display 0 listens on this port.
+const VNC_BASE_PORT: u16 = 5900;
+/// Mount tag of directory shared over virtio-9p.
+const VIRTFS_TAG: &str = "healer";
+/// Where shared directory is mounted in guest.
+const VIRTFS_GUEST_DIR: &str = "/mnt/healer";
+
+/// Distinguish vms sharing tap or bridge network.
+static NEXT_NET_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Qemu process of vm, waited on in background so that its exit is noticed at once, instead
+/// of only as failing connections to guest. Qemu is killed once this is dropped.
+struct QemuProcess {
+    kill: oneshot::Sender<()>,
+    exit: watch::Receiver<Option<ExitStatus>>,
+}
+
+impl QemuProcess {
+    fn supervise(mut child: Child) -> Self {
+        let (kill, kill_rx) = oneshot::channel();
+        let (exit_tx, exit) = watch::channel(None);
+        vm_log::spawn(async move {
+            let killed = tokio::select! {
+                _ = kill_rx => true,
+                status = &mut child => {
+                    match status {
+                        Ok(status) => {
+                            exit_tx.broadcast(Some(status)).ok();
+                        }
+                        Err(e) => warn!("Fail to wait qemu: {}", e),
+                    }
+                    false
+                }
+            };
+            if killed {
+                child.kill().ok();
+                child.await.ok();
+            }
+        });
+        Self { kill, exit }
+    }
+
+    /// Exit status of qemu, none if it is still running.
+    fn exited(&self) -> Option<ExitStatus> {
+        *self.exit.borrow()
+    }
+
+    /// Wait qemu to exit for at most `t`.
+    async fn wait(&mut self, t: Duration) -> Option<ExitStatus> {
+        let exit = &mut self.exit;
+        let wait = async move {
+            while let Some(status) = exit.recv().await {
+                if status.is_some() {
+                    return status;
+                }
+            }
+            None
+        };
+        timeout(t, wait).await.ok().flatten()
+    }
+
+    /// Kill qemu and wait it to exit.
+    async fn kill(self) {
+        let QemuProcess { kill, mut exit } = self;
+        drop(kill);
+        // supervisor drops sender once qemu is gone.
+        while exit.recv().await.is_some() {}
+    }
+}
 
 pub struct LinuxQemu {
-    handle: Option<Child>,
+    handle: Option<QemuProcess>,
     rp: Option<PipeReader>,
+    /// Unix socket of qemu monitor
+    monitor: PathBuf,
 
-    wait_boot_time: u8,
+    /// Time waiting for guest to boot, longer under tcg
+    boot_timeout: Duration,
+    /// Index of vm in network, picking its tap and address
+    net_id: usize,
+    /// Address of host seen from guest
+    host_addr: String,
     addr: String,
     port: u16,
-    key: String,
+    /// Host port of socket backing virtio serial port, if enabled
+    serial_port: Option<u16>,
+    /// Ports of ssh forwarding and virtio serial reserved for current launch
+    ports: Option<Ports>,
+    /// Holds monitor socket, overlay, shared directory and injected initrd of vm
+    work_dir: WorkDir,
+    /// Qmp socket exposed for debugging, if enabled
+    qmp: Option<PathBuf>,
+    /// Port of vnc display, kept across reboots
+    vnc: Option<Ports>,
+    /// Qcow2 overlay that vm boots from
+    overlay: Option<PathBuf>,
+    /// Recreate overlay on each boot, otherwise disk survives reboots after crashes
+    fresh_overlay: bool,
+    /// Snapshot of current boot has been saved
+    snapshot_saved: bool,
+    /// Host directory shared with guest over virtio-9p
+    share: Option<PathBuf>,
+    /// Shared directory is mounted in current boot, scp is used otherwise
+    share_mounted: bool,
+    /// Executor on host, injected into initrd if configured
+    executor: PathBuf,
+    /// Initrd with executor injected and md5 of injected executor, rebuilt on each boot
+    injected: Option<(PathBuf, md5::Digest)>,
+    ssh: SSHConf,
     user: String,
     guest: GuestConf,
+    /// Name of kernel vm runs, whose kernel, image and initrd replace those of qemu
+    kernel: Option<String>,
+    /// Cgroup limiting qemu of vm, if configured
+    cgroup: Option<Cgroup>,
     qemu: QemuConf,
 }
 
 impl LinuxQemu {
-    pub fn new(cfg: &Config) -> Self {
-        assert_eq!(cfg.guest.os, "linux");
-
-        Self {
+    pub fn new(cfg: &Config, vm: usize) -> Result<Self> {
+        let qemu = cfg.qemu.as_ref().unwrap();
+        let kernel = qemu.kernel_of(vm);
+        // qemu waits 3 times of wait_boot_time unless told otherwise
+        let mut boot_timeout = match (qemu.wait_boot_time, cfg.guest.boot_timeout) {
+            (Some(wait), _) => u64::from(wait) * 3,
+            (None, Some(timeout)) => timeout,
+            (None, None) => DEFAULT_WAIT_BOOT_TIME * 3,
+        };
+        if qemu.accel(&cfg.guest) == "tcg" {
+            if qemu.accel.is_none() {
+                TCG_WARNING.call_once(|| {
+                    warn!("/dev/kvm is not usable, falling back to tcg, guests will be much slower")
+                });
+            }
+            boot_timeout *= TCG_SLOWDOWN;
+        }
+        let work_dir = reserve_dir("qemu")?;
+        let net_id = NEXT_NET_ID.fetch_add(1, Ordering::SeqCst);
+        let (host_addr, addr) = match qemu.net.mode {
+            NetMode::User => (
+                LINUX_QEMU_USER_NET_HOST_IP_ADDR.to_string(),
+                LINUX_QEMU_HOST_IP_ADDR.to_string(),
+            ),
+            _ => {
+                let guest_addr = qemu.net.guest_addr(net_id).ok_or_else(|| {
+                    err!(exitcode::CONFIG, "No address left for vm {} in net", net_id)
+                })?;
+                (
+                    qemu.net.host_net().unwrap().0.to_string(),
+                    guest_addr.to_string(),
+                )
+            }
+        };
+        let cgroup = match qemu.cgroup.as_ref() {
+            Some(conf) => Some(Cgroup::create(conf, vm)?),
+            None => None,
+        };
+        Ok(Self {
             handle: Option::None,
             rp: Option::None,
-            wait_boot_time: cfg.qemu.wait_boot_time.unwrap_or(15),
-            addr: LINUX_QEMU_HOST_IP_ADDR.to_string(),
+            monitor: work_dir.join("monitor.sock"),
+            boot_timeout: Duration::from_secs(boot_timeout),
+            net_id,
+            host_addr,
+            addr,
             port: 0,
-            key: cfg.ssh.key_path.clone(),
-            user: LINUX_QEMU_HOST_USER.to_string(),
+            serial_port: None,
+            ports: None,
+            qmp: if qemu.qmp {
+                Some(work_dir.join("qmp.sock"))
+            } else {
+                None
+            },
+            vnc: if qemu.vnc {
+                Some(
+                    reserve_port_from(VNC_BASE_PORT)
+                        .ok_or_else(|| err!(exitcode::TEMPFAIL, "No Free port for vnc"))?,
+                )
+            } else {
+                None
+            },
+            work_dir,
+            fresh_overlay: cfg.recovery() != Recovery::Reboot,
+            overlay: None,
+            snapshot_saved: false,
+            share: None,
+            share_mounted: false,
+            executor: cfg.executor.path.clone(),
+            injected: None,
+            ssh: cfg.ssh.clone().unwrap(),
+            user: cfg.ssh.as_ref().unwrap().user(),
             guest: cfg.guest.clone(),
-            qemu: cfg.qemu.clone(),
-        }
+            kernel: kernel.map(|k| k.name.clone()),
+            cgroup,
+            qemu: kernel.map_or_else(|| qemu.clone(), |k| qemu.with_kernel(k)),
+        })
     }
 }
 
 impl LinuxQemu {
-    async fn boot(&mut self) {
-        if let Some(ref mut h) = self.handle {
-            h.kill()
-                .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to kill running guest:{}", e));
+    async fn boot(&mut self) -> Result<()> {
+        if let Some(h) = self.handle.take() {
+            h.kill().await;
             self.rp = None;
         }
 
+        if self.qemu.overlay && (self.fresh_overlay || self.overlay.is_none()) {
+            self.create_overlay().await?;
+        }
+        if self.qemu.virtfs && self.share.is_none() {
+            self.create_share().await?;
+        }
+        if self.qemu.inject {
+            self.inject_initrd().await?;
+        }
+        self.snapshot_saved = false;
+        self.launch(false).await?;
+        if self.share.is_some() {
+            self.share_mounted = self.mount_share().await?;
+        }
+        Ok(())
+    }
+
+    /// Start qemu and wait guest to come up, resume from saved snapshot of overlay if
+    /// `loadvm`. Return false if resuming failed, fail if booting failed.
+    async fn launch(&mut self, loadvm: bool) -> Result<bool> {
         const MAX_RETRY: u8 = 64;
         let mut retry = 0;
         loop {
-            let (qemu, port) = build_qemu_cli(&self.guest, &self.qemu);
-            self.port = port;
+            // release ports of last launch first, forwarding of them may have failed.
+            self.ports = None;
+            let ports = reserve_ports(2)
+                .ok_or_else(|| err!(exitcode::TEMPFAIL, "No Free port for qemu"))?;
+            // guest on tap or bridge is reached at its own address.
+            self.port = match self.qemu.net.mode {
+                NetMode::User => ports.get(0),
+                _ => self.ssh.port(),
+            };
+            let mut qemu = build_qemu_cli(&self.guest, &self.qemu, self.net_id, self.port)?;
+            if let Some(overlay) = self.overlay.as_ref() {
+                // overlay keeps image pristine already.
+                qemu.args
+                    .retain(|a| !matches!(a, Arg::Flag(f) if f == "-snapshot"));
+                set_opt(&mut qemu, "-hda", OptVal::normal(overlay.to_str().unwrap()));
+            }
+            if let Some((initrd, _)) = self.injected.as_ref() {
+                set_opt(
+                    &mut qemu,
+                    "-initrd",
+                    OptVal::normal(initrd.to_str().unwrap()),
+                );
+                let mut vals = match take_opt(&mut qemu, "-append") {
+                    Some(OptVal::Multiple { vals, .. }) => vals,
+                    Some(OptVal::Normal(val)) => vec![val],
+                    None => Vec::new(),
+                };
+                vals.push(format!("rdinit={}", initramfs::INJECT_INIT));
+                qemu.arg(Arg::new_opt("-append", OptVal::multiple(vals, Some(' '))));
+            }
+            if loadvm {
+                qemu.arg(Arg::new_opt("-loadvm", OptVal::normal(SNAPSHOT_TAG)));
+            }
+            if let Some(share) = self.share.as_ref() {
+                qemu.arg(Arg::new_opt(
+                    "-virtfs",
+                    OptVal::Normal(format!(
+                        "local,path={},mount_tag={},security_model=none,id={}",
+                        share.display(),
+                        VIRTFS_TAG,
+                        VIRTFS_TAG
+                    )),
+                ));
+            }
+            tokio::fs::remove_file(&self.monitor).await.ok();
+            qemu.arg(Arg::new_opt(
+                "-monitor",
+                OptVal::Normal(format!("unix:{},server,nowait", self.monitor.display())),
+            ));
+            if let Some(qmp) = self.qmp.as_ref() {
+                tokio::fs::remove_file(qmp).await.ok();
+                qemu.arg(Arg::new_opt(
+                    "-qmp",
+                    OptVal::Normal(format!("unix:{},server,nowait", qmp.display())),
+                ));
+            }
+            if let Some(vnc) = self.vnc.as_ref() {
+                qemu.arg(Arg::new_opt(
+                    "-vnc",
+                    OptVal::Normal(format!("127.0.0.1:{}", vnc.get(0) - VNC_BASE_PORT)),
+                ));
+            }
+            if self.qemu.virtio_serial {
+                let serial_port = ports.get(1);
+                add_virtio_serial(&mut qemu, serial_port);
+                self.serial_port = Some(serial_port);
+            }
+            self.ports = Some(ports);
 
-            let (mut handle, mut rp) = {
+            let (handle, mut rp) = {
                 let mut cmd = qemu.clone().into_cmd();
-                let (rp, wp) = long_pipe();
+                let (rp, wp) = long_pipe()?;
                 fcntl(rp.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
-                    .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to set flag on pipe:{}", e));
+                    .map_err(|e| err!(exitcode::OSERR, "Fail to set flag on pipe:{}", e))?;
                 let wp2 = wp
                     .try_clone()
-                    .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to clone pipe:{}", e));
+                    .map_err(|e| err!(exitcode::OSERR, "Fail to clone pipe:{}", e))?;
 
                 let handle = cmd
                     .stdin(std::process::Stdio::piped())
@@ -353,15 +1717,20 @@ impl LinuxQemu {
                     .stderr(wp2)
                     .kill_on_drop(true)
                     .spawn()
-                    .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to spawn qemu:{}", e));
+                    .map_err(|e| err!(exitcode::OSERR, "Fail to spawn qemu:{}", e))?;
+                if let Some(cgroup) = self.cgroup.as_ref() {
+                    // guest has barely started, so limits cover nearly all of its life.
+                    cgroup.attach(handle.id())?;
+                }
 
-                (handle, rp)
+                (QemuProcess::supervise(handle), rp)
             };
 
             let mut waited = Duration::new(0, 0);
             let wait_duration = Duration::from_millis(500);
-            let max_wait_time = Duration::new(self.wait_boot_time as u64 * 3, 0);
+            let max_wait_time = self.boot_timeout;
             let mut started = false;
+            let mut exited = None;
             let mut failed_reason = String::new();
             loop {
                 delay_for(wait_duration).await;
@@ -374,72 +1743,179 @@ impl LinuxQemu {
                     break;
                 }
 
-                if waited >= max_wait_time {
-                    handle.kill().unwrap_or_else(|e| {
-                        exits!(exitcode::OSERR, "Fail to kill failed guest:{}", e)
-                    });
-                    failed_reason
-                        .push_str(String::from_utf8_lossy(&read_all_nonblock(&mut rp)).as_ref());
+                // qemu quit early, e.g. bad option or kernel panic, no need to wait more.
+                exited = handle.exited();
+                if exited.is_some() || waited >= max_wait_time {
                     break;
                 }
             }
 
             if !started {
+                handle.kill().await;
+                failed_reason
+                    .push_str(String::from_utf8_lossy(&read_all_nonblock(&mut rp)).as_ref());
+                if loadvm && !failed_reason.contains("ould not set up host forwarding rule") {
+                    warn!(
+                        "Fail to resume guest from snapshot: {}",
+                        failed_reason.trim()
+                    );
+                    return Ok(false);
+                }
                 if !failed_reason.contains("ould not set up host forwarding rule")
                     || retry == MAX_RETRY
                 {
-                    eprintln!("Fail to boot kernel:");
-                    eprintln!("{}", failed_reason);
-                    eprintln!("======================= Command ===========================");
-                    eprintln!("{:?}", qemu);
-                    exit(1)
+                    let why = match exited {
+                        Some(status) => format!("qemu exited with {}", status),
+                        None => format!("guest did not come up in {}s", max_wait_time.as_secs()),
+                    };
+                    bail!(
+                        exitcode::UNAVAILABLE,
+                        "Fail to boot kernel, {}:\n\
+                         ======================= Console ===========================\n{}\n\
+                         ======================= Command ===========================\n{}",
+                        why,
+                        failed_reason,
+                        qemu
+                    )
                 } else {
                     retry += 1
                 }
             } else {
                 // clear useless data in pipe
                 read_all_nonblock(&mut rp);
+                if let Some(qmp) = self.qmp.as_ref() {
+                    info!("Qmp of vm on port {}: {}", self.port, qmp.display());
+                }
+                if let Some(vnc) = self.vnc.as_ref() {
+                    info!("Vnc of vm on port {}: 127.0.0.1:{}", self.port, vnc.get(0));
+                }
                 self.handle = Some(handle);
                 self.rp = Some(rp);
-                break;
+                return Ok(true);
             }
         }
     }
 
-    async fn is_alive(&self) -> bool {
-        let mut pwd = ssh_app(
-            &self.key,
+    /// Create fresh overlay backed by image, replacing the one of last boot.
+    async fn create_overlay(&mut self) -> Result<()> {
+        let overlay = self.work_dir.join("overlay.qcow2");
+        self.overlay = Some(overlay.clone());
+        tokio::fs::remove_file(&overlay).await.ok();
+
+        let image = Path::new(&self.qemu.image)
+            .canonicalize()
+            .map_err(|e| err!(exitcode::IOERR, "Fail to locate {}: {}", self.qemu.image, e))?;
+        let format = image_format(&image).await?;
+        let output = Command::new("qemu-img")
+            .args(&["create", "-f", "qcow2", "-F", &format, "-b"])
+            .arg(&image)
+            .arg(&overlay)
+            .output()
+            .await
+            .map_err(|e| err!(exitcode::OSERR, "Fail to spawn qemu-img: {}", e))?;
+        if !output.status.success() {
+            bail!(
+                exitcode::IOERR,
+                "Fail to create overlay {}: {}",
+                overlay.display(),
+                String::from_utf8_lossy(&output.stderr)
+            )
+        }
+        Ok(())
+    }
+
+    /// Build initrd with current executor injected, replacing the one of last boot.
+    async fn inject_initrd(&mut self) -> Result<()> {
+        let dst = self.work_dir.join("initrd.cpio");
+        let digest = initramfs::inject(
+            Path::new(self.qemu.initrd.as_ref().unwrap()),
+            &dst,
+            &self.executor,
+            self.qemu.init_script.as_deref(),
+            &self.ssh,
             &self.user,
-            &self.addr,
-            self.port,
-            App::new("pwd"),
         )
-        .into_cmd();
-        pwd.stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped());
-        match timeout(Duration::new(10, 0), pwd.status()).await {
-            Err(_) => false,
-            Ok(status) => match status {
-                Ok(status) => status.success(),
-                Err(e) => exits!(exitcode::OSERR, "Fail to spawn detector(ssh:pwd):{}", e),
-            },
+        .await?;
+        self.injected = Some((dst, digest));
+        Ok(())
+    }
+
+    async fn create_share(&mut self) -> Result<()> {
+        let share = self.work_dir.join("share");
+        tokio::fs::create_dir_all(&share).await.map_err(|e| {
+            err!(
+                exitcode::IOERR,
+                "Fail to create shared dir {}: {}",
+                share.display(),
+                e
+            )
+        })?;
+        self.share = Some(share);
+        Ok(())
+    }
+
+    /// Mount shared directory in guest, return false if guest can not mount it, e.g. its
+    /// kernel lacks 9p support.
+    async fn mount_share(&self) -> Result<bool> {
+        let mut mkdir = App::new("mkdir");
+        mkdir
+            .arg(Arg::new_flag("-p"))
+            .arg(Arg::new_flag(VIRTFS_GUEST_DIR));
+        let mut mount = App::new("mount");
+        mount
+            .arg(Arg::new_opt("-t", OptVal::normal("9p")))
+            .arg(Arg::new_opt(
+                "-o",
+                OptVal::normal("trans=virtio,version=9p2000.L"),
+            ))
+            .arg(Arg::new_flag(VIRTFS_TAG))
+            .arg(Arg::new_flag(VIRTFS_GUEST_DIR));
+        for app in [mkdir, mount].iter() {
+            let output = ssh_spawn(&self.ssh, &self.user, &self.addr, self.port, app)?
+                .wait_with_output()
+                .await;
+            match output {
+                Ok(output) if output.status.success() => (),
+                Ok(output) => {
+                    warn!(
+                        "Fail to mount shared dir, falling back to scp: {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                    return Ok(false);
+                }
+                Err(e) => bail!(exitcode::OSERR, "Fail to wait ssh: {}", e),
+            }
+        }
+        Ok(true)
+    }
+
+    async fn is_alive(&self) -> bool {
+        if self.handle.as_ref().map_or(false, |h| h.exited().is_some()) {
+            return false;
+        }
+        ssh_alive(&self.ssh, &self.user, &self.addr, self.port).await
+    }
+
+    fn health_probe(&self) -> HealthProbe {
+        let mut probe = HealthProbe::ssh(&self.ssh, &self.user, &self.addr, self.port);
+        if self.handle.is_some() {
+            probe.monitor = Some(self.monitor.clone());
         }
+        probe
     }
 
-    async fn run_cmd(&self, app: &App) -> Child {
+    async fn run_cmd(&self, app: &App) -> Result<Child> {
         assert!(self.handle.is_some());
 
         let mut app = app.clone();
-        let bin = self.copy(PathBuf::from(&app.bin)).await;
+        let bin = self.copy(PathBuf::from(&app.bin)).await?;
         app.bin = String::from(bin.to_str().unwrap());
-        let mut app = ssh_app(&self.key, &self.user, &self.addr, self.port, app).into_cmd();
-        app.stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .kill_on_drop(true)
-            .spawn()
-            .unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to spawn:{}", e))
+        self.run_guest_cmd(&app).await
+    }
+
+    async fn run_guest_cmd(&self, app: &App) -> Result<Child> {
+        assert!(self.handle.is_some());
+        ssh_spawn(&self.ssh, &self.user, &self.addr, self.port, app)
     }
 
     async fn clear(&mut self) {
@@ -448,97 +1924,629 @@ impl LinuxQemu {
         }
     }
 
-    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> PathBuf {
-        let path = path.as_ref();
-        assert!(path.is_file());
-
-        let file_name = path.file_name().unwrap().to_str().unwrap();
-        let guest_path = PathBuf::from(format!("~/{}", file_name));
-
-        let mut scp = SCP.clone();
-        scp.arg(Arg::new_opt("-P", OptVal::normal(&self.port.to_string())))
-            .arg(Arg::new_opt("-i", OptVal::normal(&self.key)))
-            .arg(Arg::new_flag(path.to_str().unwrap()))
-            .arg(Arg::Flag(format!(
-                "{}@{}:{}",
-                self.user,
-                self.addr,
-                guest_path.display()
-            )));
-
-        let output = scp
-            .into_cmd()
-            .output()
-            .await
-            .unwrap_or_else(|e| panic!("Failed to spawn:{}", e));
+    fn read_console(&mut self) -> Vec<u8> {
+        let mut output = Vec::new();
+        if let Some(r) = self.rp.as_mut() {
+            loop {
+                let buf = read_all_nonblock(r);
+                if buf.is_empty() {
+                    break;
+                }
+                output.extend(buf);
+            }
+        }
+        output
+    }
 
-        if !output.status.success() {
-            panic!(String::from_utf8(output.stderr).unwrap());
+    async fn save_snapshot(&mut self) -> bool {
+        self.snapshot_saved = match self.monitor_cmd(&format!("savevm {}", SNAPSHOT_TAG)).await {
+            Some(out) => !out.contains("rror"),
+            None => false,
+        };
+        self.snapshot_saved
+    }
+
+    /// Load snapshot into running qemu, or start qemu from snapshot kept in overlay if qemu
+    /// is gone, e.g. killed by kernel panic.
+    async fn restore_snapshot(&mut self) -> Result<bool> {
+        if !self.snapshot_saved {
+            return Ok(false);
+        }
+        if self.handle.is_none() {
+            return Ok(self.overlay.is_some() && self.launch(true).await?);
+        }
+        let restored = match self.monitor_cmd(&format!("loadvm {}", SNAPSHOT_TAG)).await {
+            Some(out) => !out.contains("rror"),
+            None => false,
+        };
+        if restored {
+            self.clear().await;
+            Ok(self.is_alive().await)
+        } else {
+            Ok(false)
+        }
+    }
+
+    async fn monitor_cmd(&self, cmd: &str) -> Option<String> {
+        monitor_cmd(&self.monitor, cmd).await
+    }
+
+    async fn qmp(&self, cmd: &str, args: Option<serde_json::Value>) -> Option<serde_json::Value> {
+        qmp_cmd(self.qmp.as_ref()?, cmd, args).await
+    }
+
+    /// Copy file into shared directory if it is mounted, otherwise via scp.
+    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf> {
+        let path = path.as_ref();
+        if let Some((_, injected)) = self.injected.as_ref() {
+            // executor injected at boot is still current, nothing to copy.
+            if path == self.executor.as_path()
+                && md5::compute(std::fs::read(path).unwrap_or_default()) == *injected
+            {
+                return Ok(Path::new(initramfs::INJECT_DIR).join(path.file_name().unwrap()));
+            }
+        }
+        match self.share.as_ref() {
+            Some(share) if self.share_mounted => {
+                let file_name = path.file_name().unwrap();
+                let dst = share.join(file_name);
+                // executor in guest may still run old file, replace it instead of rewriting.
+                tokio::fs::remove_file(&dst).await.ok();
+                tokio::fs::copy(path, &dst).await.map_err(|e| {
+                    err!(
+                        exitcode::IOERR,
+                        "Fail to copy {} to {}: {}",
+                        path.display(),
+                        dst.display(),
+                        e
+                    )
+                })?;
+                Ok(Path::new(VIRTFS_GUEST_DIR).join(file_name))
+            }
+            _ => scp_copy(&self.ssh, &self.user, &self.addr, self.port, path).await,
         }
-        guest_path
     }
 
     async fn try_collect_crash(&mut self) -> Option<Crash> {
         assert!(self.rp.is_some());
-        match timeout(Duration::new(30, 0), self.handle.as_mut().unwrap()).await {
-            Err(_e) => {
+        match self
+            .handle
+            .as_mut()
+            .unwrap()
+            .wait(Duration::new(30, 0))
+            .await
+        {
+            None => {
                 if !self.is_alive().await {
-                    Some(self.collect_crash())
+                    Some(self.collect_crash(true).await)
                 } else {
                     None
                 }
             }
-            Ok(_) => Some(self.collect_crash()),
+            Some(_) => Some(self.collect_crash(false).await),
         }
     }
 
-    fn collect_crash(&mut self) -> Crash {
+    /// Qemu exited with failure rather than because guest rebooted after panic(qemu runs
+    /// with `-no-reboot`), e.g. killed by oom killer or failed on host side. Return its exit
+    /// status and messages, dropping qemu of current boot.
+    fn host_failure(&mut self) -> Option<String> {
+        let status = self.handle.as_ref()?.exited()?;
+        if status.success() {
+            return None;
+        }
+        let console = self.read_console();
+        let messages = String::from_utf8_lossy(&console)
+            .lines()
+            .filter(|l| l.split(':').next().map_or(false, |p| p.starts_with("qemu")))
+            .collect::<Vec<_>>()
+            .join("\n");
         self.handle = None;
-        let crash = read_all_nonblock(self.rp.as_mut().unwrap());
-        let crash_info = String::from_utf8_lossy(&crash).to_string();
         self.rp = None;
-        Crash { inner: crash_info }
+        Some(format!("qemu exited with {}: {}", status, messages))
+    }
+
+    /// Collect report and console tail of crash, and memory of vm if qemu is still `running`,
+    /// e.g. kernel halted after panic.
+    async fn collect_crash(&mut self, running: bool) -> Crash {
+        let vmcore = match self.qemu.vmcore_dir.as_ref() {
+            Some(dir) if running => self.dump_vmcore(dir).await,
+            _ => None,
+        };
+        self.handle = None;
+        let crash = self.read_console();
+        let crash_info = String::from_utf8_lossy(&crash);
+        self.rp = None;
+        Crash {
+            console: console_tail(&crash_info),
+            vmcore,
+            ..Crash::new(
+                extract_report(&crash_info, &self.guest.os).to_string(),
+                CrashSource::Down,
+            )
+        }
+    }
+
+    async fn dump_vmcore(&self, dir: &Path) -> Option<PathBuf> {
+        // qemu resolves relative path against its own working directory
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let path = dir.join(format!(
+            "vmcore-{}-{}",
+            self.port,
+            Local::now().format("%Y%m%d%H%M%S")
+        ));
+        let out = self
+            .monitor_cmd(&format!("dump-guest-memory {}", path.display()))
+            .await?;
+        if out.contains("rror") || !path.is_file() {
+            warn!("Fail to dump vmcore: {}", out.trim());
+            None
+        } else {
+            Some(path)
+        }
+    }
+
+    /// Kernel log of guest, if it can still be logged in.
+    async fn dmesg(&self) -> Option<String> {
+        if self.handle.is_none() || !self.is_alive().await {
+            return None;
+        }
+        ssh_output(
+            &self.ssh,
+            &self.user,
+            &self.addr,
+            self.port,
+            &App::new("dmesg"),
+        )
+        .await
     }
 }
 
-fn build_qemu_cli(g: &GuestConf, q: &QemuConf) -> (App, u16) {
+/// Send human monitor command to qemu, return its output.
+async fn monitor_cmd(monitor: &Path, cmd: &str) -> Option<String> {
+    const PROMPT: &[u8] = b"(qemu) ";
+
+    async fn read_until_prompt(s: &mut UnixStream) -> std::io::Result<String> {
+        let mut out = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let n = s.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+            if out.ends_with(PROMPT) {
+                break;
+            }
+        }
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    let cmd = format!("{}\n", cmd);
+    let exec = async {
+        let mut s = UnixStream::connect(monitor).await?;
+        // banner
+        read_until_prompt(&mut s).await?;
+        s.write_all(cmd.as_bytes()).await?;
+        read_until_prompt(&mut s).await
+    };
+    match timeout(Duration::new(60, 0), exec).await {
+        Ok(Ok(out)) => Some(out),
+        Ok(Err(e)) => {
+            warn!("Fail to send monitor command `{}`: {}", cmd.trim(), e);
+            None
+        }
+        Err(_) => {
+            warn!("Monitor command `{}` time out", cmd.trim());
+            None
+        }
+    }
+}
+
+/// Execute qmp command, return its result. Socket accepts one client at a time, so this
+/// fails while a developer is attached.
+async fn qmp_cmd(
+    qmp: &Path,
+    cmd: &str,
+    args: Option<serde_json::Value>,
+) -> Option<serde_json::Value> {
+    async fn read_reply(s: &mut BufReader<UnixStream>) -> std::io::Result<serde_json::Value> {
+        loop {
+            let mut line = String::new();
+            if s.read_line(&mut line).await? == 0 {
+                return Err(std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "qmp socket closed",
+                ));
+            }
+            let msg: serde_json::Value = serde_json::from_str(&line)?;
+            // greeting and asynchronous events carry no reply
+            if msg.get("return").is_some() || msg.get("error").is_some() {
+                return Ok(msg);
+            }
+        }
+    }
+
+    let mut req = serde_json::json!({ "execute": cmd });
+    if let Some(args) = args {
+        req["arguments"] = args;
+    }
+    let exec = async {
+        let mut s = BufReader::new(UnixStream::connect(qmp).await?);
+        s.write_all(b"{\"execute\": \"qmp_capabilities\"}\n")
+            .await?;
+        read_reply(&mut s).await?;
+        s.write_all(format!("{}\n", req).as_bytes()).await?;
+        read_reply(&mut s).await
+    };
+    match timeout(Duration::new(60, 0), exec).await {
+        Ok(Ok(mut reply)) => {
+            if reply.get("return").is_some() {
+                Some(reply["return"].take())
+            } else {
+                warn!("Qmp command `{}` failed: {}", cmd, reply["error"]);
+                None
+            }
+        }
+        Ok(Err(e)) => {
+            warn!("Fail to send qmp command `{}`: {}", cmd, e);
+            None
+        }
+        Err(_) => {
+            warn!("Qmp command `{}` time out", cmd);
+            None
+        }
+    }
+}
+
+/// Format of disk image, as detected by qemu-img.
+async fn image_format(image: &Path) -> Result<String> {
+    #[derive(Deserialize)]
+    struct ImageInfo {
+        format: String,
+    }
+
+    let output = Command::new("qemu-img")
+        .args(&["info", "--output=json"])
+        .arg(image)
+        .output()
+        .await
+        .map_err(|e| err!(exitcode::OSERR, "Fail to spawn qemu-img: {}", e))?;
+    if !output.status.success() {
+        bail!(
+            exitcode::IOERR,
+            "Fail to inspect image {}: {}",
+            image.display(),
+            String::from_utf8_lossy(&output.stderr)
+        )
+    }
+    serde_json::from_slice::<ImageInfo>(&output.stdout)
+        .map(|info| info.format)
+        .map_err(|e| err!(exitcode::SOFTWARE, "Bad output of qemu-img info: {}", e))
+}
+
+/// Crash reported in console output, if any.
+pub fn find_crash(console: &str, os: &str) -> Option<Crash> {
+    if crash_patterns(os).iter().any(|p| console.contains(p)) {
+        Some(Crash {
+            console: console_tail(console),
+            ..Crash::new(
+                extract_report(console, os).to_string(),
+                CrashSource::Console,
+            )
+        })
+    } else {
+        None
+    }
+}
+
+/// Console lines that begin a crash report of each os.
+fn crash_patterns(os: &str) -> &'static [&'static str] {
+    match os {
+        "freebsd" => &[
+            "panic:",
+            "Fatal trap",
+            "lock order reversal",
+            "witness_",
+            "KASSERT",
+        ],
+        _ => &[
+            "BUG:",
+            "WARNING:",
+            "INFO:",
+            "Kernel panic",
+            "general protection fault",
+            "Unable to handle kernel",
+            "KASAN:",
+            "UBSAN:",
+        ],
+    }
+}
+
+/// Strip boot messages and other noise before crash report, keep whole output if
+/// no known report found.
+fn extract_report<'a>(console: &'a str, os: &str) -> &'a str {
+    &console[report_offset(console, os).unwrap_or(0)..]
+}
+
+/// Offset of line where first known crash report in console begins.
+pub fn report_offset(console: &str, os: &str) -> Option<usize> {
+    crash_patterns(os)
+        .iter()
+        .filter_map(|p| console.find(p))
+        .min()
+        .map(|i| console[..i].rfind('\n').map(|n| n + 1).unwrap_or(0))
+}
+
+fn build_qemu_cli(g: &GuestConf, q: &QemuConf, net_id: usize, port: u16) -> Result<App> {
     let target = format!("{}/{}", g.os, g.arch);
 
     let mut qemu = QEMUS
         .get(&target)
-        .unwrap_or_else(|| exits!(exitcode::CONFIG, "Unsupported target:{}", &target))
+        .ok_or_else(|| err!(exitcode::CONFIG, "Unsupported target:{}", &target))?
         .clone();
 
-    // use low level port
-    let port =
-        free_ipv4_port().unwrap_or_else(|| exits!(exitcode::TEMPFAIL, "No Free port to forword"));
     let cfg = q;
 
     qemu.arg(Arg::new_opt("-m", OptVal::Normal(cfg.mem_size.to_string())))
         .arg(Arg::new_opt(
             "-smp",
             OptVal::Normal(cfg.cpu_num.to_string()),
-        ))
+        ));
+    if !cfg.image.is_empty() {
+        qemu.arg(Arg::new_opt("-hda", OptVal::Normal(cfg.image.clone())));
+    } else if let Some(OptVal::Multiple { vals, sp }) = take_opt(&mut qemu, "-append") {
+        // no disk to mount as root, guest runs from initrd.
+        let vals = vals.into_iter().filter(|v| !v.starts_with("root="));
+        qemu.arg(Arg::new_opt("-append", OptVal::multiple(vals, sp)));
+    }
+    if !cfg.kernel.is_empty() {
+        qemu.arg(Arg::new_opt("-kernel", OptVal::Normal(cfg.kernel.clone())));
+    }
+    if let Some(initrd) = cfg.initrd.as_ref() {
+        qemu.arg(Arg::new_opt("-initrd", OptVal::normal(initrd)));
+    }
+    if let Some(machine) = cfg.machine.as_ref() {
+        set_opt(&mut qemu, "-machine", OptVal::normal(machine));
+    }
+    if let Some(cmdline) = cfg.cmdline.as_ref() {
+        let mut vals = match take_opt(&mut qemu, "-append") {
+            Some(OptVal::Multiple { vals, .. }) => vals,
+            Some(OptVal::Normal(val)) => vec![val],
+            None => Vec::new(),
+        };
+        vals.extend(cmdline.split_whitespace().map(String::from));
+        qemu.arg(Arg::new_opt("-append", OptVal::multiple(vals, Some(' '))));
+    }
+    for device in cfg.devices.iter() {
+        qemu.arg(Arg::new_opt("-device", OptVal::normal(device)));
+    }
+    add_net(&mut qemu, &cfg.net, net_id, port);
+    let accel = cfg.accel(g);
+    qemu.args.retain(|a| !is_enable_kvm(a));
+    qemu.arg(Arg::new_opt("-accel", OptVal::normal(accel)));
+    if accel == "tcg" {
+        // host cpu model is only available under kvm.
+        let host_cpu = match qemu.args.iter().find_map(|a| match a {
+            Arg::Option { name, val } if name == "-cpu" => Some(val),
+            _ => None,
+        }) {
+            Some(OptVal::Normal(cpu)) => cpu == "host",
+            Some(OptVal::Multiple { vals, .. }) => vals.first().map_or(false, |c| c == "host"),
+            None => false,
+        };
+        if host_cpu {
+            set_opt(&mut qemu, "-cpu", OptVal::normal("max"));
+        }
+    }
+    for arg in cfg.extra_args.iter() {
+        qemu.arg(Arg::new_flag(arg));
+    }
+    Ok(qemu)
+}
+
+fn is_enable_kvm(arg: &Arg) -> bool {
+    matches!(arg, Arg::Flag(f) if f == "-enable-kvm")
+}
+
+/// Remove option `name` from app, return its value.
+fn take_opt(app: &mut App, name: &str) -> Option<OptVal> {
+    let i = app
+        .args
+        .iter()
+        .position(|a| matches!(a, Arg::Option { name: n, .. } if n == name))?;
+    match app.args.remove(i) {
+        Arg::Option { val, .. } => Some(val),
+        Arg::Flag(_) => unreachable!(),
+    }
+}
+
+/// Set option `name` of app, replacing existing one.
+fn set_opt(app: &mut App, name: &str, val: OptVal) {
+    take_opt(app, name);
+    app.arg(Arg::new_opt(name, val));
+}
+
+/// Attach vm `id` to network: user network forwarding ssh to `port` of host, or tap with
+/// static address set through kernel command line.
+fn add_net(qemu: &mut App, net: &NetConf, id: usize, port: u16) {
+    if net.mode == NetMode::User {
+        if let Some(model) = net.model.as_ref() {
+            set_opt(qemu, "-net", OptVal::Normal(format!("nic,model={}", model)));
+        }
+        let mut vals = vec![
+            String::from("user"),
+            format!("host={}", LINUX_QEMU_USER_NET_HOST_IP_ADDR),
+            format!("hostfwd=tcp::{}-:22", port),
+        ];
+        vals.extend(net.forwards.iter().map(|f| format!("hostfwd={}", f)));
+        qemu.arg(Arg::new_opt("-net", OptVal::multiple(vals, Some(','))));
+        return;
+    }
+
+    qemu.args
+        .retain(|a| !matches!(a, Arg::Option { name, .. } if name == "-net"));
+    let netdev = match net.mode {
+        NetMode::Tap => format!(
+            "tap,id={},ifname={},script=no,downscript=no",
+            NETDEV_ID, net.taps[id]
+        ),
+        _ => format!(
+            "bridge,id={},br={}",
+            NETDEV_ID,
+            net.bridge.as_ref().unwrap()
+        ),
+    };
+    qemu.arg(Arg::new_opt("-netdev", OptVal::Normal(netdev)));
+    qemu.arg(Arg::new_opt(
+        "-device",
+        OptVal::Normal(format!(
+            "{},netdev={},mac=52:54:00:{:02x}:{:02x}:{:02x}",
+            net.model.as_deref().unwrap_or("e1000"),
+            NETDEV_ID,
+            (id >> 16) & 0xff,
+            (id >> 8) & 0xff,
+            id & 0xff
+        )),
+    ));
+
+    let (host, _) = net.host_net().unwrap();
+    let ip = format!(
+        "ip={}::{}:{}:::off",
+        net.guest_addr(id).unwrap(),
+        host,
+        net.netmask().unwrap()
+    );
+    let mut vals = match take_opt(qemu, "-append") {
+        Some(OptVal::Multiple { vals, .. }) => vals,
+        Some(OptVal::Normal(val)) => vec![val],
+        None => Vec::new(),
+    };
+    vals.push(ip);
+    qemu.arg(Arg::new_opt("-append", OptVal::multiple(vals, Some(' '))));
+}
+
+/// Expose a virtio serial port in guest, backed by a tcp socket on host that fuzzer connects to.
+fn add_virtio_serial(qemu: &mut App, port: u16) {
+    let id = "healer-serial";
+    qemu.arg(Arg::new_opt("-device", OptVal::normal("virtio-serial")))
         .arg(Arg::new_opt(
-            "-net",
+            "-chardev",
             OptVal::Multiple {
                 vals: vec![
-                    String::from("user"),
-                    format!("host={}", LINUX_QEMU_USER_NET_HOST_IP_ADDR),
-                    format!("hostfwd=tcp::{}-:22", port),
+                    format!("socket,id={}", id),
+                    format!("host={}", LINUX_QEMU_HOST_IP_ADDR),
+                    format!("port={}", port),
+                    String::from("server"),
+                    String::from("nowait"),
                 ],
                 sp: Some(','),
             },
         ))
-        .arg(Arg::new_opt("-hda", OptVal::Normal(cfg.image.clone())))
-        .arg(Arg::new_opt("-kernel", OptVal::Normal(cfg.kernel.clone())));
-    (qemu, port)
+        .arg(Arg::new_opt(
+            "-device",
+            OptVal::Multiple {
+                vals: vec![
+                    String::from("virtserialport"),
+                    format!("chardev={}", id),
+                    format!("name={}", VIRTIO_SERIAL_NAME),
+                ],
+                sp: Some(','),
+            },
+        ));
+}
+
+/// Whether guest can be logged in via ssh.
+async fn ssh_alive(conf: &SSHConf, user: &str, addr: &str, port: u16) -> bool {
+    let mut pwd = ssh_app(conf, user, addr, port, App::new("pwd")).into_cmd();
+    pwd.stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    match timeout(conf.connect_timeout(), pwd.status()).await {
+        Err(_) => false,
+        Ok(status) => match status {
+            Ok(status) => status.success(),
+            Err(e) => {
+                warn!("Fail to spawn detector(ssh:pwd):{}", e);
+                false
+            }
+        },
+    }
+}
+
+/// Run command in guest via ssh, return its stdout if it succeeded in time.
+async fn ssh_output(
+    conf: &SSHConf,
+    user: &str,
+    addr: &str,
+    port: u16,
+    app: &App,
+) -> Option<String> {
+    let child = ssh_spawn(conf, user, addr, port, app).ok()?;
+    match timeout(Duration::from_secs(30), child.wait_with_output()).await {
+        Ok(Ok(out)) if out.status.success() => {
+            Some(String::from_utf8_lossy(&out.stdout).into_owned())
+        }
+        _ => None,
+    }
+}
+
+/// Run command in guest via ssh.
+fn ssh_spawn(conf: &SSHConf, user: &str, addr: &str, port: u16, app: &App) -> Result<Child> {
+    let mut app = ssh_app(conf, user, addr, port, app.clone()).into_cmd();
+    app.stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| err!(exitcode::OSERR, "Fail to spawn:{}", e))
+}
+
+/// Copy file into home of user in guest via scp, return path in guest.
+async fn scp_copy(
+    conf: &SSHConf,
+    user: &str,
+    addr: &str,
+    port: u16,
+    path: &Path,
+) -> Result<PathBuf> {
+    assert!(path.is_file());
+
+    let file_name = path.file_name().unwrap().to_str().unwrap();
+    let guest_path = PathBuf::from(format!("~/{}", file_name));
+
+    let mut scp = SCP.clone();
+    conf.apply(&mut scp);
+    scp.arg(Arg::new_opt("-P", OptVal::normal(&port.to_string())))
+        .arg(Arg::new_flag(path.to_str().unwrap()))
+        .arg(Arg::Flag(format!(
+            "{}@{}:{}",
+            user,
+            addr,
+            guest_path.display()
+        )));
+
+    let output = scp
+        .into_cmd()
+        .output()
+        .await
+        .map_err(|e| err!(exitcode::OSERR, "Failed to spawn:{}", e))?;
+
+    if !output.status.success() {
+        bail!(
+            exitcode::UNAVAILABLE,
+            "Fail to copy {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr)
+        )
+    }
+    Ok(guest_path)
 }
 
-fn ssh_app(key: &str, user: &str, addr: &str, port: u16, app: App) -> App {
+fn ssh_app(conf: &SSHConf, user: &str, addr: &str, port: u16, app: App) -> App {
     let mut ssh = SSH.clone();
+    conf.apply(&mut ssh);
     ssh.arg(Arg::new_opt("-p", OptVal::normal(&port.to_string())))
-        .arg(Arg::new_opt("-i", OptVal::normal(key)))
         .arg(Arg::Flag(format!("{}@{}", user, addr)))
         .arg(Arg::new_flag(&app.bin));
     for app_arg in app.iter_arg() {
@@ -548,8 +2556,8 @@ fn ssh_app(key: &str, user: &str, addr: &str, port: u16, app: App) -> App {
 }
 
 #[allow(unused)]
-fn long_pipe() -> (PipeReader, PipeWriter) {
-    let (rp, wp) = pipe().unwrap_or_else(|e| exits!(exitcode::OSERR, "Fail to creat pipe:{}", e));
+fn long_pipe() -> Result<(PipeReader, PipeWriter)> {
+    let (rp, wp) = pipe().map_err(|e| err!(exitcode::OSERR, "Fail to creat pipe:{}", e))?;
 
     let mut sz = 128 << 10;
     while sz <= 2 << 20 {
@@ -557,10 +2565,10 @@ fn long_pipe() -> (PipeReader, PipeWriter) {
         sz *= 2;
     }
 
-    (rp, wp)
+    Ok((rp, wp))
 }
 
-fn read_all_nonblock(rp: &mut PipeReader) -> Vec<u8> {
+fn read_all_nonblock<R: Read>(rp: &mut R) -> Vec<u8> {
     const BUF_LEN: usize = 1024 * 1024;
     let mut result = Vec::with_capacity(BUF_LEN);
     unsafe {
@@ -0,0 +1,303 @@
+//! Android device controlled via adb. Kernel log is followed with logcat, report of crash
+//! that rebooted the device is recovered from pstore or last_kmsg after it comes back.
+
+use super::{crash_patterns, extract_report, long_pipe, read_all_nonblock, Crash, CrashSource};
+use crate::error::Result;
+use crate::utils::cli::{App, Arg, OptVal};
+use crate::Config;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use os_pipe::PipeReader;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Child;
+use tokio::time::{delay_for, timeout, Duration, Instant};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdbConf {
+    /// Serial of device, needed if more than one device is attached
+    pub serial: Option<String>,
+    /// Directory in device where executor and target are pushed, /data/local/tmp by default
+    pub work_dir: Option<String>,
+    /// Restart adbd as root after boot, needs userdebug or eng build
+    #[serde(default)]
+    pub root: bool,
+    /// Time waiting for device to boot up, in seconds
+    pub boot_wait: Option<u64>,
+}
+
+impl AdbConf {
+    pub fn check(&self) -> Result<()> {
+        if let Some(dir) = self.work_dir.as_ref() {
+            if !dir.starts_with('/') {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: work_dir {} of adb must be absolute",
+                    dir
+                )
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Executor reaches fuzzer through `adb reverse`, so host is local to device.
+pub const ADB_HOST_ADDR: &str = "127.0.0.1";
+const DEFAULT_WORK_DIR: &str = "/data/local/tmp";
+const DEFAULT_BOOT_WAIT: u64 = 180;
+/// Where kernel log of last boot is kept, newer devices use pstore.
+const LAST_KMSG: [&str; 2] = ["/sys/fs/pstore/console-ramoops-0", "/proc/last_kmsg"];
+
+pub struct Adb {
+    serial: Option<String>,
+    work_dir: String,
+    root: bool,
+    boot_wait: Duration,
+    os: String,
+    /// logcat following kernel buffer
+    logcat: Option<Child>,
+    rp: Option<PipeReader>,
+    /// Kernel log since last boot or clear
+    output: Vec<u8>,
+    /// Device has been taken over by fuzzer, so next boot must reboot it.
+    booted: bool,
+}
+
+impl Adb {
+    pub fn new(cfg: &Config) -> Self {
+        let conf = cfg.adb.as_ref().unwrap();
+        Self {
+            serial: conf.serial.clone(),
+            work_dir: conf
+                .work_dir
+                .clone()
+                .unwrap_or_else(|| DEFAULT_WORK_DIR.to_string()),
+            root: conf.root,
+            boot_wait: Duration::from_secs(
+                conf.boot_wait
+                    .or(cfg.guest.boot_timeout)
+                    .unwrap_or(DEFAULT_BOOT_WAIT),
+            ),
+            os: cfg.guest.os.clone(),
+            logcat: None,
+            rp: None,
+            output: Vec::new(),
+            booted: false,
+        }
+    }
+
+    pub fn host_addr(&self) -> &str {
+        ADB_HOST_ADDR
+    }
+
+    /// Reboot device if it was used, wait it to finish booting and start following kernel log.
+    pub async fn boot(&mut self) -> Result<()> {
+        if let Some(mut logcat) = self.logcat.take() {
+            logcat.kill().ok();
+        }
+        self.rp = None;
+        if self.booted {
+            self.adb(&["reboot"]).await;
+        }
+        if !self.wait_boot().await {
+            bail!(
+                exitcode::UNAVAILABLE,
+                "Device did not boot in {}s",
+                self.boot_wait.as_secs()
+            )
+        }
+        if self.root {
+            self.adb(&["root"]).await;
+            if !self.wait_boot().await {
+                bail!(
+                    exitcode::UNAVAILABLE,
+                    "Device did not come back after adb root"
+                )
+            }
+        }
+        self.booted = true;
+        self.follow_kmsg()?;
+        self.clear().await;
+        Ok(())
+    }
+
+    async fn wait_boot(&self) -> bool {
+        let start = Instant::now();
+        let mut wait = self.app(&["wait-for-device"]).into_cmd();
+        if timeout(self.boot_wait, wait.status()).await.is_err() {
+            return false;
+        }
+        while start.elapsed() < self.boot_wait {
+            if let Some(out) = self.adb(&["shell", "getprop", "sys.boot_completed"]).await {
+                if out.trim() == "1" {
+                    return true;
+                }
+            }
+            delay_for(Duration::from_secs(2)).await;
+        }
+        false
+    }
+
+    fn follow_kmsg(&mut self) -> Result<()> {
+        let (rp, wp) = long_pipe()?;
+        fcntl(rp.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+            .map_err(|e| err!(exitcode::OSERR, "Fail to set flag on pipe:{}", e))?;
+        let logcat = self
+            .app(&["logcat", "-b", "kernel", "-v", "raw", "-T", "1"])
+            .into_cmd()
+            .stdin(Stdio::null())
+            .stdout(wp)
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| err!(exitcode::OSERR, "Fail to spawn logcat:{}", e))?;
+        self.logcat = Some(logcat);
+        self.rp = Some(rp);
+        Ok(())
+    }
+
+    fn read_kmsg(&mut self) {
+        if let Some(rp) = self.rp.as_mut() {
+            loop {
+                let buf = read_all_nonblock(rp);
+                if buf.is_empty() {
+                    break;
+                }
+                self.output.extend(buf);
+            }
+        }
+    }
+
+    /// Make port of host reachable from device at same port of `ADB_HOST_ADDR`.
+    pub async fn forward(&mut self, port: u16) -> Result<()> {
+        let port = format!("tcp:{}", port);
+        if self.adb(&["reverse", &port, &port]).await.is_none() {
+            bail!(exitcode::UNAVAILABLE, "Fail to reverse forward {}", port)
+        }
+        Ok(())
+    }
+
+    pub async fn is_alive(&self) -> bool {
+        let mut echo = self.app(&["shell", "echo"]).into_cmd();
+        match timeout(Duration::from_secs(10), echo.output()).await {
+            Ok(Ok(out)) => out.status.success(),
+            _ => false,
+        }
+    }
+
+    pub async fn run_cmd(&self, app: &App) -> Result<Child> {
+        let mut app = app.clone();
+        let bin = self.copy(PathBuf::from(&app.bin)).await?;
+        app.bin = String::from(bin.to_str().unwrap());
+        self.run_guest_cmd(&app).await
+    }
+
+    pub async fn run_guest_cmd(&self, app: &App) -> Result<Child> {
+        let mut shell = self.app(&["shell"]);
+        shell.arg(Arg::new_flag(&app.bin));
+        for arg in app.clone().iter_arg() {
+            shell.arg(Arg::Flag(arg));
+        }
+        shell
+            .into_cmd()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| err!(exitcode::OSERR, "Fail to spawn:{}", e))
+    }
+
+    pub async fn clear(&mut self) {
+        self.read_kmsg();
+        self.output.clear();
+    }
+
+    /// Push file into work dir of device, return path in device.
+    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf> {
+        let path = path.as_ref();
+        assert!(path.is_file());
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        let guest_path = format!("{}/{}", self.work_dir, file_name);
+        if self
+            .adb(&["push", path.to_str().unwrap(), &guest_path])
+            .await
+            .is_none()
+        {
+            bail!(exitcode::UNAVAILABLE, "Fail to push {}", path.display())
+        }
+        self.adb(&["shell", "chmod", "755", &guest_path]).await;
+        Ok(PathBuf::from(guest_path))
+    }
+
+    /// Crash is reported by kernel log of device, or recovered from log of last boot if
+    /// device rebooted or disappeared.
+    pub async fn try_collect_crash(&mut self) -> Option<Crash> {
+        self.read_kmsg();
+        let output = String::from_utf8_lossy(&self.output).into_owned();
+        let reported = crash_patterns(&self.os).iter().any(|p| output.contains(p));
+        if reported {
+            return Some(Crash::new(
+                extract_report(&output, &self.os).to_string(),
+                CrashSource::Console,
+            ));
+        }
+        if self.is_alive().await {
+            return None;
+        }
+        // device rebooted by panic, report is kept in log of last boot.
+        if !self.wait_boot().await {
+            return Some(Crash::new(output, CrashSource::Down));
+        }
+        if self.root {
+            self.adb(&["root"]).await;
+            self.wait_boot().await;
+        }
+        for kmsg in LAST_KMSG.iter() {
+            if let Some(last) = self.adb(&["shell", "cat", kmsg]).await {
+                if !last.trim().is_empty() {
+                    return Some(Crash::new(
+                        extract_report(&last, &self.os).to_string(),
+                        CrashSource::Down,
+                    ));
+                }
+            }
+        }
+        Some(Crash::new(
+            extract_report(&output, &self.os).to_string(),
+            CrashSource::Down,
+        ))
+    }
+
+    fn app(&self, args: &[&str]) -> App {
+        let mut adb = App::new("adb");
+        if let Some(serial) = self.serial.as_ref() {
+            adb.arg(Arg::new_opt("-s", OptVal::normal(serial)));
+        }
+        for a in args {
+            adb.arg(Arg::new_flag(a));
+        }
+        adb
+    }
+
+    /// Run adb, return its stdout if succeeded.
+    async fn adb(&self, args: &[&str]) -> Option<String> {
+        let output = match self.app(args).into_cmd().output().await {
+            Ok(output) => output,
+            Err(e) => {
+                warn!("Fail to spawn adb: {}", e);
+                return None;
+            }
+        };
+        if output.status.success() {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            debug!(
+                "adb {}: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            None
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Physical or remote machine reached via ssh, rebooted by a power-cycle command.
+
+use super::{
+    console_tail, extract_report, read_all_nonblock, scp_copy, ssh_alive, ssh_output, ssh_spawn,
+    Crash, CrashSource, HealthProbe, SSHConf,
+};
+use crate::error::Result;
+use crate::utils::cli::App;
+use crate::Config;
+use nix::libc;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use tokio::process::{Child, Command};
+use tokio::time::{delay_for, Duration, Instant};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BareConf {
+    /// Address of target machine, reachable via ssh
+    pub addr: String,
+    /// Ssh port of target machine, port of ssh config by default
+    pub port: Option<u16>,
+    /// User to login as, user of ssh config by default
+    pub user: Option<String>,
+    /// Address of fuzzer host seen from target machine, executor connects to it
+    pub host_addr: String,
+    /// Serial console of target machine, e.g. /dev/ttyUSB0 or a file logged by console server
+    pub console: Option<PathBuf>,
+    /// Shell command that power-cycles target machine, e.g. command of a PDU
+    pub power_cycle: Option<String>,
+    /// Time waiting for target machine to boot up, in seconds
+    pub boot_wait: Option<u64>,
+}
+
+impl BareConf {
+    pub fn check(&self) -> Result<()> {
+        if self.addr.is_empty() || self.host_addr.is_empty() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: addr and host_addr of bare machine are required"
+            )
+        }
+        if let Some(console) = self.console.as_ref() {
+            if !console.exists() {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: console {} of bare machine is invalid",
+                    console.display()
+                )
+            }
+        }
+        Ok(())
+    }
+}
+
+const DEFAULT_BOOT_WAIT: u64 = 120;
+
+pub struct Bare {
+    addr: String,
+    port: u16,
+    user: String,
+    ssh: SSHConf,
+    host_addr: String,
+    console_path: Option<PathBuf>,
+    console: Option<File>,
+    power_cycle: Option<String>,
+    boot_wait: Duration,
+    os: String,
+    /// Machine has been taken over by fuzzer, so next boot must reset it.
+    booted: bool,
+}
+
+impl Bare {
+    pub fn new(cfg: &Config) -> Self {
+        let conf = cfg.bare.as_ref().unwrap();
+        Self {
+            addr: conf.addr.clone(),
+            port: conf
+                .port
+                .unwrap_or_else(|| cfg.ssh.as_ref().unwrap().port()),
+            user: conf
+                .user
+                .clone()
+                .unwrap_or_else(|| cfg.ssh.as_ref().unwrap().user()),
+            ssh: cfg.ssh.clone().unwrap(),
+            host_addr: conf.host_addr.clone(),
+            console_path: conf.console.clone(),
+            console: None,
+            power_cycle: conf.power_cycle.clone(),
+            boot_wait: Duration::from_secs(
+                conf.boot_wait
+                    .or(cfg.guest.boot_timeout)
+                    .unwrap_or(DEFAULT_BOOT_WAIT),
+            ),
+            os: cfg.guest.os.clone(),
+            booted: false,
+        }
+    }
+
+    pub fn host_addr(&self) -> &str {
+        &self.host_addr
+    }
+
+    /// Power-cycle machine if it was used or is dead, then wait it to come back.
+    pub async fn boot(&mut self) -> Result<()> {
+        if self.console.is_none() {
+            self.console = self
+                .console_path
+                .as_ref()
+                .map(|p| open_console(p))
+                .transpose()?;
+        }
+        if self.booted || !self.is_alive().await {
+            self.reset().await?;
+        }
+        self.booted = true;
+        self.clear().await;
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<()> {
+        if let Some(cmd) = self.power_cycle.as_ref() {
+            run_power_cycle(cmd).await?;
+        }
+
+        let start = Instant::now();
+        while start.elapsed() < self.boot_wait {
+            delay_for(Duration::from_secs(5)).await;
+            if self.is_alive().await {
+                return Ok(());
+            }
+        }
+        let console = self
+            .console
+            .as_mut()
+            .map(|c| String::from_utf8_lossy(&read_all_nonblock(c)).into_owned())
+            .unwrap_or_default();
+        bail!(
+            exitcode::UNAVAILABLE,
+            "Bare machine {} did not come back in {}s:\n{}",
+            self.addr,
+            self.boot_wait.as_secs(),
+            console
+        )
+    }
+
+    pub async fn is_alive(&self) -> bool {
+        ssh_alive(&self.ssh, &self.user, &self.addr, self.port).await
+    }
+
+    pub fn health_probe(&self) -> HealthProbe {
+        HealthProbe::ssh(&self.ssh, &self.user, &self.addr, self.port)
+    }
+
+    pub async fn dmesg(&self) -> Option<String> {
+        if !self.is_alive().await {
+            return None;
+        }
+        ssh_output(
+            &self.ssh,
+            &self.user,
+            &self.addr,
+            self.port,
+            &App::new("dmesg"),
+        )
+        .await
+    }
+
+    pub async fn run_cmd(&self, app: &App) -> Result<Child> {
+        let mut app = app.clone();
+        let bin = self.copy(PathBuf::from(&app.bin)).await?;
+        app.bin = String::from(bin.to_str().unwrap());
+        self.run_guest_cmd(&app).await
+    }
+
+    pub async fn run_guest_cmd(&self, app: &App) -> Result<Child> {
+        ssh_spawn(&self.ssh, &self.user, &self.addr, self.port, app)
+    }
+
+    pub async fn clear(&mut self) {
+        if let Some(console) = self.console.as_mut() {
+            read_all_nonblock(console);
+        }
+    }
+
+    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf> {
+        scp_copy(&self.ssh, &self.user, &self.addr, self.port, path.as_ref()).await
+    }
+
+    /// Machine is considered crashed once it can not be logged in, report comes from console.
+    pub async fn try_collect_crash(&mut self) -> Option<Crash> {
+        if self.is_alive().await {
+            return None;
+        }
+        let console = self.console.as_mut()?;
+        let output = read_all_nonblock(console);
+        let output = String::from_utf8_lossy(&output);
+        Some(Crash {
+            console: console_tail(&output),
+            ..Crash::new(
+                extract_report(&output, &self.os).to_string(),
+                CrashSource::Down,
+            )
+        })
+    }
+}
+
+/// Open console for reading without blocking and without becoming its controlling process.
+pub(super) fn open_console(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+        .open(path)
+        .map_err(|e| {
+            err!(
+                exitcode::IOERR,
+                "Fail to open console {}: {}",
+                path.display(),
+                e
+            )
+        })
+}
+
+pub(super) async fn run_power_cycle(cmd: &str) -> Result<()> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .status()
+        .await
+        .map_err(|e| err!(exitcode::OSERR, "Fail to spawn `{}`: {}", cmd, e))?;
+    if !status.success() {
+        bail!(
+            exitcode::UNAVAILABLE,
+            "Power-cycle command `{}` failed: {}",
+            cmd,
+            status
+        )
+    }
+    Ok(())
+}
@@ -0,0 +1,248 @@
+//! Embedded board watched through its serial console and rebooted by a power relay.
+//! Executor is deployed and talks to fuzzer over network of board.
+
+use super::bare::{open_console, run_power_cycle};
+use super::{
+    console_tail, crash_patterns, extract_report, read_all_nonblock, scp_copy, ssh_alive,
+    ssh_output, ssh_spawn, Crash, CrashSource, HealthProbe, SSHConf,
+};
+use crate::error::Result;
+use crate::utils::cli::App;
+use crate::Config;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tokio::process::{Child, Command};
+use tokio::time::{delay_for, Duration, Instant};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BoardConf {
+    /// Serial console of board, e.g. /dev/ttyUSB0
+    pub console: PathBuf,
+    /// Baud rate of console, set with stty before opening it
+    pub baud: Option<u32>,
+    /// Shell command that power-cycles board, e.g. command toggling a relay
+    pub power_cycle: String,
+    /// Line printed on console once board is booted, "login:" by default
+    pub boot_marker: Option<String>,
+    /// Network address of board, reachable via ssh
+    pub addr: String,
+    /// Ssh port of board, port of ssh config by default
+    pub port: Option<u16>,
+    /// User to login as, user of ssh config by default
+    pub user: Option<String>,
+    /// Address of fuzzer host seen from board, executor connects to it
+    pub host_addr: String,
+    /// Time waiting for board to boot up, in seconds
+    pub boot_wait: Option<u64>,
+}
+
+impl BoardConf {
+    pub fn check(&self) -> Result<()> {
+        if !self.console.exists() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: console {} of board is invalid",
+                self.console.display()
+            )
+        }
+        if self.power_cycle.is_empty() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: power_cycle command of board is required"
+            )
+        }
+        if self.addr.is_empty() || self.host_addr.is_empty() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: addr and host_addr of board are required"
+            )
+        }
+        Ok(())
+    }
+}
+
+const DEFAULT_BOOT_MARKER: &str = "login:";
+const DEFAULT_BOOT_WAIT: u64 = 180;
+
+pub struct Board {
+    console_path: PathBuf,
+    baud: Option<u32>,
+    console: Option<File>,
+    /// Console output since last boot or clear
+    output: Vec<u8>,
+    power_cycle: String,
+    boot_marker: String,
+    addr: String,
+    port: u16,
+    user: String,
+    ssh: SSHConf,
+    host_addr: String,
+    boot_wait: Duration,
+    os: String,
+}
+
+impl Board {
+    pub fn new(cfg: &Config) -> Self {
+        let conf = cfg.board.as_ref().unwrap();
+        Self {
+            console_path: conf.console.clone(),
+            baud: conf.baud,
+            console: None,
+            output: Vec::new(),
+            power_cycle: conf.power_cycle.clone(),
+            boot_marker: conf
+                .boot_marker
+                .clone()
+                .unwrap_or_else(|| DEFAULT_BOOT_MARKER.to_string()),
+            addr: conf.addr.clone(),
+            port: conf
+                .port
+                .unwrap_or_else(|| cfg.ssh.as_ref().unwrap().port()),
+            user: conf
+                .user
+                .clone()
+                .unwrap_or_else(|| cfg.ssh.as_ref().unwrap().user()),
+            ssh: cfg.ssh.clone().unwrap(),
+            host_addr: conf.host_addr.clone(),
+            boot_wait: Duration::from_secs(
+                conf.boot_wait
+                    .or(cfg.guest.boot_timeout)
+                    .unwrap_or(DEFAULT_BOOT_WAIT),
+            ),
+            os: cfg.guest.os.clone(),
+        }
+    }
+
+    pub fn host_addr(&self) -> &str {
+        &self.host_addr
+    }
+
+    /// Power-cycle board, wait boot marker on console and then its network.
+    pub async fn boot(&mut self) -> Result<()> {
+        if self.console.is_none() {
+            if let Some(baud) = self.baud {
+                self.set_baud(baud).await?;
+            }
+            self.console = Some(open_console(&self.console_path)?);
+        }
+        self.clear().await;
+        run_power_cycle(&self.power_cycle).await?;
+
+        let start = Instant::now();
+        let mut booted = false;
+        while start.elapsed() < self.boot_wait {
+            delay_for(Duration::from_secs(1)).await;
+            self.read_console();
+            if !booted {
+                booted = String::from_utf8_lossy(&self.output).contains(&self.boot_marker);
+            }
+            if booted && self.is_alive().await {
+                self.output.clear();
+                return Ok(());
+            }
+        }
+        bail!(
+            exitcode::UNAVAILABLE,
+            "Board {} did not come up in {}s:\n{}",
+            self.addr,
+            self.boot_wait.as_secs(),
+            String::from_utf8_lossy(&self.output)
+        )
+    }
+
+    async fn set_baud(&self, baud: u32) -> Result<()> {
+        let status = Command::new("stty")
+            .arg("-F")
+            .arg(&self.console_path)
+            .arg(baud.to_string())
+            .arg("raw")
+            .status()
+            .await
+            .map_err(|e| err!(exitcode::OSERR, "Fail to spawn stty: {}", e))?;
+        if !status.success() {
+            bail!(
+                exitcode::IOERR,
+                "Fail to set baud rate of {}",
+                self.console_path.display()
+            )
+        }
+        Ok(())
+    }
+
+    fn read_console(&mut self) {
+        if let Some(console) = self.console.as_mut() {
+            loop {
+                let buf = read_all_nonblock(console);
+                if buf.is_empty() {
+                    break;
+                }
+                self.output.extend(buf);
+            }
+        }
+    }
+
+    pub async fn is_alive(&self) -> bool {
+        ssh_alive(&self.ssh, &self.user, &self.addr, self.port).await
+    }
+
+    pub fn health_probe(&self) -> HealthProbe {
+        HealthProbe::ssh(&self.ssh, &self.user, &self.addr, self.port)
+    }
+
+    pub async fn dmesg(&self) -> Option<String> {
+        if !self.is_alive().await {
+            return None;
+        }
+        ssh_output(
+            &self.ssh,
+            &self.user,
+            &self.addr,
+            self.port,
+            &App::new("dmesg"),
+        )
+        .await
+    }
+
+    pub async fn run_cmd(&self, app: &App) -> Result<Child> {
+        let mut app = app.clone();
+        let bin = self.copy(PathBuf::from(&app.bin)).await?;
+        app.bin = String::from(bin.to_str().unwrap());
+        self.run_guest_cmd(&app).await
+    }
+
+    pub async fn run_guest_cmd(&self, app: &App) -> Result<Child> {
+        ssh_spawn(&self.ssh, &self.user, &self.addr, self.port, app)
+    }
+
+    pub async fn clear(&mut self) {
+        self.read_console();
+        self.output.clear();
+    }
+
+    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf> {
+        scp_copy(&self.ssh, &self.user, &self.addr, self.port, path.as_ref()).await
+    }
+
+    /// Board crashed if console shows a crash report or board can not be logged in.
+    pub async fn try_collect_crash(&mut self) -> Option<Crash> {
+        self.read_console();
+        let output = String::from_utf8_lossy(&self.output).into_owned();
+        let reported = crash_patterns(&self.os).iter().any(|p| output.contains(p));
+        if !reported && self.is_alive().await {
+            return None;
+        }
+        // give console some time to finish report
+        delay_for(Duration::from_secs(5)).await;
+        self.read_console();
+        let output = String::from_utf8_lossy(&self.output);
+        let source = if reported {
+            CrashSource::Console
+        } else {
+            CrashSource::Down
+        };
+        Some(Crash {
+            console: console_tail(&output),
+            ..Crash::new(extract_report(&output, &self.os).to_string(), source)
+        })
+    }
+}
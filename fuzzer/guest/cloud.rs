@@ -0,0 +1,436 @@
+//! VM instance of cloud, created from target image with gcloud or aws cli, provisioned via
+//! ssh and reset through api of cloud instead of rebooting.
+
+use super::{
+    console_tail, extract_report, scp_copy, ssh_alive, ssh_output, ssh_spawn, Crash, CrashSource,
+    HealthProbe, SSHConf,
+};
+use crate::error::Result;
+use crate::utils::cli::{App, Arg, OptVal};
+use crate::Config;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::process::Child;
+use tokio::time::{delay_for, Duration, Instant};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CloudConf {
+    /// Cloud provider: gce or aws
+    pub provider: String,
+    /// Image that instances boot from, image of gce or ami id of aws
+    pub image: String,
+    /// Machine type of gce or instance type of aws
+    pub machine_type: String,
+    /// Zone of gce instances, or region of aws instances
+    pub zone: String,
+    /// Name of key pair of aws that instances accept, its private key is in ssh config
+    pub key_name: Option<String>,
+    /// User to login as, user of ssh config by default
+    pub user: Option<String>,
+    /// Public address of fuzzer host, executor in instance connects to it
+    pub host_addr: String,
+    /// Time waiting for instance to boot up, in seconds
+    pub boot_wait: Option<u64>,
+}
+
+pub const PROVIDERS: [&str; 2] = ["gce", "aws"];
+
+impl CloudConf {
+    pub fn check(&self) -> Result<()> {
+        if !PROVIDERS.contains(&self.provider.as_str()) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: unsupported cloud provider {}, gce or aws expected",
+                self.provider
+            )
+        }
+        if self.image.is_empty() || self.machine_type.is_empty() || self.zone.is_empty() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: image, machine_type and zone of cloud are required"
+            )
+        }
+        if self.provider == "aws" && self.key_name.is_none() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: key_name of cloud is required by aws"
+            )
+        }
+        if self.host_addr.is_empty() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: host_addr of cloud is required"
+            )
+        }
+        Ok(())
+    }
+}
+
+const DEFAULT_BOOT_WAIT: u64 = 300;
+
+/// Distinguish instances created by this process.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Cloud {
+    conf: CloudConf,
+    name: String,
+    ssh: SSHConf,
+    user: String,
+    port: u16,
+    boot_wait: Duration,
+    os: String,
+    /// Instance id of aws, or name of gce instance, once created
+    instance: Option<String>,
+    /// Public address of instance
+    addr: String,
+    /// Length of serial output that has been seen, gce only
+    console_pos: usize,
+}
+
+impl Cloud {
+    pub fn new(cfg: &Config) -> Self {
+        let conf = cfg.cloud.clone().unwrap();
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        Self {
+            name: format!("healer-{}-{}", process::id(), id),
+            ssh: cfg.ssh.clone().unwrap(),
+            user: conf
+                .user
+                .clone()
+                .unwrap_or_else(|| cfg.ssh.as_ref().unwrap().user()),
+            boot_wait: Duration::from_secs(
+                conf.boot_wait
+                    .or(cfg.guest.boot_timeout)
+                    .unwrap_or(DEFAULT_BOOT_WAIT),
+            ),
+            os: cfg.guest.os.clone(),
+            conf,
+            instance: None,
+            port: cfg.ssh.as_ref().unwrap().port(),
+            addr: String::new(),
+            console_pos: 0,
+        }
+    }
+
+    pub fn host_addr(&self) -> &str {
+        &self.conf.host_addr
+    }
+
+    fn is_gce(&self) -> bool {
+        self.conf.provider == "gce"
+    }
+
+    /// Create instance on first boot, reset it on later boots, then wait ssh to come up.
+    pub async fn boot(&mut self) -> Result<()> {
+        if self.instance.is_none() {
+            self.create().await?;
+        } else {
+            self.reset().await?;
+        }
+
+        let start = Instant::now();
+        while start.elapsed() < self.boot_wait {
+            delay_for(Duration::from_secs(5)).await;
+            if self.addr.is_empty() {
+                self.addr = self.public_addr().await.unwrap_or_default();
+            }
+            if self.is_alive().await {
+                self.clear().await;
+                return Ok(());
+            }
+        }
+        let console = self.console().await;
+        bail!(
+            exitcode::UNAVAILABLE,
+            "Instance {} did not come up in {}s:\n{}",
+            self.name,
+            self.boot_wait.as_secs(),
+            console
+        )
+    }
+
+    async fn create(&mut self) -> Result<()> {
+        let instance = if self.is_gce() {
+            let create = self.cli(&[
+                "compute",
+                "instances",
+                "create",
+                &self.name,
+                "--zone",
+                &self.conf.zone,
+                "--machine-type",
+                &self.conf.machine_type,
+                "--image",
+                &self.conf.image,
+                "--metadata",
+                "serial-port-enable=1",
+                "--format",
+                "value(name)",
+            ]);
+            run(create).await
+        } else {
+            let tag = format!(
+                "ResourceType=instance,Tags=[{{Key=Name,Value={}}}]",
+                self.name
+            );
+            let create = self.cli(&[
+                "ec2",
+                "run-instances",
+                "--region",
+                &self.conf.zone,
+                "--image-id",
+                &self.conf.image,
+                "--instance-type",
+                &self.conf.machine_type,
+                "--key-name",
+                self.conf.key_name.as_ref().unwrap(),
+                "--tag-specifications",
+                &tag,
+                "--query",
+                "Instances[0].InstanceId",
+                "--output",
+                "text",
+            ]);
+            run(create).await
+        };
+        match instance {
+            Some(instance) => self.instance = Some(instance.trim().to_string()),
+            None => bail!(
+                exitcode::UNAVAILABLE,
+                "Fail to create instance {}",
+                self.name
+            ),
+        }
+        self.addr.clear();
+        self.console_pos = 0;
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<()> {
+        let instance = self.instance.clone().unwrap();
+        let reset = if self.is_gce() {
+            self.cli(&[
+                "compute",
+                "instances",
+                "reset",
+                &instance,
+                "--zone",
+                &self.conf.zone,
+            ])
+        } else {
+            self.cli(&[
+                "ec2",
+                "reboot-instances",
+                "--region",
+                &self.conf.zone,
+                "--instance-ids",
+                &instance,
+            ])
+        };
+        if run(reset).await.is_none() {
+            bail!(exitcode::UNAVAILABLE, "Fail to reset instance {}", instance)
+        }
+        Ok(())
+    }
+
+    async fn public_addr(&self) -> Option<String> {
+        let instance = self.instance.as_ref()?;
+        let describe = if self.is_gce() {
+            self.cli(&[
+                "compute",
+                "instances",
+                "describe",
+                instance,
+                "--zone",
+                &self.conf.zone,
+                "--format",
+                "value(networkInterfaces[0].accessConfigs[0].natIP)",
+            ])
+        } else {
+            self.cli(&[
+                "ec2",
+                "describe-instances",
+                "--region",
+                &self.conf.zone,
+                "--instance-ids",
+                instance,
+                "--query",
+                "Reservations[0].Instances[0].PublicIpAddress",
+                "--output",
+                "text",
+            ])
+        };
+        let addr = run(describe).await?.trim().to_string();
+        if addr.is_empty() || addr == "None" {
+            None
+        } else {
+            Some(addr)
+        }
+    }
+
+    /// Serial output of instance not seen before. Aws only keeps latest part of output.
+    async fn console(&mut self) -> String {
+        let instance = match self.instance.as_ref() {
+            Some(instance) => instance.clone(),
+            None => return String::new(),
+        };
+        if self.is_gce() {
+            let start = self.console_pos.to_string();
+            let output = self.cli(&[
+                "compute",
+                "instances",
+                "get-serial-port-output",
+                &instance,
+                "--zone",
+                &self.conf.zone,
+                "--start",
+                &start,
+            ]);
+            let output = run(output).await.unwrap_or_default();
+            self.console_pos += output.len();
+            output
+        } else {
+            let output = self.cli(&[
+                "ec2",
+                "get-console-output",
+                "--region",
+                &self.conf.zone,
+                "--instance-id",
+                &instance,
+                "--latest",
+                "--output",
+                "text",
+            ]);
+            run(output).await.unwrap_or_default()
+        }
+    }
+
+    pub async fn is_alive(&self) -> bool {
+        if self.addr.is_empty() {
+            return false;
+        }
+
+        ssh_alive(&self.ssh, &self.user, &self.addr, self.port).await
+    }
+
+    pub fn health_probe(&self) -> HealthProbe {
+        HealthProbe::ssh(&self.ssh, &self.user, &self.addr, self.port)
+    }
+
+    pub async fn dmesg(&self) -> Option<String> {
+        if !self.is_alive().await {
+            return None;
+        }
+        ssh_output(
+            &self.ssh,
+            &self.user,
+            &self.addr,
+            self.port,
+            &App::new("dmesg"),
+        )
+        .await
+    }
+
+    pub async fn run_cmd(&self, app: &App) -> Result<Child> {
+        let mut app = app.clone();
+        let bin = self.copy(PathBuf::from(&app.bin)).await?;
+        app.bin = String::from(bin.to_str().unwrap());
+        self.run_guest_cmd(&app).await
+    }
+
+    pub async fn run_guest_cmd(&self, app: &App) -> Result<Child> {
+        ssh_spawn(&self.ssh, &self.user, &self.addr, self.port, app)
+    }
+
+    pub async fn clear(&mut self) {
+        if self.is_gce() {
+            self.console().await;
+        }
+    }
+
+    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf> {
+        scp_copy(&self.ssh, &self.user, &self.addr, self.port, path.as_ref()).await
+    }
+
+    /// Instance crashed once it can not be logged in, report comes from its serial output.
+    pub async fn try_collect_crash(&mut self) -> Option<Crash> {
+        if self.is_alive().await {
+            return None;
+        }
+        // serial output reaches api of cloud with a delay.
+        delay_for(Duration::from_secs(10)).await;
+        let output = self.console().await;
+        Some(Crash {
+            console: console_tail(&output),
+            ..Crash::new(
+                extract_report(&output, &self.os).to_string(),
+                CrashSource::Down,
+            )
+        })
+    }
+
+    fn cli(&self, args: &[&str]) -> App {
+        let mut cli = App::new(if self.is_gce() { "gcloud" } else { "aws" });
+        for a in args {
+            cli.arg(Arg::new_flag(a));
+        }
+        if self.is_gce() {
+            cli.arg(Arg::new_opt("--verbosity", OptVal::normal("error")));
+        }
+        cli
+    }
+}
+
+/// Instances cost money, delete them when fuzzer drops its guests.
+impl Drop for Cloud {
+    fn drop(&mut self) {
+        let instance = match self.instance.take() {
+            Some(instance) => instance,
+            None => return,
+        };
+        let delete = if self.is_gce() {
+            self.cli(&[
+                "compute",
+                "instances",
+                "delete",
+                &instance,
+                "--zone",
+                &self.conf.zone,
+                "--quiet",
+            ])
+        } else {
+            self.cli(&[
+                "ec2",
+                "terminate-instances",
+                "--region",
+                &self.conf.zone,
+                "--instance-ids",
+                &instance,
+            ])
+        };
+        let mut cmd = std::process::Command::new(&delete.bin);
+        cmd.args(delete.iter_arg());
+        if let Err(e) = cmd.output() {
+            warn!("Fail to delete instance {}: {}", instance, e);
+        }
+    }
+}
+
+/// Run cli of cloud, return its stdout if succeeded.
+async fn run(cli: App) -> Option<String> {
+    let bin = cli.bin.clone();
+    let output = match cli.into_cmd().output().await {
+        Ok(output) => output,
+        Err(e) => {
+            warn!("Fail to spawn {}: {}", bin, e);
+            return None;
+        }
+    };
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        debug!("{}: {}", bin, String::from_utf8_lossy(&output.stderr));
+        None
+    }
+}
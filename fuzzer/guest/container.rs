@@ -0,0 +1,370 @@
+//! Container running on host, for fuzzing seccomp sandboxes and container runtimes.
+//! Docker compatible engines and lxc are supported. Container exiting means crash of
+//! runtime, kernel crash is reported by host kernel log.
+
+use super::{crash_patterns, extract_report, Crash, CrashSource};
+use crate::error::Result;
+use crate::utils::cli::{App, Arg, OptVal};
+use crate::Config;
+use nix::libc;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Child;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ContainerConf {
+    /// Container engine: docker, podman or lxc, docker by default
+    pub engine: Option<String>,
+    /// Image to run for docker and podman, or container to clone for lxc
+    pub image: String,
+    /// Low level runtime of docker or podman, e.g. runc, crun or runsc
+    pub runtime: Option<String>,
+    /// Seccomp profile applied to container, docker and podman only
+    pub seccomp: Option<PathBuf>,
+    /// Run container privileged
+    #[serde(default)]
+    pub privileged: bool,
+    /// Address of host seen from container, 127.0.0.1 by default since docker and
+    /// podman containers share network with host
+    pub host_addr: Option<String>,
+}
+
+pub const ENGINES: [&str; 3] = ["docker", "podman", "lxc"];
+
+impl ContainerConf {
+    pub fn check(&self) -> Result<()> {
+        let engine = self.engine.as_deref().unwrap_or("docker");
+        if !ENGINES.contains(&engine) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: unsupported container engine {}, docker, podman or lxc expected",
+                engine
+            )
+        }
+        if self.image.is_empty() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: image of container is required"
+            )
+        }
+        if engine == "lxc" && (self.runtime.is_some() || self.seccomp.is_some()) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: runtime and seccomp of container are not supported by lxc"
+            )
+        }
+        if let Some(seccomp) = self.seccomp.as_ref() {
+            if !seccomp.is_file() {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: seccomp profile {} is invalid",
+                    seccomp.display()
+                )
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Distinguish containers created by this process.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+const CONTAINER_HOST_IP_ADDR: &str = "127.0.0.1";
+/// Directory in container where files are copied to.
+const CONTAINER_WORK_DIR: &str = "/tmp";
+const KMSG: &str = "/dev/kmsg";
+/// Max length of a record of kernel log.
+const KMSG_RECORD_LEN: usize = 8192;
+
+pub struct Container {
+    engine: String,
+    image: String,
+    runtime: Option<String>,
+    seccomp: Option<PathBuf>,
+    privileged: bool,
+    host_addr: String,
+    name: String,
+    os: String,
+    /// Host kernel log, positioned at end on each clear
+    kmsg: Option<File>,
+}
+
+impl Container {
+    pub fn new(cfg: &Config) -> Self {
+        let conf = cfg.container.as_ref().unwrap();
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        Self {
+            engine: conf.engine.clone().unwrap_or_else(|| "docker".to_string()),
+            image: conf.image.clone(),
+            runtime: conf.runtime.clone(),
+            seccomp: conf.seccomp.clone(),
+            privileged: conf.privileged,
+            host_addr: conf
+                .host_addr
+                .clone()
+                .unwrap_or_else(|| CONTAINER_HOST_IP_ADDR.to_string()),
+            name: format!("healer-{}-{}", process::id(), id),
+            os: cfg.guest.os.clone(),
+            kmsg: None,
+        }
+    }
+
+    pub fn host_addr(&self) -> &str {
+        &self.host_addr
+    }
+
+    fn is_lxc(&self) -> bool {
+        self.engine == "lxc"
+    }
+
+    /// Remove container left by last boot and start a fresh one.
+    pub async fn boot(&mut self) -> Result<()> {
+        if self.kmsg.is_none() {
+            self.kmsg = Some(open_kmsg()?);
+        }
+        self.remove().await;
+
+        let start = if self.is_lxc() {
+            let mut lxc = App::new("lxc-copy");
+            lxc.arg(Arg::new_opt("-n", OptVal::normal(&self.image)))
+                .arg(Arg::new_opt("-N", OptVal::normal(&self.name)))
+                .arg(Arg::new_flag("-e"));
+            lxc
+        } else {
+            let mut run = App::new(&self.engine);
+            run.arg(Arg::new_flag("run"))
+                .arg(Arg::new_flag("-d"))
+                .arg(Arg::new_opt("--name", OptVal::normal(&self.name)))
+                .arg(Arg::new_opt("--network", OptVal::normal("host")));
+            if self.privileged {
+                run.arg(Arg::new_flag("--privileged"));
+            }
+            if let Some(runtime) = self.runtime.as_ref() {
+                run.arg(Arg::new_opt("--runtime", OptVal::normal(runtime)));
+            }
+            if let Some(seccomp) = self.seccomp.as_ref() {
+                run.arg(Arg::new_opt(
+                    "--security-opt",
+                    OptVal::normal(&format!("seccomp={}", seccomp.display())),
+                ));
+            }
+            run.arg(Arg::new_flag(&self.image))
+                .arg(Arg::new_flag("sleep"))
+                .arg(Arg::new_flag("infinity"));
+            run
+        };
+        let bin = start.bin.clone();
+        let output = start
+            .into_cmd()
+            .output()
+            .await
+            .map_err(|e| err!(exitcode::OSERR, "Fail to spawn {}: {}", bin, e))?;
+        if !output.status.success() {
+            bail!(
+                exitcode::UNAVAILABLE,
+                "Fail to start container {}: {}",
+                self.name,
+                String::from_utf8_lossy(&output.stderr)
+            )
+        }
+        if !self.is_alive().await {
+            bail!(
+                exitcode::UNAVAILABLE,
+                "Container {} exited right after start",
+                self.name
+            )
+        }
+        self.clear().await;
+        Ok(())
+    }
+
+    async fn remove(&self) {
+        let remove = if self.is_lxc() {
+            let mut lxc = App::new("lxc-stop");
+            lxc.arg(Arg::new_opt("-n", OptVal::normal(&self.name)))
+                .arg(Arg::new_flag("-k"));
+            lxc
+        } else {
+            let mut rm = App::new(&self.engine);
+            rm.arg(Arg::new_flag("rm"))
+                .arg(Arg::new_flag("-f"))
+                .arg(Arg::new_flag(&self.name));
+            rm
+        };
+        // container may not exist, failure is expected.
+        remove.into_cmd().output().await.ok();
+    }
+
+    pub async fn is_alive(&self) -> bool {
+        let state = if self.is_lxc() {
+            let mut lxc = App::new("lxc-info");
+            lxc.arg(Arg::new_opt("-n", OptVal::normal(&self.name)))
+                .arg(Arg::new_flag("-s"))
+                .arg(Arg::new_flag("-H"));
+            lxc
+        } else {
+            let mut inspect = App::new(&self.engine);
+            inspect
+                .arg(Arg::new_flag("inspect"))
+                .arg(Arg::new_opt("-f", OptVal::normal("{{.State.Running}}")))
+                .arg(Arg::new_flag(&self.name));
+            inspect
+        };
+        match state.into_cmd().output().await {
+            Ok(out) if out.status.success() => {
+                let state = String::from_utf8_lossy(&out.stdout);
+                let state = state.trim();
+                state == "true" || state == "RUNNING"
+            }
+            _ => false,
+        }
+    }
+
+    pub async fn run_cmd(&self, app: &App) -> Result<Child> {
+        let mut app = app.clone();
+        let bin = self.copy(PathBuf::from(&app.bin)).await?;
+        app.bin = String::from(bin.to_str().unwrap());
+        self.run_guest_cmd(&app).await
+    }
+
+    pub async fn run_guest_cmd(&self, app: &App) -> Result<Child> {
+        self.exec_app(app)
+            .into_cmd()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| err!(exitcode::OSERR, "Fail to spawn:{}", e))
+    }
+
+    fn exec_app(&self, app: &App) -> App {
+        let mut exec = if self.is_lxc() {
+            let mut lxc = App::new("lxc-attach");
+            lxc.arg(Arg::new_opt("-n", OptVal::normal(&self.name)))
+                .arg(Arg::new_flag("--"));
+            lxc
+        } else {
+            let mut exec = App::new(&self.engine);
+            exec.arg(Arg::new_flag("exec"))
+                .arg(Arg::new_flag("-i"))
+                .arg(Arg::new_flag(&self.name));
+            exec
+        };
+        exec.arg(Arg::new_flag(&app.bin));
+        for arg in app.clone().iter_arg() {
+            exec.arg(Arg::Flag(arg));
+        }
+        exec
+    }
+
+    pub async fn clear(&mut self) {
+        if let Some(kmsg) = self.kmsg.as_mut() {
+            kmsg.seek(SeekFrom::End(0)).ok();
+        }
+    }
+
+    /// Copy file into work dir of container, through stdin of a shell in it, so that
+    /// engines without a copy command are covered too.
+    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf> {
+        let path = path.as_ref();
+        assert!(path.is_file());
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        let guest_path = format!("{}/{}", CONTAINER_WORK_DIR, file_name);
+        let content = tokio::fs::read(path)
+            .await
+            .map_err(|e| err!(exitcode::IOERR, "Fail to read {}: {}", path.display(), e))?;
+
+        let mut sh = App::new("sh");
+        sh.arg(Arg::new_flag("-c"))
+            .arg(Arg::Flag(format!("cat > {0} && chmod 755 {0}", guest_path)));
+        let mut child = self.run_guest_cmd(&sh).await?;
+        let mut stdin = child.stdin.take().unwrap();
+        stdin
+            .write_all(&content)
+            .await
+            .map_err(|e| err!(exitcode::IOERR, "Fail to copy {}: {}", path.display(), e))?;
+        drop(stdin);
+        let output = child
+            .wait_with_output()
+            .await
+            .map_err(|e| err!(exitcode::OSERR, "Fail to wait copy: {}", e))?;
+        if !output.status.success() {
+            bail!(
+                exitcode::UNAVAILABLE,
+                "Fail to copy {} into container: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            )
+        }
+        Ok(PathBuf::from(guest_path))
+    }
+
+    /// Crash of host kernel is reported by kernel log, crash of runtime makes container exit.
+    pub async fn try_collect_crash(&mut self) -> Option<Crash> {
+        let kmsg = self.kmsg.as_mut().map(read_kmsg).unwrap_or_default();
+        if crash_patterns(&self.os).iter().any(|p| kmsg.contains(p)) {
+            return Some(Crash::new(
+                extract_report(&kmsg, &self.os).to_string(),
+                CrashSource::Console,
+            ));
+        }
+        if self.is_alive().await {
+            return None;
+        }
+        let logs = if self.is_lxc() {
+            String::new()
+        } else {
+            let mut logs = App::new(&self.engine);
+            logs.arg(Arg::new_flag("logs"))
+                .arg(Arg::new_flag(&self.name));
+            logs.into_cmd()
+                .output()
+                .await
+                .map(|out| String::from_utf8_lossy(&out.stderr).into_owned())
+                .unwrap_or_default()
+        };
+        Some(Crash::new(
+            format!("container {} exited\n{}{}", self.name, logs, kmsg),
+            CrashSource::Down,
+        ))
+    }
+}
+
+/// New records of host kernel log, with prefix of each record stripped.
+pub(super) fn read_kmsg(kmsg: &mut File) -> String {
+    let mut log = String::new();
+    // each read returns one record.
+    let mut buf = vec![0; KMSG_RECORD_LEN];
+    loop {
+        match kmsg.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                let record = String::from_utf8_lossy(&buf[..n]);
+                let msg = record.splitn(2, ';').nth(1).unwrap_or(&record);
+                log.push_str(msg);
+            }
+            // records were overwritten before being read, continue with next one.
+            Err(e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+            Err(_) => break,
+        }
+    }
+    log
+}
+
+/// Open host kernel log without blocking, positioned at end.
+pub(super) fn open_kmsg() -> Result<File> {
+    let mut kmsg = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(KMSG)
+        .map_err(|e| err!(exitcode::NOPERM, "Fail to open {}: {}", KMSG, e))?;
+    kmsg.seek(SeekFrom::End(0))
+        .map_err(|e| err!(exitcode::IOERR, "Fail to seek {}: {}", KMSG, e))?;
+    Ok(kmsg)
+}
@@ -0,0 +1,151 @@
+//! gVisor sandbox running on host, each command runs in a fresh sandbox via `runsc do`.
+
+use super::{Crash, CrashSource};
+use crate::error::Result;
+use crate::utils::cli::{App, Arg, OptVal};
+use crate::Config;
+use std::env::temp_dir;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::fs::{read_to_string, write};
+use tokio::process::Child;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GvisorConf {
+    /// Path of runsc binary
+    pub runsc: PathBuf,
+    /// Platform of sentry: ptrace or kvm
+    pub platform: Option<String>,
+}
+
+impl GvisorConf {
+    pub fn check(&self) -> Result<()> {
+        if !self.runsc.is_file() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: runsc executable file {} is invalid",
+                self.runsc.display()
+            )
+        }
+        if let Some(platform) = self.platform.as_ref() {
+            if platform != "ptrace" && platform != "kvm" {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: unsupported gvisor platform {}, ptrace or kvm expected",
+                    platform
+                )
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Distinguish panic logs of sandboxes.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Sandbox shares network with host.
+pub const GVISOR_HOST_IP_ADDR: &str = "127.0.0.1";
+
+pub struct Gvisor {
+    runsc: PathBuf,
+    platform: String,
+    /// File that sentry writes panic reports to
+    panic_log: PathBuf,
+}
+
+impl Gvisor {
+    pub fn new(cfg: &Config) -> Self {
+        let conf = cfg.gvisor.as_ref().unwrap();
+        Self {
+            runsc: conf.runsc.clone(),
+            platform: conf
+                .platform
+                .clone()
+                .unwrap_or_else(|| "ptrace".to_string()),
+            panic_log: PathBuf::new(),
+        }
+    }
+
+    /// Nothing to boot, sandbox is created for each command.
+    pub async fn boot(&mut self) -> Result<()> {
+        if self.panic_log.as_os_str().is_empty() {
+            let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+            self.panic_log =
+                temp_dir().join(format!("healer-gvisor-{}-{}.panic", process::id(), id));
+        }
+        write(&self.panic_log, "").await.map_err(|e| {
+            err!(
+                exitcode::IOERR,
+                "Fail to create panic log {}: {}",
+                self.panic_log.display(),
+                e
+            )
+        })
+    }
+
+    /// Sentry is dead once it wrote a panic report.
+    pub async fn is_alive(&self) -> bool {
+        read_to_string(&self.panic_log)
+            .await
+            .map(|s| s.trim().is_empty())
+            .unwrap_or(true)
+    }
+
+    pub async fn run_cmd(&self, app: &App) -> Result<Child> {
+        self.run_guest_cmd(app).await
+    }
+
+    pub async fn run_guest_cmd(&self, app: &App) -> Result<Child> {
+        let mut runsc = App::new(self.runsc.to_str().unwrap());
+        runsc
+            .arg(Arg::new_opt("--network", OptVal::normal("host")))
+            .arg(Arg::new_opt("--platform", OptVal::normal(&self.platform)))
+            .arg(Arg::new_opt(
+                "--panic-log",
+                OptVal::normal(self.panic_log.to_str().unwrap()),
+            ))
+            .arg(Arg::new_flag("do"))
+            .arg(Arg::new_flag(&app.bin));
+        for arg in app.clone().iter_arg() {
+            runsc.arg(Arg::Flag(arg));
+        }
+
+        runsc
+            .into_cmd()
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| err!(exitcode::OSERR, "Fail to spawn runsc:{}", e))
+    }
+
+    pub async fn clear(&mut self) {
+        if let Err(e) = write(&self.panic_log, "").await {
+            warn!(
+                "Fail to clear panic log {}: {}",
+                self.panic_log.display(),
+                e
+            );
+        }
+    }
+
+    /// Host file system is visible in sandbox, no need to copy.
+    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf> {
+        let path = path.as_ref();
+        assert!(path.is_file());
+        path.canonicalize()
+            .map_err(|e| err!(exitcode::IOERR, "Fail to locate {}: {}", path.display(), e))
+    }
+
+    pub async fn try_collect_crash(&mut self) -> Option<Crash> {
+        let report = read_to_string(&self.panic_log).await.ok()?;
+        if report.trim().is_empty() {
+            None
+        } else {
+            self.clear().await;
+            Some(Crash::new(report, CrashSource::Down))
+        }
+    }
+}
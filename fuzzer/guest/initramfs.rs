@@ -0,0 +1,111 @@
+//! Executor and an init script appended to a stock initramfs at boot, so that guest needs
+//! no image prepared with executor. Kernel unpacks concatenated cpio archives in order,
+//! so files of the appended one are added to those of initramfs.
+
+use super::SSHConf;
+use crate::error::Result;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::process::Command;
+
+/// Directory in guest holding injected files.
+pub const INJECT_DIR: &str = "/healer";
+/// Init injected into initramfs, passed to kernel as `rdinit`.
+pub const INJECT_INIT: &str = "/healer/init";
+
+/// Runs init script given by config, then hands over to init of initramfs.
+const INIT: &str = r#"#!/bin/sh
+# injected by healer
+if [ -f /healer/authorized_keys ]; then
+    mkdir -p "$HEALER_HOME/.ssh"
+    cat /healer/authorized_keys >> "$HEALER_HOME/.ssh/authorized_keys"
+    chmod 700 "$HEALER_HOME/.ssh"
+    chmod 600 "$HEALER_HOME/.ssh/authorized_keys"
+fi
+if [ -x /healer/init.sh ]; then
+    /healer/init.sh
+fi
+for init in /init /sbin/init /etc/init /bin/init; do
+    [ -x "$init" ] && exec "$init" "$@"
+done
+exec /bin/sh
+"#;
+
+/// Write initramfs at `dst`, made of `initrd` with executor, public key of ssh and
+/// `init_script` appended under `INJECT_DIR`. Return md5 of injected executor.
+pub async fn inject(
+    initrd: &Path,
+    dst: &Path,
+    executor: &Path,
+    init_script: Option<&Path>,
+    ssh: &SSHConf,
+    user: &str,
+) -> Result<md5::Digest> {
+    let stage = dst.with_extension("d");
+    fs::remove_dir_all(&stage).await.ok();
+    let dir = stage.join(INJECT_DIR.trim_start_matches('/'));
+    fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| err!(exitcode::IOERR, "Fail to create {}: {}", dir.display(), e))?;
+
+    let bin = read(executor).await?;
+    let digest = md5::compute(&bin);
+    write(&dir.join(executor.file_name().unwrap()), &bin, 0o755).await?;
+    let home = if user == "root" {
+        String::from("/root")
+    } else {
+        format!("/home/{}", user)
+    };
+    let init = INIT.replace("$HEALER_HOME", &home);
+    write(&dir.join("init"), init.as_bytes(), 0o755).await?;
+    if let Some(script) = init_script {
+        write(&dir.join("init.sh"), &read(script).await?, 0o755).await?;
+    }
+    let pub_key = PathBuf::from(format!("{}.pub", ssh.key_path));
+    if pub_key.is_file() {
+        write(&dir.join("authorized_keys"), &read(&pub_key).await?, 0o644).await?;
+    }
+
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg("find . -mindepth 1 | cpio -o -H newc -R 0:0 --quiet")
+        .current_dir(&stage)
+        .output()
+        .await
+        .map_err(|e| err!(exitcode::OSERR, "Fail to spawn cpio: {}", e))?;
+    if !output.status.success() {
+        bail!(
+            exitcode::UNAVAILABLE,
+            "Fail to pack injected files: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )
+    }
+    fs::remove_dir_all(&stage).await.ok();
+
+    let mut archive = read(initrd).await?;
+    // every archive starts at 4 byte boundary.
+    archive.resize((archive.len() + 3) & !3, 0);
+    archive.extend(output.stdout);
+    fs::write(dst, archive)
+        .await
+        .map_err(|e| err!(exitcode::IOERR, "Fail to write {}: {}", dst.display(), e))?;
+    Ok(digest)
+}
+
+async fn read(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path)
+        .await
+        .map_err(|e| err!(exitcode::IOERR, "Fail to read {}: {}", path.display(), e))
+}
+
+async fn write(path: &Path, content: &[u8], mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::write(path, content)
+        .await
+        .map_err(|e| err!(exitcode::IOERR, "Fail to write {}: {}", path.display(), e))?;
+    fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .await
+        .map_err(|e| err!(exitcode::IOERR, "Fail to chmod {}: {}", path.display(), e))?;
+    Ok(())
+}
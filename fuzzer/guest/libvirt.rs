@@ -0,0 +1,316 @@
+//! VM managed by libvirt, controlled through virsh, defined from a domain xml template.
+
+use super::bare::open_console;
+use super::{
+    console_tail, extract_report, read_all_nonblock, scp_copy, ssh_alive, ssh_output, ssh_spawn,
+    Crash, CrashSource, HealthProbe, SSHConf,
+};
+use crate::error::Result;
+use crate::utils::cli::App;
+use crate::Config;
+use std::env::temp_dir;
+use std::fs::{read_to_string, write, File};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::process::{Child, Command};
+use tokio::time::{delay_for, Duration, Instant};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LibvirtConf {
+    /// Connection uri of libvirt, e.g. qemu:///system
+    pub uri: Option<String>,
+    /// Domain xml used as template of each vm, name, uuid and mac address inside are replaced
+    pub xml: PathBuf,
+    /// Address of host seen from guest, 192.168.122.1 of libvirt default network by default
+    pub host_addr: Option<String>,
+    /// Time waiting for vm to boot up, in seconds
+    pub wait_boot_time: Option<u64>,
+}
+
+impl LibvirtConf {
+    pub fn check(&self) -> Result<()> {
+        let xml = read_to_string(&self.xml).map_err(|e| {
+            err!(
+                exitcode::CONFIG,
+                "Config Error: domain xml {} is invalid: {}",
+                self.xml.display(),
+                e
+            )
+        })?;
+        if !xml.contains("<domain") || !xml.contains("<name>") {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: {} is not a libvirt domain xml",
+                self.xml.display()
+            )
+        }
+        Ok(())
+    }
+}
+
+const DEFAULT_HOST_ADDR: &str = "192.168.122.1";
+const DEFAULT_WAIT_BOOT_TIME: u64 = 60;
+/// Name of snapshot saved by healer
+const SNAPSHOT_TAG: &str = "healer";
+
+/// Distinguish domains created by this process.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Libvirt {
+    uri: Option<String>,
+    xml: PathBuf,
+    name: String,
+    host_addr: String,
+    wait_boot_time: Duration,
+    ssh: SSHConf,
+    user: String,
+    port: u16,
+    os: String,
+    /// Address of guest, learned from libvirt after boot
+    addr: String,
+    /// Pty of serial console of guest
+    console: Option<File>,
+}
+
+impl Libvirt {
+    pub fn new(cfg: &Config) -> Self {
+        let conf = cfg.libvirt.as_ref().unwrap();
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        Self {
+            uri: conf.uri.clone(),
+            xml: conf.xml.clone(),
+            name: format!("healer-{}-{}", process::id(), id),
+            host_addr: conf
+                .host_addr
+                .clone()
+                .unwrap_or_else(|| DEFAULT_HOST_ADDR.to_string()),
+            wait_boot_time: Duration::from_secs(
+                conf.wait_boot_time
+                    .or(cfg.guest.boot_timeout)
+                    .unwrap_or(DEFAULT_WAIT_BOOT_TIME),
+            ),
+            ssh: cfg.ssh.clone().unwrap(),
+            user: cfg.ssh.as_ref().unwrap().user(),
+            port: cfg.ssh.as_ref().unwrap().port(),
+            os: cfg.guest.os.clone(),
+            addr: String::new(),
+            console: None,
+        }
+    }
+
+    pub fn host_addr(&self) -> &str {
+        &self.host_addr
+    }
+
+    /// Destroy domain left by last boot, define a fresh one from template and start it.
+    pub async fn boot(&mut self) -> Result<()> {
+        self.console = None;
+        // domain may not exist, failures are expected.
+        self.virsh(&["destroy", &self.name]).await;
+        self.virsh(&["undefine", "--snapshots-metadata", &self.name])
+            .await;
+
+        let xml = self.domain_xml()?;
+        let xml_path = temp_dir().join(format!("{}.xml", self.name));
+        write(&xml_path, xml).map_err(|e| {
+            err!(
+                exitcode::IOERR,
+                "Fail to write domain xml {}: {}",
+                xml_path.display(),
+                e
+            )
+        })?;
+        self.virsh_ok(&["define", xml_path.to_str().unwrap()])
+            .await?;
+        self.virsh_ok(&["start", &self.name]).await?;
+        if let Some(pty) = self.virsh(&["ttyconsole", &self.name]).await {
+            self.console = Some(open_console(Path::new(pty.trim()))?);
+        }
+
+        let start = Instant::now();
+        while start.elapsed() < self.wait_boot_time {
+            delay_for(Duration::from_secs(2)).await;
+            if let Some(addr) = self.domain_addr().await {
+                self.addr = addr;
+                if self.is_alive().await {
+                    self.clear().await;
+                    return Ok(());
+                }
+            }
+        }
+        let console = self
+            .console
+            .as_mut()
+            .map(|c| String::from_utf8_lossy(&read_all_nonblock(c)).into_owned())
+            .unwrap_or_default();
+        bail!(
+            exitcode::UNAVAILABLE,
+            "Fail to boot domain {}:\n{}",
+            self.name,
+            console
+        )
+    }
+
+    /// Template with name, uuid and mac addresses replaced, so that domains do not conflict.
+    fn domain_xml(&self) -> Result<String> {
+        let xml = read_to_string(&self.xml).map_err(|e| {
+            err!(
+                exitcode::IOERR,
+                "Fail to read domain xml {}: {}",
+                self.xml.display(),
+                e
+            )
+        })?;
+        let mut xml = replace_elem(
+            &xml,
+            "<name>",
+            "</name>",
+            &format!("<name>{}</name>", self.name),
+        );
+        xml = replace_elem(&xml, "<uuid>", "</uuid>", "");
+        while xml.contains("<mac address=") {
+            xml = replace_elem(&xml, "<mac address=", "/>", "");
+        }
+        Ok(xml)
+    }
+
+    /// Ipv4 address of first interface of domain.
+    async fn domain_addr(&self) -> Option<String> {
+        let out = self.virsh(&["domifaddr", &self.name]).await?;
+        out.lines()
+            .filter_map(|l| l.split_whitespace().nth(3))
+            .find(|a| a.contains('.'))
+            .and_then(|a| a.split('/').next())
+            .map(String::from)
+    }
+
+    pub async fn is_alive(&self) -> bool {
+        if self.addr.is_empty() {
+            return false;
+        }
+
+        ssh_alive(&self.ssh, &self.user, &self.addr, self.port).await
+    }
+
+    pub fn health_probe(&self) -> HealthProbe {
+        HealthProbe::ssh(&self.ssh, &self.user, &self.addr, self.port)
+    }
+
+    pub async fn dmesg(&self) -> Option<String> {
+        if !self.is_alive().await {
+            return None;
+        }
+        ssh_output(
+            &self.ssh,
+            &self.user,
+            &self.addr,
+            self.port,
+            &App::new("dmesg"),
+        )
+        .await
+    }
+
+    pub async fn run_cmd(&self, app: &App) -> Result<Child> {
+        let mut app = app.clone();
+        let bin = self.copy(PathBuf::from(&app.bin)).await?;
+        app.bin = String::from(bin.to_str().unwrap());
+        self.run_guest_cmd(&app).await
+    }
+
+    pub async fn run_guest_cmd(&self, app: &App) -> Result<Child> {
+        ssh_spawn(&self.ssh, &self.user, &self.addr, self.port, app)
+    }
+
+    pub async fn clear(&mut self) {
+        if let Some(console) = self.console.as_mut() {
+            read_all_nonblock(console);
+        }
+    }
+
+    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf> {
+        scp_copy(&self.ssh, &self.user, &self.addr, self.port, path.as_ref()).await
+    }
+
+    pub async fn save_snapshot(&mut self) -> bool {
+        self.virsh(&["snapshot-create-as", &self.name, SNAPSHOT_TAG])
+            .await
+            .is_some()
+    }
+
+    pub async fn restore_snapshot(&mut self) -> bool {
+        let restored = self
+            .virsh(&["snapshot-revert", &self.name, SNAPSHOT_TAG, "--running"])
+            .await
+            .is_some();
+        if restored {
+            self.clear().await;
+            self.is_alive().await
+        } else {
+            false
+        }
+    }
+
+    /// Domain crashed if it is not running anymore or can not be logged in.
+    pub async fn try_collect_crash(&mut self) -> Option<Crash> {
+        let running = self
+            .virsh(&["domstate", &self.name])
+            .await
+            .map(|s| s.trim() == "running")
+            .unwrap_or(false);
+        if running && self.is_alive().await {
+            return None;
+        }
+        let output = read_all_nonblock(self.console.as_mut()?);
+        let output = String::from_utf8_lossy(&output);
+        Some(Crash {
+            console: console_tail(&output),
+            ..Crash::new(
+                extract_report(&output, &self.os).to_string(),
+                CrashSource::Down,
+            )
+        })
+    }
+
+    /// Run virsh, return its stdout if succeeded.
+    async fn virsh(&self, args: &[&str]) -> Option<String> {
+        let mut virsh = Command::new("virsh");
+        if let Some(uri) = self.uri.as_ref() {
+            virsh.arg("-c").arg(uri);
+        }
+        let output = match virsh.args(args).output().await {
+            Ok(output) => output,
+            Err(e) => {
+                warn!("Fail to spawn virsh: {}", e);
+                return None;
+            }
+        };
+        if output.status.success() {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            debug!(
+                "virsh {}: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            None
+        }
+    }
+
+    async fn virsh_ok(&self, args: &[&str]) -> Result<String> {
+        self.virsh(args)
+            .await
+            .ok_or_else(|| err!(exitcode::UNAVAILABLE, "virsh {} failed", args.join(" ")))
+    }
+}
+
+/// Replace first element starting with `start` and ending with `end`.
+fn replace_elem(xml: &str, start: &str, end: &str, new: &str) -> String {
+    if let Some(s) = xml.find(start) {
+        if let Some(e) = xml[s..].find(end) {
+            let e = s + e + end.len();
+            return format!("{}{}{}", &xml[..s], new, &xml[e..]);
+        }
+    }
+    xml.to_string()
+}
@@ -0,0 +1,229 @@
+//! Host itself, executor runs directly on it, optionally inside a chroot and fresh
+//! namespaces, for containers and throwaway cloud instances where booting vm is impossible
+//! or unnecessary. Crashes of host kernel are reported by its kernel log, if readable.
+
+use super::container::{open_kmsg, read_kmsg};
+use super::{crash_patterns, extract_report, Crash, CrashSource};
+use crate::error::Result;
+use crate::utils::cli::{App, Arg};
+use crate::Config;
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+use tokio::fs::{copy, create_dir_all, set_permissions};
+use tokio::process::Child;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LocalConf {
+    /// Root directory executor runs in, prepared beforehand with /proc, /dev and
+    /// /sys/kernel/debug mounted, host root by default
+    pub chroot: Option<PathBuf>,
+    /// Run executor in fresh mount, pid, ipc and uts namespaces through unshare
+    #[serde(default)]
+    pub namespace: bool,
+}
+
+impl LocalConf {
+    pub fn check(&self) -> Result<()> {
+        if let Some(root) = self.chroot.as_ref() {
+            if !root.is_dir() {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: chroot {} of local platform is invalid",
+                    root.display()
+                )
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Distinguish work dirs of executors in chroot.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+static KMSG_WARNING: Once = Once::new();
+
+/// Executor shares network with fuzzer.
+const LOCAL_HOST_IP_ADDR: &str = "127.0.0.1";
+
+pub struct Local {
+    chroot: Option<PathBuf>,
+    namespace: bool,
+    /// Directory in chroot where files are copied to, relative to chroot
+    work_dir: PathBuf,
+    os: String,
+    /// Host kernel log, positioned at end on each clear, none if it is not readable
+    kmsg: Option<File>,
+}
+
+impl Local {
+    pub fn new(cfg: &Config) -> Self {
+        let conf = cfg.local.clone().unwrap_or_default();
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        Self {
+            chroot: conf.chroot,
+            namespace: conf.namespace,
+            work_dir: PathBuf::from(format!("/tmp/healer-{}-{}", process::id(), id)),
+            os: cfg.guest.os.clone(),
+            kmsg: None,
+        }
+    }
+
+    pub fn host_addr(&self) -> &str {
+        LOCAL_HOST_IP_ADDR
+    }
+
+    /// Nothing to boot, only prepare work dir in chroot and start watching kernel log.
+    pub async fn boot(&mut self) -> Result<()> {
+        if let Some(root) = self.chroot.as_ref() {
+            let dir = root.join(self.work_dir.strip_prefix("/").unwrap());
+            create_dir_all(&dir).await.map_err(|e| {
+                err!(
+                    exitcode::IOERR,
+                    "Fail to create work dir {}: {}",
+                    dir.display(),
+                    e
+                )
+            })?;
+        }
+        if self.kmsg.is_none() {
+            match open_kmsg() {
+                Ok(kmsg) => self.kmsg = Some(kmsg),
+                Err(e) => KMSG_WARNING.call_once(|| {
+                    warn!("{}, crashes of host kernel are not reported", e);
+                }),
+            }
+        }
+        self.clear().await;
+        Ok(())
+    }
+
+    /// Host is alive as long as fuzzer is.
+    pub async fn is_alive(&self) -> bool {
+        true
+    }
+
+    pub async fn run_cmd(&self, app: &App) -> Result<Child> {
+        let mut app = app.clone();
+        let bin = self.copy(PathBuf::from(&app.bin)).await?;
+        app.bin = String::from(bin.to_str().unwrap());
+        self.run_guest_cmd(&app).await
+    }
+
+    pub async fn run_guest_cmd(&self, app: &App) -> Result<Child> {
+        let app = self.wrap(app);
+        let bin = app.bin.clone();
+        app.into_cmd()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| err!(exitcode::OSERR, "Fail to spawn {}: {}", bin, e))
+    }
+
+    /// Command running `app` in chroot and namespaces, if configured.
+    fn wrap(&self, app: &App) -> App {
+        let mut cmds = Vec::new();
+        if self.namespace {
+            let mut unshare = App::new("unshare");
+            unshare
+                .arg(Arg::new_flag("--mount"))
+                .arg(Arg::new_flag("--pid"))
+                .arg(Arg::new_flag("--fork"))
+                .arg(Arg::new_flag("--ipc"))
+                .arg(Arg::new_flag("--uts"));
+            // proc of chroot is prepared by user.
+            if self.chroot.is_none() {
+                unshare.arg(Arg::new_flag("--mount-proc"));
+            }
+            unshare.arg(Arg::new_flag("--"));
+            cmds.push(unshare);
+        }
+        if let Some(root) = self.chroot.as_ref() {
+            let mut chroot = App::new("chroot");
+            chroot.arg(Arg::new_flag(root.to_str().unwrap()));
+            cmds.push(chroot);
+        }
+
+        let mut cmds = cmds.into_iter();
+        let mut wrapped = match cmds.next() {
+            Some(first) => first,
+            None => return app.clone(),
+        };
+        for cmd in cmds {
+            wrapped.arg(Arg::new_flag(&cmd.bin));
+            for arg in cmd.iter_arg() {
+                wrapped.arg(Arg::Flag(arg));
+            }
+        }
+        wrapped.arg(Arg::new_flag(&app.bin));
+        for arg in app.clone().iter_arg() {
+            wrapped.arg(Arg::Flag(arg));
+        }
+        wrapped
+    }
+
+    pub async fn clear(&mut self) {
+        if let Some(kmsg) = self.kmsg.as_mut() {
+            kmsg.seek(SeekFrom::End(0)).ok();
+        }
+    }
+
+    /// Copy file into work dir in chroot, return its path seen in chroot. Without chroot,
+    /// file is used in place.
+    pub async fn copy<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf> {
+        let path = path.as_ref();
+        assert!(path.is_file());
+        let root = match self.chroot.as_ref() {
+            Some(root) => root,
+            None => {
+                return path
+                    .canonicalize()
+                    .map_err(|e| err!(exitcode::IOERR, "Fail to locate {}: {}", path.display(), e))
+            }
+        };
+        let guest_path = self.work_dir.join(path.file_name().unwrap());
+        let host_path = root.join(guest_path.strip_prefix("/").unwrap());
+        copy(path, &host_path).await.map_err(|e| {
+            err!(
+                exitcode::IOERR,
+                "Fail to copy {} to {}: {}",
+                path.display(),
+                host_path.display(),
+                e
+            )
+        })?;
+        // only content is copied, keep executables runnable.
+        let perm = path
+            .metadata()
+            .map(|m| m.permissions())
+            .map_err(|e| err!(exitcode::IOERR, "Fail to stat {}: {}", path.display(), e))?;
+        set_permissions(&host_path, perm).await.map_err(|e| {
+            err!(
+                exitcode::IOERR,
+                "Fail to set permissions of {}: {}",
+                host_path.display(),
+                e
+            )
+        })?;
+        Ok(guest_path)
+    }
+
+    /// Crash of host kernel that it survived, e.g. a WARNING or KASAN report, is reported
+    /// by kernel log. Executor dying alone is not a crash.
+    pub async fn try_collect_crash(&mut self) -> Option<Crash> {
+        let kmsg = read_kmsg(self.kmsg.as_mut()?);
+        if crash_patterns(&self.os).iter().any(|p| kmsg.contains(p)) {
+            Some(Crash::new(
+                extract_report(&kmsg, &self.os).to_string(),
+                CrashSource::Console,
+            ))
+        } else {
+            None
+        }
+    }
+}
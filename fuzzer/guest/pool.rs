@@ -0,0 +1,56 @@
+//! Guests booted ahead of time, so that executor whose guest went down continues with a
+//! standby one at once, while its old guest reboots in background.
+
+use super::Guest;
+use crate::error::Result;
+use crate::utils::vm_log;
+use crate::Config;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(Clone)]
+pub struct GuestPool {
+    cfg: Arc<Config>,
+    standby: Arc<Mutex<VecDeque<Guest>>>,
+}
+
+impl GuestPool {
+    /// Pool keeping `standby_num` guests of config, none if it is not configured.
+    pub fn new(cfg: &Arc<Config>) -> Option<Self> {
+        match cfg.standby_num {
+            Some(n) if n != 0 => Some(Self {
+                cfg: cfg.clone(),
+                standby: Arc::new(Mutex::new(VecDeque::with_capacity(n))),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Boot standby guests in background, numbered after working ones.
+    pub fn fill(&self) -> Result<()> {
+        for i in 0..self.cfg.standby_num.unwrap_or(0) {
+            self.recycle(Guest::new(&self.cfg, self.cfg.vm_num + i)?);
+        }
+        Ok(())
+    }
+
+    /// Take a booted guest running `kernel`, none if all such standby guests are still
+    /// booting.
+    pub async fn take(&self, kernel: Option<&str>) -> Option<Guest> {
+        let mut standby = self.standby.lock().await;
+        let i = standby.iter().position(|g| g.kernel() == kernel)?;
+        standby.remove(i)
+    }
+
+    /// Boot guest in background and keep it as standby, guest that fails to boot is dropped.
+    pub fn recycle(&self, mut guest: Guest) {
+        let standby = self.standby.clone();
+        vm_log::spawn(async move {
+            match guest.boot().await {
+                Ok(()) => standby.lock().await.push_back(guest),
+                Err(e) => warn!("Fail to boot standby guest: {}", e),
+            }
+        });
+    }
+}
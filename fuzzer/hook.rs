@@ -0,0 +1,78 @@
+//! Hooks of campaign events, letting embedders react to them, e.g. updating dashboards or
+//! filing tickets, without patching fuzzer.
+
+use crate::stats::Stats;
+use core::prog::Prog;
+use std::sync::Arc;
+
+/// Callbacks of campaign events, all of them do nothing by default.
+///
+/// Callbacks are called synchronously from fuzzing loop of vm the event happens on, so they
+/// should return quickly, sending slow work to a task or thread of their own. Fuzzer is
+/// built with `panic = "abort"`, a panicking callback takes down whole fuzzer.
+pub trait Hook: Send + Sync {
+    /// Prog executed on `vm` brought new coverage, it is minimized and recorded as test
+    /// case `id`.
+    fn on_new_coverage(&self, _vm: usize, _id: usize, _p: &Prog, _blocks: usize, _branches: usize) {
+    }
+
+    /// Prog executed on `vm` crashed guest, `repro` tells whether re-executing it crashed
+    /// guest again. Crashes matching ignores or suppressions are not reported.
+    fn on_crash(&self, _vm: usize, _title: &str, _report: &str, _p: &Prog, _repro: bool) {}
+
+    /// Guest of `vm` is about to be restarted, rebooted or restored from snapshot, after
+    /// crash, hang, health check failure or recycling.
+    fn on_vm_restart(&self, _vm: usize) {}
+
+    /// Corpus and state are saved to work dir, with stats at the time.
+    fn on_checkpoint(&self, _stats: &Stats) {}
+}
+
+/// Hooks registered by embedder, passed to `spawn_fuzzer_with`.
+#[derive(Clone, Default)]
+pub struct Hooks {
+    hooks: Vec<Arc<dyn Hook>>,
+}
+
+impl Hooks {
+    /// Register `hook`, hooks are called in order of registering.
+    pub fn register<H: Hook + 'static>(&mut self, hook: H) -> &mut Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub(crate) fn new_coverage(
+        &self,
+        vm: usize,
+        id: usize,
+        p: &Prog,
+        blocks: usize,
+        branches: usize,
+    ) {
+        for hook in self.hooks.iter() {
+            hook.on_new_coverage(vm, id, p, blocks, branches);
+        }
+    }
+
+    pub(crate) fn crash(&self, vm: usize, title: &str, report: &str, p: &Prog, repro: bool) {
+        for hook in self.hooks.iter() {
+            hook.on_crash(vm, title, report, p, repro);
+        }
+    }
+
+    pub(crate) fn vm_restart(&self, vm: usize) {
+        for hook in self.hooks.iter() {
+            hook.on_vm_restart(vm);
+        }
+    }
+
+    pub(crate) fn checkpoint(&self, stats: &Stats) {
+        for hook in self.hooks.iter() {
+            hook.on_checkpoint(stats);
+        }
+    }
+}
@@ -1,5 +1,9 @@
-use std::path::PathBuf;
-use std::process::{exit, id};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::id;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 #[macro_use]
@@ -9,124 +13,986 @@ extern crate serde;
 #[macro_use]
 extern crate log;
 
+use log::Level;
 use regex::Regex;
-use tokio::fs::{create_dir_all, read};
+use tokio::fs::read;
 use tokio::signal::ctrl_c;
-use tokio::sync::{broadcast, Barrier};
-use tokio::time::{delay_for, Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot, Barrier, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{delay_for, interval_at, Duration, Instant, Interval};
 
 use core::prog::Prog;
 use core::target::Target;
-use fots::types::Items;
+use fots::types::{FnInfo, Group, GroupId, Items};
 
-use crate::exec::{Executor, ExecutorConf};
-use crate::fuzzer::Fuzzer;
-use crate::guest::{GuestConf, QemuConf, SSHConf};
+use crate::breaker::{Breaker, BreakerConf};
+use crate::corpus::{parse_dump, AutosaveConf, Corpus, EvictionConf, Meta, SelectionConf};
+use crate::dist::{ManagerConf, Outbox, Worker};
+pub use crate::error::{Error, Result};
+use crate::exec::{Executor, ExecutorConf, Recovery};
+use crate::fuzzer::{Fuzzer, GenConf};
+use crate::guest::{
+    AdbConf, BareConf, BoardConf, CloudConf, ContainerConf, GuestConf, GuestPool, GvisorConf,
+    LibvirtConf, LocalConf, QemuConf, SSHConf,
+};
+pub use crate::hook::{Hook, Hooks};
 #[cfg(feature = "mail")]
 use crate::mail::MailConf;
+use crate::plateau::PlateauConf;
+use crate::schedule::ScheduleConf;
 use crate::stats::SamplerConf;
+pub use crate::stats::Stats;
+use crate::store::{StoreConf, STORE_FILE};
+use crate::throughput::ThroughputConf;
+use crate::upload::{UploadConf, Uploader};
+pub use crate::utils::daemon::PidFile;
+use crate::utils::{daemon, vm_log, workdir};
 
+#[macro_use]
+mod error;
 #[macro_use]
 #[allow(dead_code)]
 mod utils;
+mod breaker;
+pub mod cmd;
 pub mod corpus;
+pub mod dist;
 mod exec;
 pub mod feedback;
 mod fuzzer;
 mod guest;
+mod hook;
 #[cfg(feature = "mail")]
 mod mail;
+mod plateau;
 pub mod report;
+mod schedule;
+mod shard;
+mod smoke;
 mod stats;
+mod store;
+mod syz;
+mod throughput;
+mod triage;
+mod upload;
+
+/// Compiled fots file, or files merged into one target.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum FotsBin {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+impl fmt::Display for FotsBin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FotsBin::One(path) => write!(f, "{}", path.display()),
+            FotsBin::Many(paths) => {
+                let paths = paths.iter().map(|p| p.display().to_string());
+                write!(f, "{}", paths.collect::<Vec<_>>().join(", "))
+            }
+        }
+    }
+}
+
+/// Description bundle merged into target and groups it brought.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bundle {
+    pub path: PathBuf,
+    pub groups: Vec<GroupId>,
+}
+
+/// Minutes between checkpoints if not configured.
+const DEFAULT_CHECKPOINT_INTERVAL: u64 = 30;
+/// Minutes without progress before watchdog restarts vm, if not configured.
+const DEFAULT_WATCHDOG: u64 = 15;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
-    pub fots_bin: PathBuf,
+    /// Compiled fots file, list of them or directory of them, merged into one target
+    pub fots_bin: FotsBin,
     pub curpus: Option<PathBuf>,
+    /// Outputs of each run go to a fresh directory under it, current directory if not given
+    pub work_dir: Option<PathBuf>,
+    /// Directory of run to resume, outputs go there too
+    pub resume: Option<PathBuf>,
     pub vm_num: usize,
+    /// Guests booted ahead of time, replacing guests that go down
+    pub standby_num: Option<usize>,
+    /// Reboot each guest after running this long, in minutes
+    pub auto_reboot_duration: Option<u64>,
+    /// Reboot each guest after executing this many progs
+    pub auto_reboot_exec: Option<usize>,
+    /// Save corpus, state and stats to run directory this often, in minutes, 30 by default,
+    /// 0 to save only on exit
+    pub checkpoint_interval: Option<u64>,
+    /// Save corpus to run directory more often than checkpoints, reloading it on next start
+    pub autosave: Option<AutosaveConf>,
+    /// Keep corpus on disk as it grows, with a bounded cache of it in memory, if given
+    pub corpus_store: Option<StoreConf>,
+    /// Weights of choosing progs of corpus for mutation and replay, uniform if not given
+    pub selection: Option<SelectionConf>,
+    /// Reject progs bringing no new coverage that differ from one in corpus only by constants
+    /// and ids, true by default
+    pub dedup: Option<bool>,
+    /// Cap on progs of corpus, evicting subsumed and unproductive ones beyond it, if given
+    pub eviction: Option<EvictionConf>,
+    /// Restart vm that completes no execution for this long, in minutes, 15 by default,
+    /// 0 to disable
+    pub watchdog: Option<u64>,
+    /// Recycle vms whose throughput stays far below median of vms, if given
+    pub throughput: Option<ThroughputConf>,
+    /// Stop after fuzzing this long, in minutes, including boot
+    pub max_runtime: Option<u64>,
+    /// Stop after executing this many progs
+    pub max_execs: Option<usize>,
+    /// Self-test target, executor and kernel before fuzzing, true by default
+    pub smoke_test: Option<bool>,
+    /// Seed of all random choices, runs with same seed and target run same progs
+    pub seed: Option<u64>,
+    /// Pause or stop fuzzing once target crashes too often or guests keep failing to boot
+    pub breaker: Option<BreakerConf>,
+    /// Ratio between generating, mutating and replaying corpus per iteration
+    pub schedule: Option<ScheduleConf>,
     pub suppressions: Option<Vec<String>>,
     pub ignores: Option<Vec<String>>,
     pub guest: GuestConf,
-    pub qemu: QemuConf,
-    pub ssh: SSHConf,
+    pub qemu: Option<QemuConf>,
+    pub ssh: Option<SSHConf>,
+    pub gvisor: Option<GvisorConf>,
+    pub bare: Option<BareConf>,
+    pub libvirt: Option<LibvirtConf>,
+    pub board: Option<BoardConf>,
+    pub adb: Option<AdbConf>,
+    pub container: Option<ContainerConf>,
+    pub cloud: Option<CloudConf>,
+    /// Chroot and namespaces of executor on local platform, none by default
+    pub local: Option<LocalConf>,
     pub executor: ExecutorConf,
     pub sampler: Option<SamplerConf>,
+    /// Only fuzz groups or calls matching these patterns, see `filter_items`
+    pub enable: Option<Vec<String>>,
+    /// Never fuzz groups or calls matching these patterns, see `filter_items`
+    pub disable: Option<Vec<String>>,
+    /// Calls not to be generated, by declared or called name
+    pub disabled_calls: Option<Vec<String>>,
+    /// Knobs of prog generation
+    pub gen: Option<GenConf>,
+    /// Weights of choosing groups for generation by their identifier, 1 if not given
+    pub weights: Option<BTreeMap<String, u32>>,
+    /// Number of disjoint parts groups are split into, vm `i` only generates and mutates
+    /// progs of part `i % shards`, 1 by default
+    pub shards: Option<usize>,
+    /// Switch strategies once coverage plateaus, if given
+    pub plateau: Option<PlateauConf>,
+    /// Manager of distributed campaign, this process works for it if given
+    pub manager: Option<ManagerConf>,
+    /// Remote storage that corpus, crashes and stats are uploaded to periodically
+    pub upload: Option<UploadConf>,
+    /// Write logs as json lines, see `utils::event`
+    #[serde(default)]
+    pub log_json: bool,
 
     #[cfg(feature = "mail")]
     pub mail: Option<MailConf>,
 }
 
 impl Config {
-    pub fn check(&self) {
-        if !self.fots_bin.is_file() {
-            eprintln!(
-                "Config Error: fots file {} is invalid",
-                self.fots_bin.display()
-            );
-            exit(exitcode::CONFIG)
+    pub fn check(&self) -> Result<()> {
+        for fots_bin in self.fots_bins()? {
+            if !fots_bin.is_file() {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: fots file {} is invalid",
+                    fots_bin.display()
+                )
+            }
         }
 
         if let Some(suppressions) = &self.suppressions {
             for s in suppressions {
-                Regex::new(&s).unwrap_or_else(|e| {
-                    eprintln!(
+                Regex::new(&s).map_err(|e| {
+                    err!(
+                        exitcode::CONFIG,
                         "Config Error: suppressions regex \"{}\" compile failed: {}",
-                        s, e
-                    );
-                    exit(exitcode::CONFIG)
-                });
+                        s,
+                        e
+                    )
+                })?;
             }
         }
 
         if let Some(ignores) = &self.ignores {
             for i in ignores {
-                Regex::new(&i).unwrap_or_else(|e| {
-                    eprintln!(
+                Regex::new(&i).map_err(|e| {
+                    err!(
+                        exitcode::CONFIG,
                         "Config Error: ignores regex \"{}\" compile failed: {}",
-                        i, e
-                    );
-                    exit(exitcode::CONFIG)
-                });
+                        i,
+                        e
+                    )
+                })?;
+            }
+        }
+
+        if let Some(resume) = &self.resume {
+            if !resume.join("corpus").is_file() || !resume.join("state").is_file() {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: {} is not a run to resume, its corpus or state is missing",
+                    resume.display()
+                )
+            }
+        }
+
+        if let Some(shards) = self.shards {
+            if shards == 0 || shards > self.vm_num {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: shards should be between 1 and vm_num {}, each shard needs a vm",
+                    self.vm_num
+                )
+            }
+            if shards > 1 && self.seed.is_some() {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: shards is not supported with seed, vms take turns in any order"
+                )
             }
         }
 
         if let Some(corpus) = &self.curpus {
             if !corpus.is_file() {
-                eprintln!("Config Error: corpus file {} is invalid", corpus.display());
-                exit(exitcode::CONFIG)
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: corpus file {} is invalid",
+                    corpus.display()
+                )
             }
         }
 
         let cpu_num = num_cpus::get();
         if self.vm_num == 0 || self.vm_num > cpu_num * 8 {
-            eprintln!(
+            bail!(
+                exitcode::CONFIG,
                 "Config Error: invalid vm num {}, vm num must between (0,{}] on your system",
                 self.vm_num,
                 cpu_num * 8
-            );
-            exit(exitcode::CONFIG)
+            )
+        }
+
+        if self.executor.recovery == Some(Recovery::Overlay) && self.guest.platform != "qemu" {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: overlay recovery is only supported on qemu"
+            )
+        }
+
+        let standby_num = self.standby_num.unwrap_or(0);
+        if standby_num != 0 {
+            if ["bare", "board", "adb"].contains(&self.guest.platform.as_str()) {
+                bail!(exitcode::CONFIG, "Config Error: {} platform drives a single machine, standby_num is not supported",
+                    self.guest.platform)
+            }
+            if self.vm_num + standby_num > cpu_num * 8 {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: vm num {} plus standby num {} exceeds {} on your system",
+                    self.vm_num,
+                    standby_num,
+                    cpu_num * 8
+                )
+            }
+        }
+
+        if self.auto_reboot_duration == Some(0) || self.auto_reboot_exec == Some(0) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: auto_reboot_duration and auto_reboot_exec should be positive"
+            )
+        }
+
+        if self.max_runtime == Some(0) || self.max_execs == Some(0) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: max_runtime and max_execs should be positive"
+            )
+        }
+
+        if let Some(interval) = self.checkpoint_interval {
+            if interval != 0 && interval < 5 {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: checkpoint_interval should be 0 or at least 5 minutes"
+                )
+            }
+        }
+
+        if let Some(autosave) = self.autosave.as_ref() {
+            autosave.check()?;
+        }
+
+        if let Some(store) = self.corpus_store.as_ref() {
+            store.check()?;
+        }
+
+        if let Some(selection) = self.selection.as_ref() {
+            selection.check()?;
+        }
+
+        if let Some(eviction) = self.eviction.as_ref() {
+            eviction.check()?;
+        }
+
+        if let Some(watchdog) = self.watchdog {
+            if watchdog != 0 && watchdog < 3 {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: watchdog should be 0 or at least 3 minutes, guests need time to boot"
+                )
+            }
         }
 
         if let Some(sampler) = self.sampler.as_ref() {
-            sampler.check()
+            sampler.check()?;
+        }
+        if let Some(throughput) = self.throughput.as_ref() {
+            throughput.check()?;
+        }
+        if let Some(gen) = self.gen.as_ref() {
+            gen.check()?;
+        }
+        if let Some(breaker) = self.breaker.as_ref() {
+            breaker.check()?;
+        }
+        if let Some(schedule) = self.schedule.as_ref() {
+            schedule.check()?;
+        }
+        if let Some(plateau) = self.plateau.as_ref() {
+            plateau.check()?;
+            if self.seed.is_some() {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: plateau is not supported with seed, strategies switch by time"
+                )
+            }
+        }
+        if let Some(manager) = self.manager.as_ref() {
+            manager.check()?;
+            if self.seed.is_some() {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: seed is not supported by worker, findings of other workers come at any time"
+                )
+            }
+        }
+
+        if let Some(upload) = self.upload.as_ref() {
+            upload.check()?;
         }
 
         #[cfg(feature = "mail")]
         if let Some(mail) = mail.as_ref() {
-            mail.check()
+            mail.check()?;
+        }
+
+        self.guest.check()?;
+        self.executor.check()?;
+        if self.guest.os != "linux" {
+            self.executor.check_portable(&self.guest.os)?;
+        }
+        match self.guest.platform.as_str() {
+            "gvisor" => match self.gvisor.as_ref() {
+                Some(gvisor) => gvisor.check()?,
+                None => {
+                    bail!(
+                        exitcode::CONFIG,
+                        "Config Error: gvisor platform requires gvisor config"
+                    )
+                }
+            },
+            "bare" => match (self.bare.as_ref(), self.ssh.as_ref()) {
+                (Some(bare), Some(ssh)) => {
+                    bare.check()?;
+                    ssh.check()?;
+                    if self.vm_num != 1 {
+                        bail!(exitcode::CONFIG, "Config Error: bare platform drives exactly one machine, vm_num must be 1")
+                    }
+                }
+                _ => {
+                    bail!(
+                        exitcode::CONFIG,
+                        "Config Error: bare platform requires bare and ssh config"
+                    )
+                }
+            },
+            "libvirt" => match (self.libvirt.as_ref(), self.ssh.as_ref()) {
+                (Some(libvirt), Some(ssh)) => {
+                    libvirt.check()?;
+                    ssh.check()?;
+                }
+                _ => {
+                    bail!(
+                        exitcode::CONFIG,
+                        "Config Error: libvirt platform requires libvirt and ssh config"
+                    )
+                }
+            },
+            "board" => match (self.board.as_ref(), self.ssh.as_ref()) {
+                (Some(board), Some(ssh)) => {
+                    board.check()?;
+                    ssh.check()?;
+                    if self.vm_num != 1 {
+                        bail!(exitcode::CONFIG, "Config Error: board platform drives exactly one board, vm_num must be 1")
+                    }
+                }
+                _ => {
+                    bail!(
+                        exitcode::CONFIG,
+                        "Config Error: board platform requires board and ssh config"
+                    )
+                }
+            },
+            "adb" => match self.adb.as_ref() {
+                Some(adb) => {
+                    adb.check()?;
+                    if self.vm_num != 1 {
+                        bail!(exitcode::CONFIG, "Config Error: adb platform drives exactly one device, vm_num must be 1")
+                    }
+                }
+                None => {
+                    bail!(
+                        exitcode::CONFIG,
+                        "Config Error: adb platform requires adb config"
+                    )
+                }
+            },
+            "container" => match self.container.as_ref() {
+                Some(container) => container.check()?,
+                None => {
+                    bail!(
+                        exitcode::CONFIG,
+                        "Config Error: container platform requires container config"
+                    )
+                }
+            },
+            "local" => {
+                if let Some(local) = self.local.as_ref() {
+                    local.check()?;
+                }
+            }
+            "cloud" => match (self.cloud.as_ref(), self.ssh.as_ref()) {
+                (Some(cloud), Some(ssh)) => {
+                    cloud.check()?;
+                    ssh.check()?;
+                }
+                _ => {
+                    bail!(
+                        exitcode::CONFIG,
+                        "Config Error: cloud platform requires cloud and ssh config"
+                    )
+                }
+            },
+            _ => match (self.qemu.as_ref(), self.ssh.as_ref()) {
+                (Some(qemu), Some(ssh)) => {
+                    qemu.check(&self.guest.os)?;
+                    qemu.net
+                        .check_vms(self.vm_num + self.standby_num.unwrap_or(0))?;
+                    qemu.check_kernels(self.vm_num)?;
+                    ssh.check()?;
+                    if self.recovery() != Recovery::Reboot && !qemu.overlay {
+                        bail!(exitcode::CONFIG, "Config Error: snapshot and overlay recovery on qemu require overlay, snapshot is lost with qemu otherwise")
+                    }
+                    if qemu.virtfs
+                        && (self.recovery() == Recovery::Snapshot
+                            || self.executor.snapshot_interval.is_some())
+                    {
+                        bail!(exitcode::CONFIG, "Config Error: virtfs can not be used with snapshots, qemu refuses to save vm with 9p mounted")
+                    }
+                }
+                _ => {
+                    bail!(
+                        exitcode::CONFIG,
+                        "Config Error: qemu platform requires qemu and ssh config"
+                    )
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Description bundles of target, files of directory sorted by name.
+    pub fn fots_bins(&self) -> Result<Vec<PathBuf>> {
+        let paths = match &self.fots_bin {
+            FotsBin::One(path) if path.is_dir() => {
+                let entries = std::fs::read_dir(path).map_err(|e| {
+                    err!(
+                        exitcode::CONFIG,
+                        "Config Error: fail to read fots dir {}: {}",
+                        path.display(),
+                        e
+                    )
+                })?;
+                let mut paths = entries
+                    .filter_map(|e| e.ok().map(|e| e.path()))
+                    .filter(|p| p.is_file())
+                    .collect::<Vec<_>>();
+                paths.sort();
+                paths
+            }
+            FotsBin::One(path) => vec![path.clone()],
+            FotsBin::Many(paths) => paths.clone(),
+        };
+        if paths.is_empty() {
+            bail!(exitcode::CONFIG, "Config Error: no fots file is given")
+        }
+        Ok(paths)
+    }
+
+    /// Fots file deployed to guests, merged one in work dir if there are multiple bundles.
+    pub fn target_path(&self) -> PathBuf {
+        match self.fots_bins() {
+            Ok(paths) if paths.len() == 1 => paths[0].clone(),
+            _ => workdir::path("fots"),
+        }
+    }
+
+    /// Whether to run smoke test before fuzzing, which needs coverage of executor.
+    pub fn smoke_test(&self) -> bool {
+        self.smoke_test.unwrap_or(true) && !self.executor.script_mode
+    }
+
+    /// Interval of checkpoints, none if disabled.
+    pub fn checkpoint_interval(&self) -> Option<Duration> {
+        match self
+            .checkpoint_interval
+            .unwrap_or(DEFAULT_CHECKPOINT_INTERVAL)
+        {
+            0 => None,
+            m => Some(Duration::from_secs(m * 60)),
+        }
+    }
+
+    /// Time without progress before watchdog restarts vm, none if disabled.
+    pub fn watchdog(&self) -> Option<Duration> {
+        match self.watchdog.unwrap_or(DEFAULT_WATCHDOG) {
+            0 => None,
+            m => Some(Duration::from_secs(m * 60)),
+        }
+    }
+
+    /// Recovery policy after crash: given one, snapshot with fast_reboot, overlay on qemu
+    /// booting from overlay, reboot otherwise.
+    pub fn recovery(&self) -> Recovery {
+        let overlay =
+            self.guest.platform == "qemu" && self.qemu.as_ref().map_or(false, |q| q.overlay);
+        match self.executor.recovery {
+            Some(recovery) => recovery,
+            None if self.executor.fast_reboot => Recovery::Snapshot,
+            None if overlay => Recovery::Overlay,
+            None => Recovery::Reboot,
+        }
+    }
+
+    /// Name of kernel `vm` runs, if several kernels are configured.
+    pub fn kernel_of(&self, vm: usize) -> Option<&str> {
+        if self.guest.platform != "qemu" {
+            return None;
+        }
+        let kernel = self.qemu.as_ref()?.kernel_of(vm)?;
+        Some(&kernel.name)
+    }
+}
+
+/// Built-in presets of config, each a list of overrides layered over config file.
+const PRESETS: &[(&str, &[&str])] = &[
+    // short sanity run of new kernel or descriptions.
+    (
+        "quick",
+        &["max_runtime=30", "checkpoint_interval=0", "smoke_test=true"],
+    ),
+    // long campaign, recycling guests and shifting toward what brings coverage.
+    (
+        "thorough",
+        &[
+            "smoke_test=true",
+            "checkpoint_interval=15",
+            "auto_reboot_duration=360",
+            "executor.memleak_check=true",
+            "schedule.generate=1",
+            "schedule.mutate=4",
+            "schedule.replay=1",
+            "schedule.adaptive=true",
+            "breaker.max_boot_failures=5",
+        ],
+    ),
+    // bounded run of continuous integration, failing fast on broken kernel.
+    (
+        "ci",
+        &[
+            "max_runtime=60",
+            "checkpoint_interval=0",
+            "smoke_test=true",
+            "log_json=true",
+            "breaker.max_boot_failures=3",
+            "breaker.exit=true",
+        ],
+    ),
+];
+
+/// Overrides of preset `name`, see `PRESETS`.
+pub fn preset(name: &str) -> Result<Vec<(String, toml::Value)>> {
+    match PRESETS.iter().find(|(n, _)| *n == name) {
+        Some((_, settings)) => settings.iter().map(|s| parse_override(s)).collect(),
+        None => bail!(
+            exitcode::USAGE,
+            "Unknown preset {}, should be one of {}",
+            name,
+            PRESETS
+                .iter()
+                .map(|(n, _)| *n)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Parse `key=value` override of config, value is taken as string if it is not valid toml.
+pub fn parse_override(setting: &str) -> Result<(String, toml::Value)> {
+    let (key, raw) = match setting.find('=') {
+        Some(i) => (&setting[..i], &setting[i + 1..]),
+        None => bail!(
+            exitcode::USAGE,
+            "Invalid override \"{}\", should be key=value",
+            setting
+        ),
+    };
+    let value = toml::from_str::<toml::Value>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|t| t.get("v").cloned())
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()));
+    Ok((key.trim().to_string(), value))
+}
+
+/// Set value of dotted `key` in config, e.g. `executor.memleak_check`, creating missing tables.
+pub fn set_config(conf: &mut toml::Value, key: &str, value: toml::Value) -> Result<()> {
+    let mut keys = key.split('.').collect::<Vec<_>>();
+    let last = keys.pop().unwrap();
+    let mut table = conf;
+    for k in keys {
+        table = match table {
+            toml::Value::Table(t) => t.entry(k).or_insert(toml::Value::Table(Default::default())),
+            _ => bail!(
+                exitcode::USAGE,
+                "Invalid override {}: path goes through non-table",
+                key
+            ),
+        };
+    }
+    match table {
+        toml::Value::Table(t) => {
+            t.insert(last.to_string(), value);
+            Ok(())
+        }
+        _ => bail!(
+            exitcode::USAGE,
+            "Invalid override {}: path goes through non-table",
+            key
+        ),
+    }
+}
+
+/// Keys of config holding paths, resolved against directory of config file if relative.
+/// Arrays on the way, e.g. `qemu.kernels`, are walked into.
+const PATH_KEYS: [&str; 21] = [
+    "fots_bin",
+    "curpus",
+    "work_dir",
+    "resume",
+    "executor.path",
+    "executor.harness",
+    "qemu.image",
+    "qemu.kernel",
+    "qemu.initrd",
+    "qemu.vmcore_dir",
+    "qemu.init_script",
+    "qemu.kernels.kernel",
+    "qemu.kernels.image",
+    "qemu.kernels.initrd",
+    "ssh.key_path",
+    "gvisor.runsc",
+    "bare.console",
+    "board.console",
+    "libvirt.xml",
+    "container.seccomp",
+    "local.chroot",
+];
+
+/// Expand `${NAME}` in string values of config with environment variables, `${WORK_DIR}`
+/// being *work_dir* of config(current directory if not given), then resolve relative paths
+/// of `PATH_KEYS` against `base`, directory of config file, if given. Values that are
+/// expanded already stay as they are, so overrides can be expanded after config file.
+pub fn expand_config(conf: &mut toml::Value, base: Option<&Path>) -> Result<()> {
+    let mut vars = env::vars().collect::<HashMap<_, _>>();
+    // work dir may refer to other variables, but not to itself.
+    if let Some(toml::Value::String(work_dir)) = conf.get_mut("work_dir") {
+        *work_dir = expand_vars(work_dir, &vars)?;
+    }
+    if let Some(base) = base {
+        resolve_path(conf, &["work_dir"], base);
+    }
+    let work_dir = match conf.get("work_dir") {
+        Some(toml::Value::String(work_dir)) => PathBuf::from(work_dir),
+        _ => env::current_dir().unwrap_or_default(),
+    };
+    vars.insert(
+        String::from("WORK_DIR"),
+        work_dir.to_string_lossy().into_owned(),
+    );
+    expand_value(conf, &vars)?;
+    if let Some(base) = base {
+        for key in PATH_KEYS.iter() {
+            resolve_path(conf, &key.split('.').collect::<Vec<_>>(), base);
         }
+    }
+    Ok(())
+}
+
+fn expand_value(value: &mut toml::Value, vars: &HashMap<String, String>) -> Result<()> {
+    match value {
+        toml::Value::String(s) => *s = expand_vars(s, vars)?,
+        toml::Value::Array(values) => {
+            for v in values.iter_mut() {
+                expand_value(v, vars)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, v) in table.iter_mut() {
+                expand_value(v, vars)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Replace each `${NAME}` in `s` with value of variable `NAME`.
+fn expand_vars(s: &str, vars: &HashMap<String, String>) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => bail!(
+                exitcode::CONFIG,
+                "Config Error: unclosed variable in \"{}\"",
+                s
+            ),
+        };
+        let name = &rest[start + 2..end];
+        let val = match vars.get(name) {
+            Some(val) => val,
+            None => bail!(
+                exitcode::CONFIG,
+                "Config Error: variable {} in \"{}\" is not set",
+                name,
+                s
+            ),
+        };
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(val);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Join relative path at dotted `keys` of config to `base`, empty values are kept.
+fn resolve_path(value: &mut toml::Value, keys: &[&str], base: &Path) {
+    match value {
+        toml::Value::Array(values) => {
+            for v in values.iter_mut() {
+                resolve_path(v, keys, base);
+            }
+        }
+        toml::Value::Table(table) if !keys.is_empty() => {
+            if let Some(v) = table.get_mut(keys[0]) {
+                resolve_path(v, &keys[1..], base);
+            }
+        }
+        toml::Value::String(s) if keys.is_empty() => {
+            if !s.is_empty() && Path::new(s.as_str()).is_relative() {
+                *s = base.join(s.as_str()).to_string_lossy().into_owned();
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Fuzz until SIGINT or SIGTERM is received, limit of run is reached, or executor fails,
+/// returning final stats; on SIGHUP, config returned by `reload` is applied, on SIGTTIN and
+/// SIGTTOU, one vm is booted or drained, on SIGUSR1, state is dumped to work dir, and on
+/// SIGUSR2, debug logs are toggled.
+pub async fn fuzz<F>(cfg: Config, reload: F) -> Result<Stats>
+where
+    F: Fn() -> Result<Config> + Send + 'static,
+{
+    let handle = spawn_fuzzer(cfg);
+    let stop = handle.stop.clone();
+    tokio::spawn(async move {
+        wait_exit_signal().await;
+        stop.send(()).ok();
+    });
+    let reload_tx = handle.reload.clone();
+    if cfg!(unix) {
+        tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut sig_hup =
+                signal(SignalKind::hangup()).expect("failed to set up SIGHUP signal handler");
+            while sig_hup.recv().await.is_some() {
+                warn!("HUP signal recved, reloading config...");
+                match reload() {
+                    Ok(cfg) => {
+                        reload_tx.send(cfg).ok();
+                    }
+                    Err(e) => error!("Fail to reload config: {}", e),
+                }
+            }
+        });
+        let scale_tx = handle.scale.clone();
+        let dump_tx = handle.dump.clone();
+        tokio::spawn(async move {
+            use nix::sys::signal::Signal;
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut sig_ttin = signal(SignalKind::from_raw(Signal::SIGTTIN as i32))
+                .expect("failed to set up SIGTTIN signal handler");
+            let mut sig_ttou = signal(SignalKind::from_raw(Signal::SIGTTOU as i32))
+                .expect("failed to set up SIGTTOU signal handler");
+            let mut sig_usr1 = signal(SignalKind::user_defined1())
+                .expect("failed to set up SIGUSR1 signal handler");
+            let mut sig_usr2 = signal(SignalKind::user_defined2())
+                .expect("failed to set up SIGUSR2 signal handler");
+            loop {
+                tokio::select! {
+                    Some(()) = sig_ttin.recv() => {
+                        scale_tx.send(1).ok();
+                    }
+                    Some(()) = sig_ttou.recv() => {
+                        scale_tx.send(-1).ok();
+                    }
+                    Some(()) = sig_usr1.recv() => {
+                        warn!("USR1 signal recved, dumping state...");
+                        dump_tx.send(()).ok();
+                    }
+                    Some(()) = sig_usr2.recv() => {
+                        let verbose = toggle_verbose();
+                        warn!("USR2 signal recved, debug logs {}", if verbose { "on" } else { "off" });
+                    }
+                    else => break,
+                }
+            }
+        });
+    }
+    handle.join().await
+}
+
+/// Handle of fuzzer started by `spawn_fuzzer`, dropping it detaches the fuzzer.
+pub struct FuzzerHandle {
+    stop: mpsc::UnboundedSender<()>,
+    reload: mpsc::UnboundedSender<Config>,
+    scale: mpsc::UnboundedSender<isize>,
+    dump: mpsc::UnboundedSender<()>,
+    task: JoinHandle<Result<Stats>>,
+}
+
+impl FuzzerHandle {
+    /// Ask fuzzer to stop, data is persisted before `join` returns.
+    pub fn stop(&self) {
+        self.stop.send(()).ok();
+    }
+
+    /// Apply sampler, disabled_calls, gen, weights and vm_num of `cfg` without restarting
+    /// guests, other changed values are logged and ignored.
+    pub fn reload(&self, cfg: Config) {
+        self.reload.send(cfg).ok();
+    }
+
+    /// Boot `delta` more vms, or drain `-delta` vms if it is negative.
+    pub fn scale(&self, delta: isize) {
+        self.scale.send(delta).ok();
+    }
+
+    /// Dump state of fuzzer to work dir without stopping it, see `dump_state`.
+    pub fn dump(&self) {
+        self.dump.send(()).ok();
+    }
+
+    /// Wait for fuzzer to stop, either by `stop` or because of error, returning final stats.
+    pub async fn join(self) -> Result<Stats> {
+        let FuzzerHandle {
+            stop,
+            reload,
+            scale,
+            dump,
+            task,
+        } = self;
+        let ret = task
+            .await
+            .map_err(|e| err!(exitcode::SOFTWARE, "Fuzzer task failed: {}", e))?;
+        drop((stop, reload, scale, dump));
+        ret
+    }
+}
+
+/// Start fuzzing in background, errors of booting or fuzzing are returned by `join` of handle.
+pub fn spawn_fuzzer(cfg: Config) -> FuzzerHandle {
+    spawn_fuzzer_with(cfg, Hooks::default())
+}
 
-        self.guest.check();
-        self.executor.check();
-        self.qemu.check();
-        self.ssh.check();
+/// Start fuzzing in background like `spawn_fuzzer`, telling `hooks` about events of campaign.
+pub fn spawn_fuzzer_with(cfg: Config, hooks: Hooks) -> FuzzerHandle {
+    let (stop_tx, stop_rx) = mpsc::unbounded_channel();
+    let (reload_tx, reload_rx) = mpsc::unbounded_channel();
+    let (scale_tx, scale_rx) = mpsc::unbounded_channel();
+    let (dump_tx, dump_rx) = mpsc::unbounded_channel();
+    let task = tokio::spawn(run(cfg, hooks, stop_rx, reload_rx, scale_rx, dump_rx));
+    FuzzerHandle {
+        scale: scale_tx,
+        dump: dump_tx,
+        stop: stop_tx,
+        reload: reload_tx,
+        task,
     }
 }
 
-pub async fn fuzz(cfg: Config) {
+async fn run(
+    cfg: Config,
+    hooks: Hooks,
+    mut stop: mpsc::UnboundedReceiver<()>,
+    mut reload: mpsc::UnboundedReceiver<Config>,
+    mut scale: mpsc::UnboundedReceiver<isize>,
+    mut dump: mpsc::UnboundedReceiver<()>,
+) -> Result<Stats> {
+    let started = Instant::now();
     let cfg = Arc::new(cfg);
-    let (target, corpus) = tokio::join!(load_target(&cfg), load_corpus(&cfg.curpus));
-    check_corpus(&target, &corpus);
+    let seeds = seed_corpus(&cfg);
+    let (items, corpus) = tokio::join!(load_items(&cfg), load_corpus(&seeds));
+    let ((mut items, bundles), mut corpus) = (items?, corpus?);
+    persist_bundles(&bundles).await?;
+    let filtered = filter_items(&mut items, &cfg)?;
+    let target = Target::from(items);
+    if filtered {
+        let dropped = retain_known(&target, &mut corpus);
+        if dropped != 0 {
+            info!(
+                "{} progs of corpus dropped, their calls are filtered",
+                dropped
+            );
+        }
+    }
+    check_corpus(&target, &corpus)?;
+    check_shards(&target, &cfg)?;
     info!("Corpus: {}", corpus.len());
     info!(
         "Syscalls: {}  Groups: {}",
@@ -134,45 +1000,519 @@ pub async fn fuzz(cfg: Config) {
         target.groups.len()
     );
 
-    let fuzzer = Fuzzer::new(target, corpus, &cfg);
+    seed_rng(&cfg);
+    let mut fuzzer = Fuzzer::new(target, bundles, corpus, &cfg);
+    let mut corpus = match cfg.corpus_store.as_ref() {
+        Some(conf) => {
+            let path = workdir::path(STORE_FILE);
+            let corpus = Corpus::open(conf, &path)?;
+            info!(
+                "Corpus stored in {}, {} progs recovered",
+                path.display(),
+                corpus.len().await
+            );
+            corpus
+        }
+        None => Corpus::default(),
+    };
+    corpus.set_selection(cfg.selection.clone());
+    corpus.set_eviction(cfg.eviction.clone());
+    if cfg.dedup.unwrap_or(true) {
+        corpus.set_dedup(fuzzer.target.clone()).await?;
+    }
+    fuzzer.corpus = Arc::new(corpus);
+    if cfg.manager.is_some() {
+        fuzzer.outbox = Some(Arc::new(Outbox::default()));
+    }
+    fuzzer.hooks = Arc::new(hooks);
+    let (tripped_tx, mut tripped) = mpsc::unbounded_channel();
+    fuzzer.breaker = cfg
+        .breaker
+        .clone()
+        .map(|conf| Arc::new(Breaker::new(conf, tripped_tx)));
+    if let Some(resume) = cfg.resume.as_ref() {
+        fuzzer.restore().await?;
+        info!(
+            "Resumed {}, corpus: {}",
+            resume.display(),
+            fuzzer.corpus.len().await
+        );
+    }
+    let worker = match cfg.manager.as_ref() {
+        Some(manager) => Some(Worker::connect(manager, &fuzzer).await?),
+        None => None,
+    };
     info!(
         "Booting {} {}/{} on {} ...",
         cfg.vm_num, cfg.guest.os, cfg.guest.arch, cfg.guest.platform
     );
     let now = std::time::Instant::now();
-    let shutdown = start_fuzz(fuzzer.clone(), cfg.clone()).await;
+    let sampler = Arc::new(Mutex::new(cfg.sampler.clone()));
+    let (shutdown, mut failure, mut vms) =
+        start_fuzz(fuzzer.clone(), cfg.clone(), sampler.clone()).await?;
     info!("Boot finished, cost {}s.", now.elapsed().as_secs());
+    if let Some(worker) = worker {
+        tokio::spawn(worker.work(fuzzer.clone(), shutdown.subscribe()));
+    }
+    let uploader = cfg
+        .upload
+        .clone()
+        .map(|conf| (conf.interval(), Arc::new(Uploader::new(conf))));
+    if let Some(autosave) = cfg.autosave.as_ref() {
+        let corpus = fuzzer.corpus.clone();
+        let interval = autosave.interval();
+        let shutdown = shutdown.subscribe();
+        tokio::spawn(async move {
+            corpus
+                .autosave(&workdir::path("corpus"), interval, shutdown)
+                .await
+        });
+    }
+    if let Some((interval, uploader)) = uploader.as_ref() {
+        tokio::spawn(upload_periodically(
+            uploader.clone(),
+            *interval,
+            fuzzer.clone(),
+            shutdown.subscribe(),
+        ));
+    }
+
+    // limit of executions counts from here, not from start of resumed run.
+    let exec_base = fuzzer.exec_cnt.load(Ordering::SeqCst);
+    let mut current = (*cfg).clone();
+    let mut checkpoint = cfg
+        .checkpoint_interval()
+        .map(|i| interval_at(Instant::now() + i, i));
+    let mut ret = Ok(());
+    loop {
+        tokio::select! {
+            Some(()) = stop.recv() => break,
+            reason = limit_reached(&fuzzer, &cfg, started, exec_base) => {
+                warn!("{}, stopping", reason);
+                break;
+            }
+            _ = next_tick(&mut checkpoint) => {
+                match fuzzer.checkpoint().await {
+                    Ok(_) => info!("Checkpoint saved"),
+                    Err(e) => warn!("Fail to save checkpoint: {}", e),
+                }
+            }
+            Some(e) = failure.recv() => {
+                error!("Executor failed: {}", e);
+                ret = Err(e);
+                break;
+            }
+            Some(reason) = tripped.recv() => {
+                #[cfg(feature = "mail")]
+                mail::send(
+                    lettre_email::EmailBuilder::new()
+                        .subject("Healer Circuit Breaker Tripped")
+                        .body(&reason),
+                )
+                .await;
+                if fuzzer.breaker.as_ref().map_or(false, |b| b.exits()) {
+                    ret = Err(err!(exitcode::UNAVAILABLE, "Circuit breaker tripped: {}", reason));
+                    break;
+                }
+            }
+            Some(new) = reload.recv() => {
+                reload_config(&fuzzer, &sampler, &mut vms, &mut current, new).await
+            }
+            Some(()) = dump.recv() => match dump_state(&fuzzer).await {
+                Ok(_) => info!("State dumped to {}", workdir::path("").display()),
+                Err(e) => warn!("Fail to dump state: {}", e),
+            },
+            Some(delta) = scale.recv() => {
+                let vm_num = (current.vm_num as isize + delta).max(0) as usize;
+                let new = Config { vm_num, ..current.clone() };
+                reload_config(&fuzzer, &sampler, &mut vms, &mut current, new).await
+            }
+            else => break,
+        }
+    }
+
+    warn!("Stopping, persisting data...");
+    shutdown.send(()).ok();
+    let stats = fuzzer.stats().snapshot().await;
+    fuzzer.persist().await?;
+    if let Some((_, uploader)) = uploader.as_ref() {
+        match uploader.upload().await {
+            Ok(()) => info!("Outputs uploaded"),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    let now = Instant::now();
+    let wait_time = Duration::new(5, 0);
+    while shutdown.receiver_count() != 0 {
+        delay_for(Duration::from_millis(200)).await;
+        if now.elapsed() >= wait_time {
+            warn!("Wait time out, force to exit...");
+            return ret.and(Err(err!(exitcode::SOFTWARE, "Wait for executors time out")));
+        }
+    }
+    info!("All done");
+    // TODO clear resources when exiting, e.g. qemu process.
+    ret.map(|_| stats)
+}
+
+/// Save corpus, state and test cases as on exit, with current stats in `snapshot.json` and
+/// covered blocks in `cover.txt`, for inspecting live campaign.
+async fn dump_state(fuzzer: &Fuzzer) -> Result<()> {
+    fuzzer.checkpoint().await?;
+    fuzzer.record.psersist().await?;
+
+    let stats = fuzzer.stats().snapshot().await;
+    let snapshot = serde_json::json!({
+        "time": chrono::Local::now().to_rfc3339(),
+        "stats": stats,
+    });
+    let path = workdir::path("snapshot.json");
+    workdir::write_atomic(&path, serde_json::to_string_pretty(&snapshot).unwrap())
+        .await
+        .map_err(|e| err!(exitcode::IOERR, "Fail to write {}: {}", path.display(), e))?;
+
+    let (mut blocks, _) = fuzzer.feedback.dump().await;
+    blocks.sort();
+    let cover = blocks
+        .iter()
+        .map(|b| format!("{:#x}\n", b.addr()))
+        .collect::<String>();
+    let path = workdir::path("cover.txt");
+    workdir::write_atomic(&path, cover)
+        .await
+        .map_err(|e| err!(exitcode::IOERR, "Fail to write {}: {}", path.display(), e))
+}
+
+/// Dump state and upload outputs of run every `interval`, until shutdown.
+async fn upload_periodically(
+    uploader: Arc<Uploader>,
+    interval: Duration,
+    fuzzer: Fuzzer,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    let mut tick = interval_at(Instant::now() + interval, interval);
+    loop {
+        tokio::select! {
+            _ = tick.tick() => (),
+            _ = shutdown.recv() => break,
+        }
+        // upload in progress is abandoned on shutdown, fuzzer uploads once more on exit.
+        tokio::select! {
+            _ = dump_and_upload(&uploader, &fuzzer) => (),
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+async fn dump_and_upload(uploader: &Uploader, fuzzer: &Fuzzer) {
+    if let Err(e) = dump_state(fuzzer).await {
+        warn!("Fail to dump state: {}", e);
+    }
+    match uploader.upload().await {
+        Ok(()) => info!("Outputs uploaded"),
+        Err(e) => warn!("{}", e),
+    }
+}
+
+/// Toggle debug logs, returning whether they are on now.
+pub fn toggle_verbose() -> bool {
+    use log::LevelFilter;
+    // loggers are built with debug level, max level decides what is logged.
+    let verbose = log::max_level() < LevelFilter::Debug;
+    log::set_max_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    verbose
+}
+
+/// Wait until `max_runtime` or `max_execs` is reached, never if neither is given.
+async fn limit_reached(
+    fuzzer: &Fuzzer,
+    cfg: &Config,
+    started: Instant,
+    exec_base: usize,
+) -> String {
+    if cfg.max_runtime.is_none() && cfg.max_execs.is_none() {
+        return std::future::pending().await;
+    }
+    loop {
+        if let Some(max_runtime) = cfg.max_runtime {
+            if started.elapsed() >= Duration::from_secs(max_runtime * 60) {
+                return format!("Max runtime {}m reached", max_runtime);
+            }
+        }
+        if let Some(max_execs) = cfg.max_execs {
+            if fuzzer.exec_cnt.load(Ordering::SeqCst) - exec_base >= max_execs {
+                return format!("Max execs {} reached", max_execs);
+            }
+        }
+        delay_for(Duration::from_secs(1)).await;
+    }
+}
+
+/// Tick of checkpoint interval, never if checkpoint is disabled.
+async fn next_tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Vms fuzzing, which can be booted or drained while fuzzing.
+struct Vms {
+    fuzzer: Fuzzer,
+    cfg: Arc<Config>,
+    pool: Option<GuestPool>,
+    shutdown: broadcast::Sender<()>,
+    failure: mpsc::UnboundedSender<Error>,
+    /// Drain of each running vm by its id
+    running: BTreeMap<usize, oneshot::Sender<()>>,
+}
+
+impl Vms {
+    /// Boot vm `id` and fuzz on it in background. Vms booted at start pass `barrier` once
+    /// booted and fail fuzzer if they fail to boot, vms booted later are just dropped.
+    fn spawn(&mut self, id: usize, barrier: Option<Arc<Barrier>>) {
+        let cfg = self.cfg.clone();
+        let fuzzer = self.fuzzer.clone();
+        let shutdown = self.shutdown.subscribe();
+        let pool = self.pool.clone();
+        let failure = self.failure.clone();
+        let (drain_tx, drain_rx) = oneshot::channel();
+        self.running.insert(id, drain_tx);
 
-    wait_exit_signal(fuzzer, shutdown).await
+        // logs of vm go to its own log file too.
+        tokio::spawn(vm_log::scope(id, async move {
+            let booted = async {
+                let mut executor = Executor::new(
+                    &cfg,
+                    id,
+                    fuzzer.health_restarts.clone(),
+                    pool,
+                    fuzzer.hooks.clone(),
+                )?;
+                executor.start().await?;
+                // first vm running each kernel collects its info and tests it.
+                if barrier.is_some() && (0..id).all(|vm| cfg.kernel_of(vm) != cfg.kernel_of(id)) {
+                    let kernel = executor.kernel_info().await;
+                    match kernel.as_ref() {
+                        Some(kernel) => {
+                            info!("Guest kernel: {}", kernel.uname);
+                            fuzzer.record.set_kernel(kernel.clone()).await?;
+                        }
+                        None => warn!("Fail to collect kernel info of guest"),
+                    }
+                    if cfg.smoke_test() {
+                        smoke::smoke_test(&mut executor, &fuzzer.target, kernel.as_ref(), &cfg)
+                            .await?;
+                    }
+                }
+                Ok::<_, Error>(executor)
+            }
+            .await;
+            let ret = match (booted, barrier) {
+                (booted, Some(barrier)) => {
+                    // always pass the barrier, so that boot failure is noticed by start_fuzz.
+                    barrier.wait().await;
+                    booted
+                }
+                (Ok(executor), None) => {
+                    event!(Level::Info, "vm_boot", { "vm": id }, "Vm {} booted", id);
+                    Ok(executor)
+                }
+                (Err(e), None) => {
+                    event!(
+                        Level::Error,
+                        "vm_boot_failed",
+                        { "vm": id, "error": e.to_string() },
+                        "Fail to boot vm {}: {}",
+                        id,
+                        e
+                    );
+                    return;
+                }
+            };
+            let ret = match ret {
+                Ok(executor) => tokio::select! {
+                    ret = fuzzer.fuzz(executor, shutdown) => ret,
+                    _ = drain_rx => {
+                        event!(Level::Info, "vm_drain", { "vm": id }, "Vm {} drained", id);
+                        Ok(())
+                    }
+                },
+                Err(e) => Err(e),
+            };
+            if let Err(e) = ret {
+                failure.send(e).ok();
+            }
+        }));
+    }
+
+    /// Boot or drain vms until `vm_num` vms are running, latest booted ones are drained
+    /// first.
+    fn scale(&mut self, vm_num: usize) {
+        let running = self.running.len();
+        if vm_num < running {
+            let drained = self.running.keys().rev().take(running - vm_num).copied();
+            for id in drained.collect::<Vec<_>>() {
+                if let Some(drain) = self.running.remove(&id) {
+                    drain.send(()).ok();
+                }
+            }
+        } else if vm_num > running {
+            // ids of standby guests are kept, so that new vms never collide with them.
+            let standby = self.cfg.vm_num..self.cfg.vm_num + self.cfg.standby_num.unwrap_or(0);
+            let ids = (0..)
+                .filter(|id| !standby.contains(id) && !self.running.contains_key(id))
+                .take(vm_num - running)
+                .collect::<Vec<_>>();
+            for id in ids {
+                self.spawn(id, None);
+            }
+        }
+    }
 }
 
-async fn start_fuzz(fuzzer: Fuzzer, cfg: Arc<Config>) -> broadcast::Sender<()> {
+/// Start fuzzing on each vm, executor errors are sent to returned receiver.
+async fn start_fuzz(
+    fuzzer: Fuzzer,
+    cfg: Arc<Config>,
+    sampler: Arc<Mutex<Option<SamplerConf>>>,
+) -> Result<(broadcast::Sender<()>, mpsc::UnboundedReceiver<Error>, Vms)> {
     let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    let (failure_tx, mut failure_rx) = mpsc::unbounded_channel();
     let barrier = Arc::new(Barrier::new(cfg.vm_num + 1));
-    for _ in 0..cfg.vm_num {
-        let cfg = cfg.clone();
-        let fuzzer = fuzzer.clone();
-        let barrier = barrier.clone();
-        let shutdown = shutdown_tx.subscribe();
-
-        tokio::spawn(async move {
-            let mut executor = Executor::new(&cfg);
-            executor.start().await;
-            barrier.wait().await;
-            fuzzer.fuzz(executor, shutdown).await;
-        });
+    let pool = GuestPool::new(&cfg);
+    let mut vms = Vms {
+        fuzzer: fuzzer.clone(),
+        cfg: cfg.clone(),
+        pool: pool.clone(),
+        shutdown: shutdown_tx.clone(),
+        failure: failure_tx,
+        running: BTreeMap::new(),
+    };
+    for id in 0..cfg.vm_num {
+        vms.spawn(id, Some(barrier.clone()));
     }
     barrier.wait().await;
+    if let Ok(e) = failure_rx.try_recv() {
+        shutdown_tx.send(()).ok();
+        return Err(e);
+    }
+    // standby guests are booted after working ones, not to slow them down.
+    if let Some(pool) = pool.as_ref() {
+        pool.fill()?;
+    }
 
     let stats_source = fuzzer.stats();
     tokio::spawn(async move {
-        let mut sampler = stats::Sampler::new(stats_source);
-        sampler.sample(&cfg.sampler, shutdown_rx).await;
+        let mut sampler = stats::Sampler::new(stats_source, sampler, cfg.checkpoint_interval());
+        if cfg.resume.is_some() {
+            sampler.restore().await;
+        }
+        sampler.sample(shutdown_rx).await;
     });
-    shutdown_tx
+    Ok((shutdown_tx, failure_rx, vms))
 }
 
-async fn wait_exit_signal(fuzzer: Fuzzer, shutdown: broadcast::Sender<()>) {
+/// Apply reloadable values of `new` config, i.e. sampler, disabled_calls, gen, weights and
+/// vm_num, logging which changed values are applied and which are rejected, since they need
+/// restarting.
+async fn reload_config(
+    fuzzer: &Fuzzer,
+    sampler: &Mutex<Option<SamplerConf>>,
+    vms: &mut Vms,
+    current: &mut Config,
+    new: Config,
+) {
+    if let Err(e) = new.check() {
+        error!("Reloaded config rejected: {}", e);
+        return;
+    }
+
+    macro_rules! changed {
+        ( $( $field:ident ),* ) => {
+            vec![ $( (stringify!($field), format!("{:?}", current.$field) != format!("{:?}", new.$field)) ),* ]
+                .into_iter()
+                .filter(|(_, changed)| *changed)
+                .map(|(field, _)| field)
+                .collect::<Vec<_>>()
+        };
+    }
+    let rejected = changed!(
+        fots_bin,
+        curpus,
+        work_dir,
+        resume,
+        standby_num,
+        auto_reboot_duration,
+        auto_reboot_exec,
+        checkpoint_interval,
+        autosave,
+        corpus_store,
+        selection,
+        dedup,
+        eviction,
+        max_runtime,
+        max_execs,
+        smoke_test,
+        seed,
+        breaker,
+        schedule,
+        enable,
+        disable,
+        shards,
+        plateau,
+        throughput,
+        manager,
+        log_json,
+        suppressions,
+        ignores,
+        guest,
+        qemu,
+        ssh,
+        gvisor,
+        bare,
+        libvirt,
+        board,
+        adb,
+        container,
+        cloud,
+        executor
+    );
+    let applied = changed!(sampler, disabled_calls, gen, weights, vm_num);
+
+    *sampler.lock().await = new.sampler.clone();
+    fuzzer.reload(&new).await;
+    current.sampler = new.sampler;
+    current.disabled_calls = new.disabled_calls;
+    current.gen = new.gen;
+    current.weights = new.weights;
+    if current.vm_num != new.vm_num {
+        info!("Scaling vms from {} to {}", current.vm_num, new.vm_num);
+        vms.scale(new.vm_num);
+        current.vm_num = new.vm_num;
+    }
+
+    if !rejected.is_empty() {
+        warn!(
+            "Config reloaded, changes of {} are rejected, they need restarting",
+            rejected.join(", ")
+        );
+    }
+    if applied.is_empty() {
+        info!("Config reloaded, nothing to apply");
+    } else {
+        info!("Config reloaded, applied changes of {}", applied.join(", "));
+    }
+}
+
+async fn wait_exit_signal() {
     if cfg!(unix) {
         use tokio::signal::unix::{signal, SignalKind};
         let mut sig_ir =
@@ -195,73 +1535,299 @@ async fn wait_exit_signal(fuzzer: Fuzzer, shutdown: broadcast::Sender<()>) {
             .expect("failed to set up ctrl-c signal handler");
         warn!("INTERUPTE signal recved");
     }
-
-    warn!("Stopping, persisting data...");
-    shutdown.send(()).unwrap();
-    fuzzer.persist().await;
-
-    let now = Instant::now();
-    let wait_time = Duration::new(5, 0);
-    while shutdown.receiver_count() != 0 {
-        delay_for(Duration::from_millis(200)).await;
-        if now.elapsed() >= wait_time {
-            warn!("Wait time out, force to exit...");
-            exit(exitcode::SOFTWARE);
-        }
-    }
-    info!("All done");
-    // TODO clear resources when exiting, e.g. qemu process.
-    exit(exitcode::OK);
 }
 
-fn check_corpus(t: &Target, corpus: &[Prog]) {
+fn check_corpus(t: &Target, corpus: &[Prog]) -> Result<()> {
     for p in corpus.iter() {
         if !t.groups.contains_key(&p.gid) {
-            eprintln!("Config Error: fots_bin/corpus not match: corpus contains unknown groups");
-            exit(1);
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: fots_bin/corpus not match: corpus contains unknown groups"
+            );
         }
         for c in p.calls.iter() {
             if !t.fns.contains_key(&c.fid) {
-                eprintln!("Config Error: fots_bin/corpus not match: corpus contains unknown fn");
-                exit(1);
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: fots_bin/corpus not match: corpus contains unknown fn"
+                );
             }
         }
     }
+    Ok(())
+}
+
+/// Every shard needs a group of its own.
+fn check_shards(t: &Target, cfg: &Config) -> Result<()> {
+    let shards = cfg.shards.unwrap_or(1);
+    if shards > t.groups.len() {
+        bail!(
+            exitcode::CONFIG,
+            "Config Error: shards {} exceeds {} groups of target",
+            shards,
+            t.groups.len()
+        );
+    }
+    Ok(())
+}
+
+/// Corpus to start from: `curpus` if given, otherwise corpus saved by latest run in work dir if
+/// autosave loads it. Resumed run reloads its own corpus instead.
+fn seed_corpus(cfg: &Config) -> Option<PathBuf> {
+    if cfg.curpus.is_some() {
+        return cfg.curpus.clone();
+    }
+    if cfg.resume.is_some() || !cfg.autosave.as_ref().map_or(false, |a| a.load()) {
+        return None;
+    }
+    let path = workdir::latest(cfg.work_dir.as_deref(), "corpus")?;
+    info!("Loading corpus saved by previous run: {}", path.display());
+    Some(path)
 }
 
-async fn load_corpus(path: &Option<PathBuf>) -> Vec<Prog> {
+async fn load_corpus(path: &Option<PathBuf>) -> Result<Vec<Prog>> {
     if let Some(path) = path.as_ref() {
-        let data = read(path).await.unwrap();
-        bincode::deserialize(&data).unwrap()
+        let entries = load_dump(path).await?;
+        Ok(entries.into_iter().map(|(p, _)| p).collect())
     } else {
-        Vec::new()
+        Ok(Vec::new())
+    }
+}
+
+/// Progs of corpus dump at `path` with their meta.
+async fn load_dump(path: &Path) -> Result<Vec<(Prog, Meta)>> {
+    let data = read(path).await.map_err(|e| {
+        err!(
+            exitcode::IOERR,
+            "Fail to read corpus {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+    parse_dump(&data).map_err(|e| err!(exitcode::DATAERR, "Fail to load corpus: {}", e))
+}
+
+async fn load_target(cfg: &Config) -> Result<Target> {
+    Ok(Target::from(load_items(cfg).await?.0))
+}
+
+/// Load description bundles of config, merging them in order, so that ids of the first one
+/// are kept. Merged items are written to work dir for guests if there are multiple bundles.
+async fn load_items(cfg: &Config) -> Result<(Items, Vec<Bundle>)> {
+    let mut merged: Option<Items> = None;
+    let mut bundles = Vec::new();
+    for path in cfg.fots_bins()? {
+        let data = read(&path).await.map_err(|e| {
+            err!(
+                exitcode::DATAERR,
+                "Fail to load fots file {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        let items = Items::load(&data).map_err(|e| {
+            err!(
+                exitcode::DATAERR,
+                "Fail to parse fots file {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        let groups = match merged.as_mut() {
+            Some(merged) => {
+                let base = merged.groups.len();
+                merged.merge(items);
+                merged.groups[base..].iter().map(|g| g.id).collect()
+            }
+            None => {
+                let groups = items.groups.iter().map(|g| g.id).collect();
+                merged = Some(items);
+                groups
+            }
+        };
+        bundles.push(Bundle { path, groups });
+    }
+    let merged = merged.unwrap();
+    if bundles.len() > 1 {
+        let path = cfg.target_path();
+        let data = merged
+            .dump()
+            .map_err(|e| err!(exitcode::SOFTWARE, "Fail to dump merged fots: {}", e))?;
+        workdir::write_atomic(&path, data).await.map_err(|e| {
+            err!(
+                exitcode::IOERR,
+                "Fail to write merged fots {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+    }
+    Ok((merged, bundles))
+}
+
+/// Record bundles in work dir, so that group of any prog maps to bundle it came from.
+async fn persist_bundles(bundles: &[Bundle]) -> Result<()> {
+    let path = workdir::path("bundles.json");
+    let data = serde_json::to_string_pretty(bundles).unwrap();
+    workdir::write_atomic(&path, data).await.map_err(|e| {
+        err!(
+            exitcode::IOERR,
+            "Fail to write bundles {}: {}",
+            path.display(),
+            e
+        )
+    })
+}
+
+/// Drop groups and calls of items filtered by `enable` and `disable` of config, returning
+/// whether any filter is given. Pattern like `Socket*` matches identifier of group, pattern
+/// prefixed with `fn:` matches declared or called name of call, `*` matches any string.
+/// Patterns matching nothing are config errors, so are filters leaving nothing to fuzz.
+fn filter_items(items: &mut Items, cfg: &Config) -> Result<bool> {
+    if cfg.enable.is_none() && cfg.disable.is_none() {
+        return Ok(false);
+    }
+    let enable = cfg.enable.as_deref().map(patterns).transpose()?;
+    let disable = patterns(cfg.disable.as_deref().unwrap_or(&[]))?;
+    for (name, pattern) in enable.iter().flatten().chain(disable.iter()) {
+        if !items.groups.iter().any(|g| pattern.matches(g)) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: filter {} matches no group or call of {}",
+                name,
+                cfg.fots_bin
+            )
+        }
+    }
+
+    for g in items.groups.iter_mut() {
+        let ident = g.ident.clone();
+        g.fns.retain(|f| {
+            let matched = |(_, p): &(String, Pattern)| p.matches_fn(&ident, f);
+            enable.as_ref().map_or(true, |e| e.iter().any(matched)) && !disable.iter().any(matched)
+        });
+    }
+    items.groups.retain(|g| !g.fns.is_empty());
+    if items.groups.is_empty() {
+        bail!(
+            exitcode::CONFIG,
+            "Config Error: enable and disable filter out every call"
+        )
+    }
+    let calls = items.groups.iter().map(|g| g.fns.len()).sum::<usize>();
+    info!(
+        "Filtered: {} groups, {} calls left",
+        items.groups.len(),
+        calls
+    );
+    Ok(true)
+}
+
+/// Filter pattern of group or call.
+enum Pattern {
+    Group(Regex),
+    Fn(Regex),
+}
+
+impl Pattern {
+    fn matches(&self, g: &Group) -> bool {
+        match self {
+            Pattern::Group(r) => r.is_match(&g.ident),
+            Pattern::Fn(_) => g.fns.iter().any(|f| self.matches_fn(&g.ident, f)),
+        }
+    }
+
+    fn matches_fn(&self, group: &str, f: &FnInfo) -> bool {
+        match self {
+            Pattern::Group(r) => r.is_match(group),
+            Pattern::Fn(r) => r.is_match(&f.dec_name) || r.is_match(&f.call_name),
+        }
+    }
+}
+
+fn patterns(filters: &[String]) -> Result<Vec<(String, Pattern)>> {
+    filters
+        .iter()
+        .map(|f| {
+            let (glob, is_fn) = match f.strip_prefix("fn:") {
+                Some(glob) => (glob, true),
+                None => (&f[..], false),
+            };
+            let re = glob.split('*').map(regex::escape).collect::<Vec<_>>();
+            let re = Regex::new(&format!("^{}$", re.join(".*")))
+                .map_err(|e| err!(exitcode::CONFIG, "Config Error: filter {}: {}", f, e))?;
+            let pattern = if is_fn {
+                Pattern::Fn(re)
+            } else {
+                Pattern::Group(re)
+            };
+            Ok((f.clone(), pattern))
+        })
+        .collect()
+}
+
+/// Make random choices from rng seeded by `seed` of config, if given.
+pub(crate) fn seed_rng(cfg: &Config) {
+    if let Some(seed) = cfg.seed {
+        core::rng::seed(seed);
+        info!("Seed: {}", seed);
     }
 }
 
-async fn load_target(cfg: &Config) -> Target {
-    let items = Items::load(&read(&cfg.fots_bin).await.unwrap_or_else(|e| {
-        error!("Fail to load fots file: {}", e);
-        exit(exitcode::DATAERR);
-    }))
-    .unwrap();
-    Target::from(items)
+/// Drop progs calling anything unknown to target, e.g. calls filtered out, returning number
+/// of dropped progs.
+pub(crate) fn retain_known(t: &Target, progs: &mut Vec<Prog>) -> usize {
+    let len = progs.len();
+    progs.retain(|p| is_known(t, p));
+    len - progs.len()
+}
+
+/// Whether group and all calls of `p` are in target `t`.
+pub(crate) fn is_known(t: &Target, p: &Prog) -> bool {
+    t.groups.contains_key(&p.gid) && p.calls.iter().all(|c| t.fns.contains_key(&c.fid))
 }
 
-pub async fn prepare_env() {
-    init_logger();
+pub async fn prepare_env(cfg: &Config) -> Result<()> {
+    // logs go to work dir too, so it is set up first.
+    let run_dir = workdir::init(cfg.work_dir.as_deref(), cfg.resume.as_deref())?;
+    init_logger(cfg.log_json);
     let pid = id(); // pid
     std::env::set_var("HEALER_FUZZER_PID", format!("{}", pid));
     info!("Pid: {}", pid);
+    info!("Work dir: {}", run_dir.display());
+    Ok(())
+}
 
-    use tokio::io::ErrorKind::*;
-    if let Err(e) = create_dir_all("./crashes").await {
-        if e.kind() != AlreadyExists {
-            exits!(exitcode::IOERR, "Fail to create crash dir: {}", e);
-        }
-    }
+/// Fork into background for `--daemon`, refusing to if a fuzzer with same work dir is
+/// running already. It must be called before tokio runtime starts.
+pub fn daemonize(cfg: &Config) -> Result<()> {
+    daemon::daemonize(cfg.work_dir.as_deref())
+}
+
+/// Write pid file of daemon to work dir and send its stdout and stderr to
+/// `log/daemon.log` of run directory, after `prepare_env`. Pid file is removed once
+/// returned one is dropped.
+pub fn detach(cfg: &Config) -> Result<PidFile> {
+    let pid_file = PidFile::write(cfg.work_dir.as_deref())?;
+    daemon::redirect_output(&workdir::path("log/daemon.log"))?;
+    info!(
+        "Pid file: {}",
+        daemon::pid_file(cfg.work_dir.as_deref()).display()
+    );
+    Ok(pid_file)
 }
 
-fn init_logger() {
+/// Stop fuzzer running in background with work dir of `cfg` by SIGTERM, waiting at most
+/// `timeout` for it to persist outputs and exit, returning its pid.
+pub async fn stop(cfg: &Config, timeout: Duration) -> Result<i32> {
+    daemon::stop(cfg.work_dir.as_deref(), timeout)
+        .await
+        .map(|pid| pid.as_raw())
+}
+
+fn init_logger(json: bool) {
+    use crate::utils::event::{self, JsonLines};
+    use crate::utils::vm_log::VmAppender;
     use log::LevelFilter;
     use log4rs::append::console::ConsoleAppender;
     use log4rs::append::file::FileAppender;
@@ -269,50 +1835,64 @@ fn init_logger() {
     use log4rs::append::rolling_file::RollingFileAppender;
     use log4rs::config::{Appender, Config, Logger, Root};
     use log4rs::encode::pattern::PatternEncoder;
+    use log4rs::encode::Encode;
+
+    event::set_json(json);
+    let encoder = |pattern: &str| -> Box<dyn Encode> {
+        if json {
+            Box::new(JsonLines)
+        } else {
+            Box::new(PatternEncoder::new(pattern))
+        }
+    };
 
     let stdout = ConsoleAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(
-            "{d(%Y-%m-%d %H:%M:%S)} {h({l})} {t} - {m}{n}",
-        )))
+        .encoder(encoder("{d(%Y-%m-%d %H:%M:%S)} {h({l})} {t} - {m}{n}"))
         .build();
 
     let fuzzer_appender = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(
-            "{d(%Y-%m-%d %H:%M:%S)} {h({l})} - {m}{n}",
-        )))
-        .build("log/fuzzer.log")
+        .encoder(encoder("{d(%Y-%m-%d %H:%M:%S)} {h({l})} - {m}{n}"))
+        .build(workdir::path("log/fuzzer.log"))
         .unwrap();
 
     let stats_trigger = trigger::size::SizeTrigger::new(1024 * 1024 * 100);
     let stats_roll = roll::fixed_window::FixedWindowRoller::builder()
-        .build("stats.log.{}", 2)
+        .build(&workdir::path("log/stats.log.{}").to_string_lossy(), 2)
         .unwrap();
     let stats_policy = CompoundPolicy::new(Box::new(stats_trigger), Box::new(stats_roll));
     let stats_appender = RollingFileAppender::builder()
         .append(false)
-        .encoder(Box::new(PatternEncoder::new(
-            "{d(%Y-%m-%d %H:%M:%S)} {h({l})} - {m}{n}",
-        )))
-        .build("log/stats.log", Box::new(stats_policy))
+        .encoder(encoder("{d(%Y-%m-%d %H:%M:%S)} {h({l})} - {m}{n}"))
+        .build(workdir::path("log/stats.log"), Box::new(stats_policy))
         .unwrap();
 
+    let vm_appender = VmAppender::new(encoder("{d(%Y-%m-%d %H:%M:%S)} {h({l})} {t} - {m}{n}"));
+
     let config = Config::builder()
         .appender(Appender::builder().build("stdout", Box::new(stdout)))
+        .appender(Appender::builder().build("vm_appender", Box::new(vm_appender)))
         .appender(Appender::builder().build("fuzzer_appender", Box::new(fuzzer_appender)))
         .appender(Appender::builder().build("stats_appender", Box::new(stats_appender)))
         .logger(
             Logger::builder()
                 .appender("stats_appender")
-                .build("fuzzer::stats", LevelFilter::Info),
+                .build("fuzzer::stats", LevelFilter::Debug),
         )
         .logger(
             Logger::builder()
                 .appender("fuzzer_appender")
-                .build("fuzzer::fuzzer", LevelFilter::Info),
+                .build("fuzzer::fuzzer", LevelFilter::Debug),
+        )
+        .build(
+            Root::builder()
+                .appender("stdout")
+                .appender("vm_appender")
+                .build(LevelFilter::Debug),
         )
-        .build(Root::builder().appender("stdout").build(LevelFilter::Info))
         .unwrap();
     log4rs::init_config(config).unwrap();
+    // debug logs are off until toggled.
+    log::set_max_level(LevelFilter::Info);
 }
 
 const HEALER: &str = r"
@@ -328,3 +1908,79 @@ const HEALER: &str = r"
 pub fn show_info() {
     println!("{}", HEALER);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_config, expand_vars, resolve_path, PATH_KEYS};
+    use std::collections::{HashMap, HashSet};
+    use std::path::Path;
+
+    fn vars() -> HashMap<String, String> {
+        let mut vars = HashMap::new();
+        vars.insert(String::from("HOME"), String::from("/home/fuzz"));
+        vars.insert(String::from("KERNEL"), String::from("5.4"));
+        vars
+    }
+
+    #[test]
+    fn expand_vars_replaces_each_var() {
+        let expanded = expand_vars("${HOME}/linux-${KERNEL}/bzImage", &vars()).unwrap();
+        assert_eq!(expanded, "/home/fuzz/linux-5.4/bzImage");
+        assert_eq!(expand_vars("no vars", &vars()).unwrap(), "no vars");
+        assert_eq!(expand_vars("$HOME", &vars()).unwrap(), "$HOME");
+    }
+
+    #[test]
+    fn expand_vars_rejects_unset_and_unclosed() {
+        assert_eq!(
+            expand_vars("${NOT_SET}/x", &vars()).unwrap_err().code,
+            exitcode::CONFIG
+        );
+        assert_eq!(
+            expand_vars("${HOME", &vars()).unwrap_err().code,
+            exitcode::CONFIG
+        );
+    }
+
+    #[test]
+    fn resolve_path_joins_relative_paths_only() {
+        let mut conf: toml::Value = toml::from_str(
+            r#"
+            fots_bin = "target.fots"
+            curpus = "/abs/corpus"
+            work_dir = ""
+            [[qemu.kernels]]
+            kernel = "bzImage"
+            [[qemu.kernels]]
+            kernel = "/boot/bzImage"
+            "#,
+        )
+        .unwrap();
+        let base = Path::new("/etc/healer");
+        resolve_path(&mut conf, &["fots_bin"], base);
+        resolve_path(&mut conf, &["curpus"], base);
+        resolve_path(&mut conf, &["work_dir"], base);
+        resolve_path(&mut conf, &["qemu", "kernels", "kernel"], base);
+        assert_eq!(conf["fots_bin"].as_str(), Some("/etc/healer/target.fots"));
+        assert_eq!(conf["curpus"].as_str(), Some("/abs/corpus"));
+        assert_eq!(conf["work_dir"].as_str(), Some(""));
+        let kernels = conf["qemu"]["kernels"].as_array().unwrap();
+        assert_eq!(kernels[0]["kernel"].as_str(), Some("/etc/healer/bzImage"));
+        assert_eq!(kernels[1]["kernel"].as_str(), Some("/boot/bzImage"));
+    }
+
+    #[test]
+    fn path_keys_are_distinct_and_resolved() {
+        let keys = PATH_KEYS.iter().collect::<HashSet<_>>();
+        assert_eq!(keys.len(), PATH_KEYS.len());
+
+        let mut conf: toml::Value =
+            toml::from_str("fots_bin = \"a.fots\"\n[qemu]\nimage = \"${WORK_DIR}/img\"\n").unwrap();
+        conf.as_table_mut()
+            .unwrap()
+            .insert("work_dir".into(), "/w".into());
+        expand_config(&mut conf, Some(Path::new("/cfg"))).unwrap();
+        assert_eq!(conf["fots_bin"].as_str(), Some("/cfg/a.fots"));
+        assert_eq!(conf["qemu"]["image"].as_str(), Some("/w/img"));
+    }
+}
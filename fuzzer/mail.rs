@@ -1,3 +1,4 @@
+use crate::error::Result;
 use lettre::smtp::authentication::Credentials;
 use lettre::smtp::{ClientSecurity, ConnectionReuseParameters, SmtpTransport};
 use lettre::{ClientTlsParameters, EmailAddress, Envelope, SmtpClient, Transport};
@@ -19,49 +20,63 @@ pub struct MailConf {
 }
 
 impl MailConf {
-    pub fn check(&self) {
-        ONCE.call_once(|| {
-            let passwd = env::var("HEALER_MAIL_PASSWD").unwrap_or_else(|_| {
-                eprintln!("Config Error: HEALER_MAIL_PASSWD env not found");
-                exit(exitcode::CONFIG)
-            });
+    pub fn check(&self) -> Result<()> {
+        let mut ret = Ok(());
+        ONCE.call_once(|| ret = self.setup());
+        ret
+    }
 
-            let creds = Credentials::new(self.sender.clone(), passwd);
-            let tls = TlsConnector::builder();
-            let param =
-                ClientTlsParameters::new("smtp-mail.outlook.com".into(), tls.build().unwrap());
-            let mailer = SmtpClient::new(
-                ("smtp-mail.outlook.com", 587),
-                ClientSecurity::Required(param),
+    fn setup(&self) -> Result<()> {
+        let passwd = env::var("HEALER_MAIL_PASSWD").map_err(|_| {
+            err!(
+                exitcode::CONFIG,
+                "Config Error: HEALER_MAIL_PASSWD env not found"
             )
-            .unwrap()
-            .credentials(creds)
-            .connection_reuse(ConnectionReuseParameters::ReuseUnlimited)
-            .smtp_utf8(true)
-            .transport();
+        })?;
+
+        let creds = Credentials::new(self.sender.clone(), passwd);
+        let tls = TlsConnector::builder();
+        let param = ClientTlsParameters::new("smtp-mail.outlook.com".into(), tls.build().unwrap());
+        let mailer = SmtpClient::new(
+            ("smtp-mail.outlook.com", 587),
+            ClientSecurity::Required(param),
+        )
+        .unwrap()
+        .credentials(creds)
+        .connection_reuse(ConnectionReuseParameters::ReuseUnlimited)
+        .smtp_utf8(true)
+        .transport();
 
-            let sender_addr = EmailAddress::new(self.sender.clone()).unwrap_or_else(|e| {
-                eprintln!("Config Error: invalid sender addr {}: {}", self.sender, e);
-                exit(exitcode::CONFIG)
-            });
-            let recivers = self
-                .receivers
-                .iter()
-                .map(|r| {
-                    EmailAddress::new(r.clone()).unwrap_or_else(|e| {
-                        eprintln!("Config Error: invalid reciver addr {}: {}", self.sender, e);
-                        exit(exitcode::CONFIG)
-                    })
+        let sender_addr = EmailAddress::new(self.sender.clone()).map_err(|e| {
+            err!(
+                exitcode::CONFIG,
+                "Config Error: invalid sender addr {}: {}",
+                self.sender,
+                e
+            )
+        })?;
+        let recivers = self
+            .receivers
+            .iter()
+            .map(|r| {
+                EmailAddress::new(r.clone()).map_err(|e| {
+                    err!(
+                        exitcode::CONFIG,
+                        "Config Error: invalid reciver addr {}: {}",
+                        self.sender,
+                        e
+                    )
                 })
-                .collect();
+            })
+            .collect::<Result<_>>()?;
 
-            let envelope = Envelope::new(Some(sender_addr), recivers).unwrap();
+        let envelope = Envelope::new(Some(sender_addr), recivers).unwrap();
 
-            unsafe {
-                MAILER = Some(Mutex::new(mailer));
-                ENVELOPE = Some(envelope);
-            }
-        })
+        unsafe {
+            MAILER = Some(Mutex::new(mailer));
+            ENVELOPE = Some(envelope);
+        }
+        Ok(())
     }
 }
 
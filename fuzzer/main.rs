@@ -1,35 +1,547 @@
-use fuzzer::{fuzz, prepare_env, show_info, Config};
+use fuzzer::corpus::ExportFormat;
+use fuzzer::{
+    cmd, daemonize, detach, dist, expand_config, fuzz, parse_override, prepare_env, preset,
+    set_config, show_info, stop, Config, Error, Result,
+};
+use std::fs::read_to_string;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process::exit;
+use std::time::Duration;
 use structopt::StructOpt;
-use tokio::fs::read_to_string;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "fuzzer", about = "Kernel fuzzer of healer.")]
 struct Settings {
     #[structopt(short = "c", long = "config", default_value = "healer-fuzzer.toml")]
     config: PathBuf,
+    /// Built-in preset layered over config, before overrides: quick, thorough or ci
+    #[structopt(long)]
+    preset: Option<String>,
+    /// Override value of config, e.g. `--set vm_num=8 --set executor.memleak_check=true`
+    #[structopt(long = "set", number_of_values = 1)]
+    set: Vec<String>,
+    /// Override vm_num of config
+    #[structopt(long)]
+    vm_num: Option<usize>,
+    /// Override corpus of config
+    #[structopt(long)]
+    corpus: Option<PathBuf>,
+    /// Override work_dir of config
+    #[structopt(long)]
+    work_dir: Option<PathBuf>,
+    /// Resume run persisted in given directory, overriding resume of config
+    #[structopt(long)]
+    resume: Option<PathBuf>,
+    /// Generate progs without any vm, printing their scripts and statistics of calls and
+    /// types they cover, then exit
+    #[structopt(long)]
+    dry_run: bool,
+    /// Number of progs of dry run, 100 by default
+    #[structopt(short = "n", requires = "dry-run")]
+    num: Option<usize>,
+    /// Run all checks of config and load target and corpus without booting anything,
+    /// printing effective config
+    #[structopt(long)]
+    check_config: bool,
+    /// Fuzz in background, with pid file in work dir, until `stop` with same config
+    #[structopt(long)]
+    daemon: bool,
+    /// Fuzz if not given
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-#[tokio::main]
-async fn main() {
-    let settings = Settings::from_args();
-    let cfg_data = read_to_string(&settings.config).await.unwrap_or_else(|e| {
-        eprintln!(
-            "Config file not found: {}: {}",
-            settings.config.display(),
-            e
-        );
-        exit(exitcode::IOERR)
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Fuzz until SIGINT or SIGTERM is received, or max_runtime or max_execs is reached
+    Run,
+    /// Stop fuzzer running in background with work dir of config, waiting for it to persist
+    /// its outputs
+    Stop {
+        /// Seconds to wait for fuzzer to exit
+        #[structopt(short = "t", long, default_value = "300")]
+        timeout: u64,
+    },
+    /// Re-execute prog of a saved crash, exiting with 1 if it is not reproduced
+    Repro {
+        /// Crash saved in crashes directory
+        crash: PathBuf,
+    },
+    /// Re-execute saved crashes and group reproduced ones by their report
+    Triage {
+        /// Directory of saved crashes
+        #[structopt(default_value = "crashes")]
+        dir: PathBuf,
+    },
+    /// Execute corpus or saved crashes without generating progs, reporting coverage and
+    /// whether crashes are reproduced, exiting with 1 if any saved crash is not
+    Replay {
+        /// Corpus dump, or directory of saved crashes
+        path: PathBuf,
+        /// Times to execute each prog
+        #[structopt(short = "r", long, default_value = "1")]
+        repeat: usize,
+    },
+    /// Execute each prog of corpus dump once, tracing comparisons of kernel instead of
+    /// coverage, and print operands compared by each call
+    Cmps {
+        /// Corpus dump
+        dump: PathBuf,
+    },
+    /// Execute corpus dump and keep the smallest subset of it covering the same blocks and
+    /// branches, e.g. as compact seeds for a new kernel
+    Distill {
+        /// Corpus dump
+        dump: PathBuf,
+        /// Where to write distilled corpus
+        #[structopt(short = "o", long)]
+        out: PathBuf,
+    },
+    /// Convert syzkaller corpus.db into corpus dump, mapping calls onto target and skipping
+    /// calls not found in it
+    Import {
+        /// Corpus database of syzkaller
+        db: PathBuf,
+        /// Where to write corpus dump
+        #[structopt(short = "o", long)]
+        out: PathBuf,
+    },
+    /// Write each prog of corpus dump to a file of its own, as C program or text, with a
+    /// manifest in index.json, for inspecting and running them outside of fuzzer
+    Export {
+        /// Corpus dump
+        dump: PathBuf,
+        /// Directory to write progs to
+        #[structopt(short = "o", long)]
+        out: PathBuf,
+        /// Form of progs, c or text
+        #[structopt(short = "f", long, default_value = "c")]
+        format: ExportFormat,
+    },
+    /// Manage distributed campaign, serving workers that have manager in their config
+    Manager {
+        /// Address to listen on, e.g. 0.0.0.0:7878
+        listen: SocketAddr,
+    },
+    /// Generate progs without guest
+    Gen {
+        /// Number of progs
+        #[structopt(short = "n", default_value = "1")]
+        num: usize,
+        /// Write progs as corpus dump instead of printing them
+        #[structopt(short = "o", long)]
+        out: Option<PathBuf>,
+    },
+    /// Inspect corpus dumps, merging them if out is given
+    Corpus {
+        /// Corpus dumps
+        dumps: Vec<PathBuf>,
+        /// Write merged corpus without duplicated progs
+        #[structopt(short = "o", long)]
+        out: Option<PathBuf>,
+        /// Print every prog
+        #[structopt(short = "v", long)]
+        verbose: bool,
+        #[structopt(subcommand)]
+        command: Option<CorpusCommand>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum CorpusCommand {
+    /// Execute corpus dumps, e.g. of runs on different machines, and merge them keeping only
+    /// progs that add coverage
+    Merge {
+        /// Corpus dumps, merged in order
+        #[structopt(required = true)]
+        dumps: Vec<PathBuf>,
+        /// Where to write merged corpus
+        #[structopt(short = "o", long)]
+        out: PathBuf,
+    },
+    /// Print id, group, calls and coverage brought by each prog of corpus dump
+    List {
+        /// Corpus dump
+        dump: PathBuf,
+        #[structopt(flatten)]
+        filter: FilterArgs,
+    },
+    /// Print progs of corpus dump by their ids in list
+    Show {
+        /// Corpus dump
+        dump: PathBuf,
+        /// Ids of progs
+        #[structopt(required = true)]
+        ids: Vec<usize>,
+        /// Form of progs, c or text
+        #[structopt(short = "f", long, default_value = "c")]
+        format: ExportFormat,
+    },
+    /// Write progs of corpus dump that call given syscalls or are of given groups to a new dump
+    Filter {
+        /// Corpus dump
+        dump: PathBuf,
+        #[structopt(flatten)]
+        filter: FilterArgs,
+        /// Where to write progs
+        #[structopt(short = "o", long)]
+        out: PathBuf,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct FilterArgs {
+    /// Only progs calling this syscall, by declared or called name, repeatable
+    #[structopt(long = "call", number_of_values = 1)]
+    calls: Vec<String>,
+    /// Only progs of this group, repeatable
+    #[structopt(long = "group", number_of_values = 1)]
+    groups: Vec<String>,
+}
+
+impl From<FilterArgs> for cmd::Filter {
+    fn from(args: FilterArgs) -> Self {
+        Self {
+            calls: args.calls,
+            groups: args.groups,
+        }
+    }
+}
+
+fn main() {
+    let mut settings = Settings::from_args();
+    let command = settings.command.take().unwrap_or(Command::Run);
+    let conf = load_config(&settings).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(e.code)
     });
+    if settings.daemon {
+        let detachable =
+            matches!(command, Command::Run) && !settings.check_config && !settings.dry_run;
+        if !detachable {
+            eprintln!("--daemon is only supported by run");
+            exit(exitcode::USAGE)
+        }
+        // threads do not survive fork, so runtime starts after it.
+        if let Err(e) = conf.check().and_then(|_| daemonize(&conf)) {
+            eprintln!("{}", e);
+            exit(e.code)
+        }
+    }
 
-    let conf: Config = toml::from_str(&cfg_data).unwrap_or_else(|e| {
-        eprintln!("Config Error:{}", e);
-        exit(exitcode::CONFIG);
+    let mut rt = tokio::runtime::Runtime::new().unwrap_or_else(|e| {
+        eprintln!("Fail to start runtime: {}", e);
+        exit(exitcode::OSERR)
+    });
+    let ret = rt.block_on(async move {
+        if settings.check_config {
+            let check = cmd::check_config(&conf).await?;
+            let effective = toml::to_string_pretty(&load_toml(&settings)?).map_err(|e| {
+                Error::new(exitcode::SOFTWARE, format!("Fail to dump config: {}", e))
+            })?;
+            println!("{}", effective);
+            println!(
+                "Groups: {}  Syscalls: {}  Corpus: {}",
+                check.groups, check.calls, check.corpus
+            );
+            if check.dropped != 0 {
+                println!(
+                    "{} progs of corpus dropped, their calls are filtered",
+                    check.dropped
+                );
+            }
+            println!("Config OK");
+            return Ok(());
+        }
+        if settings.dry_run {
+            let stats = cmd::dry_run(&conf, settings.num.unwrap_or(100)).await?;
+            print_gen_stats(&stats);
+            return Ok(());
+        }
+        match command {
+            Command::Run => {
+                conf.check()?;
+                show_info();
+                prepare_env(&conf).await?;
+                let _pid_file = if settings.daemon {
+                    Some(detach(&conf)?)
+                } else {
+                    None
+                };
+                // config is read again on SIGHUP, with same overrides.
+                let stats = fuzz(conf, move || load_config(&settings)).await?;
+                println!("Summary:\n{}", stats);
+                Ok(())
+            }
+            Command::Stop { timeout } => {
+                let pid = stop(&conf, Duration::from_secs(timeout)).await?;
+                println!("Fuzzer {} stopped", pid);
+                Ok(())
+            }
+            Command::Repro { crash } => {
+                conf.check()?;
+                prepare_env(&conf).await?;
+                match cmd::repro(&conf, &crash).await? {
+                    Some(crash) => println!("Reproduced:\n{}", crash),
+                    None => {
+                        println!("Not reproduced");
+                        exit(1)
+                    }
+                }
+                Ok(())
+            }
+            Command::Triage { dir } => {
+                conf.check()?;
+                prepare_env(&conf).await?;
+                let triaged = cmd::triage(&conf, &dir).await?;
+                for t in triaged.iter() {
+                    println!("========== Reproduced by {} crashes:", t.files.len());
+                    for f in t.files.iter() {
+                        println!("{}", f.display());
+                    }
+                    println!("{}", t.crash);
+                }
+                println!("{} unique crashes reproduced", triaged.len());
+                Ok(())
+            }
+            Command::Replay { path, repeat } => {
+                conf.check()?;
+                prepare_env(&conf).await?;
+                let replay = cmd::replay(&conf, &path, repeat).await?;
+                let mut missed = 0;
+                for r in replay.replayed.iter() {
+                    match (&r.crash, r.saved_crash) {
+                        (Some(crash), true) => println!("{}: reproduced\n{}", r.name, crash),
+                        (Some(crash), false) => println!("{}: crashed\n{}", r.name, crash),
+                        (None, true) => {
+                            missed += 1;
+                            println!("{}: not reproduced", r.name)
+                        }
+                        (None, false) => (),
+                    }
+                }
+                let crashed = replay.replayed.iter().filter(|r| r.crash.is_some()).count();
+                println!(
+                    "Progs: {}  Exec: {}  Failed: {}  Crashed: {}  Blocks: {}  Branches: {}",
+                    replay.replayed.len(),
+                    replay.exec,
+                    replay.failed,
+                    crashed,
+                    replay.blocks,
+                    replay.branches
+                );
+                if missed != 0 {
+                    exit(1)
+                }
+                Ok(())
+            }
+            Command::Distill { dump, out } => {
+                conf.check()?;
+                prepare_env(&conf).await?;
+                let distill = cmd::distill(&conf, &dump, &out).await?;
+                println!(
+                    "Progs: {}  Kept: {}  Failed: {}  Crashed: {}  Blocks: {}  Branches: {}",
+                    distill.progs,
+                    distill.kept,
+                    distill.failed,
+                    distill.crashed,
+                    distill.blocks,
+                    distill.branches
+                );
+                Ok(())
+            }
+            Command::Import { db, out } => {
+                let imported = cmd::import(&conf, &db, &out).await?;
+                for (name, n) in imported.unknown.iter() {
+                    println!("Unknown call {}: {} times", name, n);
+                }
+                println!(
+                    "Programs: {}  Imported: {}  Dropped: {}  Near duplicates: {}  Calls skipped: {}",
+                    imported.records,
+                    imported.progs.len(),
+                    imported.dropped,
+                    imported.near_duplicates,
+                    imported.skipped_calls
+                );
+                Ok(())
+            }
+            Command::Cmps { dump } => {
+                conf.check()?;
+                prepare_env(&conf).await?;
+                for (i, cmps) in cmd::cmps(&conf, &dump).await?.iter().enumerate() {
+                    let cmps = match cmps {
+                        Some(cmps) => cmps,
+                        None => {
+                            println!("#{}: failed or crashed", i);
+                            continue;
+                        }
+                    };
+                    println!("#{}:", i);
+                    for (call, cmps) in cmps.iter().enumerate() {
+                        println!("  call {}: {} comparisons", call, cmps.len());
+                        for c in cmps.iter() {
+                            println!(
+                                "    pc {:#x}: {:#x} vs {:#x}, size {}, const {}",
+                                c.pc,
+                                c.arg1,
+                                c.arg2,
+                                c.size(),
+                                c.is_const()
+                            );
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Command::Export { dump, out, format } => {
+                let n = cmd::export(&conf, &dump, &out, format).await?;
+                println!("{} progs exported to {}", n, out.display());
+                Ok(())
+            }
+            Command::Manager { listen } => {
+                prepare_env(&conf).await?;
+                dist::manage(&conf, listen).await
+            }
+            Command::Gen { num, out } => cmd::gen(&conf, num, out.as_deref()).await,
+            Command::Corpus {
+                command: Some(CorpusCommand::Merge { dumps, out }),
+                ..
+            } => {
+                conf.check()?;
+                prepare_env(&conf).await?;
+                let merge = cmd::merge(&conf, &dumps, &out).await?;
+                for d in merge.dumps.iter() {
+                    println!(
+                        "{}: {} progs, {} added, {} duplicated, {} covering nothing new, {} without coverage",
+                        d.path.display(),
+                        d.progs,
+                        d.merged.added,
+                        d.merged.duplicates,
+                        d.merged.redundant,
+                        d.merged.uncovered
+                    );
+                }
+                println!(
+                    "Progs: {}  Failed: {}  Crashed: {}  Blocks: {}  Branches: {}",
+                    merge.progs, merge.failed, merge.crashed, merge.blocks, merge.branches
+                );
+                Ok(())
+            }
+            Command::Corpus {
+                command: Some(CorpusCommand::List { dump, filter }),
+                ..
+            } => cmd::list(&conf, &dump, &filter.into()).await,
+            Command::Corpus {
+                command: Some(CorpusCommand::Show { dump, ids, format }),
+                ..
+            } => cmd::show(&conf, &dump, &ids, format).await,
+            Command::Corpus {
+                command: Some(CorpusCommand::Filter { dump, filter, out }),
+                ..
+            } => {
+                let n = cmd::filter(&conf, &dump, &filter.into(), &out).await?;
+                println!("{} progs written to {}", n, out.display());
+                Ok(())
+            }
+            Command::Corpus {
+                dumps,
+                out,
+                verbose,
+                command: None,
+            } => {
+                if dumps.is_empty() {
+                    return Err(Error::new(
+                        exitcode::USAGE,
+                        "No corpus dump given".to_string(),
+                    ));
+                }
+                cmd::corpus(&conf, &dumps, out.as_deref(), verbose).await
+            }
+        }
     });
+    if let Err(e) = ret {
+        eprintln!("{}", e);
+        exit(e.code)
+    }
+}
+
+fn print_gen_stats(stats: &cmd::GenStats) {
+    println!(
+        "========== Progs: {}  Calls: {}  Types: {}/{}",
+        stats.progs, stats.calls, stats.types.0, stats.types.1
+    );
+    println!("Groups:");
+    for (name, n) in stats.group_freq.iter() {
+        println!("  {:<32} {}", name, n);
+    }
+    println!("Calls:");
+    for (name, n) in stats.call_freq.iter() {
+        println!("  {:<32} {}", name, n);
+    }
+    if !stats.missed_calls.is_empty() {
+        println!(
+            "Never generated({}): {}",
+            stats.missed_calls.len(),
+            stats.missed_calls.join(", ")
+        );
+    }
+}
+
+/// Read config, layering overrides of command line over it.
+fn load_config(settings: &Settings) -> Result<Config> {
+    load_toml(settings)?
+        .try_into()
+        .map_err(|e| Error::new(exitcode::CONFIG, format!("Config Error:{}", e)))
+}
 
-    conf.check();
-    show_info();
-    prepare_env().await;
-    fuzz(conf).await
+/// Read config as toml, layering preset and overrides of command line over it. Variables
+/// are expanded, and relative paths of config file are taken as relative to it, while
+/// relative paths of overrides are taken as relative to current directory.
+fn load_toml(settings: &Settings) -> Result<toml::Value> {
+    let data = read_to_string(&settings.config).map_err(|e| {
+        Error::new(
+            exitcode::IOERR,
+            format!(
+                "Config file not found: {}: {}",
+                settings.config.display(),
+                e
+            ),
+        )
+    })?;
+    let mut conf: toml::Value = toml::from_str(&data)
+        .map_err(|e| Error::new(exitcode::CONFIG, format!("Config Error:{}", e)))?;
+    let base = settings
+        .config
+        .canonicalize()
+        .ok()
+        .and_then(|path| path.parent().map(PathBuf::from));
+    expand_config(&mut conf, base.as_deref())?;
+    let mut overrides = match settings.preset.as_ref() {
+        Some(name) => preset(name)?,
+        None => Vec::new(),
+    };
+    for s in settings.set.iter() {
+        overrides.push(parse_override(s)?);
+    }
+    if let Some(vm_num) = settings.vm_num {
+        overrides.push((String::from("vm_num"), toml::Value::Integer(vm_num as i64)));
+    }
+    if let Some(corpus) = settings.corpus.as_ref() {
+        let corpus = toml::Value::String(corpus.display().to_string());
+        overrides.push((String::from("curpus"), corpus));
+    }
+    if let Some(work_dir) = settings.work_dir.as_ref() {
+        let work_dir = toml::Value::String(work_dir.display().to_string());
+        overrides.push((String::from("work_dir"), work_dir));
+    }
+    if let Some(resume) = settings.resume.as_ref() {
+        let resume = toml::Value::String(resume.display().to_string());
+        overrides.push((String::from("resume"), resume));
+    }
+    for (key, value) in overrides {
+        set_config(&mut conf, &key, value)?;
+    }
+    expand_config(&mut conf, None)?;
+    Ok(conf)
 }
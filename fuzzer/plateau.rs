@@ -0,0 +1,143 @@
+//! Detection of coverage plateau. Coverage is sampled from fuzz loop, once it grows too slowly
+//! over a whole window, fuzzer switches to the next strategy: mutating more, then generating
+//! out-of-spec values, then reshuffling group weights on every further plateau.
+
+use crate::error::Result;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+/// Minutes of window, if not configured.
+const DEFAULT_WINDOW: u64 = 30;
+/// Growth of coverage in percent below which window is a plateau, if not configured.
+const DEFAULT_MIN_GROWTH: f64 = 0.1;
+/// Interval between samples of coverage.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+/// Factor that share of mutation is raised by once mutate strategy is switched to.
+pub const MUTATE_BOOST: usize = 4;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlateauConf {
+    /// Minutes that coverage is watched over, 30 by default
+    pub window: Option<u64>,
+    /// Growth of coverage over window in percent, below which it is a plateau, 0.1 by default
+    pub min_growth: Option<f64>,
+}
+
+impl PlateauConf {
+    pub fn check(&self) -> Result<()> {
+        if self.window.map_or(false, |w| w < 5) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: window of plateau should be at least 5 minutes"
+            )
+        }
+        if self.min_growth.map_or(false, |g| g.is_nan() || g < 0.0) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: min_growth of plateau should not be negative"
+            )
+        }
+        Ok(())
+    }
+}
+
+/// Strategy switched to on plateau, in order of escalation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Mutate progs of corpus more often than generating fresh ones
+    Mutate = 1,
+    /// Generate out-of-spec values, see `core::gen::Config::negative`
+    Negative = 2,
+    /// Choose groups by random weights, repeated on every further plateau
+    Reshuffle = 3,
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Strategy::Mutate => "mutate",
+            Strategy::Negative => "negative",
+            Strategy::Reshuffle => "reshuffle",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub struct Plateau {
+    window: Duration,
+    min_growth: f64,
+    /// Time and coverage of samples within window, oldest first
+    samples: Mutex<VecDeque<(Instant, usize)>>,
+    /// Number of plateaus met so far
+    stage: AtomicUsize,
+}
+
+impl Plateau {
+    pub fn new(conf: &PlateauConf) -> Self {
+        Self {
+            window: Duration::from_secs(conf.window.unwrap_or(DEFAULT_WINDOW) * 60),
+            min_growth: conf.min_growth.unwrap_or(DEFAULT_MIN_GROWTH),
+            samples: Mutex::new(VecDeque::new()),
+            stage: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn min_growth(&self) -> f64 {
+        self.min_growth
+    }
+
+    /// Whether coverage should be sampled now.
+    pub fn due(&self) -> bool {
+        let samples = self.samples.lock().unwrap();
+        samples
+            .back()
+            .map_or(true, |(t, _)| t.elapsed() >= SAMPLE_INTERVAL)
+    }
+
+    /// Record coverage `cover`, return strategy to switch to if it grew too slowly over
+    /// the whole window. Samples are dropped then, so that next strategy gets a whole window.
+    pub fn sample(&self, cover: usize) -> Option<Strategy> {
+        let mut samples = self.samples.lock().unwrap();
+        // another vm sampled meanwhile.
+        if samples
+            .back()
+            .map_or(false, |(t, _)| t.elapsed() < SAMPLE_INTERVAL)
+        {
+            return None;
+        }
+        let now = Instant::now();
+        samples.push_back((now, cover));
+        // keep the latest sample that is a whole window old as base.
+        while samples.len() > 1 && now.duration_since(samples[1].0) >= self.window {
+            samples.pop_front();
+        }
+        let (since, base) = samples[0];
+        if now.duration_since(since) < self.window {
+            return None;
+        }
+        let growth = cover.saturating_sub(base) as f64;
+        if growth > base as f64 * self.min_growth / 100.0 {
+            return None;
+        }
+        samples.clear();
+        samples.push_back((now, cover));
+        let stage = self.stage.fetch_add(1, Ordering::SeqCst) + 1;
+        Some(match stage {
+            1 => Strategy::Mutate,
+            2 => Strategy::Negative,
+            _ => Strategy::Reshuffle,
+        })
+    }
+
+    /// Whether `strategy` has been switched to.
+    pub fn reached(&self, strategy: Strategy) -> bool {
+        self.stage.load(Ordering::SeqCst) >= strategy as usize
+    }
+}
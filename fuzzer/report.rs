@@ -1,18 +1,22 @@
+use crate::error::Result;
 use crate::feedback::{Block, Branch};
-use crate::guest::Crash;
+use crate::guest::{Crash, KernelInfo};
 #[cfg(feature = "mail")]
 use crate::mail;
+use crate::utils::workdir;
+use crate::Bundle;
 use chrono::prelude::*;
 use chrono::DateTime;
 use circular_queue::CircularQueue;
 use core::c::to_script;
 use core::prog::Prog;
 use core::target::Target;
-use executor::Reason;
+use executor::{CallInfo, Reason};
 #[cfg(feature = "mail")]
 use lettre_email::EmailBuilder;
 use serde::Serialize;
 use std::collections::HashSet;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::fs::write;
 use tokio::sync::Mutex;
@@ -23,13 +27,26 @@ pub struct TestCaseRecord {
     crash: Mutex<CircularQueue<CrashedCase>>,
 
     target: Arc<Target>,
+    /// Description bundles merged into target
+    bundles: Vec<Bundle>,
     id_n: Mutex<usize>,
+    /// Kernels of guests, each collected on first boot of it
+    kernels: Mutex<Vec<KernelInfo>>,
 
     normal_num: Mutex<usize>,
     failed_num: Mutex<usize>,
     crashed_num: Mutex<usize>,
 }
 
+/// Next case id and numbers of cases, kept across resumed runs.
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct RecordState {
+    pub next_id: usize,
+    pub normal_num: usize,
+    pub failed_num: usize,
+    pub crashed_num: usize,
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct TestCase {
     pub id: usize,
@@ -50,6 +67,11 @@ pub struct ExecutedCase {
     pub new_branch: usize,
     /// new block of last call
     pub new_block: usize,
+    /// return value and errno of each call, none if the call was skipped
+    pub rets: Vec<Option<(i64, i32)>>,
+    /// name of kernel the coverage was found on, if several kernels are fuzzed
+    #[serde(default)]
+    pub kernel: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -65,34 +87,59 @@ pub struct CrashedCase {
     pub p: String,
     pub repo: bool,
     pub crash: Crash,
+    /// Kernel that crashed
+    #[serde(default)]
+    pub kernel: Option<KernelInfo>,
+    /// Prog that crashed, for re-executing it
+    #[serde(default)]
+    pub prog: Option<Prog>,
+    /// Description bundle the prog came from
+    #[serde(default)]
+    pub bundle: Option<PathBuf>,
 }
 
 #[allow(clippy::len_without_is_empty)]
 impl TestCaseRecord {
-    pub fn new(t: Arc<Target>) -> Self {
+    pub fn new(t: Arc<Target>, bundles: Vec<Bundle>) -> Self {
         Self {
             normal: Mutex::new(CircularQueue::with_capacity(1024 * 64)),
             failed: Mutex::new(CircularQueue::with_capacity(1024 * 64)),
             crash: Mutex::new(CircularQueue::with_capacity(1024)),
             target: t,
+            bundles,
 
             id_n: Mutex::new(0),
+            kernels: Mutex::new(Vec::new()),
             normal_num: Mutex::new(0),
             failed_num: Mutex::new(0),
             crashed_num: Mutex::new(0),
         }
     }
 
+    /// Record prog that brought new coverage, returning id of its test case.
+    #[allow(clippy::too_many_arguments)]
     pub async fn insert_executed(
         &self,
+        kernel: Option<&str>,
         p: &Prog,
+        info: &[CallInfo],
         blocks: &[Vec<Block>],
         branches: &[Vec<Branch>],
         new_block: &HashSet<Block>,
         new_branch: &HashSet<Branch>,
-    ) {
+    ) -> usize {
         let block_num = blocks.iter().map(|blocks| blocks.len()).collect();
         let branch_num = branches.iter().map(|branches| branches.len()).collect();
+        let rets = info
+            .iter()
+            .map(|i| {
+                if i.skipped {
+                    None
+                } else {
+                    Some((i.ret, i.errno))
+                }
+            })
+            .collect();
         let id = self.next_id().await;
         let title = self.title_of(&p, id);
         let stmts = to_script(&p, &self.target);
@@ -108,6 +155,8 @@ impl TestCaseRecord {
             branch_num,
             new_branch: new_branch.len(),
             new_block: new_block.len(),
+            rets,
+            kernel: kernel.map(String::from),
         };
         {
             let mut execs = self.normal.lock().await;
@@ -117,9 +166,17 @@ impl TestCaseRecord {
             let mut exec_n = self.normal_num.lock().await;
             *exec_n += 1;
         }
+        id
     }
 
-    pub async fn insert_crash(&self, p: Prog, crash: Crash, repo: bool) {
+    /// Record crash, which is kept in memory even if persisting it failed.
+    pub async fn insert_crash(
+        &self,
+        kernel: Option<&str>,
+        p: Prog,
+        crash: Crash,
+        repo: bool,
+    ) -> Result<()> {
         let id = self.next_id().await;
         let stmts = to_script(&p, &self.target);
         let case = CrashedCase {
@@ -131,18 +188,21 @@ impl TestCaseRecord {
             p: stmts.to_string(),
             crash,
             repo,
+            kernel: self.kernel_info(kernel).await,
+            bundle: self.bundle_of(&p),
+            prog: Some(p),
         };
 
-        self.persist_crash_case(&case).await;
+        let persisted = self.persist_crash_case(&case).await;
 
         {
+            // counted under lock of crashes, so that `crashes_since` sees both consistent.
             let mut crashes = self.crash.lock().await;
             crashes.push(case);
-        }
-        {
             let mut crashed_num = self.crashed_num.lock().await;
             *crashed_num += 1;
         }
+        persisted
     }
 
     pub async fn insert_failed(&self, p: Prog, reason: Reason) {
@@ -168,8 +228,63 @@ impl TestCaseRecord {
         }
     }
 
-    pub async fn psersist(&self) {
-        tokio::join!(self.persist_normal_case(), self.persist_failed_case());
+    /// Record kernel of guest, saving it to `./kernel.json` and its config to
+    /// `./kernel.config`, or `./kernel-<name>.json` and `./kernel-<name>.config` for named
+    /// kernels, in work dir.
+    pub async fn set_kernel(&self, kernel: KernelInfo) -> Result<()> {
+        let stem = match kernel.name.as_ref() {
+            Some(name) => format!("kernel-{}", name),
+            None => String::from("kernel"),
+        };
+        let info = serde_json::to_string_pretty(&kernel).unwrap();
+        write(workdir::path(format!("{}.json", stem)), info)
+            .await
+            .map_err(|e| err!(exitcode::IOERR, "Fail to persist kernel info: {}", e))?;
+        if let Some(config) = kernel.config.as_ref() {
+            write(workdir::path(format!("{}.config", stem)), config)
+                .await
+                .map_err(|e| err!(exitcode::IOERR, "Fail to persist kernel config: {}", e))?;
+        }
+        self.kernels.lock().await.push(kernel);
+        Ok(())
+    }
+
+    /// Recorded info of kernel with `name`, or only its name if info was not collected.
+    /// Crashes recorded after the first `n` ones, with number of crashes recorded so far.
+    /// Crashes already dropped from memory are skipped.
+    pub async fn crashes_since(&self, n: usize) -> (Vec<CrashedCase>, usize) {
+        let crashes = self.crash.lock().await;
+        let crashed_num = *self.crashed_num.lock().await;
+        let mut new = crashes
+            .iter()
+            .take(crashed_num.saturating_sub(n))
+            .cloned()
+            .collect::<Vec<_>>();
+        new.reverse();
+        (new, crashed_num)
+    }
+
+    fn bundle_of(&self, p: &Prog) -> Option<PathBuf> {
+        self.bundles
+            .iter()
+            .find(|b| b.groups.contains(&p.gid))
+            .map(|b| b.path.clone())
+    }
+
+    async fn kernel_info(&self, name: Option<&str>) -> Option<KernelInfo> {
+        let kernels = self.kernels.lock().await;
+        match kernels.iter().find(|k| k.name.as_deref() == name) {
+            Some(kernel) => Some(kernel.clone()),
+            None => name.map(|name| KernelInfo {
+                name: Some(name.to_string()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub async fn psersist(&self) -> Result<()> {
+        let (normal, failed) = tokio::join!(self.persist_normal_case(), self.persist_failed_case());
+        normal.and(failed)
     }
 
     pub async fn len(&self) -> (usize, usize, usize) {
@@ -189,46 +304,46 @@ impl TestCaseRecord {
         )
     }
 
-    async fn persist_normal_case(&self) {
+    async fn persist_normal_case(&self) -> Result<()> {
         let cases = self.normal.lock().await;
         if cases.is_empty() {
-            return;
+            return Ok(());
         }
         let cases = cases.asc_iter().cloned().collect::<Vec<_>>();
 
-        let path = "./normal_case.json";
+        let path = workdir::path("normal_case.json");
         let report = serde_json::to_string_pretty(&cases).unwrap();
 
-        write(&path, report).await.unwrap_or_else(|e| {
-            exits!(
+        write(&path, report).await.map_err(|e| {
+            err!(
                 exitcode::IOERR,
                 "Fail to persist normal test case to {} : {}",
-                path,
+                path.display(),
                 e
             )
         })
     }
 
-    async fn persist_failed_case(&self) {
+    async fn persist_failed_case(&self) -> Result<()> {
         let cases = self.failed.lock().await;
         if cases.is_empty() {
-            return;
+            return Ok(());
         }
         let cases = cases.asc_iter().cloned().collect::<Vec<_>>();
-        let path = "./failed_case.json";
+        let path = workdir::path("failed_case.json");
         let report = serde_json::to_string_pretty(&cases).unwrap();
-        write(&path, report).await.unwrap_or_else(|e| {
-            exits!(
+        write(&path, report).await.map_err(|e| {
+            err!(
                 exitcode::IOERR,
                 "Fail to persist failed test case to {} : {}",
-                path,
+                path.display(),
                 e
             )
         })
     }
 
-    async fn persist_crash_case(&self, case: &CrashedCase) {
-        let path = format!("./crashes/{}", &case.meta.title);
+    async fn persist_crash_case(&self, case: &CrashedCase) -> Result<()> {
+        let path = workdir::path("crashes").join(&case.meta.title);
         let crash = serde_json::to_string_pretty(case).unwrap();
 
         #[cfg(feature = "mail")]
@@ -239,11 +354,11 @@ impl TestCaseRecord {
         )
         .await;
 
-        write(&path, crash).await.unwrap_or_else(|e| {
-            exits!(
+        write(&path, crash).await.map_err(|e| {
+            err!(
                 exitcode::IOERR,
-                "Fail to persist failed test case to {} : {}",
-                path,
+                "Fail to persist crashed test case to {} : {}",
+                path.display(),
                 e
             )
         })
@@ -255,6 +370,25 @@ impl TestCaseRecord {
         format!("{}_{}_{}", group, f, id)
     }
 
+    pub async fn state(&self) -> RecordState {
+        let next_id = *self.id_n.lock().await;
+        let (normal_num, failed_num, crashed_num) = self.len().await;
+        RecordState {
+            next_id,
+            normal_num,
+            failed_num,
+            crashed_num,
+        }
+    }
+
+    /// Continue ids and numbers of cases of resumed run.
+    pub async fn restore(&self, state: &RecordState) {
+        *self.id_n.lock().await = state.next_id;
+        *self.normal_num.lock().await = state.normal_num;
+        *self.failed_num.lock().await = state.failed_num;
+        *self.crashed_num.lock().await = state.crashed_num;
+    }
+
     async fn next_id(&self) -> usize {
         let mut id = self.id_n.lock().await;
         let next = *id;
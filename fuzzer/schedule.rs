@@ -0,0 +1,129 @@
+//! Schedule between generating fresh progs, mutating progs of corpus and re-executing them.
+
+use crate::error::Result;
+use crate::plateau::MUTATE_BOOST;
+use core::rng::thread_rng;
+use rand::distributions::{Distribution, WeightedIndex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Mutex;
+
+/// Executions after which counts of adaptive schedule are halved, so that it follows what
+/// brings coverage recently.
+const ADAPT_WINDOW: f64 = 1024.0;
+
+/// Way a prog to execute is got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Generate = 0,
+    Mutate = 1,
+    Replay = 2,
+}
+
+const KINDS: [Kind; 3] = [Kind::Generate, Kind::Mutate, Kind::Replay];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduleConf {
+    /// Weight of generating fresh prog
+    pub generate: u32,
+    /// Weight of mutating prog of corpus
+    pub mutate: u32,
+    /// Weight of re-executing prog of corpus as it is
+    pub replay: u32,
+    /// Shift weights toward what brings new coverage recently
+    #[serde(default)]
+    pub adaptive: bool,
+}
+
+impl ScheduleConf {
+    pub fn check(&self) -> Result<()> {
+        if self.generate == 0 {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: generate of schedule should be positive, corpus starts from generated progs"
+            )
+        }
+        Ok(())
+    }
+}
+
+pub struct Schedule {
+    conf: ScheduleConf,
+    /// Executions of each kind, and ones of them that brought new coverage
+    hits: Mutex<[(f64, f64); 3]>,
+    /// Weight of mutate raised on coverage plateau
+    boosted: AtomicBool,
+}
+
+impl Schedule {
+    pub fn new(conf: ScheduleConf) -> Self {
+        Self {
+            conf,
+            hits: Mutex::new([(0.0, 0.0); 3]),
+            boosted: AtomicBool::new(false),
+        }
+    }
+
+    /// Raise weight of mutate by `MUTATE_BOOST`.
+    pub fn boost_mutate(&self) {
+        self.boosted.store(true, Ordering::SeqCst);
+    }
+
+    /// Choose how to get next prog, only generating if corpus is empty.
+    pub async fn choose(&self, corpus_empty: bool) -> Kind {
+        if corpus_empty {
+            return Kind::Generate;
+        }
+        let weights = self.weights(&*self.hits.lock().await);
+        // generate is always positive, so weights are valid.
+        let i = WeightedIndex::new(&weights)
+            .unwrap()
+            .sample(&mut thread_rng());
+        KINDS[i]
+    }
+
+    /// Record execution of prog got by `kind`, and whether it brought new coverage.
+    pub async fn record(&self, kind: Kind, new_cover: bool) {
+        if !self.conf.adaptive {
+            return;
+        }
+        let mut hits = self.hits.lock().await;
+        let hit = &mut hits[kind as usize];
+        hit.0 += 1.0;
+        if new_cover {
+            hit.1 += 1.0;
+        }
+        if hits.iter().map(|(exec, _)| exec).sum::<f64>() >= ADAPT_WINDOW {
+            for (exec, new) in hits.iter_mut() {
+                *exec /= 2.0;
+                *new /= 2.0;
+            }
+            let weights = self.weights(&hits);
+            let sum = weights.iter().sum::<f64>();
+            debug!(
+                "Schedule: generate {:.2}, mutate {:.2}, replay {:.2}",
+                weights[0] / sum,
+                weights[1] / sum,
+                weights[2] / sum
+            );
+        }
+    }
+
+    /// Weights of each kind, scaled by rate of bringing new coverage if adaptive.
+    fn weights(&self, hits: &[(f64, f64); 3]) -> Vec<f64> {
+        let mut weights = [self.conf.generate, self.conf.mutate, self.conf.replay];
+        if self.boosted.load(Ordering::SeqCst) {
+            weights[1] *= MUTATE_BOOST as u32;
+        }
+        weights
+            .iter()
+            .zip(hits.iter())
+            .map(|(w, (exec, new))| {
+                if self.conf.adaptive {
+                    *w as f64 * (new + 1.0) / (exec + 1.0)
+                } else {
+                    *w as f64
+                }
+            })
+            .collect()
+    }
+}
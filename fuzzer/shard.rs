@@ -0,0 +1,128 @@
+//! Sharding of groups across vms. Groups are split into disjoint shards, vm `i` generates and
+//! mutates progs of shard `i % shards` only, with relations of each shard behind a lock of its
+//! own, while corpus and coverage are still shared by all vms.
+
+use crate::utils::split::Split;
+use crate::Config;
+use core::analyze::RTable;
+use core::rng::thread_rng;
+use core::target::Target;
+use fots::types::GroupId;
+use itertools::Itertools;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+
+/// Max weight of group given by reshuffle.
+const RESHUFFLE_MAX_WEIGHT: u32 = 8;
+
+/// Groups and their weights, choosing index of group.
+pub type GroupWeights = (Vec<GroupId>, WeightedIndex<u32>);
+
+pub struct Shard {
+    /// Groups of shard, sorted
+    pub gids: Vec<GroupId>,
+    /// Relations of calls in each group of shard
+    pub rt: Mutex<HashMap<GroupId, RTable>>,
+    /// Weights of choosing each group of shard for generation, uniform if none, replaced
+    /// when config is reloaded
+    pub weights: Mutex<Option<GroupWeights>>,
+}
+
+impl Shard {
+    /// Choose group of shard to generate prog of, by weights if given.
+    pub async fn choose_group(&self) -> GroupId {
+        match self.weights.lock().await.as_ref() {
+            Some((gids, weights)) => gids[weights.sample(&mut thread_rng())],
+            None => *self.gids.choose(&mut thread_rng()).unwrap(),
+        }
+    }
+}
+
+pub struct Shards(Vec<Shard>);
+
+impl Shards {
+    /// Split groups of `target` into `n` shards of contiguous group ids, taking relations of
+    /// each group from `rt`.
+    pub fn new(target: &Target, mut rt: HashMap<GroupId, RTable>, n: usize, cfg: &Config) -> Self {
+        let gids = target.groups.keys().copied().sorted().collect::<Vec<_>>();
+        warn_unknown_weights(target, cfg);
+        let mut gids = gids.into_iter();
+        let shards = Split::new(target.groups.len(), n)
+            .map(|len| {
+                let gids = gids.by_ref().take(len).collect::<Vec<_>>();
+                let rt = gids
+                    .iter()
+                    .map(|gid| (*gid, rt.remove(gid).unwrap()))
+                    .collect();
+                let weights = group_weights(target, &gids, cfg);
+                Shard {
+                    gids,
+                    rt: Mutex::new(rt),
+                    weights: Mutex::new(weights),
+                }
+            })
+            .collect();
+        Self(shards)
+    }
+
+    /// Shard that vm `id` fuzzes.
+    pub fn of_vm(&self, id: usize) -> &Shard {
+        &self.0[id % self.0.len()]
+    }
+
+    /// Shard that group `gid` belongs to.
+    pub fn of_group(&self, gid: GroupId) -> Option<&Shard> {
+        self.0.iter().find(|s| s.gids.binary_search(&gid).is_ok())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Shard> {
+        self.0.iter()
+    }
+
+    /// Apply group weights of reloaded config.
+    pub async fn reload(&self, target: &Target, cfg: &Config) {
+        warn_unknown_weights(target, cfg);
+        for shard in self.0.iter() {
+            *shard.weights.lock().await = group_weights(target, &shard.gids, cfg);
+        }
+    }
+
+    /// Replace weights of groups with random ones, until config is reloaded.
+    pub async fn reshuffle(&self) {
+        for shard in self.0.iter() {
+            let weights = shard
+                .gids
+                .iter()
+                .map(|_| thread_rng().gen_range(1, RESHUFFLE_MAX_WEIGHT + 1));
+            *shard.weights.lock().await =
+                Some((shard.gids.clone(), WeightedIndex::new(weights).unwrap()));
+        }
+    }
+}
+
+fn warn_unknown_weights(target: &Target, cfg: &Config) {
+    for ident in cfg.weights.iter().flat_map(|w| w.keys()) {
+        if target.iter_group().all(|g| &g.ident != ident) {
+            warn!("Weighted group {} is not found in target", ident);
+        }
+    }
+}
+
+/// Weights of groups `gids` in config, matched against group identifier, 1 for groups not
+/// given. None if no weight is given, so that group is chosen uniformly.
+fn group_weights(target: &Target, gids: &[GroupId], cfg: &Config) -> Option<GroupWeights> {
+    let weights = cfg.weights.as_ref().filter(|w| !w.is_empty())?;
+    let w = gids
+        .iter()
+        .map(|gid| *weights.get(&target.groups[gid].ident).unwrap_or(&1));
+    match WeightedIndex::new(w) {
+        Ok(weights) => Some((gids.to_vec(), weights)),
+        Err(e) => {
+            warn!("Invalid group weights, groups are chosen uniformly: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Self-test of target, executor and kernel run before fuzzing, so that a campaign is not
+//! silently wasted on missing calls or coverage.
+
+use crate::error::Result;
+use crate::exec::Executor;
+use crate::guest::KernelInfo;
+use crate::Config;
+use core::analyze::static_analyze;
+use core::gen::gen_prog;
+use core::target::Target;
+use executor::ExecResult;
+use itertools::Itertools;
+use nix::errno::Errno;
+
+/// Length of prog executed for each group.
+const SMOKE_PROG_LEN: usize = 4;
+
+/// Check that kcov, and kmemleak if memleak check is on, are available in guest of
+/// `executor`, then execute a short prog of each group, logging groups whose calls are
+/// missing(ENOSYS), which bring no coverage, fail or crash. Fails only if guest lacks a
+/// needed feature or no group brings any coverage.
+pub async fn smoke_test(
+    executor: &mut Executor,
+    target: &Target,
+    kernel: Option<&KernelInfo>,
+    cfg: &Config,
+) -> Result<()> {
+    match kernel {
+        Some(kernel) => {
+            if !kernel.kcov {
+                bail!(
+                    exitcode::UNAVAILABLE,
+                    "Smoke test failed: kcov is not available in guest, no coverage is collected"
+                )
+            }
+            if cfg.executor.memleak_check && !kernel.kmemleak {
+                bail!(
+                    exitcode::UNAVAILABLE,
+                    "Smoke test failed: memleak check is on, but kmemleak is not available in guest"
+                )
+            }
+        }
+        None => warn!("Smoke test: kernel of guest is unknown, kcov and kmemleak are not checked"),
+    }
+
+    let rt = static_analyze(target);
+    let conf = core::gen::Config {
+        prog_max_len: SMOKE_PROG_LEN,
+        ..Default::default()
+    };
+    let mut covered = 0;
+    let mut failed = 0;
+    for g in target.iter_group().sorted_by_key(|g| &g.ident) {
+        let p = gen_prog(g.id, &rt[&g.id], target, &conf);
+        let problem = match executor.exec(&p, target).await? {
+            Ok(ExecResult::Ok(info)) | Ok(ExecResult::Hanged { info, .. }) => {
+                let missing = p
+                    .calls
+                    .iter()
+                    .zip(info.iter())
+                    .filter(|(_, i)| !i.skipped && i.errno == Errno::ENOSYS as i32)
+                    .map(|(c, _)| target.fn_of(c.fid).call_name.as_str())
+                    .unique()
+                    .join(", ");
+                if info.iter().any(|i| !i.covs.is_empty()) {
+                    covered += 1;
+                    if missing.is_empty() {
+                        None
+                    } else {
+                        Some(format!("missing calls(ENOSYS): {}", missing))
+                    }
+                } else if missing.is_empty() {
+                    Some("no coverage".to_string())
+                } else {
+                    Some(format!("no coverage, missing calls(ENOSYS): {}", missing))
+                }
+            }
+            Ok(ExecResult::Failed(reason)) => Some(format!("failed: {}", reason)),
+            Ok(ExecResult::Cmps(_)) => unreachable!(),
+            Err(crash) => {
+                executor.start().await?;
+                let crash = crash.unwrap_or_default();
+                Some(format!(
+                    "crashed: {}",
+                    crash.inner.lines().next().unwrap_or_default()
+                ))
+            }
+        };
+        if let Some(problem) = problem {
+            failed += 1;
+            warn!("Smoke test: group {}, {}", g.ident, problem);
+        }
+    }
+
+    let total = target.groups.len();
+    if covered == 0 {
+        bail!(
+            exitcode::UNAVAILABLE,
+            "Smoke test failed: none of {} groups brings coverage, check kcov and executor",
+            total
+        )
+    }
+    info!(
+        "Smoke test: {}/{} groups passed, {} bring coverage",
+        total - failed,
+        total,
+        covered
+    );
+    Ok(())
+}
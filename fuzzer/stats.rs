@@ -1,31 +1,77 @@
 use crate::corpus::Corpus;
+use crate::error::Result;
 use crate::feedback::FeedBack;
 #[cfg(feature = "mail")]
 use crate::mail;
 use crate::report::TestCaseRecord;
-use crate::utils::queue::CQueue;
+use crate::triage::Triage;
+use crate::utils::workdir;
 #[cfg(feature = "mail")]
 use lettre_email::EmailBuilder;
 
 use circular_queue::CircularQueue;
 use core::prog::Prog;
-use std::process::exit;
+use executor::CallInfo;
+use fots::types::FnId;
+use log::Level;
+use std::collections::HashMap;
+use std::fmt;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::fs::write;
+use tokio::fs::read;
 use tokio::sync::broadcast;
+use tokio::sync::Mutex;
 use tokio::time;
 use tokio::time::Duration;
 
 pub struct StatSource {
     pub corpus: Arc<Corpus>,
     pub feedback: Arc<FeedBack>,
-    pub candidates: Arc<CQueue<Prog>>,
+    pub candidates: Arc<Triage>,
     pub record: Arc<TestCaseRecord>,
     pub exec: Arc<AtomicUsize>,
+    pub call_times: Arc<CallTimes>,
+    pub health_restarts: Arc<AtomicUsize>,
+    pub stalls: Arc<AtomicUsize>,
+    pub slow_recycles: Arc<AtomicUsize>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl StatSource {
+    /// Current stats of fuzzer.
+    pub async fn snapshot(&self) -> Stats {
+        let (
+            corpus,
+            (blocks, branches),
+            candidates,
+            (normal_case, failed_case, crashed_case),
+            (latency, slow_calls),
+        ) = tokio::join!(
+            self.corpus.len(),
+            self.feedback.len(),
+            self.candidates.remaining(),
+            self.record.len(),
+            self.call_times.summary()
+        );
+        Stats {
+            exec: self.exec.load(Ordering::SeqCst),
+            corpus,
+            blocks,
+            branches,
+            candidates,
+            normal_case,
+            failed_case,
+            crashed_case,
+            latency,
+            slow_calls,
+            health_restarts: self.health_restarts.load(Ordering::SeqCst),
+            stalls: self.stalls.load(Ordering::SeqCst),
+            slow_recycles: self.slow_recycles.load(Ordering::SeqCst),
+            near_duplicates: self.corpus.near_duplicates.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Stats {
     pub corpus: usize,
     pub blocks: usize,
@@ -37,6 +83,100 @@ pub struct Stats {
     pub normal_case: usize,
     pub failed_case: usize,
     pub crashed_case: usize,
+    /// Number of calls in each bucket of `LATENCY_BUCKETS`.
+    pub latency: Vec<usize>,
+    /// Calls that are considered pathologically slow.
+    pub slow_calls: usize,
+    /// Guests restarted because they stopped responding to health probes.
+    pub health_restarts: usize,
+    /// Vms restarted by watchdog because they stopped making progress.
+    #[serde(default)]
+    pub stalls: usize,
+    /// Vms recycled because their throughput fell far below median of vms.
+    #[serde(default)]
+    pub slow_recycles: usize,
+    /// Progs rejected by corpus as near duplicates of progs in it.
+    #[serde(default)]
+    pub near_duplicates: usize,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "exec: {}", self.exec)?;
+        writeln!(f, "corpus: {}", self.corpus)?;
+        writeln!(f, "blocks: {}", self.blocks)?;
+        writeln!(f, "branches: {}", self.branches)?;
+        writeln!(f, "candidates: {}", self.candidates)?;
+        writeln!(f, "normal_case: {}", self.normal_case)?;
+        writeln!(f, "failed_case: {}", self.failed_case)?;
+        writeln!(f, "crashed_case: {}", self.crashed_case)?;
+        writeln!(f, "slow_calls: {}", self.slow_calls)?;
+        writeln!(f, "health_restarts: {}", self.health_restarts)?;
+        writeln!(f, "stalls: {}", self.stalls)?;
+        writeln!(f, "slow_recycles: {}", self.slow_recycles)?;
+        write!(f, "near_duplicates: {}", self.near_duplicates)
+    }
+}
+
+/// Upper bounds of buckets of call latency distribution, in microseconds.
+pub const LATENCY_BUCKETS: [u64; 6] = [10, 100, 1_000, 10_000, 100_000, u64::MAX];
+/// Call that takes longer than this is slow, in microseconds.
+pub const SLOW_CALL: u64 = 100_000;
+/// Min executions of a call before judging whether it is slow.
+const SLOW_MIN_SAMPLES: usize = 16;
+
+/// Execution time of calls, collected from results of executed progs.
+#[derive(Default)]
+pub struct CallTimes {
+    inner: Mutex<CallTimesInner>,
+}
+
+#[derive(Default)]
+struct CallTimesInner {
+    latency: [usize; LATENCY_BUCKETS.len()],
+    /// Number of executions and slow executions of each call.
+    calls: HashMap<FnId, (usize, usize)>,
+}
+
+impl CallTimes {
+    pub async fn record(&self, p: &Prog, info: &[CallInfo]) {
+        let mut inner = self.inner.lock().await;
+        for (call, i) in p.calls.iter().zip(info.iter()) {
+            if i.skipped {
+                continue;
+            }
+            let bucket = LATENCY_BUCKETS
+                .iter()
+                .position(|b| i.elapsed <= *b)
+                .unwrap();
+            inner.latency[bucket] += 1;
+            let (total, slow) = inner.calls.entry(call.fid).or_default();
+            *total += 1;
+            if i.elapsed > SLOW_CALL {
+                *slow += 1;
+            }
+        }
+    }
+
+    /// Call is slow if most of its executions are slow.
+    pub async fn is_slow(&self, fid: FnId) -> bool {
+        let inner = self.inner.lock().await;
+        match inner.calls.get(&fid) {
+            Some(&(total, slow)) => total >= SLOW_MIN_SAMPLES && slow * 2 > total,
+            None => false,
+        }
+    }
+
+    /// Latency distribution and number of slow calls.
+    pub async fn summary(&self) -> (Vec<usize>, usize) {
+        let inner = self.inner.lock().await;
+        let slow_calls = inner
+            .calls
+            .values()
+            .filter(|&&(total, slow)| total >= SLOW_MIN_SAMPLES && slow * 2 > total)
+            .count();
+        (inner.latency.to_vec(), slow_calls)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -57,37 +197,70 @@ impl Default for SamplerConf {
 }
 
 impl SamplerConf {
-    pub fn check(&self) {
+    pub fn check(&self) -> Result<()> {
         if self.sample_interval < 10
             || self.report_interval <= 10
             || self.sample_interval * 60 < self.report_interval
         {
-            eprintln!("Config Error: invalid sample conf: sample interval should longger than 10s, \
+            bail!(exitcode::CONFIG, "Config Error: invalid sample conf: sample interval should longger than 10s, \
                                     report internval should long than 10m and sample interval should \
-                                    not longger than report interval");
-            exit(exitcode::CONFIG)
+                                    not longger than report interval")
         }
+        Ok(())
     }
 }
 
 pub struct Sampler {
     pub source: StatSource,
     pub stats: CircularQueue<Stats>,
+    /// Sampler config, which can be replaced while sampling
+    pub conf: Arc<Mutex<Option<SamplerConf>>>,
+    /// Interval of saving sampled stats, only saved on exit if not given
+    pub checkpoint_interval: Option<Duration>,
 }
 
 impl Sampler {
-    pub fn new(source: StatSource) -> Self {
+    pub fn new(
+        source: StatSource,
+        conf: Arc<Mutex<Option<SamplerConf>>>,
+        checkpoint_interval: Option<Duration>,
+    ) -> Self {
         Self {
             source,
             stats: CircularQueue::with_capacity(1024),
+            conf,
+            checkpoint_interval,
+        }
+    }
+    /// Continue stats sampled by resumed run.
+    pub async fn restore(&mut self) {
+        let path = workdir::path("stats.json");
+        let stats = read(&path)
+            .await
+            .ok()
+            .and_then(|stats| serde_json::from_slice::<Vec<Stats>>(&stats).ok());
+        match stats {
+            Some(stats) => {
+                for stat in stats {
+                    self.stats.push(stat);
+                }
+            }
+            None => warn!("Fail to load stats from {}", path.display()),
+        }
+    }
+
+    pub async fn sample(&mut self, mut shutdown: broadcast::Receiver<()>) {
+        tokio::select! {
+            _ = shutdown.recv() => (),
+            _ = self.do_sample() => (),
+        }
+        if let Err(e) = self.persist().await {
+            error!("{}", e);
         }
     }
-    pub async fn sample(
-        &mut self,
-        conf: &Option<SamplerConf>,
-        mut shutdown: broadcast::Receiver<()>,
-    ) {
-        let interval = match conf {
+
+    async fn interval(&self) -> (Duration, Duration) {
+        match self.conf.lock().await.as_ref() {
             Some(SamplerConf {
                 sample_interval,
                 report_interval,
@@ -96,63 +269,72 @@ impl Sampler {
                 Duration::new(report_interval * 60, 0),
             ),
             None => (Duration::new(15, 0), Duration::new(60 * 60, 0)),
-        };
-        tokio::select! {
-            _ = shutdown.recv() => (),
-            _ = self.do_sample(interval) => (),
         }
-        self.persist().await;
     }
 
-    async fn do_sample(&mut self, (sample_interval, report_interval): (Duration, Duration)) {
+    async fn do_sample(&mut self) {
         let mut last_report = Duration::new(0, 0);
+        let mut last_checkpoint = Duration::new(0, 0);
         loop {
+            let (sample_interval, report_interval) = self.interval().await;
             time::delay_for(sample_interval).await;
             last_report += sample_interval;
+            last_checkpoint += sample_interval;
 
-            let (corpus, (blocks, branches), candidates, (normal_case, failed_case, crashed_case)) = tokio::join!(
-                self.source.corpus.len(),
-                self.source.feedback.len(),
-                self.source.candidates.len(),
-                self.source.record.len()
-            );
-            let exec = self.source.exec.load(Ordering::SeqCst);
-
-            let stat = Stats {
-                exec,
-                corpus,
-                blocks,
-                branches,
-                candidates,
-                normal_case,
-                failed_case,
-                crashed_case,
-            };
-
+            let stat = self.source.snapshot().await;
             if report_interval <= last_report {
                 #[cfg(feature = "mail")]
                 self.report(&stat).await;
                 last_report = Duration::new(0, 0);
             }
 
-            self.stats.push(stat);
-            info!(
-                "exec {}, blocks {}, branches {}, failed {}, crashed {}",
-                exec, blocks, branches, failed_case, crashed_case
+            event!(
+                Level::Info,
+                "stats",
+                {
+                    "exec": stat.exec,
+                    "corpus": stat.corpus,
+                    "blocks": stat.blocks,
+                    "branches": stat.branches,
+                    "failed": stat.failed_case,
+                    "crashed": stat.crashed_case,
+                    "slow_calls": stat.slow_calls,
+                    "health_restarts": stat.health_restarts,
+                    "stalls": stat.stalls,
+                    "slow_recycles": stat.slow_recycles,
+                    "near_duplicates": stat.near_duplicates,
+                },
+                "exec {}, blocks {}, branches {}, failed {}, crashed {}, slow calls {}, unhealthy restarts {}, stalls {}, slow recycles {}, near duplicates {}",
+                stat.exec, stat.blocks, stat.branches, stat.failed_case, stat.crashed_case, stat.slow_calls, stat.health_restarts, stat.stalls, stat.slow_recycles, stat.near_duplicates
             );
+            self.stats.push(stat);
+
+            if let Some(checkpoint_interval) = self.checkpoint_interval {
+                if checkpoint_interval <= last_checkpoint {
+                    if let Err(e) = self.persist().await {
+                        warn!("{}", e);
+                    }
+                    last_checkpoint = Duration::new(0, 0);
+                }
+            }
         }
     }
 
-    async fn persist(&self) {
+    async fn persist(&self) -> Result<()> {
         if self.stats.is_empty() {
-            return;
+            return Ok(());
         }
 
         let stats = self.stats.asc_iter().cloned().collect::<Vec<_>>();
-        let path = "./stats.json";
+        let path = workdir::path("stats.json");
         let stats = serde_json::to_string_pretty(&stats).unwrap();
-        write(&path, stats).await.unwrap_or_else(|e| {
-            exits!(exitcode::IOERR, "Fail to persist stats to {} : {}", path, e)
+        workdir::write_atomic(&path, stats).await.map_err(|e| {
+            err!(
+                exitcode::IOERR,
+                "Fail to persist stats to {} : {}",
+                path.display(),
+                e
+            )
         })
     }
 
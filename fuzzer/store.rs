@@ -0,0 +1,263 @@
+//! Disk-backed storage of corpus. Progs are appended to `corpus.log` of run directory as soon
+//! as they are inserted, each record being length of prog followed by prog in bincode, while
+//! memory only keeps where each prog is, hashes for dropping duplicates, and a bounded cache
+//! of progs. Progs evicted from corpus are removed by appending a record of their hash. Log
+//! left by previous run in the same directory is recovered on open.
+
+use crate::corpus::prog_hash;
+use crate::error::Result;
+use core::prog::Prog;
+use fots::types::GroupId;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+pub const STORE_FILE: &str = "corpus.log";
+/// Progs kept in memory, if not configured.
+const DEFAULT_CACHE: usize = 4096;
+/// Bytes of length before each prog.
+const HEADER_LEN: u64 = 4;
+/// Length of record removing prog of the hash that follows it.
+const REMOVED: u32 = u32::MAX;
+/// Bytes of hash of removed prog.
+const HASH_LEN: u64 = 8;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StoreConf {
+    /// Progs kept in memory for mutation, 4096 by default
+    pub cache: Option<usize>,
+}
+
+impl StoreConf {
+    pub fn check(&self) -> Result<()> {
+        if self.cache.map_or(false, |c| c < 16) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: cache of corpus_store should be at least 16 progs"
+            )
+        }
+        Ok(())
+    }
+
+    pub fn cache(&self) -> usize {
+        self.cache.unwrap_or(DEFAULT_CACHE)
+    }
+}
+
+/// Where a prog is in store.
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub offset: u64,
+    pub len: u32,
+    pub gid: GroupId,
+    /// Number of calls and hash of prog, for scoring it without reading it
+    pub calls: usize,
+    pub hash: u64,
+}
+
+#[derive(Debug)]
+pub struct Store {
+    path: PathBuf,
+    file: File,
+    /// Location of each prog, in order of insertion
+    pub entries: Vec<Entry>,
+    /// Hash of each prog, two progs of same hash are taken as duplicates
+    hashes: HashSet<u64>,
+    end: u64,
+    /// Max number of progs kept in memory
+    pub cache: usize,
+}
+
+impl Store {
+    /// Open store at `path`, recovering progs appended before. A record torn by crash is
+    /// dropped with whatever follows it. Returns the latest recovered progs that fit in cache.
+    pub fn open(path: &Path, cache: usize) -> Result<(Self, Vec<Prog>)> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| err!(exitcode::IOERR, "Fail to open {}: {}", path.display(), e))?;
+        let mut entries = Vec::new();
+        let mut hashes = HashSet::new();
+        let mut end = 0;
+        let mut recent = VecDeque::new();
+        {
+            // index of latest entry of each hash, and entries removed since.
+            let mut latest = HashMap::new();
+            let mut removed = HashSet::new();
+            let mut reader = BufReader::new(&file);
+            while let Some(record) = read_record(&mut reader) {
+                match record {
+                    Record::Prog(len, p) => {
+                        let hash = prog_hash(&p);
+                        latest.insert(hash, entries.len());
+                        entries.push(Entry {
+                            offset: end,
+                            len,
+                            gid: p.gid,
+                            calls: p.calls.len(),
+                            hash,
+                        });
+                        hashes.insert(hash);
+                        end += HEADER_LEN + len as u64;
+                        if recent.len() == cache {
+                            recent.pop_front();
+                        }
+                        recent.push_back(p);
+                    }
+                    Record::Removed(hash) => {
+                        if let Some(i) = latest.remove(&hash) {
+                            removed.insert(i);
+                        }
+                        hashes.remove(&hash);
+                        end += HEADER_LEN + HASH_LEN;
+                    }
+                }
+            }
+            if !removed.is_empty() {
+                entries = entries
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| !removed.contains(i))
+                    .map(|(_, e)| e)
+                    .collect();
+                recent.retain(|p| hashes.contains(&prog_hash(p)));
+            }
+        }
+        let store = Self {
+            path: path.to_path_buf(),
+            file,
+            entries,
+            hashes,
+            end,
+            cache,
+        };
+        let size = store.file.metadata().map(|m| m.len()).unwrap_or(0);
+        if size != store.end {
+            warn!(
+                "{}: {} bytes of torn record dropped",
+                path.display(),
+                size - store.end
+            );
+            store.file.set_len(store.end).map_err(|e| {
+                err!(
+                    exitcode::IOERR,
+                    "Fail to truncate {}: {}",
+                    path.display(),
+                    e
+                )
+            })?;
+        }
+        Ok((store, recent.into()))
+    }
+
+    /// Append `p` and sync it to disk, false if it is stored already.
+    pub fn append(&mut self, p: &Prog) -> Result<bool> {
+        let hash = prog_hash(p);
+        if !self.hashes.insert(hash) {
+            return Ok(false);
+        }
+        let data = bincode::serialize(p).unwrap();
+        let mut record = (data.len() as u32).to_le_bytes().to_vec();
+        record.extend(data);
+        self.file
+            .write_all(&record)
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| {
+                err!(
+                    exitcode::IOERR,
+                    "Fail to append to {}: {}",
+                    self.path.display(),
+                    e
+                )
+            })?;
+        self.entries.push(Entry {
+            offset: self.end,
+            len: record.len() as u32 - HEADER_LEN as u32,
+            gid: p.gid,
+            calls: p.calls.len(),
+            hash,
+        });
+        self.end += record.len() as u64;
+        Ok(true)
+    }
+
+    /// Remove progs of `removed` hashes, syncing records of their removal to disk.
+    pub fn remove(&mut self, removed: &HashSet<u64>) -> Result<()> {
+        let mut records = Vec::new();
+        for hash in removed.iter().filter(|h| self.hashes.contains(*h)) {
+            records.extend(&REMOVED.to_le_bytes());
+            records.extend(&hash.to_le_bytes());
+        }
+        self.file
+            .write_all(&records)
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| {
+                err!(
+                    exitcode::IOERR,
+                    "Fail to append to {}: {}",
+                    self.path.display(),
+                    e
+                )
+            })?;
+        self.end += records.len() as u64;
+        self.entries.retain(|e| !removed.contains(&e.hash));
+        self.hashes.retain(|h| !removed.contains(h));
+        Ok(())
+    }
+
+    /// Read prog at `entry`.
+    pub fn read(&self, entry: &Entry) -> Result<Prog> {
+        let mut data = vec![0; entry.len as usize];
+        self.file
+            .read_exact_at(&mut data, entry.offset + HEADER_LEN)
+            .map_err(|e| {
+                err!(
+                    exitcode::IOERR,
+                    "Fail to read {}: {}",
+                    self.path.display(),
+                    e
+                )
+            })?;
+        bincode::deserialize(&data).map_err(|e| {
+            err!(
+                exitcode::DATAERR,
+                "Bad record in {}: {}",
+                self.path.display(),
+                e
+            )
+        })
+    }
+
+    /// Read all progs, in order of insertion.
+    pub fn read_all(&self) -> Result<Vec<Prog>> {
+        self.entries.iter().map(|e| self.read(e)).collect()
+    }
+}
+
+enum Record {
+    /// Length and prog appended
+    Prog(u32, Prog),
+    /// Hash of prog removed
+    Removed(u64),
+}
+
+/// Next record of log, none at its end or at a torn record.
+fn read_record<R: Read>(r: &mut R) -> Option<Record> {
+    let mut len = [0; HEADER_LEN as usize];
+    r.read_exact(&mut len).ok()?;
+    let len = u32::from_le_bytes(len);
+    if len == REMOVED {
+        let mut hash = [0; HASH_LEN as usize];
+        r.read_exact(&mut hash).ok()?;
+        return Some(Record::Removed(u64::from_le_bytes(hash)));
+    }
+    let mut data = vec![0; len as usize];
+    r.read_exact(&mut data).ok()?;
+    bincode::deserialize(&data)
+        .ok()
+        .map(|p| Record::Prog(len, p))
+}
@@ -0,0 +1,288 @@
+//! Import of syzkaller corpus. `corpus.db` is a log of records, each one a key and a program
+//! text compressed by deflate, where later records replace or delete earlier ones of same key.
+//! Each call of a program is mapped onto target by its name, `name$variant` of syzkaller
+//! matching `name@variant` declared in fots, or any call of `name` otherwise. Arguments of
+//! syzkaller are not carried over, values are generated for mapped calls by their fots types.
+
+use crate::error::Result;
+use core::gen::{gen_seq, Config};
+use core::prog::Prog;
+use core::target::Target;
+use flate2::bufread::DeflateDecoder;
+use fots::types::GroupId;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+
+const DB_MAGIC: u32 = 0xbaddb;
+const REC_MAGIC: u32 = 0xfee1bad;
+/// Sequence number of record deleting its key.
+const SEQ_DELETED: u64 = u64::MAX;
+
+/// Outcome of importing corpus.db.
+#[derive(Default)]
+pub struct Imported {
+    pub progs: Vec<Prog>,
+    /// Programs in db
+    pub records: usize,
+    /// Programs none of whose calls are found in target
+    pub dropped: usize,
+    /// Calls not found in target, or not in group chosen for their program
+    pub skipped_calls: usize,
+    /// Names of calls not found in target and times they occur
+    pub unknown: BTreeMap<String, usize>,
+    /// Programs dropped as near duplicates of ones imported before them
+    pub near_duplicates: usize,
+}
+
+/// Import programs of syzkaller `corpus.db` at `path` as progs of `target`, generating
+/// values of mapped calls by `conf`.
+pub fn import(target: &Target, path: &Path, conf: &Config) -> Result<Imported> {
+    let data = std::fs::read(path)
+        .map_err(|e| err!(exitcode::NOINPUT, "Fail to read {}: {}", path.display(), e))?;
+    let texts = parse_db(&data)
+        .map_err(|e| err!(exitcode::DATAERR, "Fail to parse {}: {}", path.display(), e))?;
+    let names = fn_names(target);
+    let mut imported = Imported {
+        records: texts.len(),
+        ..Default::default()
+    };
+    for text in texts {
+        let text = String::from_utf8_lossy(&text);
+        let calls = call_names(&text);
+        match map_prog(target, &names, &calls, conf) {
+            Some((p, skipped)) => {
+                imported.skipped_calls += skipped;
+                imported.progs.push(p);
+            }
+            None => {
+                imported.skipped_calls += calls.len();
+                imported.dropped += 1;
+            }
+        }
+        for call in calls {
+            if lookup(&names, call).is_empty() {
+                *imported.unknown.entry(call.to_string()).or_insert(0) += 1;
+            }
+        }
+    }
+    Ok(imported)
+}
+
+/// Values of live records of db, in order they were first written.
+fn parse_db(mut data: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    if read_u32(&mut data)? != DB_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "bad db magic"));
+    }
+    let _version = read_u64(&mut data)?;
+    let mut keys = HashMap::new();
+    let mut vals: Vec<Option<Vec<u8>>> = Vec::new();
+    while !data.is_empty() {
+        // records torn at end of db are dropped, as syzkaller does.
+        let (key, val) = match read_record(&mut data) {
+            Ok(record) => record,
+            Err(e) => {
+                warn!("Bad record of corpus.db, dropping the rest: {}", e);
+                break;
+            }
+        };
+        match keys.get(&key) {
+            Some(&i) => vals[i] = val,
+            None => {
+                keys.insert(key, vals.len());
+                vals.push(val);
+            }
+        }
+    }
+    Ok(vals.into_iter().flatten().collect())
+}
+
+/// Key and value of next record, none as value if record deletes key.
+fn read_record(data: &mut &[u8]) -> io::Result<(Vec<u8>, Option<Vec<u8>>)> {
+    if read_u32(data)? != REC_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad record magic",
+        ));
+    }
+    let mut key = vec![0; read_u32(data)? as usize];
+    data.read_exact(&mut key)?;
+    if read_u64(data)? == SEQ_DELETED {
+        return Ok((key, None));
+    }
+    let mut val = vec![0; read_u32(data)? as usize];
+    if !val.is_empty() {
+        let mut decoder = DeflateDecoder::new(&mut *data);
+        decoder.read_exact(&mut val)?;
+        // consume end of deflate stream, so that next record follows.
+        io::copy(&mut decoder, &mut io::sink())?;
+    }
+    Ok((key, Some(val)))
+}
+
+fn read_u32<R: BufRead>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: BufRead>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Names of calls of program text, e.g. `openat$kvm` of `r0 = openat$kvm(...)`.
+fn call_names(text: &str) -> Vec<&str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| {
+            let call = match l.find(" = ") {
+                // result of call, not a value among its args.
+                Some(i) if !l[..i].contains('(') => &l[i + 3..],
+                _ => l,
+            };
+            call.find('(').map(|i| call[..i].trim())
+        })
+        .collect()
+}
+
+/// Group and index in group of each call of target, by declared name and by called name.
+struct FnNames {
+    declared: HashMap<String, (GroupId, usize)>,
+    called: HashMap<String, Vec<(GroupId, usize)>>,
+}
+
+fn fn_names(target: &Target) -> FnNames {
+    let mut names = FnNames {
+        declared: HashMap::new(),
+        called: HashMap::new(),
+    };
+    let mut groups = target.iter_group().collect::<Vec<_>>();
+    groups.sort_by_key(|g| g.id);
+    for g in groups {
+        for (i, f) in g.fns.iter().enumerate() {
+            names.declared.insert(f.dec_name.clone(), (g.id, i));
+            names
+                .called
+                .entry(f.call_name.clone())
+                .or_default()
+                .push((g.id, i));
+        }
+    }
+    names
+}
+
+/// Calls of target that syzkaller call `name` maps onto: `name@variant` for `name$variant`,
+/// or every call of base name if no such call is declared.
+fn lookup(names: &FnNames, name: &str) -> Vec<(GroupId, usize)> {
+    let declared = name.replacen('$', "@", 1);
+    if let Some(f) = names.declared.get(&declared) {
+        return vec![*f];
+    }
+    let base = name.split('$').next().unwrap();
+    names.called.get(base).cloned().unwrap_or_default()
+}
+
+/// Prog of calls mapped into the group that most calls map into, and number of calls
+/// skipped, none if no call maps.
+fn map_prog(
+    target: &Target,
+    names: &FnNames,
+    calls: &[&str],
+    conf: &Config,
+) -> Option<(Prog, usize)> {
+    let candidates = calls.iter().map(|c| lookup(names, c)).collect::<Vec<_>>();
+    let mut votes: BTreeMap<GroupId, usize> = BTreeMap::new();
+    for c in candidates.iter() {
+        let mut gids = c.iter().map(|(gid, _)| *gid).collect::<Vec<_>>();
+        gids.dedup();
+        for gid in gids {
+            *votes.entry(gid).or_insert(0) += 1;
+        }
+    }
+    // lowest group id wins a tie.
+    let gid = votes
+        .iter()
+        .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(gid, _)| *gid)?;
+    let seq = candidates
+        .iter()
+        .filter_map(|c| c.iter().find(|(g, _)| *g == gid).map(|(_, i)| *i))
+        .collect::<Vec<_>>();
+    let skipped = calls.len() - seq.len();
+    Some((gen_seq(&seq, gid, target, conf), skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn db(records: &[(&[u8], Option<&[u8]>)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&DB_MAGIC.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        for (seq, (key, val)) in records.iter().enumerate() {
+            data.extend_from_slice(&REC_MAGIC.to_le_bytes());
+            data.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            data.extend_from_slice(key);
+            match val {
+                Some(val) => {
+                    data.extend_from_slice(&(seq as u64).to_le_bytes());
+                    data.extend_from_slice(&(val.len() as u32).to_le_bytes());
+                    if !val.is_empty() {
+                        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                        encoder.write_all(val).unwrap();
+                        data.extend(encoder.finish().unwrap());
+                    }
+                }
+                None => data.extend_from_slice(&SEQ_DELETED.to_le_bytes()),
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn db_keeps_live_records_in_order() {
+        let data = db(&[
+            (b"a", Some(b"one")),
+            (b"b", Some(b"two")),
+            (b"c", Some(b"three")),
+            (b"a", Some(b"four")),
+            (b"b", None),
+        ]);
+        let vals = parse_db(&data).unwrap();
+        assert_eq!(vals, vec![b"four".to_vec(), b"three".to_vec()]);
+    }
+
+    #[test]
+    fn db_drops_torn_tail() {
+        let mut data = db(&[(b"a", Some(b"one")), (b"b", Some(b"two"))]);
+        data.truncate(data.len() - 2);
+        assert_eq!(parse_db(&data).unwrap(), vec![b"one".to_vec()]);
+    }
+
+    #[test]
+    fn db_rejects_bad_magic() {
+        let mut data = db(&[(b"a", Some(b"one"))]);
+        data[0] ^= 0xff;
+        assert!(parse_db(&data).is_err());
+    }
+
+    #[test]
+    fn call_names_of_prog() {
+        let text = "# comment\n\
+                    r0 = openat$kvm(0xffffffffffffff9c, &(0x7f0000000000)='/dev/kvm\\x00', 0x0, 0x0)\n\
+                    \n\
+                    ioctl$KVM_CREATE_VM(r0, 0xae01, 0x0)\n\
+                    write(r0, &(0x7f0000000040)=\"a = b(\", 0x6)\n  close(r0)  \n";
+        assert_eq!(
+            call_names(text),
+            vec!["openat$kvm", "ioctl$KVM_CREATE_VM", "write", "close"]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Throughput of each vm, in execs per second, for recycling vms that fall far below median
+//! of all vms for a sustained period, e.g. guest degraded by leaked memory or a busy device.
+
+use crate::error::Result;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::time::Duration;
+
+/// Fraction of median below which vm is slow, if not configured.
+const DEFAULT_RATIO: f64 = 0.25;
+/// Minutes that vm stays slow before it is recycled, if not configured.
+const DEFAULT_MINUTES: u64 = 10;
+/// Least number of vms reporting throughput for median to be meaningful.
+const MIN_VMS: usize = 3;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ThroughputConf {
+    /// Fraction of median throughput of all vms below which vm is slow, 0.25 by default
+    pub ratio: Option<f64>,
+    /// Minutes that vm stays slow before it is recycled, 10 by default
+    pub minutes: Option<u64>,
+}
+
+impl ThroughputConf {
+    pub fn check(&self) -> Result<()> {
+        if self
+            .ratio
+            .map_or(false, |r| r.is_nan() || r <= 0.0 || r >= 1.0)
+        {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: ratio of throughput should be in (0, 1)"
+            )
+        }
+        if self.minutes.map_or(false, |m| m < 3) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: minutes of throughput should be at least 3, guests need time to boot"
+            )
+        }
+        Ok(())
+    }
+}
+
+pub struct Throughput {
+    pub ratio: f64,
+    pub period: Duration,
+    /// Latest execs per second of each vm
+    rates: Mutex<HashMap<usize, f64>>,
+}
+
+/// Throughput of vm being tracked, forgotten once dropped.
+pub struct Tracked<'a> {
+    throughput: &'a Throughput,
+    id: usize,
+}
+
+impl Tracked<'_> {
+    /// Record execs per second of vm, return median of all vms, if enough vms report.
+    pub fn report(&self, rate: f64) -> Option<f64> {
+        let mut rates = self.throughput.rates.lock().unwrap();
+        rates.insert(self.id, rate);
+        if rates.len() < MIN_VMS {
+            return None;
+        }
+        let mut all = rates.values().copied().collect::<Vec<_>>();
+        all.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        Some(all[all.len() / 2])
+    }
+
+    /// Stop counting vm in median, e.g. while it is paused.
+    pub fn pause(&self) {
+        self.throughput.rates.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Drop for Tracked<'_> {
+    fn drop(&mut self) {
+        self.pause();
+    }
+}
+
+impl Throughput {
+    pub fn new(conf: &ThroughputConf) -> Self {
+        Self {
+            ratio: conf.ratio.unwrap_or(DEFAULT_RATIO),
+            period: Duration::from_secs(conf.minutes.unwrap_or(DEFAULT_MINUTES) * 60),
+            rates: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn track(&self, id: usize) -> Tracked<'_> {
+        Tracked {
+            throughput: self,
+            id,
+        }
+    }
+
+    /// Execs per second of each vm, ordered by id.
+    pub fn rates(&self) -> Vec<(usize, f64)> {
+        let mut rates = self
+            .rates
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, rate)| (*id, *rate))
+            .collect::<Vec<_>>();
+        rates.sort_by_key(|(id, _)| *id);
+        rates
+    }
+}
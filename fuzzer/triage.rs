@@ -0,0 +1,139 @@
+//! Triage of seed corpus before fuzzing. Seeds are deduplicated and partitioned across vms
+//! booted at start, each vm executes its own part, taking over seeds of others once its part
+//! is done, and keeps seeds bringing new coverage. Generation starts once all seeds are triaged.
+
+use crate::utils::queue::CQueue;
+use core::prog::Prog;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tokio::time::{delay_for, Duration, Instant};
+
+/// How often vm done with triage checks whether others are done too.
+const WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Triage {
+    /// Seeds left of each vm booted at start
+    parts: Vec<CQueue<Prog>>,
+    /// Seeds taken by vms but not analyzed yet
+    taken: AtomicUsize,
+    /// Vms done with seeds, waiting others to finish theirs
+    waiting: Mutex<HashSet<usize>>,
+    seeds: usize,
+    duplicates: usize,
+    started: Mutex<Option<Instant>>,
+    finished: AtomicBool,
+}
+
+/// Seeds taken from triage, counted as not analyzed until dropped.
+pub struct Taken<'a> {
+    triage: &'a Triage,
+    n: usize,
+}
+
+impl Drop for Taken<'_> {
+    fn drop(&mut self) {
+        self.triage.taken.fetch_sub(self.n, Ordering::SeqCst);
+    }
+}
+
+impl Triage {
+    /// Deduplicate `seeds` and deal them to `vms` vms in turn.
+    pub fn new(seeds: Vec<Prog>, vms: usize) -> Self {
+        let total = seeds.len();
+        let mut seen = HashSet::new();
+        let mut parts = vec![Vec::new(); vms.max(1)];
+        let mut n = 0;
+        for p in seeds {
+            if seen.insert(p.clone()) {
+                let i = n % parts.len();
+                parts[i].push(p);
+                n += 1;
+            }
+        }
+        Self {
+            parts: parts.into_iter().map(CQueue::from).collect(),
+            taken: AtomicUsize::new(0),
+            waiting: Mutex::new(HashSet::new()),
+            seeds: n,
+            duplicates: total - n,
+            started: Mutex::new(None),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Take at most `n` seeds for vm `id`, from its own part, or from largest part of other
+    /// vms once its own is empty. Nothing is returned once all seeds are taken.
+    pub async fn take(&self, id: usize, n: usize) -> (Vec<Prog>, Taken<'_>) {
+        self.started
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now);
+        // reserve before popping, so that seeds being taken are never missed by `remaining`.
+        self.taken.fetch_add(n, Ordering::SeqCst);
+        let mut seeds = match self.parts.get(id) {
+            Some(part) => part.pop_n(n).await,
+            None => Vec::new(),
+        };
+        if seeds.is_empty() {
+            let mut largest = (0, 0);
+            for (i, part) in self.parts.iter().enumerate() {
+                let len = part.len().await;
+                if len > largest.1 {
+                    largest = (i, len);
+                }
+            }
+            if largest.1 != 0 {
+                seeds = self.parts[largest.0].pop_n(n).await;
+            }
+        }
+        self.taken.fetch_sub(n - seeds.len(), Ordering::SeqCst);
+        let taken = Taken {
+            triage: self,
+            n: seeds.len(),
+        };
+        (seeds, taken)
+    }
+
+    /// Give back seeds of vm `id` that were not executed.
+    pub async fn put_back(&self, id: usize, seeds: Vec<Prog>) {
+        let part = &self.parts[id.min(self.parts.len() - 1)];
+        for p in seeds {
+            part.push(p).await;
+        }
+    }
+
+    /// Seeds not triaged yet, including those being taken.
+    pub async fn remaining(&self) -> usize {
+        let mut n = self.taken.load(Ordering::SeqCst);
+        for part in self.parts.iter() {
+            n += part.len().await;
+        }
+        n
+    }
+
+    /// Wait other vms to finish their seeds, once vm `id` has none left. The first vm
+    /// returning gets numbers of seeds and of dropped duplicates and time triage took, others
+    /// get none.
+    pub async fn wait(&self, id: usize) -> Option<(usize, usize, Duration)> {
+        self.waiting.lock().unwrap().insert(id);
+        while self.remaining().await != 0 {
+            delay_for(WAIT_INTERVAL).await;
+        }
+        self.waiting.lock().unwrap().remove(&id);
+        if self.finished.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        let elapsed = self
+            .started
+            .lock()
+            .unwrap()
+            .map_or_else(|| Duration::new(0, 0), |t| t.elapsed());
+        Some((self.seeds, self.duplicates, elapsed))
+    }
+
+    /// Whether vm `id` is idle waiting other vms to finish triage.
+    pub fn is_waiting(&self, id: usize) -> bool {
+        self.waiting.lock().unwrap().contains(&id)
+    }
+}
@@ -0,0 +1,206 @@
+//! Upload of outputs of run to remote storage, so that long-running remote campaigns survive
+//! loss of host and can be monitored centrally. Files are pushed by `aws`, `gsutil` or `curl`,
+//! which should be installed and authorized already.
+
+use crate::error::Result;
+use crate::utils::cli::{App, Arg, OptVal};
+use crate::utils::workdir;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::fs::read_dir;
+use tokio::sync::Mutex;
+use tokio::time::{timeout, Duration};
+
+/// Minutes between uploads, if not configured.
+const DEFAULT_INTERVAL: u64 = 60;
+/// Time limit of uploading one file.
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Outputs of run uploaded besides crashes, relative to run directory.
+const FILES: [&str; 4] = ["corpus", "state", "stats.json", "snapshot.json"];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadConf {
+    /// Destination, s3://<bucket>/<prefix>, gs://<bucket>/<prefix> or http(s) url that files
+    /// are PUT under
+    pub url: String,
+    /// Minutes between uploads, 60 by default
+    pub interval: Option<u64>,
+    /// Headers of http uploads, e.g. "Authorization: Bearer <token>"
+    #[serde(default)]
+    pub headers: Vec<String>,
+}
+
+impl UploadConf {
+    pub fn check(&self) -> Result<()> {
+        if Backend::of(&self.url).is_none() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: unsupported upload url {}, s3://, gs://, http:// or https:// expected",
+                self.url
+            )
+        }
+        if !self.headers.is_empty() && Backend::of(&self.url) != Some(Backend::Http) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: headers of upload are only sent to http urls"
+            )
+        }
+        if self.interval == Some(0) {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: interval of upload should be positive"
+            )
+        }
+        Ok(())
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval.unwrap_or(DEFAULT_INTERVAL) * 60)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    S3,
+    Gcs,
+    Http,
+}
+
+impl Backend {
+    fn of(url: &str) -> Option<Self> {
+        if url.starts_with("s3://") {
+            Some(Backend::S3)
+        } else if url.starts_with("gs://") {
+            Some(Backend::Gcs)
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            Some(Backend::Http)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Uploader {
+    conf: UploadConf,
+    backend: Backend,
+    /// Modification time and length of each file when it was last uploaded
+    uploaded: Mutex<HashMap<PathBuf, (SystemTime, u64)>>,
+}
+
+impl Uploader {
+    pub fn new(conf: UploadConf) -> Self {
+        let backend = Backend::of(&conf.url).unwrap();
+        Self {
+            conf,
+            backend,
+            uploaded: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Upload corpus, state, stats and crashes in run directory that changed since last
+    /// upload. Files failing to upload are retried next time.
+    pub async fn upload(&self) -> Result<()> {
+        let mut files = FILES.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let crashes = workdir::path("crashes");
+        let mut entries = read_dir(&crashes)
+            .await
+            .map_err(|e| err!(exitcode::IOERR, "Fail to read {}: {}", crashes.display(), e))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| err!(exitcode::IOERR, "Fail to read {}: {}", crashes.display(), e))?
+        {
+            files.push(Path::new("crashes").join(entry.file_name()));
+        }
+
+        let mut uploaded = self.uploaded.lock().await;
+        let mut failed = 0;
+        for name in files {
+            let path = workdir::path(&name);
+            let meta = match path.metadata() {
+                Ok(meta) if meta.is_file() => meta,
+                _ => continue,
+            };
+            let version = (
+                meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                meta.len(),
+            );
+            if uploaded.get(&name) == Some(&version) {
+                continue;
+            }
+            match self.put(&path, &name).await {
+                Ok(()) => {
+                    uploaded.insert(name, version);
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    failed += 1;
+                }
+            }
+        }
+        if failed != 0 {
+            bail!(
+                exitcode::UNAVAILABLE,
+                "Fail to upload {} files to {}",
+                failed,
+                self.conf.url
+            )
+        }
+        Ok(())
+    }
+
+    /// Upload file `path` as `name` under url.
+    async fn put(&self, path: &Path, name: &Path) -> Result<()> {
+        let dest = format!("{}/{}", self.conf.url.trim_end_matches('/'), name.display());
+        let mut app = match self.backend {
+            Backend::S3 => {
+                let mut aws = App::new("aws");
+                aws.arg(Arg::new_flag("s3"))
+                    .arg(Arg::new_flag("cp"))
+                    .arg(Arg::new_flag("--only-show-errors"));
+                aws
+            }
+            Backend::Gcs => {
+                let mut gsutil = App::new("gsutil");
+                gsutil.arg(Arg::new_flag("-q")).arg(Arg::new_flag("cp"));
+                gsutil
+            }
+            Backend::Http => {
+                let mut curl = App::new("curl");
+                curl.arg(Arg::new_flag("-sSf"));
+                for header in self.conf.headers.iter() {
+                    curl.arg(Arg::new_opt("-H", OptVal::normal(header)));
+                }
+                curl.arg(Arg::new_flag("-T"));
+                curl
+            }
+        };
+        app.arg(Arg::new_flag(path.to_str().unwrap()))
+            .arg(Arg::new_flag(&dest));
+        let bin = app.bin.clone();
+        let mut cmd = app.into_cmd();
+        cmd.kill_on_drop(true);
+        let output = match timeout(UPLOAD_TIMEOUT, cmd.output()).await {
+            Ok(output) => {
+                output.map_err(|e| err!(exitcode::OSERR, "Fail to spawn {}: {}", bin, e))?
+            }
+            Err(_) => bail!(
+                exitcode::UNAVAILABLE,
+                "Time out: upload {} to {}",
+                path.display(),
+                dest
+            ),
+        };
+        if !output.status.success() {
+            bail!(
+                exitcode::UNAVAILABLE,
+                "Fail to upload {} to {}: {}",
+                path.display(),
+                dest,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        }
+        Ok(())
+    }
+}
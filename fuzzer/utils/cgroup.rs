@@ -0,0 +1,157 @@
+//! Cgroup v2 of each vm, capping cpu and memory of its qemu process, so that one runaway
+//! guest can't starve other guests or the host.
+
+use crate::error::Result;
+use std::fs::{create_dir_all, read_to_string, remove_dir, write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Cgroup directory that cgroups of vms are created under, if not configured.
+const DEFAULT_ROOT: &str = "/sys/fs/cgroup/healer";
+/// Period of cpu.max, in microseconds.
+const CPU_PERIOD: u64 = 100_000;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CgroupConf {
+    /// Cgroup v2 directory that each vm gets its own cgroup under, /sys/fs/cgroup/healer
+    /// by default
+    pub root: Option<PathBuf>,
+    /// Cpus each qemu process may use, e.g. 1.5, unlimited if not given
+    pub cpus: Option<f64>,
+    /// Memory each qemu process may use, in MB, unlimited if not given
+    pub memory: Option<u64>,
+}
+
+impl CgroupConf {
+    /// Check limits, memory of qemu process should leave room for `mem_size` MB of guest.
+    pub fn check(&self, mem_size: u32) -> Result<()> {
+        if self.cpus.is_none() && self.memory.is_none() {
+            bail!(
+                exitcode::CONFIG,
+                "Config Error: cgroup needs cpus or memory"
+            )
+        }
+        if let Some(cpus) = self.cpus {
+            if cpus.is_nan() || cpus <= 0.0 {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: cpus of cgroup should be positive"
+                )
+            }
+        }
+        if let Some(memory) = self.memory {
+            if memory <= u64::from(mem_size) {
+                bail!(
+                    exitcode::CONFIG,
+                    "Config Error: memory of cgroup {}MB should be bigger than mem_size {}MB of qemu, which qemu itself adds to",
+                    memory,
+                    mem_size
+                )
+            }
+        }
+        Ok(())
+    }
+
+    fn root(&self) -> &Path {
+        self.root
+            .as_deref()
+            .unwrap_or_else(|| Path::new(DEFAULT_ROOT))
+    }
+
+    /// Controllers needed by configured limits.
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = Vec::new();
+        if self.cpus.is_some() {
+            controllers.push("cpu");
+        }
+        if self.memory.is_some() {
+            controllers.push("memory");
+        }
+        controllers
+    }
+}
+
+/// Cgroup owned by one vm, removed on drop once its processes are gone.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Create cgroup `<root>/<pid>-vm<vm>` with limits of `conf`, creating root and
+    /// enabling controllers of it if needed.
+    pub fn create(conf: &CgroupConf, vm: usize) -> Result<Self> {
+        let root = conf.root();
+        create_dir_all(root).map_err(|e| {
+            err!(
+                exitcode::OSERR,
+                "Fail to create cgroup {}: {}, cgroup v2 should be mounted and writable",
+                root.display(),
+                e
+            )
+        })?;
+        let available = read_to_string(root.join("cgroup.controllers")).unwrap_or_default();
+        for c in conf.controllers() {
+            if !available.split_whitespace().any(|a| a == c) {
+                bail!(
+                    exitcode::OSERR,
+                    "Controller {} is not available in cgroup {}, enable it in cgroup.subtree_control of its parent",
+                    c,
+                    root.display()
+                )
+            }
+            write_file(&root.join("cgroup.subtree_control"), &format!("+{}", c))?;
+        }
+
+        let path = root.join(format!("{}-vm{}", process::id(), vm));
+        // left by last run with same pid, e.g. in container.
+        remove_dir(&path).ok();
+        create_dir_all(&path).map_err(|e| {
+            err!(
+                exitcode::OSERR,
+                "Fail to create cgroup {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        let cgroup = Self { path };
+        if let Some(cpus) = conf.cpus {
+            let quota = (cpus * CPU_PERIOD as f64) as u64;
+            write_file(
+                &cgroup.path.join("cpu.max"),
+                &format!("{} {}", quota, CPU_PERIOD),
+            )?;
+        }
+        if let Some(memory) = conf.memory {
+            write_file(
+                &cgroup.path.join("memory.max"),
+                &(memory * 1024 * 1024).to_string(),
+            )?;
+        }
+        Ok(cgroup)
+    }
+
+    /// Move process `pid` into cgroup.
+    pub fn attach(&self, pid: u32) -> Result<()> {
+        write_file(&self.path.join("cgroup.procs"), &pid.to_string())
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // fails if killed qemu has not exited yet, nothing to do then.
+        remove_dir(&self.path).ok();
+    }
+}
+
+fn write_file(path: &Path, content: &str) -> Result<()> {
+    write(path, content).map_err(|e| {
+        err!(
+            exitcode::OSERR,
+            "Fail to write {} to {}: {}",
+            content,
+            path.display(),
+            e
+        )
+    })
+}
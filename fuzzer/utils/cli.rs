@@ -1,3 +1,4 @@
+use std::fmt;
 use tokio::process::Command;
 
 #[derive(Clone, Debug)]
@@ -40,6 +41,25 @@ impl App {
     }
 }
 
+/// Command line that can be pasted into shell, arguments with special chars are quoted.
+impl fmt::Display for App {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.bin)?;
+        for arg in self.clone().iter_arg() {
+            let plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_=,.:/@+%".contains(c));
+            if plain {
+                write!(f, " {}", arg)?;
+            } else {
+                write!(f, " '{}'", arg.replace('\'', "'\\''"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
 struct IterArg {
     args: Vec<Arg>,
     state: ArgState,
@@ -0,0 +1,137 @@
+//! Detached operation of fuzzer: it forks into background and keeps its pid in `fuzzer.pid`
+//! under work dir(current directory if not configured), so that `fuzzer stop` with same
+//! config finds and stops it.
+
+use crate::error::Result;
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::{dup2, fork, setsid, ForkResult, Pid};
+use std::fs::{self, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use tokio::time::{delay_for, Duration, Instant};
+
+pub const PID_FILE: &str = "fuzzer.pid";
+
+/// How often `stop` checks whether fuzzer exited.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Pid file of fuzzer running with work dir `work_dir`.
+pub fn pid_file(work_dir: Option<&Path>) -> PathBuf {
+    work_dir.unwrap_or_else(|| Path::new(".")).join(PID_FILE)
+}
+
+/// Pid of fuzzer in `pid_file`, if it is still running.
+fn running(pid_file: &Path) -> Option<Pid> {
+    let pid = fs::read_to_string(pid_file).ok()?.trim().parse().ok()?;
+    let pid = Pid::from_raw(pid);
+    match kill(pid, None) {
+        Ok(()) | Err(nix::Error::Sys(Errno::EPERM)) => Some(pid),
+        Err(_) => None,
+    }
+}
+
+/// Fork into background: parent prints pid of child and exits, child leaves session of
+/// terminal, with stdin, stdout and stderr on /dev/null until `redirect_output`. Threads
+/// do not survive fork, so this must be called before runtime starts.
+pub fn daemonize(work_dir: Option<&Path>) -> Result<()> {
+    let pid_file = pid_file(work_dir);
+    if let Some(pid) = running(&pid_file) {
+        bail!(
+            exitcode::USAGE,
+            "Fuzzer {} is running with {} already, stop it by `fuzzer stop` first",
+            pid,
+            pid_file.display()
+        )
+    }
+    match fork() {
+        Ok(ForkResult::Parent { child }) => {
+            println!(
+                "Fuzzer runs in background, pid {}, stop it by `fuzzer stop`",
+                child
+            );
+            process::exit(0)
+        }
+        Ok(ForkResult::Child) => (),
+        Err(e) => bail!(exitcode::OSERR, "Fail to fork: {}", e),
+    }
+    setsid().map_err(|e| err!(exitcode::OSERR, "Fail to create session: {}", e))?;
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .map_err(|e| err!(exitcode::OSERR, "Fail to open /dev/null: {}", e))?;
+    for fd in 0..3 {
+        dup2(null.as_raw_fd(), fd)
+            .map_err(|e| err!(exitcode::OSERR, "Fail to redirect fd {}: {}", fd, e))?;
+    }
+    Ok(())
+}
+
+/// Append stdout and stderr to `path`, so that logs on stdout and panics of daemon are kept.
+pub fn redirect_output(path: &Path) -> Result<()> {
+    let f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| err!(exitcode::IOERR, "Fail to open {}: {}", path.display(), e))?;
+    for fd in 1..3 {
+        dup2(f.as_raw_fd(), fd)
+            .map_err(|e| err!(exitcode::OSERR, "Fail to redirect fd {}: {}", fd, e))?;
+    }
+    Ok(())
+}
+
+/// Pid file of this process, removed once dropped.
+pub struct PidFile(PathBuf);
+
+impl PidFile {
+    pub fn write(work_dir: Option<&Path>) -> Result<Self> {
+        let path = pid_file(work_dir);
+        fs::write(&path, format!("{}\n", process::id()))
+            .map_err(|e| err!(exitcode::IOERR, "Fail to write {}: {}", path.display(), e))?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.0).ok();
+    }
+}
+
+/// Send SIGTERM to fuzzer running with work dir `work_dir` and wait at most `timeout` for
+/// it to persist its outputs and exit, returning its pid.
+pub async fn stop(work_dir: Option<&Path>, timeout: Duration) -> Result<Pid> {
+    let pid_file = pid_file(work_dir);
+    let pid = match running(&pid_file) {
+        Some(pid) => pid,
+        None => {
+            // left by fuzzer that was killed.
+            if pid_file.exists() {
+                fs::remove_file(&pid_file).ok();
+            }
+            bail!(
+                exitcode::UNAVAILABLE,
+                "No fuzzer is running with {}",
+                pid_file.display()
+            )
+        }
+    };
+    kill(pid, Signal::SIGTERM)
+        .map_err(|e| err!(exitcode::NOPERM, "Fail to stop fuzzer {}: {}", pid, e))?;
+    let started = Instant::now();
+    while running(&pid_file) == Some(pid) {
+        if started.elapsed() >= timeout {
+            bail!(
+                exitcode::TEMPFAIL,
+                "Fuzzer {} is still running after {}s",
+                pid,
+                timeout.as_secs()
+            )
+        }
+        delay_for(STOP_POLL_INTERVAL).await;
+    }
+    Ok(pid)
+}
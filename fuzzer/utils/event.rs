@@ -0,0 +1,95 @@
+//! Structured log events. Each log line is encoded as a json object by `JsonLines` if
+//! `log_json` is set, events logged with `event!` carry their type and fields there too,
+//! while text logs only show their message.
+use crate::utils::vm_log;
+use chrono::Local;
+use log::Record;
+use log4rs::encode::{Encode, Write};
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Type and fields of event being logged on this thread.
+    static EVENT: RefCell<Option<(&'static str, Value)>> = RefCell::new(None);
+}
+
+/// Log event of type `kind` at `level` with `fields`, message is formatted from rest arguments,
+/// e.g. `event!(Level::Info, "vm_boot", { "vm": id }, "Vm {} booted", id)`.
+macro_rules! event {
+    ( $level:expr, $kind:expr, { $( $field:tt : $value:expr ),* $(,)? }, $( $arg:tt )+ ) => {{
+        let level: log::Level = $level;
+        if level <= log::max_level() {
+            $crate::utils::event::log(
+                level,
+                module_path!(),
+                $kind,
+                serde_json::json!({ $( $field : $value ),* }),
+                format_args!( $( $arg )+ ),
+            )
+        }
+    }};
+}
+
+/// Whether logs are json lines.
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+pub fn log(
+    level: log::Level,
+    target: &str,
+    kind: &'static str,
+    fields: Value,
+    msg: std::fmt::Arguments,
+) {
+    // appenders of log4rs encode on the logging thread, before `log!` returns.
+    EVENT.with(|e| *e.borrow_mut() = Some((kind, fields)));
+    log!(target: target, level, "{}", msg);
+    EVENT.with(|e| *e.borrow_mut() = None);
+}
+
+/// Encoder of log lines as json objects of time, level, target, vm of task if any, event type,
+/// message and fields of the event, plain logs are events of type "log".
+#[derive(Debug)]
+pub struct JsonLines;
+
+impl Encode for JsonLines {
+    fn encode(
+        &self,
+        w: &mut dyn Write,
+        record: &Record,
+    ) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let mut line = Map::new();
+        line.insert("time".into(), Local::now().to_rfc3339().into());
+        line.insert("level".into(), record.level().to_string().into());
+        line.insert("target".into(), record.target().into());
+        if let Some(id) = vm_log::current() {
+            line.insert("vm".into(), id.into());
+        }
+        EVENT.with(|e| match &*e.borrow() {
+            Some((kind, fields)) => {
+                line.insert("event".into(), (*kind).into());
+                if let Value::Object(fields) = fields {
+                    for (k, v) in fields {
+                        line.insert(k.clone(), v.clone());
+                    }
+                }
+            }
+            None => {
+                line.insert("event".into(), "log".into());
+            }
+        });
+        line.insert("msg".into(), record.args().to_string().into());
+        serde_json::to_writer(&mut *w, &line)?;
+        w.write_all(b"\n")?;
+        Ok(())
+    }
+}
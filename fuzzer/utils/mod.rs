@@ -1,11 +1,16 @@
+pub mod cgroup;
 pub mod cli;
+pub mod daemon;
+#[macro_use]
+pub mod event;
 pub mod process;
 pub mod queue;
+pub mod registry;
 pub mod split;
+pub mod vm_log;
+pub mod workdir;
 
 use std::future::Future;
-use std::net::{Ipv4Addr, SocketAddrV4, TcpListener};
-use std::sync::Mutex;
 use tokio::sync::broadcast;
 
 pub async fn transaction_loop<T, F, Z>(
@@ -32,26 +37,3 @@ pub async fn transaction_loop<T, F, Z>(
         transaction.await;
     }
 }
-
-lazy_static! {
-    static ref SCAN_RANGE: Mutex<(u16, u16)> = Mutex::new((1 << 12, 0xFFFF));
-}
-
-pub fn free_ipv4_port() -> Option<u16> {
-    let mut r = SCAN_RANGE.lock().unwrap();
-
-    if r.0 >= r.1 {
-        r.0 = 1 << 12;
-        r.1 = 0xFFFF;
-    }
-
-    let mut addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, r.0);
-    for p in r.0..r.1 {
-        addr.set_port(p);
-        if TcpListener::bind(&addr).is_ok() {
-            r.0 = p + 1;
-            return Some(p);
-        }
-    }
-    None
-}
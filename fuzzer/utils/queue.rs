@@ -24,6 +24,13 @@ impl<T> CQueue<T> {
         inner.pop_front()
     }
 
+    /// Pop at most n values from front.
+    pub async fn pop_n(&self, n: usize) -> Vec<T> {
+        let mut inner = self.inner.lock().await;
+        let n = std::cmp::min(n, inner.len());
+        inner.drain(..n).collect()
+    }
+
     pub async fn len(&self) -> usize {
         let inner = self.inner.lock().await;
         inner.len()
@@ -0,0 +1,136 @@
+//! Ports and working directories of vm instances, reserved up front so that instances
+//! booted concurrently never pick the same ones, and released once their owner drops them.
+
+use crate::error::Result;
+use std::collections::BTreeSet;
+use std::env::temp_dir;
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+
+/// Ports below are left to system services.
+const PORT_START: u16 = 1 << 12;
+
+struct Registry {
+    /// Ports handed out and not released yet
+    ports: BTreeSet<u16>,
+    /// Where next scan for free ports starts
+    next_port: u16,
+    next_dir: usize,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+        ports: BTreeSet::new(),
+        next_port: PORT_START,
+        next_dir: 0,
+    });
+}
+
+/// Consecutive local ports reserved by `reserve_ports`, released on drop.
+#[derive(Debug)]
+pub struct Ports {
+    base: u16,
+    num: u16,
+}
+
+impl Ports {
+    /// `i`th port of range.
+    pub fn get(&self, i: u16) -> u16 {
+        assert!(i < self.num);
+        self.base + i
+    }
+}
+
+impl Drop for Ports {
+    fn drop(&mut self) {
+        let mut r = REGISTRY.lock().unwrap();
+        for p in self.base..self.base + self.num {
+            r.ports.remove(&p);
+        }
+    }
+}
+
+/// Reserve `num` consecutive ports that are free on localhost and not held by others of
+/// this process, none if there are no such ports.
+pub fn reserve_ports(num: u16) -> Option<Ports> {
+    assert!(num != 0);
+    let mut r = REGISTRY.lock().unwrap();
+    let last = u16::MAX - num;
+    // scan from cursor, wrapping around once.
+    let scan = (r.next_port..=last).chain(PORT_START..r.next_port.min(last));
+    let ports = r.reserve(scan, num)?;
+    r.next_port = if ports.base + num > last {
+        PORT_START
+    } else {
+        ports.base + num
+    };
+    Some(ports)
+}
+
+/// Reserve lowest free port not below `start`, for services expecting well-known ports,
+/// e.g. vnc displays starting from 5900.
+pub fn reserve_port_from(start: u16) -> Option<Ports> {
+    REGISTRY.lock().unwrap().reserve(start..u16::MAX, 1)
+}
+
+impl Registry {
+    /// Reserve first `num` ports starting at one of `bases` that are free.
+    fn reserve<I: Iterator<Item = u16>>(&mut self, bases: I, num: u16) -> Option<Ports> {
+        let ports = &self.ports;
+        let base = bases
+            .filter(|b| (*b..*b + num).all(|p| !ports.contains(&p)))
+            .find(|b| (*b..*b + num).all(bindable))?;
+        self.ports.extend(base..base + num);
+        Some(Ports { base, num })
+    }
+}
+
+fn bindable(port: u16) -> bool {
+    TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)).is_ok()
+}
+
+/// Directory owned by one instance, removed with everything inside on drop.
+#[derive(Debug)]
+pub struct WorkDir {
+    path: PathBuf,
+}
+
+impl WorkDir {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, p: P) -> PathBuf {
+        self.path.join(p)
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.path).ok();
+    }
+}
+
+/// Create fresh directory `<tmp>/healer-<pid>/<name>-<id>`.
+pub fn reserve_dir(name: &str) -> Result<WorkDir> {
+    let id = {
+        let mut r = REGISTRY.lock().unwrap();
+        r.next_dir += 1;
+        r.next_dir
+    };
+    let path = temp_dir()
+        .join(format!("healer-{}", process::id()))
+        .join(format!("{}-{}", name, id));
+    std::fs::remove_dir_all(&path).ok();
+    std::fs::create_dir_all(&path).map_err(|e| {
+        err!(
+            exitcode::IOERR,
+            "Fail to create work dir {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+    Ok(WorkDir { path })
+}
@@ -0,0 +1,89 @@
+//! Logs of each vm. Tasks working for a vm run in its scope, so that their logs also go to
+//! `log/vm-<id>.log` through `VmAppender`.
+use crate::utils::workdir;
+use log::Record;
+use log4rs::append::Append;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::encode::Encode;
+use std::collections::hash_map::{Entry, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
+use tokio::task::JoinHandle;
+
+tokio::task_local! {
+    static VM: usize;
+}
+
+/// Run `f` as task of vm `id`.
+pub async fn scope<F: Future>(id: usize, f: F) -> F::Output {
+    VM.scope(id, f).await
+}
+
+/// Vm current task works for, if any.
+pub fn current() -> Option<usize> {
+    VM.try_with(|id| *id).ok()
+}
+
+/// Spawn task in scope of vm of current task, if any.
+pub fn spawn<F>(f: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match current() {
+        Some(id) => tokio::spawn(VM.scope(id, f)),
+        None => tokio::spawn(f),
+    }
+}
+
+/// Appender of logs of vm tasks to log file of the vm, logs of other tasks are dropped.
+pub struct VmAppender {
+    encoder: Box<dyn Encode>,
+    files: Mutex<HashMap<usize, SimpleWriter<BufWriter<File>>>>,
+}
+
+impl VmAppender {
+    pub fn new(encoder: Box<dyn Encode>) -> Self {
+        Self {
+            encoder,
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl fmt::Debug for VmAppender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VmAppender")
+            .field("encoder", &self.encoder)
+            .finish()
+    }
+}
+
+impl Append for VmAppender {
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let id = match current() {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let mut files = self.files.lock().unwrap();
+        let w = match files.entry(id) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(workdir::path(format!("log/vm-{}.log", id)))?;
+                e.insert(SimpleWriter(BufWriter::new(file)))
+            }
+        };
+        self.encoder.encode(w, record)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,146 @@
+//! Where outputs of fuzzer go: `<work_dir>/run-<time>-<pid>` if work dir is configured,
+//! current directory otherwise, with `index.json` describing the layout.
+
+use crate::error::Result;
+use chrono::Local;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::RwLock;
+use tokio::fs::{rename, File};
+use tokio::io::AsyncWriteExt;
+
+/// Outputs of a run, relative to its directory, and what they are.
+pub const LAYOUT: [(&str, &str); 15] = [
+    ("crashes", "crashed cases, one json file each"),
+    ("corpus", "corpus dump, loadable as corpus of next run"),
+    (
+        "corpus.log",
+        "progs of corpus appended as found, if corpus_store is configured",
+    ),
+    (
+        "state",
+        "coverage, relations and counters, for resuming the run",
+    ),
+    (
+        "bundles.json",
+        "fots files merged into target and ids of groups each brought",
+    ),
+    (
+        "fots",
+        "merged fots deployed to guests, if multiple fots files are given",
+    ),
+    ("normal_case.json", "recently executed cases"),
+    ("failed_case.json", "recently failed cases"),
+    ("stats.json", "sampled stats"),
+    (
+        "snapshot.json",
+        "stats when state was last dumped by SIGUSR1 or for upload",
+    ),
+    (
+        "cover.txt",
+        "covered blocks when state was last dumped by SIGUSR1 or for upload",
+    ),
+    (
+        "kernel.json",
+        "kernel info of guests, kernel-<name>.json for named kernels",
+    ),
+    (
+        "kernel.config",
+        "kernel config of guests, kernel-<name>.config for named kernels",
+    ),
+    ("log/fuzzer.log", "log of fuzzer, log/stats.log for stats"),
+    (
+        "log",
+        "executor-<vm>.log, console-<vm>.log and vm-<vm>.log(logs of fuzzer about it) of each vm",
+    ),
+];
+
+lazy_static! {
+    static ref RUN_DIR: RwLock<PathBuf> = RwLock::new(PathBuf::from("."));
+}
+
+/// Create directory of this run under `work_dir`, or use current directory if not given,
+/// and write index of its layout. Resumed run keeps using its directory.
+pub fn init(work_dir: Option<&Path>, resume: Option<&Path>) -> Result<PathBuf> {
+    let dir = match (resume, work_dir) {
+        (Some(resume), _) => resume.to_path_buf(),
+        (None, Some(work_dir)) => work_dir.join(format!(
+            "run-{}-{}",
+            Local::now().format("%Y%m%d-%H%M%S"),
+            process::id()
+        )),
+        (None, None) => PathBuf::from("."),
+    };
+    std::fs::create_dir_all(dir.join("crashes")).map_err(|e| {
+        err!(
+            exitcode::IOERR,
+            "Fail to create work dir {}: {}",
+            dir.display(),
+            e
+        )
+    })?;
+
+    let layout = LAYOUT
+        .iter()
+        .map(|(path, desc)| (path.to_string(), serde_json::Value::from(*desc)))
+        .collect::<serde_json::Map<_, _>>();
+    let index = serde_json::json!({
+        "pid": process::id(),
+        "started": Local::now().to_rfc3339(),
+        "layout": layout,
+    });
+    let index_path = dir.join("index.json");
+    std::fs::write(&index_path, serde_json::to_string_pretty(&index).unwrap()).map_err(|e| {
+        err!(
+            exitcode::IOERR,
+            "Fail to write {}: {}",
+            index_path.display(),
+            e
+        )
+    })?;
+
+    *RUN_DIR.write().unwrap() = dir.clone();
+    Ok(dir)
+}
+
+/// Path of output of this run.
+pub fn path<P: AsRef<Path>>(name: P) -> PathBuf {
+    RUN_DIR.read().unwrap().join(name)
+}
+
+/// Output `name` of the latest run under `work_dir` other than this one, or of current
+/// directory if work dir is not configured, none if there is no such output.
+pub fn latest(work_dir: Option<&Path>, name: &str) -> Option<PathBuf> {
+    let work_dir = match work_dir {
+        Some(work_dir) => work_dir,
+        None => return Some(PathBuf::from(name)).filter(|p| p.is_file()),
+    };
+    let current = RUN_DIR.read().unwrap().clone();
+    // names of runs start with time they started, so the latest one sorts last.
+    std::fs::read_dir(work_dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .map_or(false, |n| n.starts_with("run-"))
+                && *p != current
+                && p.join(name).is_file()
+        })
+        .max()
+        .map(|p| p.join(name))
+}
+
+/// Write file through a temporary one renamed over it, so that it is never left half
+/// written, e.g. by power loss.
+pub async fn write_atomic<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut f = File::create(&tmp).await?;
+    f.write_all(contents.as_ref()).await?;
+    f.sync_all().await?;
+    rename(&tmp, path).await
+}
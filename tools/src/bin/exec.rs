@@ -1,7 +1,7 @@
 use core::prog::Prog;
 use executor::exec::fork_exec;
 use executor::exec::ExecResult;
-use executor::Config;
+use executor::{Config, ExecOpt, Feature, Sandbox, Timeouts};
 use std::fs::read;
 use std::path::PathBuf;
 use std::process::exit;
@@ -19,6 +19,27 @@ struct Settings {
     memleak_check: bool,
     #[structopt(short = "c", long)]
     concurrency: bool,
+    /// Inject fault into call with given index
+    #[structopt(long)]
+    fault_call: Option<usize>,
+    /// Timeout of whole prog, in milliseconds
+    #[structopt(long, default_value = "10000")]
+    prog_timeout: u64,
+    /// Timeout of each call, in milliseconds
+    #[structopt(long, default_value = "1000")]
+    call_timeout: u64,
+    /// Collect comparison operands instead of coverage
+    #[structopt(long)]
+    cmps: bool,
+    /// Sandbox of executed prog: none, setuid or namespace
+    #[structopt(short = "s", long, default_value = "none")]
+    sandbox: Sandbox,
+    /// Environment features set up before executing prog: tmpfs, netdev, binderfs, loopdev
+    #[structopt(short = "e", long, use_delimiter = true)]
+    features: Vec<Feature>,
+    /// Report fds and mounts left behind by prog
+    #[structopt(short = "l", long = "leak-check")]
+    leak_check: bool,
 }
 
 fn main() {
@@ -36,21 +57,76 @@ fn main() {
 
     let len = p.len();
     let conf = Config {
+        fork_server: false,
+        sandbox: settings.sandbox,
+        features: settings.features,
+        leak_check: settings.leak_check,
+        // sanitizer callbacks are only exported by executor binary
+        harness: None,
+    };
+    let timeouts = Timeouts {
+        prog: settings.prog_timeout,
+        call: settings.call_timeout,
+    };
+    let opt = ExecOpt {
+        cmps: settings.cmps,
+        threaded: settings.concurrency,
         memleak_check: settings.memleak_check,
-        concurrency: settings.concurrency,
+        fault_call: settings.fault_call,
+        ..Default::default()
     };
-    match fork_exec(p, &target, &conf) {
-        ExecResult::Ok(covs) => {
+    let (result, findings) = fork_exec(p, &target, &conf, &timeouts, &opt, &mut |_| ());
+    for f in findings.iter() {
+        eprintln!("Found {:?}:\n{}", f.kind, f.report);
+    }
+    match result {
+        ExecResult::Ok(info) => {
             let mut total = 0;
             let mut each = Vec::new();
-            for c in covs.iter() {
-                total += c.len();
-                each.push(c.len());
+            for i in info.iter() {
+                total += i.covs.len();
+                each.push(i.covs.len());
             }
 
             println!("Prog len:{},Total pc:{},Executed:{:?}", len, total, each);
+            for (idx, i) in info.iter().enumerate() {
+                if let Some(fault) = i.fault() {
+                    println!("call {}: faulted, {}", idx, fault);
+                } else if i.skipped {
+                    println!("call {}: skipped", idx);
+                } else {
+                    println!(
+                        "call {}: ret {}, errno {}, {}us",
+                        idx, i.ret, i.errno, i.elapsed
+                    );
+                }
+            }
             exit(exitcode::OK)
         }
+        ExecResult::Cmps(cmps) => {
+            for (idx, cmps) in cmps.iter().enumerate() {
+                println!("call {}: {} comparisons", idx, cmps.len());
+                for c in cmps {
+                    println!(
+                        "    pc {:#x}: {:#x} vs {:#x}, size {}, const {}",
+                        c.pc,
+                        c.arg1,
+                        c.arg2,
+                        c.size(),
+                        c.is_const()
+                    );
+                }
+            }
+            exit(exitcode::OK)
+        }
+        ExecResult::Hanged { call, info } => {
+            let each = info.iter().map(|i| i.covs.len()).collect::<Vec<_>>();
+            match call {
+                Some(call) => eprintln!("Hanged: call {} of {}, Executed:{:?}", call, len, each),
+                None => eprintln!("Hanged: prog of {} calls", len),
+            }
+            exit(exitcode::TEMPFAIL)
+        }
         ExecResult::Failed(e) => {
             eprintln!("Error: {}", e);
             exit(exitcode::SOFTWARE)
@@ -149,6 +149,12 @@ fn report_crash(crash: &CrashedCase) -> String {
     writeln!(buf, "# {}", crash.meta.title).unwrap();
     writeln!(buf, "**Id**:   {}</br>", crash.meta.id).unwrap();
     writeln!(buf, "**Repo**: {}</br>", crash.repo).unwrap();
+    writeln!(
+        buf,
+        "**Kind**: {:?}, found by {:?}</br>",
+        crash.crash.kind, crash.crash.source
+    )
+    .unwrap();
     writeln!(buf, "**Test Time**: {}</br>", crash.meta.test_time).unwrap();
     writeln!(buf, "## Prog").unwrap();
     writeln!(buf, "``` c").unwrap();